@@ -1,18 +1,22 @@
 use sea_orm::{ entity::prelude::*, ActiveValue::Set, DbErr };
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
 use async_trait::async_trait;
-use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use shared::{clock, otp, rng, OrgId, UserId};
+use super::config::{self, SecurityConfig};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "api_keys", schema_name = "public")]
+#[sea_orm(table_name = "api_keys")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
-  pub user_id: Option<Uuid>,
-  pub organisation_id: Option<Uuid>,
+  pub user_id: Option<UserId>,
+  pub organisation_id: Option<OrgId>,
+  #[sea_orm(unique)]
   pub api_access_key: String,
+  /// SHA-256 of the secret, which is only ever shown when the key is issued
+  #[serde(skip_serializing)]
   pub api_secret_key: String,
   pub key_issued_at: ChronoDateTimeUtc,
   pub expires_on: Option<ChronoDateTimeUtc>,
@@ -54,33 +58,52 @@ impl Related<super::organisation::Entity> for Entity {
   }
 }
 
-pub fn generante_api_key() -> (String, String) {
-  let api_access_key_size: usize = 64;
-  let api_secret_key_size: usize = 64;
-  (rand::thread_rng()
-  .sample_iter(&Alphanumeric)
-  .take(api_access_key_size)
-  .map(char::from)
-  .collect(),
-  rand::thread_rng()
-  .sample_iter(&Alphanumeric)
-  .take(api_secret_key_size)
-  .map(char::from)
-  .collect())
+pub fn generante_api_key(security: &SecurityConfig) -> (String, String) {
+  let api_access_key_size: usize = security.api_key_length;
+  let api_secret_key_size: usize = security.api_key_length;
+  (rng::alphanumeric(api_access_key_size), rng::alphanumeric(api_secret_key_size))
+}
+
+/// The secrets are long and random so a plain digest is enough, unlike passwords
+pub fn hash_secret(secret: &str) -> String {
+  hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+impl Model {
+  pub fn check_secret(&self, secret: &str) -> bool {
+    otp::constant_time_eq(&self.api_secret_key, &hash_secret(secret))
+  }
+}
+
+/// Inserts the key with a new access key and secret, at the length its organisation or user
+/// asks for. The secret is returned here and only its hash is kept.
+pub async fn issue<C>(db: &C, mut key: ActiveModel) -> Result<(Model, String), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let organisation_id = if key.organisation_id.is_not_set() { None } else { *key.organisation_id.as_ref() };
+  let user_id = if key.user_id.is_not_set() { None } else { *key.user_id.as_ref() };
+  let security = match (organisation_id, user_id) {
+    (Some(organisation_id), _) => config::security_for_organisation(db, organisation_id).await?,
+    (None, Some(user_id)) => config::security_for_user(db, user_id).await?,
+    // Refused by before_save
+    (None, None) => config::get().security.clone(),
+  };
+  let (api_access_key, api_secret_key) = generante_api_key(&security);
+  key.api_access_key = Set(api_access_key);
+  key.api_secret_key = Set(api_secret_key.clone());
+  Ok((key.insert(db).await?, api_secret_key))
 }
 
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
-    let (api_access_key, api_secret_key) = generante_api_key();
     Self {
       id: Set(Uuid::new_v4()),
-      api_access_key: Set(api_access_key),
-      api_secret_key: Set(api_secret_key),
-      key_issued_at: Set(Utc::now()),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      key_issued_at: Set(clock::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
@@ -96,10 +119,20 @@ impl ActiveModelBehavior for ActiveModel {
         insert
       )));
     }
+    if insert && (self.api_access_key.is_not_set() || self.api_secret_key.is_not_set()) {
+      return Err(DbErr::Custom(format!(
+        "[before_save] Api keys are generated by auth_api_key::issue, insert: {}",
+        insert
+      )));
+    }
+    // Only the hash of a new secret is stored
+    if self.api_secret_key.is_set() {
+      self.api_secret_key = Set(hash_secret(self.api_secret_key.as_ref()));
+    }
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
       if self.ip_address_last_used.is_set() {
-        self.key_last_used_at = Set(Some(Utc::now()));
+        self.key_last_used_at = Set(Some(clock::now()));
       }
     }
     Ok(self)
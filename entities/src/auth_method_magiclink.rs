@@ -1,17 +1,18 @@
 use sea_orm::{ entity::prelude::*, ActiveValue::Set };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
-use rand::{distributions::Alphanumeric, Rng};
+use chrono::Duration;
+use shared::{clock, rng, UserId};
+use super::config::{self, SecurityConfig};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "auth_method_passes", schema_name = "public")]
+#[sea_orm(table_name = "auth_method_magiclinks")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
   #[sea_orm(unique)]
-  pub user_id: Uuid,
+  pub user_id: UserId,
   pub email_id: Option<Uuid>,
   pub phone_id: Option<Uuid>,
   #[serde(skip_serializing)]
@@ -70,18 +71,23 @@ impl Related<super::phone::Entity> for Entity {
   }
 }
 
-pub fn generate_expires_at() -> ChronoDateTimeUtc {
-  let link_valid_mins: i64 = 10;
-  return Utc::now() + Duration::minutes(link_valid_mins);
+impl Model {
+  /// Whether the link can still be used to log in with the given hash
+  pub fn is_valid(&self, link_hash: &str) -> bool {
+    self.link_used_at.is_none()
+      && self.link_hash.as_deref() == Some(link_hash)
+      && self.link_hash_expires_at.map(|expires_at| expires_at > clock::now()).unwrap_or(false)
+  }
+}
+
+pub fn generate_expires_at(security: &SecurityConfig) -> ChronoDateTimeUtc {
+  let link_valid_mins: i64 = security.magic_link_valid_mins;
+  clock::now() + Duration::minutes(link_valid_mins)
 }
 
-pub fn generate_login_link_hash() -> String {
-  let link_hash_size: usize = 256;
-  rand::thread_rng()
-  .sample_iter(&Alphanumeric)
-  .take(link_hash_size)
-  .map(char::from)
-  .collect()
+pub fn generate_login_link_hash(security: &SecurityConfig) -> String {
+  let link_hash_size: usize = security.magic_link_hash_length;
+  rng::alphanumeric(link_hash_size)
 }
 
 #[async_trait]
@@ -90,35 +96,39 @@ impl ActiveModelBehavior for ActiveModel {
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      link_hash: Set(Some(generate_login_link_hash())),
-      link_hash_expires_at: Set(Some(generate_expires_at())),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
       // Link used means it's no longer valid so null it
       if self.link_used_at.is_set() {
         self.link_hash = Set(None);
       }
-      if self.link_hash.is_set() {
-        // If link hash is being set then update the expires at date
-        if self.link_hash.as_ref().is_some() {
-          self.link_hash = Set(Some(generate_login_link_hash()));
-          self.link_hash_expires_at = Set(Some(generate_expires_at()));
-        // If link hash is empty then set expiry to empty
-        } else {
-          self.link_hash_expires_at = Set(None);
-        }
-      }
+    }
+    // New links and links being set again get a hash and expiry under the user's settings
+    if (insert && self.link_hash.is_not_set()) || (self.link_hash.is_set() && self.link_hash.as_ref().is_some()) {
+      let user_id = match self.user_id.is_not_set() {
+        false => *self.user_id.as_ref(),
+        true => match Entity::find_by_id(*self.id.as_ref()).one(db).await? {
+          Some(stored) => stored.user_id,
+          None => return Err(DbErr::RecordNotFound(format!("auth_method_magiclink {}", self.id.as_ref()))),
+        },
+      };
+      let security = config::security_for_user(db, user_id).await?;
+      self.link_hash = Set(Some(generate_login_link_hash(&security)));
+      self.link_hash_expires_at = Set(Some(generate_expires_at(&security)));
+    // If link hash is empty then set expiry to empty
+    } else if self.link_hash.is_set() {
+      self.link_hash_expires_at = Set(None);
     }
     Ok(self)
   }
@@ -1,14 +1,15 @@
-use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use sea_orm::{ entity::prelude::*, sea_query::Expr, ActiveValue::{self, Set} };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
-use rand::{distributions::Alphanumeric, Rng};
+use chrono::Duration;
 use bcrypt::{
   hash_with_result as bcrypt_hash_with_result,
   verify as bcrypt_verify,
   Version as BcryptVersion,
   BcryptError, HashParts
 };
+use shared::{clock, otp, UserId};
+use super::config::{security_for_user, SecurityConfig};
 
 #[derive(Debug, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "password_hash_cipher")]
@@ -18,12 +19,12 @@ pub enum PassHashCipher {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "auth_method_passes", schema_name = "public")]
+#[sea_orm(table_name = "auth_method_passes")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
-  pub user_id: Uuid,
+  pub user_id: UserId,
   #[serde(skip_serializing)]
   #[sea_orm(nullable)]
   pub pass_hash: Option<String>,
@@ -31,12 +32,16 @@ pub struct Model {
   pub pass_hash_cipher: PassHashCipher,
   pub pass_last_changed_at: ChronoDateTimeUtc,
   pub force_pass_change: bool,
-  #[sea_orm(nullable, unique)]
+  #[serde(skip_serializing)]
+  #[sea_orm(nullable)]
   pub pass_reset_code: Option<String>, // Shorter reset code for sending via SMS
+  #[serde(skip_serializing)]
   #[sea_orm(nullable, unique)]
   pub pass_reset_str: Option<String>, // Longer reset string for including in links
   #[sea_orm(nullable)]
   pub pass_reset_code_expires_at: Option<ChronoDateTimeUtc>,
+  /// Wrong guesses against the current reset code or string
+  pub pass_reset_attempts: i32,
   pub created_at: ChronoDateTimeUtc,
   pub updated_at: ChronoDateTimeUtc,
 }
@@ -59,14 +64,14 @@ impl Related<super::user::Entity> for Entity {
   }
 }
 
-pub fn hash_pass(pass: &Option<String>, cipher: &PassHashCipher) -> Option<String> {
+pub fn hash_pass(pass: &Option<String>, cipher: &PassHashCipher, security: &SecurityConfig) -> Option<String> {
   if pass.is_none() {
     return None
   }
   let pass_value: String = pass.as_ref().unwrap().to_string();
   match cipher {
     PassHashCipher::Bcrypt => {
-      let bcrypt_cost: u32 = 32;
+      let bcrypt_cost: u32 = security.bcrypt_cost;
       let bcrypt_version: BcryptVersion = BcryptVersion::TwoB;
       let hash_result: Result<HashParts,BcryptError> = bcrypt_hash_with_result(pass_value, bcrypt_cost);
       Some(hash_result.unwrap().format_for_version(bcrypt_version))
@@ -75,24 +80,123 @@ pub fn hash_pass(pass: &Option<String>, cipher: &PassHashCipher) -> Option<Strin
 }
 
 pub fn verify_pass_hash(pass: Option<String>, hash: Option<&String>, cipher: &PassHashCipher) -> bool {
-  if pass.is_none() || hash.is_none() { 
-    false
-  } else {
-    match cipher {
+  match (pass, hash) {
+    (Some(pass), Some(hash)) => match cipher {
       PassHashCipher::Bcrypt => {
-        bcrypt_verify(pass.unwrap(), hash.unwrap()).unwrap()
+        bcrypt_verify(pass, hash).unwrap()
       }
-    }
+    },
+    _ => false,
+  }
+}
+
+/// The reset string for links and the fixed width numeric code for SMS
+pub fn gen_pass_reset_codes(security: &SecurityConfig) -> (String, String) {
+  (otp::alphanumeric_code(128), otp::numeric_code(security.otp_length))
+}
+
+impl Model {
+  fn check_reset(&self, expected: Option<&str>, guess: &str, security: &SecurityConfig) -> otp::Outcome {
+    otp::check(
+      expected,
+      self.pass_reset_code_expires_at,
+      self.pass_reset_attempts,
+      security.otp_max_attempts,
+      guess,
+    )
+  }
+
+  pub fn check_reset_code(&self, guess: &str, security: &SecurityConfig) -> otp::Outcome {
+    self.check_reset(self.pass_reset_code.as_deref(), guess, security)
+  }
+
+  pub fn check_reset_str(&self, guess: &str, security: &SecurityConfig) -> otp::Outcome {
+    self.check_reset(self.pass_reset_str.as_deref(), guess, security)
   }
 }
 
-pub fn gen_pass_reset_codes() -> (String, u32) {
-  let mut rng = rand::thread_rng();
-  (rand::thread_rng()
-  .sample_iter(&Alphanumeric)
-  .take(128)
-  .map(char::from)
-  .collect(), rng.gen_range(1..99999999))
+/// Checks a guess at the reset code, using up the code and string when it matches
+/// and counting it when it doesn't. The caller sets the new password once accepted.
+pub async fn redeem_reset_code<C>(db: &C, pass: Model, guess: &str) -> Result<(Model, otp::Outcome), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let security = security_for_user(db, pass.user_id).await?;
+  let outcome = pass.check_reset_code(guess, &security);
+  let code = pass.pass_reset_code.clone().map(|code| (Column::PassResetCode, code));
+  redeem(db, pass, code, outcome, &security).await
+}
+
+/// As `redeem_reset_code`, for the string sent in links
+pub async fn redeem_reset_str<C>(db: &C, pass: Model, guess: &str) -> Result<(Model, otp::Outcome), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let security = security_for_user(db, pass.user_id).await?;
+  let outcome = pass.check_reset_str(guess, &security);
+  let code = pass.pass_reset_str.clone().map(|code| (Column::PassResetStr, code));
+  redeem(db, pass, code, outcome, &security).await
+}
+
+/// Only applies while the guessed code is unchanged and has attempts left, so concurrent
+/// guesses can't share an attempt or use the code twice
+async fn redeem<C>(
+  db: &C,
+  pass: Model,
+  code: Option<(Column, String)>,
+  outcome: otp::Outcome,
+  security: &SecurityConfig,
+) -> Result<(Model, otp::Outcome), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let (Some((column, code)), otp::Outcome::Accepted | otp::Outcome::Rejected { .. }) = (code, outcome) else {
+    return Ok((pass, outcome));
+  };
+  let now = clock::now();
+  let update = Entity::update_many()
+    .filter(Column::Id.eq(pass.id))
+    .filter(column.eq(code))
+    .filter(Column::PassResetAttempts.lt(security.otp_max_attempts));
+  let result = match outcome {
+    otp::Outcome::Accepted => update.set(ActiveModel {
+      pass_reset_code: Set(None),
+      pass_reset_str: Set(None),
+      pass_reset_code_expires_at: Set(None),
+      updated_at: Set(now),
+      ..ActiveModelTrait::default()
+    }),
+    _ => update
+      .col_expr(Column::PassResetAttempts, Expr::col(Column::PassResetAttempts).add(1))
+      .col_expr(Column::UpdatedAt, Expr::value(now)),
+  }.exec(db).await?;
+  let updated = Entity::find_by_id(pass.id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("auth_method_pass {}", pass.id)))?;
+  let attempts_left = security.otp_max_attempts - updated.pass_reset_attempts;
+  let outcome = match (result.rows_affected, outcome) {
+    (1, otp::Outcome::Accepted) => otp::Outcome::Accepted,
+    (1, _) => otp::Outcome::Rejected { attempts_left },
+    // Another guess got there first, using the code up or its last attempt
+    _ if attempts_left <= 0 => otp::Outcome::LockedOut,
+    _ => otp::Outcome::Expired,
+  };
+  Ok((updated, outcome))
+}
+
+impl ActiveModel {
+  /// The owner's id, loaded for partial updates that don't carry it
+  async fn stored_user_id<C>(&self, db: &C) -> Result<UserId, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !self.user_id.is_not_set() {
+      return Ok(*self.user_id.as_ref());
+    }
+    match Entity::find_by_id(*self.id.as_ref()).one(db).await? {
+      Some(stored) => Ok(stored.user_id),
+      None => Err(DbErr::RecordNotFound(format!("auth_method_pass {}", self.id.as_ref()))),
+    }
+  }
 }
 
 #[async_trait]
@@ -101,38 +205,49 @@ impl ActiveModelBehavior for ActiveModel {
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      pass_reset_attempts: Set(0),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
       // If the pass_hash_cipher is changed but pass_hash isn't also set then clear password
       // An unusual situation but it will force the user to reset the password
-      if self.pass_hash_cipher.is_set() && self.pass_hash.is_not_set() {
+      // A hash loaded with the model is Unchanged rather than NotSet, so `is_not_set` never matched it
+      if self.pass_hash_cipher.is_set() && !self.pass_hash.is_set() {
         self.pass_hash = Set(None);
       }
     }
+    // Hashing and reset codes follow the user's organisations' settings
+    let security = match self.pass_hash.is_set() || self.pass_reset_code.is_set() || self.pass_reset_str.is_set() {
+      true => Some(security_for_user(db, self.stored_user_id(db).await?).await?),
+      false => None,
+    };
     // If the password is set then hash it*
-    if self.pass_hash.is_set() {
-      self.pass_hash = Set(hash_pass(self.pass_hash.as_ref(),self.pass_hash_cipher.as_ref()));
+    if let (true, Some(security)) = (self.pass_hash.is_set(), &security) {
+      self.pass_hash = Set(hash_pass(self.pass_hash.as_ref(),self.pass_hash_cipher.as_ref(), security));
     }
     // Normally these will be set together but lets check both here
-    if self.pass_reset_code.is_set() || self.pass_reset_str.is_set() {
-      // If either value is set and isn't None then update our reset code expiry
-      if self.pass_reset_code.as_ref().is_some() || self.pass_reset_str.as_ref().is_some() {
-        self.pass_reset_code_expires_at = Set(Some(Utc::now() + Duration::hours(24)));
+    if let (true, Some(security)) = (self.pass_reset_code.is_set() || self.pass_reset_str.is_set(), &security) {
+      let is_new = |code: &ActiveValue<Option<String>>| code.is_set() && code.as_ref().is_some();
+      // If either value is set and isn't None then update our reset code expiry and attempt count
+      if is_new(&self.pass_reset_code) || is_new(&self.pass_reset_str) {
+        self.pass_reset_code_expires_at = Set(Some(clock::now() + Duration::hours(security.pass_reset_valid_hours)));
+        self.pass_reset_attempts = Set(0);
+      } else {
+        self.pass_reset_code_expires_at = Set(None);
       }
     }
     if self.pass_last_changed_at.is_set() {
-      self.pass_last_changed_at = Set(Utc::now());
+      self.pass_last_changed_at = Set(clock::now());
     }
     Ok(self)
       //     Err(DbErr::Custom(format!(
@@ -1,11 +1,12 @@
-use sea_orm::entity::prelude::*;
-use chrono::{Duration, Utc};
+use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
+use shared::clock;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
 #[sea_orm(table_name = "auth_tokens")]
 pub struct Model {
-  #[sea_orm(primary_key)]
+  #[sea_orm(primary_key, auto_increment = false)]
   pub token: String,
   // pub user_id: Uuid,
   pub refresh: Option<String>,
@@ -18,6 +19,10 @@ pub struct Model {
   pub updated_at: ChronoDateTimeUtc,
 }
 
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
 // #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 // pub enum Relation {
 //   #[sea_orm(
@@ -41,8 +46,8 @@ impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
@@ -52,7 +57,7 @@ impl ActiveModelBehavior for ActiveModel {
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
     }
     Ok(self)
   }
@@ -0,0 +1,287 @@
+use std::net::IpAddr;
+use sea_orm::{ entity::prelude::*, ActiveValue::Set, IntoActiveModel };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use chrono::Duration;
+use shared::{clock, OrgId};
+use super::{certificate_authority, certificate_role, config, key_pair, x509::{self, CrlReason}};
+
+/// Why a certificate was revoked, the RFC 5280 reasons that apply to a permanent revocation
+#[derive(Debug, Copy, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "certificate_revocation_reasons")]
+pub enum RevocationReason {
+  #[sea_orm(string_value = "Unspecified")]
+  Unspecified,
+  #[sea_orm(string_value = "KeyCompromise")]
+  KeyCompromise,
+  #[sea_orm(string_value = "CaCompromise")]
+  CaCompromise,
+  #[sea_orm(string_value = "AffiliationChanged")]
+  AffiliationChanged,
+  #[sea_orm(string_value = "Superseded")]
+  Superseded,
+  #[sea_orm(string_value = "CessationOfOperation")]
+  CessationOfOperation,
+  #[sea_orm(string_value = "PrivilegeWithdrawn")]
+  PrivilegeWithdrawn,
+}
+
+impl RevocationReason {
+  pub fn crl_reason(&self) -> CrlReason {
+    match self {
+      RevocationReason::Unspecified => CrlReason::Unspecified,
+      RevocationReason::KeyCompromise => CrlReason::KeyCompromise,
+      RevocationReason::CaCompromise => CrlReason::CaCompromise,
+      RevocationReason::AffiliationChanged => CrlReason::AffiliationChanged,
+      RevocationReason::Superseded => CrlReason::Superseded,
+      RevocationReason::CessationOfOperation => CrlReason::CessationOfOperation,
+      RevocationReason::PrivilegeWithdrawn => CrlReason::PrivilegeWithdrawn,
+    }
+  }
+}
+
+/// What is kept about every certificate a CA issues, leaf or CA
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "certificates")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  /// The CA that issued it
+  pub certificate_authority_id: Uuid,
+  #[sea_orm(nullable)]
+  pub certificate_role_id: Option<Uuid>,
+  /// Lowercase hex
+  #[sea_orm(unique)]
+  pub serial: String,
+  pub subject: String,
+  pub not_before: ChronoDateTimeUtc,
+  pub not_after: ChronoDateTimeUtc,
+  #[sea_orm(column_type = "Text")]
+  pub certificate: String,
+  pub is_ca: bool,
+  #[sea_orm(nullable)]
+  pub revoked_at: Option<ChronoDateTimeUtc>,
+  #[sea_orm(nullable)]
+  pub revocation_reason: Option<RevocationReason>,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "super::certificate_authority::Entity",
+    from = "Column::CertificateAuthorityId",
+    to = "super::certificate_authority::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  CertificateAuthority,
+  #[sea_orm(
+    belongs_to = "super::certificate_role::Entity",
+    from = "Column::CertificateRoleId",
+    to = "super::certificate_role::Column::Id",
+    on_update = "Cascade",
+    on_delete = "SetNull"
+  )]
+  CertificateRole,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::certificate_authority::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::CertificateAuthority.def()
+  }
+}
+
+impl Related<super::certificate_role::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::CertificateRole.def()
+  }
+}
+
+/// A certificate to issue under a role. Without a `csr` a key pair is generated for it.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct IssueRequest {
+  /// Defaults to the CSR's common name
+  pub common_name: Option<String>,
+  /// DNS names and IP addresses besides the common name
+  #[serde(default)]
+  pub sans: Vec<String>,
+  pub ttl_hours: Option<i64>,
+  /// PEM PKCS#10
+  pub csr: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Issued {
+  pub certificate: Model,
+  /// The issuing CA's certificate up to the root
+  pub chain: Vec<String>,
+  /// PKCS#8 PEM, only when the key pair was generated. It isn't kept.
+  pub private_key: Option<String>,
+}
+
+fn invalid(err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid certificate request, insert: true, {}", err))
+}
+
+/// Keeps the metadata of a certificate `authority` signed
+pub async fn record<C>(
+  db: &C,
+  authority: &certificate_authority::Model,
+  role_id: Option<Uuid>,
+  template: &x509::CertificateTemplate,
+  certificate: String,
+  is_ca: bool,
+) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  ActiveModel {
+    organisation_id: Set(authority.organisation_id),
+    certificate_authority_id: Set(authority.id),
+    certificate_role_id: Set(role_id),
+    serial: Set(x509::serial_hex(&template.serial)),
+    subject: Set(template.subject.to_string()),
+    not_before: Set(template.not_before),
+    not_after: Set(template.not_after),
+    certificate: Set(certificate),
+    is_ca: Set(is_ca),
+    ..Default::default()
+  }.insert(db).await
+}
+
+/// Issues a leaf certificate under `role`, names and validity are checked against its template
+pub async fn issue<C>(db: &C, role: &certificate_role::Model, request: IssueRequest) -> Result<Issued, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let authority = certificate_authority::Entity::find_by_id(role.certificate_authority_id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("certificate authority {}", role.certificate_authority_id)))?;
+  certificate_authority::check_usable(db, &authority).await?;
+  let template = &role.template;
+  let csr = request.csr.as_deref().map(x509::parse_request).transpose().map_err(invalid)?;
+  let common_name = request.common_name.clone()
+    .or_else(|| csr.as_ref().and_then(|csr| csr.common_name.clone()))
+    .ok_or_else(|| invalid("common_name is required".to_string()))?;
+
+  let mut names = vec![common_name.clone()];
+  names.extend(request.sans.iter().cloned());
+  names.sort();
+  names.dedup();
+  if let Some(name) = names.iter().find(|name| !template.allows_name(name)) {
+    return Err(invalid(format!("{} is not allowed by role {}", name, role.name)));
+  }
+  let (ip_addresses, dns_names): (Vec<_>, Vec<_>) = names.into_iter().partition(|name| name.parse::<IpAddr>().is_ok());
+
+  let now = clock::now();
+  let ttl = template.ttl(request.ttl_hours).map_err(invalid)?;
+
+  // The private key is handed back once and never stored
+  let (public_key, private_key) = match csr {
+    Some(csr) => (csr.public_key, None),
+    None => {
+      let security = config::security_for_organisation(db, authority.organisation_id).await?;
+      let pair = key_pair::generate(template.key_algo.unwrap_or(security.key_algo), security.rsa_key_bits)
+        .map_err(DbErr::Custom)?;
+      (x509::public_key_info(&pair.public_key).map_err(DbErr::Custom)?, Some(pair.private_key))
+    },
+  };
+
+  let (issuer_key, issuer_certificate) = certificate_authority::issuer(db, &authority).await?;
+  let (crl_url, ocsp_url) = certificate_authority::revocation_urls(&config::get().pki.public_url, authority.id);
+  let certificate_template = x509::CertificateTemplate {
+    serial: x509::random_serial(),
+    issuer: issuer_certificate.tbs_certificate.subject.clone(),
+    subject: x509::common_name_only(&common_name).map_err(invalid)?,
+    not_before: now,
+    not_after: (now + Duration::hours(ttl)).min(authority.not_after),
+    public_key,
+    authority_key_id: Some(x509::key_identifier(&issuer_certificate.tbs_certificate.subject_public_key_info)),
+    ca: None,
+    key_usage: template.key_usage.clone(),
+    extended_key_usage: template.extended_key_usage.clone(),
+    dns_names,
+    ip_addresses: ip_addresses.iter().filter_map(|ip| ip.parse().ok()).collect(),
+    crl_url,
+    ocsp_url,
+  };
+  let pem = certificate_authority::sign(&issuer_key, &certificate_template).await?;
+  let certificate = record(db, &authority, Some(role.id), &certificate_template, pem, false).await?;
+  Ok(Issued {
+    certificate,
+    chain: certificate_authority::chain(db, &authority).await?,
+    private_key,
+  })
+}
+
+/// Serials are compared as lowercase hex, colons as printed by openssl are dropped
+pub fn normalise_serial(serial: &str) -> String {
+  serial.chars().filter(|c| *c != ':').collect::<String>().to_ascii_lowercase()
+}
+
+/// Revokes one of the organisation's certificates and re-signs its issuer's CRL straight away
+pub async fn revoke<C>(db: &C, organisation_id: OrgId, serial: &str, reason: RevocationReason) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let serial = normalise_serial(serial);
+  let certificate = Entity::find()
+    .filter(Column::OrganisationId.eq(organisation_id))
+    .filter(Column::Serial.eq(serial.clone()))
+    .one(db)
+    .await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("certificate {}", serial)))?;
+  if certificate.revoked_at.is_some() {
+    return Err(DbErr::Custom(format!("[before_save] Certificate {} is already revoked, insert: false", serial)));
+  }
+  let authority_id = certificate.certificate_authority_id;
+  let mut active_certificate = certificate.into_active_model();
+  active_certificate.revoked_at = Set(Some(clock::now()));
+  active_certificate.revocation_reason = Set(Some(reason));
+  let certificate = active_certificate.update(db).await?;
+  let authority = certificate_authority::Entity::find_by_id(authority_id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("certificate authority {}", authority_id)))?;
+  certificate_authority::publish_crl(db, &authority).await?;
+  Ok(certificate)
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
@@ -0,0 +1,394 @@
+use sea_orm::{ entity::prelude::*, ActiveValue::Set, Condition, IntoActiveModel, QueryOrder };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use chrono::Duration;
+use shared::{clock, OrgId};
+use super::{certificate, config, key_provider, pki_key::{self, KeyAlgos, Owner}, x509};
+
+/// Longest chain of CAs below a root when none is given
+pub const DEFAULT_MAX_PATH_LENGTH: u8 = 1;
+
+/// An organisation's root or intermediate CA, its key is a `pki_key` owned by the organisation
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "certificate_authorities")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  /// The CA that issued this one's certificate, `None` for a root
+  #[sea_orm(nullable)]
+  pub parent_id: Option<Uuid>,
+  pub pki_key_id: Uuid,
+  pub name: String,
+  pub subject: String,
+  /// How many CAs may sit below this one
+  pub max_path_length: i32,
+  #[sea_orm(column_type = "Text")]
+  pub certificate: String,
+  pub serial: String,
+  pub not_after: ChronoDateTimeUtc,
+  /// Numbers the CRLs this CA signs, the latest is `crl`
+  pub crl_number: i64,
+  #[serde(skip_serializing)]
+  #[sea_orm(column_type = "Text", nullable)]
+  pub crl: Option<String>,
+  #[sea_orm(nullable)]
+  pub crl_next_update: Option<ChronoDateTimeUtc>,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "Entity",
+    from = "Column::ParentId",
+    to = "Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Parent,
+  #[sea_orm(
+    belongs_to = "super::pki_key::Entity",
+    from = "Column::PkiKeyId",
+    to = "super::pki_key::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  PkiKey,
+  #[sea_orm(has_many = "super::certificate_role::Entity")]
+  CertificateRole,
+  #[sea_orm(has_many = "super::certificate::Entity")]
+  Certificate,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::pki_key::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::PkiKey.def()
+  }
+}
+
+impl Related<super::certificate_role::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::CertificateRole.def()
+  }
+}
+
+impl Related<super::certificate::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Certificate.def()
+  }
+}
+
+/// A CA to create, its key is generated unless an existing organisation key is named
+#[derive(Clone, Debug, Deserialize)]
+pub struct NewAuthority {
+  pub name: String,
+  /// RFC 4514, e.g. `CN=Example Issuing CA,O=Example`
+  pub subject: String,
+  pub ttl_days: i64,
+  pub key_id: Option<Uuid>,
+  pub algo: Option<KeyAlgos>,
+  pub max_path_length: Option<u8>,
+}
+
+fn invalid(err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid certificate authority, insert: true, {}", err))
+}
+
+/// The organisation key a new CA signs with
+async fn authority_key<C>(db: &C, organisation_id: OrgId, new: &NewAuthority) -> Result<pki_key::Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  match new.key_id {
+    Some(key_id) => pki_key::find_for_owner(db, Owner::Organisation(organisation_id), Some(key_id)).await?
+      .ok_or_else(|| invalid(format!("key {} does not belong to the organisation", key_id)))
+      .and_then(|key| match key.status.can_sign() {
+        true => Ok(key),
+        false => Err(invalid(format!("key {} is not an active version", key_id))),
+      }),
+    None => pki_key::generate(db, Owner::Organisation(organisation_id), new.algo).await,
+  }
+}
+
+/// Signs a certificate with the issuer's key, wherever that lives
+pub async fn sign(issuer_key: &pki_key::Model, template: &x509::CertificateTemplate) -> Result<String, DbErr> {
+  let scheme = x509::signing_scheme(issuer_key.algo);
+  let tbs = x509::tbs_certificate(template, scheme).map_err(invalid)?;
+  let der = x509_cert::der::Encode::to_der(&tbs).map_err(|err| DbErr::Custom(err.to_string()))?;
+  let signature = key_provider::sign(issuer_key, scheme, &der).await
+    .map_err(|err| DbErr::Custom(format!("unable to sign with key {}: {}", issuer_key.id, err)))?;
+  x509::assemble(tbs, &signature).map_err(DbErr::Custom)
+}
+
+async fn authority_key_of<C>(db: &C, authority: &Model) -> Result<pki_key::Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  pki_key::Entity::find_by_id(authority.pki_key_id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("pki_key {}", authority.pki_key_id)))
+}
+
+fn public_key_of(key: &pki_key::Model) -> Result<x509_cert::spki::SubjectPublicKeyInfoOwned, DbErr> {
+  let public_key = key.public_key.as_deref().ok_or_else(|| invalid(format!("key {} has no public key", key.id)))?;
+  x509::public_key_info(public_key).map_err(invalid)
+}
+
+/// The key a CA signs with and its parsed certificate, for issuing from it
+pub async fn issuer<C>(db: &C, authority: &Model) -> Result<(pki_key::Model, x509_cert::Certificate), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let key = authority_key_of(db, authority).await?;
+  let certificate = x509::parse_certificate(&authority.certificate).map_err(DbErr::Custom)?;
+  Ok((key, certificate))
+}
+
+/// Creates a self-signed root CA
+pub async fn create_root<C>(db: &C, organisation_id: OrgId, new: NewAuthority) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let subject = x509::parse_name(&new.subject).map_err(invalid)?;
+  if new.ttl_days <= 0 {
+    return Err(invalid("ttl_days must be positive".to_string()));
+  }
+  let key = authority_key(db, organisation_id, &new).await?;
+  let max_path_length = new.max_path_length.unwrap_or(DEFAULT_MAX_PATH_LENGTH);
+  let now = clock::now();
+  let template = x509::CertificateTemplate {
+    serial: x509::random_serial(),
+    issuer: subject.clone(),
+    subject,
+    not_before: now,
+    not_after: now + Duration::days(new.ttl_days),
+    public_key: public_key_of(&key)?,
+    authority_key_id: None,
+    ca: Some(Some(max_path_length)),
+    key_usage: Vec::new(),
+    extended_key_usage: Vec::new(),
+    dns_names: Vec::new(),
+    ip_addresses: Vec::new(),
+    crl_url: None,
+    ocsp_url: None,
+  };
+  let pem = sign(&key, &template).await?;
+  let authority = ActiveModel {
+    organisation_id: Set(organisation_id),
+    parent_id: Set(None),
+    pki_key_id: Set(key.id),
+    name: Set(new.name),
+    subject: Set(template.subject.to_string()),
+    max_path_length: Set(max_path_length as i32),
+    certificate: Set(pem.clone()),
+    serial: Set(x509::serial_hex(&template.serial)),
+    not_after: Set(template.not_after),
+    ..Default::default()
+  }.insert(db).await?;
+  certificate::record(db, &authority, None, &template, pem, true).await?;
+  publish_crl(db, &authority).await
+}
+
+/// Creates a CA whose certificate is issued by `parent`, it never outlives the parent
+pub async fn create_intermediate<C>(db: &C, parent: &Model, new: NewAuthority) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let subject = x509::parse_name(&new.subject).map_err(invalid)?;
+  if new.ttl_days <= 0 {
+    return Err(invalid("ttl_days must be positive".to_string()));
+  }
+  if parent.max_path_length <= 0 {
+    return Err(invalid(format!("{} cannot have CAs below it", parent.name)));
+  }
+  let max_path_length = match new.max_path_length {
+    Some(requested) if (requested as i32) < parent.max_path_length => requested,
+    Some(_) => return Err(invalid(format!("max_path_length must be below {}", parent.max_path_length))),
+    None => (parent.max_path_length - 1) as u8,
+  };
+  check_usable(db, parent).await?;
+  let (parent_key, parent_certificate) = issuer(db, parent).await?;
+  let key = authority_key(db, parent.organisation_id, &new).await?;
+  let (crl_url, ocsp_url) = revocation_urls(&config::get().pki.public_url, parent.id);
+  let now = clock::now();
+  let template = x509::CertificateTemplate {
+    serial: x509::random_serial(),
+    issuer: parent_certificate.tbs_certificate.subject.clone(),
+    subject,
+    not_before: now,
+    not_after: (now + Duration::days(new.ttl_days)).min(parent.not_after),
+    public_key: public_key_of(&key)?,
+    authority_key_id: Some(x509::key_identifier(&parent_certificate.tbs_certificate.subject_public_key_info)),
+    ca: Some(Some(max_path_length)),
+    key_usage: Vec::new(),
+    extended_key_usage: Vec::new(),
+    dns_names: Vec::new(),
+    ip_addresses: Vec::new(),
+    crl_url,
+    ocsp_url,
+  };
+  let pem = sign(&parent_key, &template).await?;
+  let authority = ActiveModel {
+    organisation_id: Set(parent.organisation_id),
+    parent_id: Set(Some(parent.id)),
+    pki_key_id: Set(key.id),
+    name: Set(new.name),
+    subject: Set(template.subject.to_string()),
+    max_path_length: Set(max_path_length as i32),
+    certificate: Set(pem.clone()),
+    serial: Set(x509::serial_hex(&template.serial)),
+    not_after: Set(template.not_after),
+    ..Default::default()
+  }.insert(db).await?;
+  // Recorded against the parent like anything else it issues
+  certificate::record(db, parent, None, &template, pem, true).await?;
+  publish_crl(db, &authority).await
+}
+
+/// Where a CA's CRL and OCSP responder are served, when the server's public URL is known
+pub fn revocation_urls(public_url: &str, authority_id: Uuid) -> (Option<String>, Option<String>) {
+  if public_url.is_empty() {
+    return (None, None);
+  }
+  let base = format!("{}/pki/cas/{}", public_url.trim_end_matches('/'), authority_id);
+  (Some(format!("{}/crl", base)), Some(format!("{}/ocsp", base)))
+}
+
+/// Fails when `authority` may no longer issue, because it has expired or its certificate was revoked
+pub async fn check_usable<C>(db: &C, authority: &Model) -> Result<(), DbErr>
+where
+  C: ConnectionTrait,
+{
+  if authority.not_after <= clock::now() {
+    return Err(invalid(format!("{} has expired", authority.name)));
+  }
+  let revoked = certificate::Entity::find()
+    .filter(certificate::Column::Serial.eq(authority.serial.clone()))
+    .filter(certificate::Column::RevokedAt.is_not_null())
+    .one(db)
+    .await?;
+  if revoked.is_some() {
+    return Err(invalid(format!("{} has been revoked", authority.name)));
+  }
+  Ok(())
+}
+
+/// Signs a new CRL listing the unexpired certificates `authority` issued that were revoked
+pub async fn publish_crl<C>(db: &C, authority: &Model) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let (key, certificate) = issuer(db, authority).await?;
+  let now = clock::now();
+  let revoked = certificate::Entity::find()
+    .filter(certificate::Column::CertificateAuthorityId.eq(authority.id))
+    .filter(certificate::Column::RevokedAt.is_not_null())
+    .filter(certificate::Column::NotAfter.gt(now))
+    .order_by_asc(certificate::Column::RevokedAt)
+    .all(db)
+    .await?;
+  let mut entries = Vec::with_capacity(revoked.len());
+  for certificate in revoked {
+    entries.push(x509::RevokedEntry {
+      serial: hex::decode(&certificate.serial).map_err(|err| DbErr::Custom(err.to_string()))?,
+      revoked_at: certificate.revoked_at.unwrap_or(now),
+      reason: certificate.revocation_reason.unwrap_or(certificate::RevocationReason::Unspecified).crl_reason(),
+    });
+  }
+  let crl_number = authority.crl_number + 1;
+  let next_update = now + Duration::hours(config::get().pki.crl_valid_hours);
+  let scheme = x509::signing_scheme(key.algo);
+  let tbs = x509::tbs_cert_list(&certificate, &entries, now, next_update, crl_number as u64, scheme).map_err(DbErr::Custom)?;
+  let der = x509_cert::der::Encode::to_der(&tbs).map_err(|err| DbErr::Custom(err.to_string()))?;
+  let signature = key_provider::sign(&key, scheme, &der).await
+    .map_err(|err| DbErr::Custom(format!("unable to sign with key {}: {}", key.id, err)))?;
+  let mut active_authority = authority.clone().into_active_model();
+  active_authority.crl_number = Set(crl_number);
+  active_authority.crl = Set(Some(x509::assemble_crl(tbs, &signature).map_err(DbErr::Custom)?));
+  active_authority.crl_next_update = Set(Some(next_update));
+  active_authority.update(db).await
+}
+
+/// Re-signs the CRLs of unexpired CAs that are past half their validity, returning how many were
+pub async fn refresh_crls<C>(db: &C) -> Result<usize, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let now = clock::now();
+  let stale_after = now + Duration::minutes(config::get().pki.crl_valid_hours * 30);
+  let authorities = Entity::find()
+    .filter(Column::NotAfter.gt(now))
+    .filter(Condition::any()
+      .add(Column::CrlNextUpdate.is_null())
+      .add(Column::CrlNextUpdate.lt(stale_after)))
+    .all(db)
+    .await?;
+  let count = authorities.len();
+  for authority in authorities {
+    publish_crl(db, &authority).await?;
+  }
+  Ok(count)
+}
+
+/// The PEM certificates from `authority` up to its root
+pub async fn chain<C>(db: &C, authority: &Model) -> Result<Vec<String>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let mut chain = vec![authority.certificate.clone()];
+  let mut parent_id = authority.parent_id;
+  while let Some(id) = parent_id {
+    let parent = Entity::find_by_id(id).one(db).await?
+      .ok_or_else(|| DbErr::RecordNotFound(format!("certificate authority {}", id)))?;
+    chain.push(parent.certificate);
+    parent_id = parent.parent_id;
+  }
+  Ok(chain)
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      crl_number: Set(0),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if self.name.is_set() && self.name.as_ref().trim().is_empty() {
+      return Err(DbErr::Custom(format!(
+        "[before_save] Certificate authority name cannot be blank, insert: {}",
+        insert
+      )));
+    }
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
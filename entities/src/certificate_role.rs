@@ -0,0 +1,183 @@
+use std::net::IpAddr;
+use sea_orm::{ entity::prelude::*, ActiveValue::Set, FromJsonQueryResult };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use shared::{clock, OrgId};
+use super::{pki_key::KeyAlgos, x509::{ExtendedKeyUsage, KeyUsage}};
+
+/// What a role may issue, the template every certificate issued under it is checked against
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+#[serde(default)]
+pub struct RoleTemplate {
+  /// Names may be one of these domains, `example.com`
+  pub allowed_domains: Vec<String>,
+  /// And, when set, any name below them, `api.example.com`
+  pub allow_subdomains: bool,
+  /// Any DNS name at all, for roles that trust whoever may issue
+  pub allow_any_name: bool,
+  pub allow_ip_sans: bool,
+  /// Validity when a request doesn't ask for one
+  pub ttl_hours: i64,
+  pub max_ttl_hours: i64,
+  pub key_usage: Vec<KeyUsage>,
+  pub extended_key_usage: Vec<ExtendedKeyUsage>,
+  /// The kind of key generated when a request has no CSR, the organisation's default otherwise
+  pub key_algo: Option<KeyAlgos>,
+}
+
+impl Default for RoleTemplate {
+  fn default() -> Self {
+    Self {
+      allowed_domains: Vec::new(),
+      allow_subdomains: false,
+      allow_any_name: false,
+      allow_ip_sans: false,
+      ttl_hours: 24,
+      max_ttl_hours: 24 * 30,
+      key_usage: vec![KeyUsage::DigitalSignature, KeyUsage::KeyEncipherment],
+      extended_key_usage: vec![ExtendedKeyUsage::ServerAuth, ExtendedKeyUsage::ClientAuth],
+      key_algo: None,
+    }
+  }
+}
+
+impl RoleTemplate {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.ttl_hours <= 0 || self.max_ttl_hours <= 0 {
+      return Err("ttl_hours and max_ttl_hours must be positive".to_string());
+    }
+    if self.ttl_hours > self.max_ttl_hours {
+      return Err("ttl_hours cannot exceed max_ttl_hours".to_string());
+    }
+    if self.key_usage.is_empty() {
+      return Err("key_usage cannot be empty".to_string());
+    }
+    if let Some(domain) = self.allowed_domains.iter().find(|domain| !valid_dns_name(domain)) {
+      return Err(format!("invalid domain {}", domain));
+    }
+    Ok(())
+  }
+
+  /// Whether the role may put `name` in a certificate
+  pub fn allows_name(&self, name: &str) -> bool {
+    if name.parse::<IpAddr>().is_ok() {
+      return self.allow_ip_sans;
+    }
+    if !valid_dns_name(name) {
+      return false;
+    }
+    let name = name.to_ascii_lowercase();
+    self.allow_any_name || self.allowed_domains.iter().any(|domain| {
+      let domain = domain.to_ascii_lowercase();
+      name == domain || (self.allow_subdomains && name.strip_suffix(&domain).is_some_and(|prefix| prefix.ends_with('.')))
+    })
+  }
+
+  /// The requested validity in hours, or the role's default, as long as it's within the maximum
+  pub fn ttl(&self, requested: Option<i64>) -> Result<i64, String> {
+    match requested {
+      Some(hours) if hours <= 0 => Err("ttl_hours must be positive".to_string()),
+      Some(hours) if hours > self.max_ttl_hours => Err(format!("ttl_hours cannot exceed {}", self.max_ttl_hours)),
+      Some(hours) => Ok(hours),
+      None => Ok(self.ttl_hours),
+    }
+  }
+}
+
+/// Letters, digits and hyphens in dot separated labels, the leftmost may be `*`
+fn valid_dns_name(name: &str) -> bool {
+  !name.is_empty() && name.len() <= 253 && name.split('.').enumerate().all(|(i, label)| {
+    (i == 0 && label == "*")
+      || (!label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+  })
+}
+
+/// A named template for the certificates a CA issues, e.g. `web-server` or `service-client`
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "certificate_roles")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  pub certificate_authority_id: Uuid,
+  pub name: String,
+  #[sea_orm(column_type = "JsonBinary")]
+  pub template: RoleTemplate,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "super::certificate_authority::Entity",
+    from = "Column::CertificateAuthorityId",
+    to = "super::certificate_authority::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  CertificateAuthority,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::certificate_authority::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::CertificateAuthority.def()
+  }
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if self.name.is_set() && self.name.as_ref().trim().is_empty() {
+      return Err(DbErr::Custom(format!(
+        "[before_save] Certificate role name cannot be blank, insert: {}",
+        insert
+      )));
+    }
+    if self.template.is_set() {
+      if let Err(err) = self.template.as_ref().validate() {
+        return Err(DbErr::Custom(format!(
+          "[before_save] Invalid certificate role template, insert: {}, {}",
+          insert, err
+        )));
+      }
+    }
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
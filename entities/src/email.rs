@@ -1,21 +1,26 @@
-use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use sea_orm::{ entity::prelude::*, sea_query::Expr, ActiveValue::Set, IntoActiveModel };
 use serde::{Deserialize, Serialize};
 //use serde_email::Email;
-use chrono::Utc;
 use async_trait::async_trait;
+use chrono::Duration;
+use shared::{clock, otp, UserId};
+use super::config::{self, SecurityConfig};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "user_emails", schema_name = "public")]
+#[sea_orm(table_name = "user_emails")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
-  pub user_id: Uuid,
+  pub user_id: UserId,
   pub email_address: String,
-  #[sea_orm(nullable, unique)]
+  #[serde(skip_serializing)]
+  #[sea_orm(nullable)]
   pub verification_code: Option<String>,
   #[sea_orm(nullable)]
   pub verification_code_expires_at: Option<ChronoDateTimeUtc>,
+  /// Wrong guesses against the current verification code
+  pub verification_attempts: i32,
   pub is_primary: bool,
   pub is_verified: bool,
   #[sea_orm(nullable)]
@@ -44,30 +49,116 @@ impl Related<super::user::Entity> for Entity {
   }
 }
 
+impl Model {
+  pub fn check_verification_code(&self, guess: &str, security: &SecurityConfig) -> otp::Outcome {
+    otp::check(
+      self.verification_code.as_deref(),
+      self.verification_code_expires_at,
+      self.verification_attempts,
+      security.otp_max_attempts,
+      guess,
+    )
+  }
+}
+
+/// A code to send to the email, set it as `verification_code` to start the clock
+pub fn generate_verification_code(security: &SecurityConfig) -> String {
+  otp::numeric_code(security.otp_length)
+}
+
+/// The settings of the address's user, whose id is loaded for partial updates
+async fn security_for<C>(db: &C, email: &ActiveModel) -> Result<SecurityConfig, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let user_id = match email.user_id.is_not_set() {
+    false => *email.user_id.as_ref(),
+    true => match Entity::find_by_id(*email.id.as_ref()).one(db).await? {
+      Some(stored) => stored.user_id,
+      None => return Err(DbErr::RecordNotFound(format!("email {}", email.id.as_ref()))),
+    },
+  };
+  config::security_for_user(db, user_id).await
+}
+
+/// Checks a guess, verifying the email when it matches and counting it when it doesn't.
+/// Both only apply while the code is unchanged and has attempts left, so concurrent guesses
+/// can't share an attempt or use the code twice.
+pub async fn redeem_verification_code<C>(db: &C, email: Model, guess: &str) -> Result<(Model, otp::Outcome), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let security = security_for(db, &email.clone().into_active_model()).await?;
+  let outcome = email.check_verification_code(guess, &security);
+  let (Some(code), otp::Outcome::Accepted | otp::Outcome::Rejected { .. }) = (email.verification_code.clone(), outcome) else {
+    return Ok((email, outcome));
+  };
+  let now = clock::now();
+  let update = Entity::update_many()
+    .filter(Column::Id.eq(email.id))
+    .filter(Column::VerificationCode.eq(code))
+    .filter(Column::VerificationAttempts.lt(security.otp_max_attempts));
+  let result = match outcome {
+    otp::Outcome::Accepted => update.set(ActiveModel {
+      is_verified: Set(true),
+      verified_at: Set(Some(now)),
+      verification_code: Set(None),
+      verification_code_expires_at: Set(None),
+      updated_at: Set(now),
+      ..ActiveModelTrait::default()
+    }),
+    _ => update
+      .col_expr(Column::VerificationAttempts, Expr::col(Column::VerificationAttempts).add(1))
+      .col_expr(Column::UpdatedAt, Expr::value(now)),
+  }.exec(db).await?;
+  let updated = Entity::find_by_id(email.id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("email {}", email.id)))?;
+  let attempts_left = security.otp_max_attempts - updated.verification_attempts;
+  let outcome = match (result.rows_affected, outcome) {
+    (1, otp::Outcome::Accepted) => otp::Outcome::Accepted,
+    (1, _) => otp::Outcome::Rejected { attempts_left },
+    // Another guess got there first, using the code up or its last attempt
+    _ if attempts_left <= 0 => otp::Outcome::LockedOut,
+    _ => otp::Outcome::Expired,
+  };
+  Ok((updated, outcome))
+}
+
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      verification_attempts: Set(0),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
+    }
+    // A new code gets a fresh expiry and attempt count
+    if self.verification_code.is_set() {
+      if self.verification_code.as_ref().is_some() {
+        let valid_mins = security_for(db, &self).await?.verification_code_valid_mins;
+        self.verification_code_expires_at = Set(Some(clock::now() + Duration::minutes(valid_mins)));
+        self.verification_attempts = Set(0);
+      } else {
+        self.verification_code_expires_at = Set(None);
+      }
     }
     // When a email becomes verified, updated the verified_at date
     if self.is_verified.is_set() {
       if *self.is_verified.as_ref() {
-        self.verified_at = Set(Some(Utc::now()));
+        self.verified_at = Set(Some(clock::now()));
       } else {
         self.verified_at = Set(None);
       }
@@ -2,21 +2,21 @@ use sea_orm::{ entity::prelude::*, ActiveValue::Set };
 use serde::{Deserialize, Serialize};
 // use url::Url;
 use async_trait::async_trait;
-use chrono::Utc;
+use shared::{clock, GroupId};
 
 // use s3::bucket::Bucket;
 // use s3::creds::Credentials;
 // use anyhow::Result;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "files", schema_name = "public")]
+#[sea_orm(table_name = "files")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
   pub organisation_profile_id: Option<Uuid>,
   pub user_profile_id: Option<Uuid>,
-  pub group_id: Option<Uuid>,
+  pub group_id: Option<GroupId>,
   // #[sea_orm(column_type = "Text")]
   // #[serde(with = "url_serde")]
   pub s3_file_url: String,
@@ -36,7 +36,7 @@ pub enum Relation {
   UserProfile,
   #[sea_orm(
     belongs_to = "super::organisation_profile::Entity",
-    from = "Column::UserProfileId",
+    from = "Column::OrganisationProfileId",
     to = "super::organisation_profile::Column::Id",
     on_update = "Cascade",
     on_delete = "Cascade"
@@ -76,8 +76,8 @@ impl ActiveModelBehavior for ActiveModel {
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      created_at: Set(chrono::Utc::now()),
-      updated_at: Set(chrono::Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
@@ -88,7 +88,7 @@ impl ActiveModelBehavior for ActiveModel {
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
     }
     Ok(self)
   }
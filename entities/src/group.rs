@@ -1,15 +1,22 @@
-use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use std::collections::HashMap;
+use sea_orm::{ entity::prelude::*, ActiveValue::Set, FromQueryResult, Statement };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
+use shared::{clock, GroupId, OrgId, UserId};
+
+/// Guards the recursive queries against runaway hierarchies
+pub const MAX_GROUP_DEPTH: i32 = 32;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "groups", schema_name = "public")]
+#[sea_orm(table_name = "groups")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
-  pub id: Uuid,
+  pub id: GroupId,
   pub name: String,
-  pub organisation_id: Uuid,
+  pub organisation_id: OrgId,
+  #[sea_orm(nullable)]
+  pub parent_group_id: Option<GroupId>,
   #[sea_orm(nullable)]
   pub group_image_file_id: Option<Uuid>,
   #[sea_orm(nullable)]
@@ -30,6 +37,14 @@ pub enum Relation {
     on_delete = "Cascade"
   )]
   Organisation,
+  #[sea_orm(
+    belongs_to = "Entity",
+    from = "Column::ParentGroupId",
+    to = "Column::Id",
+    on_update = "Cascade",
+    on_delete = "SetNull"
+  )]
+  ParentGroup,
   #[sea_orm(has_many = "super::file::Entity")]
   File,
   #[sea_orm(has_many = "super::pki_key::Entity")]
@@ -74,25 +89,205 @@ impl Related<super::group_access_role::Entity> for Entity {
   }
 }
 
+/// A group the user is in, either directly or through membership of one of its parent groups
+#[derive(Clone, Debug, PartialEq, Eq, FromQueryResult, Serialize)]
+pub struct EffectiveMembership {
+  pub group_id: GroupId,
+  pub group_access_role_id: Uuid,
+  /// The group the user is a direct member of
+  pub via_group_id: GroupId,
+  pub depth: i32,
+}
+
+/// Members of a group are effectively members of every group nested below it, never of
+/// the groups above it. Where the user reaches a group more than one way the closest membership wins.
+pub async fn find_effective_memberships<C>(db: &C, user_id: UserId) -> Result<Vec<EffectiveMembership>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let memberships = EffectiveMembership::find_by_statement(Statement::from_sql_and_values(
+    db.get_database_backend(),
+    r#"WITH RECURSIVE effective (group_id, group_access_role_id, via_group_id, depth) AS (
+      SELECT m.group_id, m.group_access_role_id, m.group_id, 0
+      FROM users_groups_group_access_roles m
+      WHERE m.user_id = $1
+      UNION ALL
+      SELECT g.id, e.group_access_role_id, e.via_group_id, e.depth + 1
+      FROM effective e
+      JOIN groups g ON g.parent_group_id = e.group_id
+      WHERE e.depth < $2
+    )
+    SELECT group_id, group_access_role_id, via_group_id, depth FROM effective"#,
+    vec![user_id.into(), MAX_GROUP_DEPTH.into()],
+  )).all(db).await?;
+
+  let mut closest: HashMap<GroupId, EffectiveMembership> = HashMap::new();
+  for membership in memberships {
+    match closest.get(&membership.group_id) {
+      Some(existing) if existing.depth <= membership.depth => {},
+      _ => { closest.insert(membership.group_id, membership); },
+    }
+  }
+  let mut memberships: Vec<EffectiveMembership> = closest.into_values().collect();
+  memberships.sort_by_key(|membership| (membership.depth, membership.group_id));
+  Ok(memberships)
+}
+
+/// The role a user effectively holds in a group, directly or inherited from a parent group
+pub async fn find_effective_role<C>(db: &C, user_id: UserId, group_id: GroupId) -> Result<Option<super::group_access_role::Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let membership = find_effective_memberships(db, user_id).await?
+    .into_iter()
+    .find(|membership| membership.group_id == group_id);
+  match membership {
+    Some(membership) => super::group_access_role::Entity::find_by_id(membership.group_access_role_id).one(db).await,
+    None => Ok(None),
+  }
+}
+
+#[derive(Debug, FromQueryResult)]
+struct GroupRow {
+  id: GroupId,
+}
+
+/// The group followed by each of its parents, nearest first
+pub async fn find_ancestor_ids<C>(db: &C, group_id: GroupId) -> Result<Vec<GroupId>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let ids = GroupRow::find_by_statement(Statement::from_sql_and_values(
+    db.get_database_backend(),
+    r#"WITH RECURSIVE ancestors (id, parent_group_id, depth) AS (
+      SELECT g.id, g.parent_group_id, 0 FROM groups g WHERE g.id = $1
+      UNION ALL
+      SELECT g.id, g.parent_group_id, a.depth + 1
+      FROM ancestors a
+      JOIN groups g ON g.id = a.parent_group_id
+      WHERE a.depth < $2
+    )
+    SELECT id FROM ancestors ORDER BY depth"#,
+    vec![group_id.into(), MAX_GROUP_DEPTH.into()],
+  )).all(db).await?;
+  Ok(ids.into_iter().map(|row| row.id).collect())
+}
+
+/// The group and every group nested below it
+pub async fn find_descendant_ids<C>(db: &C, group_id: GroupId) -> Result<Vec<GroupId>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let ids = GroupRow::find_by_statement(Statement::from_sql_and_values(
+    db.get_database_backend(),
+    r#"WITH RECURSIVE descendants (id, depth) AS (
+      SELECT g.id, 0 FROM groups g WHERE g.id = $1
+      UNION ALL
+      SELECT g.id, d.depth + 1
+      FROM descendants d
+      JOIN groups g ON g.parent_group_id = d.id
+      WHERE d.depth < $2
+    )
+    SELECT DISTINCT id FROM descendants"#,
+    vec![group_id.into(), MAX_GROUP_DEPTH.into()],
+  )).all(db).await?;
+  Ok(ids.into_iter().map(|row| row.id).collect())
+}
+
+/// Every user who is effectively in the group, including members of its parent groups
+pub async fn find_effective_member_ids<C>(db: &C, group_id: GroupId) -> Result<Vec<UserId>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let group_ids = find_ancestor_ids(db, group_id).await?;
+  let mut user_ids: Vec<UserId> = super::users_groups_group_access_roles::Entity::find()
+    .filter(super::users_groups_group_access_roles::Column::GroupId.is_in(group_ids))
+    .all(db)
+    .await?
+    .into_iter()
+    .map(|membership| membership.user_id)
+    .collect();
+  user_ids.sort_unstable();
+  user_ids.dedup();
+  Ok(user_ids)
+}
+
+/// Walks up from the proposed parent making sure the group doesn't end up as its own ancestor
+async fn check_parent<C>(db: &C, group_id: GroupId, organisation_id: OrgId, parent_group_id: GroupId) -> Result<(), String>
+where
+  C: ConnectionTrait,
+{
+  let mut next = Some(parent_group_id);
+  let mut depth = 0;
+  while let Some(ancestor_id) = next {
+    if ancestor_id == group_id {
+      return Err("parent_group_id would create a cycle".to_string());
+    }
+    depth += 1;
+    if depth > MAX_GROUP_DEPTH {
+      return Err(format!("groups cannot be nested more than {} deep", MAX_GROUP_DEPTH));
+    }
+    let ancestor = Entity::find_by_id(ancestor_id).one(db).await
+      .map_err(|err| err.to_string())?
+      .ok_or_else(|| format!("parent group {} does not exist", ancestor_id))?;
+    if ancestor.organisation_id != organisation_id {
+      return Err("parent group must belong to the same organisation".to_string());
+    }
+    next = ancestor.parent_group_id;
+  }
+  Ok(())
+}
+
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
-      id: Set(Uuid::new_v4()),
-      created_at: Set(chrono::Utc::now()),
-      updated_at: Set(chrono::Utc::now()),
+      id: Set(GroupId::new()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(chrono::Utc::now());
+      self.updated_at = Set(clock::now());
+    }
+    // Its parent, children, keys and memberships all stay with the organisation, so a group
+    // can't move to another one
+    if !insert && self.organisation_id.is_set() {
+      let stored = Entity::find_by_id(*self.id.as_ref()).one(db).await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("group {}", self.id.as_ref())))?;
+      if stored.organisation_id != *self.organisation_id.as_ref() {
+        return Err(DbErr::Custom(format!(
+          "[before_save] Invalid organisation, insert: {}, groups cannot move to another organisation",
+          insert
+        )));
+      }
+    }
+    // Moving a group needs to keep the hierarchy a tree within one organisation
+    if self.parent_group_id.is_set() && !self.id.is_not_set() {
+      if let Some(parent_group_id) = *self.parent_group_id.as_ref() {
+        // Partial updates are checked against the organisation the group is stored in
+        let organisation_id = match self.organisation_id.is_not_set() {
+          false => *self.organisation_id.as_ref(),
+          true => match Entity::find_by_id(*self.id.as_ref()).one(db).await? {
+            Some(stored) => stored.organisation_id,
+            None => return Err(DbErr::RecordNotFound(format!("group {}", self.id.as_ref()))),
+          },
+        };
+        if let Err(err) = check_parent(db, *self.id.as_ref(), organisation_id, parent_group_id).await {
+          return Err(DbErr::Custom(format!(
+            "[before_save] Invalid parent group, insert: {}, {}",
+            insert, err
+          )));
+        }
+      }
     }
     Ok(self)
   }
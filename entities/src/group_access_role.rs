@@ -1,7 +1,8 @@
 use sea_orm::{ entity::prelude::*, ActiveValue::Set };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
-use chrono::Utc;
+use crate::permission::{check_role_permissions, PermissionSet};
+use shared::clock;
 
 #[derive(Debug, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "group_role_permissions")]
@@ -18,10 +19,26 @@ pub enum GroupRolePermissions {
   Denied,
   #[sea_orm(string_value = "DeniedBlocked")]
   DeniedBlocked,
+  #[sea_orm(string_value = "Custom")]
+  Custom,
+}
+
+impl GroupRolePermissions {
+  /// The built-in permissions for each level, custom roles have none of their own
+  pub fn preset(&self) -> Option<PermissionSet> {
+    match self {
+      Self::AllowOwner => Some(PermissionSet::owner()),
+      Self::AllowAdmin => Some(PermissionSet::admin()),
+      Self::AllowReadWrite => Some(PermissionSet::read_write()),
+      Self::AllowReadOnly => Some(PermissionSet::read_only()),
+      Self::Denied | Self::DeniedBlocked => Some(PermissionSet::none()),
+      Self::Custom => None,
+    }
+  }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
-#[sea_orm(table_name = "group_access_roles", schema_name = "public")]
+#[sea_orm(table_name = "group_access_roles")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
@@ -30,6 +47,8 @@ pub struct Model {
   #[sea_orm(nullable)]
   pub description: Option<String>,
   pub group_role_permissions: GroupRolePermissions,
+  #[sea_orm(column_type = "JsonBinary", nullable)]
+  pub permissions: Option<PermissionSet>,
   pub created_at: ChronoDateTimeUtc,
   pub updated_at: ChronoDateTimeUtc,
 }
@@ -56,25 +75,58 @@ impl Related<super::group::Entity> for Entity {
   }
 }
 
+impl Model {
+  /// The explicit permission set for custom roles, otherwise the preset for the level
+  pub fn effective_permissions(&self) -> PermissionSet {
+    match self.group_role_permissions.preset() {
+      Some(preset) => preset,
+      None => self.permissions.clone().unwrap_or_default(),
+    }
+  }
+
+  pub fn allows(&self, resource: &str, verb: &str) -> bool {
+    self.effective_permissions().allows(resource, verb)
+  }
+}
+
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
+    }
+    // Only custom roles carry their own permissions, the other levels are presets. Partial
+    // updates are checked against the stored role.
+    if self.group_role_permissions.is_set() || self.permissions.is_set() {
+      let stored = match insert || (!self.group_role_permissions.is_not_set() && !self.permissions.is_not_set()) {
+        true => None,
+        false => Entity::find_by_id(*self.id.as_ref()).one(db).await?,
+      };
+      let level = match self.group_role_permissions.is_not_set() {
+        true => stored.as_ref().map(|role| role.group_role_permissions.clone()),
+        false => Some(self.group_role_permissions.as_ref().clone()),
+      };
+      let permissions = match self.permissions.is_not_set() {
+        true => stored.and_then(|role| role.permissions),
+        false => self.permissions.as_ref().clone(),
+      };
+      if let Some(level) = level {
+        check_role_permissions(level == GroupRolePermissions::Custom, permissions.as_ref(), insert)?;
+      }
     }
     Ok(self)
   }
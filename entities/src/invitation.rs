@@ -0,0 +1,420 @@
+use sea_orm::{ entity::prelude::*, sea_query::{Expr, Func}, ActiveValue::Set, TransactionTrait };
+use serde::{Deserialize, Serialize};
+use serde_email::Email;
+use async_trait::async_trait;
+use chrono::Duration;
+use shared::{clock, rng, GroupId, OrgId, UserId};
+use super::{
+  config, email, group, group_access_role, organisation, organisation_access_role, permission::PermissionSet,
+  user::{self, LockedState}, users_groups_group_access_roles, users_organisations_organisations_access_roles,
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "invitation_status")]
+pub enum InvitationStatus {
+  #[sea_orm(string_value = "Pending")]
+  Pending,
+  #[sea_orm(string_value = "Accepted")]
+  Accepted,
+  #[sea_orm(string_value = "Declined")]
+  Declined,
+  #[sea_orm(string_value = "Revoked")]
+  Revoked,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "invitations")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub email_address: String,
+  pub organisation_id: OrgId,
+  pub organisation_access_role_id: Uuid,
+  #[sea_orm(nullable)]
+  pub group_id: Option<GroupId>,
+  #[sea_orm(nullable)]
+  pub group_access_role_id: Option<Uuid>,
+  pub inviter_user_id: UserId,
+  #[serde(skip_serializing)]
+  #[sea_orm(unique)]
+  pub token: String,
+  pub status: InvitationStatus,
+  pub expires_at: ChronoDateTimeUtc,
+  #[sea_orm(nullable)]
+  pub responded_at: Option<ChronoDateTimeUtc>,
+  #[sea_orm(nullable)]
+  pub accepted_user_id: Option<UserId>,
+  #[sea_orm(nullable)]
+  pub email_id: Option<Uuid>,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "super::organisation_access_role::Entity",
+    from = "Column::OrganisationAccessRoleId",
+    to = "super::organisation_access_role::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  OrganisationAccessRole,
+  #[sea_orm(
+    belongs_to = "super::group::Entity",
+    from = "Column::GroupId",
+    to = "super::group::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Group,
+  #[sea_orm(
+    belongs_to = "super::group_access_role::Entity",
+    from = "Column::GroupAccessRoleId",
+    to = "super::group_access_role::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  GroupAccessRole,
+  #[sea_orm(
+    belongs_to = "super::user::Entity",
+    from = "Column::InviterUserId",
+    to = "super::user::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Inviter,
+  #[sea_orm(
+    belongs_to = "super::user::Entity",
+    from = "Column::AcceptedUserId",
+    to = "super::user::Column::Id",
+    on_update = "Cascade",
+    on_delete = "SetNull"
+  )]
+  AcceptedUser,
+  #[sea_orm(
+    belongs_to = "super::email::Entity",
+    from = "Column::EmailId",
+    to = "super::email::Column::Id",
+    on_update = "Cascade",
+    on_delete = "SetNull"
+  )]
+  Email,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::group::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Group.def()
+  }
+}
+
+impl Related<super::email::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Email.def()
+  }
+}
+
+impl Model {
+  /// Only pending invitations that haven't expired can be accepted or declined
+  pub fn is_open(&self) -> bool {
+    self.status == InvitationStatus::Pending && self.expires_at > clock::now()
+  }
+}
+
+pub fn generate_expires_at() -> ChronoDateTimeUtc {
+  expires_at_for(&super::config::get().security)
+}
+
+/// Expiry under an organisation's own security settings
+pub fn expires_at_for(security: &super::config::SecurityConfig) -> ChronoDateTimeUtc {
+  clock::now() + Duration::days(security.invitation_valid_days)
+}
+
+/// Who to invite and the roles they get on accepting
+#[derive(Clone, Debug, Deserialize)]
+pub struct NewInvitation {
+  pub email_address: Email,
+  pub organisation_access_role_id: Uuid,
+  pub group_id: Option<GroupId>,
+  pub group_access_role_id: Option<Uuid>,
+  /// At most the organisation's `invitation_valid_days`, which is also the default
+  pub expires_in_hours: Option<i64>,
+}
+
+fn invalid(insert: bool, err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid invitation, insert: {}, {}", insert, err))
+}
+
+/// Invites someone into the organisation, and optionally one of its groups. The inviter can't
+/// hand out a role granting more than their own roles there do.
+pub async fn create<C>(db: &C, organisation_id: OrgId, inviter_user_id: UserId, new: NewInvitation) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let organisation_role = organisation_access_role::Entity::find_by_id(new.organisation_access_role_id).one(db).await?
+    .ok_or_else(|| invalid(true, "organisation_access_role_id does not exist".to_string()))?;
+  let mut held = organisation::find_effective_role(db, inviter_user_id, organisation_id).await?
+    .map(|role| role.effective_permissions())
+    .unwrap_or_default();
+  if !held.covers(&organisation_role.effective_permissions()) {
+    return Err(invalid(true, "the organisation role grants more than your own".to_string()));
+  }
+  match (new.group_id, new.group_access_role_id) {
+    (Some(group_id), Some(group_access_role_id)) => {
+      group::Entity::find_by_id(group_id)
+        .filter(group::Column::OrganisationId.eq(organisation_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| invalid(true, "group_id does not belong to the organisation".to_string()))?;
+      let group_role = group_access_role::Entity::find_by_id(group_access_role_id).one(db).await?
+        .ok_or_else(|| invalid(true, "group_access_role_id does not exist".to_string()))?;
+      // Either of the inviter's roles can cover the group role, as either lets them act in the group
+      if let Some(role) = group::find_effective_role(db, inviter_user_id, group_id).await? {
+        held = PermissionSet([held.0, role.effective_permissions().0].concat());
+      }
+      if !held.covers(&group_role.effective_permissions()) {
+        return Err(invalid(true, "the group role grants more than your own".to_string()));
+      }
+    },
+    (None, None) => {},
+    _ => return Err(invalid(true, "group_id and group_access_role_id must be set together".to_string())),
+  }
+
+  let security = config::security_for_organisation(db, organisation_id).await?;
+  let max_hours = security.invitation_valid_days * 24;
+  let expires_at = match new.expires_in_hours {
+    None => expires_at_for(&security),
+    Some(hours) if hours <= 0 || hours > max_hours => {
+      return Err(invalid(true, format!("expires_in_hours must be between 1 and {}", max_hours)));
+    },
+    Some(hours) => clock::now() + Duration::hours(hours),
+  };
+  ActiveModel {
+    email_address: Set(new.email_address.to_lowercase().to_string()),
+    organisation_id: Set(organisation_id),
+    organisation_access_role_id: Set(new.organisation_access_role_id),
+    group_id: Set(new.group_id),
+    group_access_role_id: Set(new.group_access_role_id),
+    inviter_user_id: Set(inviter_user_id),
+    expires_at: Set(expires_at),
+    ..Default::default()
+  }.insert(db).await
+}
+
+pub async fn find_by_token<C>(db: &C, token: &str) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  Entity::find()
+    .filter(Column::Token.eq(token))
+    .one(db)
+    .await?
+    .ok_or_else(|| DbErr::RecordNotFound("invitation".to_string()))
+}
+
+/// Closes the invitation only if it is still pending, so of two responses racing each other one
+/// fails. Only revoking works once it has expired.
+async fn respond<C>(db: &C, invitation: &Model, response: ActiveModel) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let now = clock::now();
+  let mut update = Entity::update_many()
+    .filter(Column::Id.eq(invitation.id))
+    .filter(Column::Status.eq(InvitationStatus::Pending));
+  if *response.status.as_ref() != InvitationStatus::Revoked {
+    update = update.filter(Column::ExpiresAt.gt(now));
+  }
+  let result = update
+    .set(ActiveModel { responded_at: Set(Some(now)), updated_at: Set(now), ..response })
+    .exec(db)
+    .await?;
+  if result.rows_affected != 1 {
+    return Err(invalid(false, "invitation is no longer open".to_string()));
+  }
+  Entity::find_by_id(invitation.id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("invitation {}", invitation.id)))
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Accepted {
+  #[serde(flatten)]
+  pub invitation: Model,
+  pub user_created: bool,
+}
+
+/// The invited address for a signed in user, who must have verified it. Without a user the
+/// token alone is enough, but only while nobody has verified the address, and then the user
+/// is created with it verified since the token proves control of it.
+async fn invited_email<C>(db: &C, invitation: &Model, user_id: Option<UserId>) -> Result<(email::Model, bool), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let addresses = email::Entity::find()
+    .filter(Expr::expr(Func::lower(Expr::col(email::Column::EmailAddress))).eq(invitation.email_address.as_str()))
+    .all(db)
+    .await?;
+  match user_id {
+    Some(user_id) => {
+      let address = addresses.into_iter()
+        .find(|address| address.user_id == user_id)
+        .ok_or_else(|| invalid(false, "the invitation is for another email address".to_string()))?;
+      if !address.is_verified {
+        return Err(invalid(false, format!("verify {} before accepting the invitation", address.email_address)));
+      }
+      Ok((address, false))
+    },
+    None if addresses.iter().any(|address| address.is_verified) => {
+      Err(invalid(false, format!("sign in as {} to accept the invitation", invitation.email_address)))
+    },
+    None => {
+      let new_user = user::ActiveModel {
+        locked_state: Set(LockedState::Unlocked),
+        locked_state_updated_at: Set(clock::now()),
+        locked_state_expires_at: Set(None),
+        pki_key_id: Set(None),
+        ..Default::default()
+      }.insert(db).await?;
+      let address = email::ActiveModel {
+        user_id: Set(new_user.id),
+        email_address: Set(invitation.email_address.clone()),
+        verification_code: Set(None),
+        verification_code_expires_at: Set(None),
+        is_primary: Set(true),
+        is_verified: Set(true),
+        ..Default::default()
+      }.insert(db).await?;
+      Ok((address, true))
+    },
+  }
+}
+
+/// Accepts for the signed in user, or for a new user when nobody holds the invited address.
+/// Roles the user already holds in the organisation or group are kept, not replaced by the
+/// invitation's.
+pub async fn accept<C>(db: &C, token: &str, user_id: Option<UserId>) -> Result<Accepted, DbErr>
+where
+  C: ConnectionTrait + TransactionTrait,
+{
+  let txn = db.begin().await?;
+  let invitation = find_by_token(&txn, token).await?;
+  if !invitation.is_open() {
+    return Err(invalid(false, "invitation is no longer open".to_string()));
+  }
+  let (invited_email, user_created) = invited_email(&txn, &invitation, user_id).await?;
+  let user_id = invited_email.user_id;
+  let accepted = respond(&txn, &invitation, ActiveModel {
+    status: Set(InvitationStatus::Accepted),
+    accepted_user_id: Set(Some(user_id)),
+    email_id: Set(Some(invited_email.id)),
+    ..ActiveModelTrait::default()
+  }).await?;
+
+  let org_membership = users_organisations_organisations_access_roles::Entity::find_by_id((user_id, accepted.organisation_id))
+    .one(&txn)
+    .await?;
+  if org_membership.is_none() {
+    users_organisations_organisations_access_roles::ActiveModel {
+      user_id: Set(user_id),
+      organisation_id: Set(accepted.organisation_id),
+      organisation_access_role_id: Set(accepted.organisation_access_role_id),
+      ..Default::default()
+    }.insert(&txn).await?;
+  }
+  if let (Some(group_id), Some(group_access_role_id)) = (accepted.group_id, accepted.group_access_role_id) {
+    let group_membership = users_groups_group_access_roles::Entity::find_by_id((user_id, group_id))
+      .one(&txn)
+      .await?;
+    if group_membership.is_none() {
+      users_groups_group_access_roles::ActiveModel {
+        user_id: Set(user_id),
+        group_id: Set(group_id),
+        group_access_role_id: Set(group_access_role_id),
+        ..Default::default()
+      }.insert(&txn).await?;
+    }
+  }
+  txn.commit().await?;
+  Ok(Accepted { invitation: accepted, user_created })
+}
+
+pub async fn decline<C>(db: &C, token: &str) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let invitation = find_by_token(db, token).await?;
+  respond(db, &invitation, ActiveModel { status: Set(InvitationStatus::Declined), ..ActiveModelTrait::default() }).await
+}
+
+/// Only pending invitations can be revoked, whether or not they have expired
+pub async fn revoke<C>(db: &C, invitation: &Model) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  if invitation.status != InvitationStatus::Pending {
+    return Err(invalid(false, "only pending invitations can be revoked".to_string()));
+  }
+  respond(db, invitation, ActiveModel { status: Set(InvitationStatus::Revoked), ..ActiveModelTrait::default() }).await
+}
+
+pub fn generate_invitation_token() -> String {
+  let invitation_token_size: usize = 64;
+  rng::alphanumeric(invitation_token_size)
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      token: Set(generate_invitation_token()),
+      status: Set(InvitationStatus::Pending),
+      expires_at: Set(generate_expires_at()),
+      responded_at: Set(None),
+      accepted_user_id: Set(None),
+      email_id: Set(None),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    let has_group = !self.group_id.is_not_set() && self.group_id.as_ref().is_some();
+    let has_group_role = !self.group_access_role_id.is_not_set() && self.group_access_role_id.as_ref().is_some();
+    if insert && has_group != has_group_role {
+      return Err(DbErr::Custom(format!(
+        "[before_save] group_id and group_access_role_id must be set together, insert: {}",
+        insert
+      )));
+    }
+    // Any response to the invitation closes it
+    if self.status.is_set() && *self.status.as_ref() != InvitationStatus::Pending {
+      self.responded_at = Set(Some(clock::now()));
+    }
+    Ok(self)
+  }
+}
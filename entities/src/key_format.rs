@@ -0,0 +1,578 @@
+//! Bringing keys made elsewhere in, and handing keys out, in the formats other tools use.
+//!
+//! Imports are normalised to the PKCS#8 PEM `key_pair` works with. PKCS#8 DER and PKCS#12 are
+//! binary so they travel base64 encoded, JWKs are JSON and PEM and OpenSSH keys are text.
+//! Passphrase protected PKCS#8 and PKCS#12 exports use PBES2 with PBKDF2-SHA256 and AES-256-CBC,
+//! OpenSSH exports what `ssh-keygen` does.
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use cms::{content_info::ContentInfo, encrypted_data::EncryptedData};
+use ed25519_dalek::SigningKey;
+use hmac::{Mac, SimpleHmac};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use pkcs12::{
+  digest_info::DigestInfo,
+  kdf::{derive_key_utf8, Pkcs12KeyType},
+  mac_data::MacData,
+  pbe_params::{self, Pkcs12PbeParams},
+  pfx::{self, Pfx},
+  safe_bag::{SafeBag, SafeContents},
+};
+use pkcs8::{
+  der::{
+    asn1::{ContextSpecific, OctetString},
+    oid::db::{rfc5911, rfc5912},
+    pem, Any, Decode, Encode, SecretDocument, Tag,
+  },
+  pkcs5, DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncryptedPrivateKeyInfo, LineEnding,
+  PrivateKeyInfo,
+};
+use rand::RngCore;
+use rsa::{
+  pkcs1::DecodeRsaPrivateKey,
+  traits::{PrivateKeyParts, PublicKeyParts},
+  BigUint, RsaPrivateKey, RsaPublicKey,
+};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{digest::{core_api::BlockSizeUser, Digest, FixedOutputReset}, Sha256, Sha384, Sha512};
+use shared::rng::SharedRng;
+use ssh_key::private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair};
+use zeroize::{Zeroize, Zeroizing};
+use super::{key_pair, pki_key::KeyAlgos, ssh, x509};
+
+/// PBKDF2 rounds for passphrase protected exports
+const PBKDF2_ITERATIONS: u32 = 100_000;
+/// PKCS#12 KDF rounds for the MAC over an exported PKCS#12 file
+const PKCS12_MAC_ITERATIONS: i32 = 10_000;
+/// Most PBKDF2 or PKCS#12 KDF rounds, or scrypt N * r * p, an imported key may ask for
+const MAX_KDF_ITERATIONS: u64 = 10_000_000;
+/// scrypt needs 128 * N * r bytes
+const MAX_SCRYPT_MEMORY: u64 = 256 * 1024 * 1024;
+/// Each bcrypt-pbkdf round is far dearer than a PBKDF2 one, `ssh-keygen` defaults to 16
+const MAX_BCRYPT_PBKDF_ROUNDS: u32 = 1_000;
+const SALT_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyFormat {
+  /// PKCS#8, or when importing also PKCS#1, SEC1 and OpenSSH private keys. Public keys are
+  /// SubjectPublicKeyInfo.
+  Pem,
+  /// Base64 DER, public keys are SubjectPublicKeyInfo
+  Pkcs8,
+  /// Base64, private keys only
+  Pkcs12,
+  Jwk,
+  OpenSsh,
+}
+
+fn describe(err: impl std::fmt::Display) -> String {
+  err.to_string()
+}
+
+fn secret(document: SecretDocument) -> Zeroizing<Vec<u8>> {
+  document.to_bytes()
+}
+
+fn decode_base64(data: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+  // Base64 from files is often wrapped
+  let data = data.split_whitespace().collect::<String>();
+  STANDARD.decode(data).map(Zeroizing::new).map_err(|_| "key data must be base64".to_string())
+}
+
+fn required(passphrase: Option<&str>) -> Result<&str, String> {
+  passphrase.ok_or_else(|| "the key is encrypted, a passphrase is required".to_string())
+}
+
+/// Refuses work factors that would tie up a worker before anything is derived
+fn check_iterations(iterations: u64) -> Result<(), String> {
+  match iterations {
+    1..=MAX_KDF_ITERATIONS => Ok(()),
+    _ => Err(format!("KDF iterations must be between 1 and {}", MAX_KDF_ITERATIONS)),
+  }
+}
+
+fn check_scheme(scheme: &pkcs5::EncryptionScheme) -> Result<(), String> {
+  match scheme.pbes2().map(|parameters| &parameters.kdf) {
+    Some(pkcs5::pbes2::Kdf::Pbkdf2(kdf)) => check_iterations(kdf.iteration_count.into()),
+    Some(pkcs5::pbes2::Kdf::Scrypt(kdf)) => {
+      let (cost, block_size) = (kdf.cost_parameter, u64::from(kdf.block_size));
+      if cost.saturating_mul(block_size).saturating_mul(128) > MAX_SCRYPT_MEMORY {
+        return Err(format!("scrypt parameters need more than {} bytes", MAX_SCRYPT_MEMORY));
+      }
+      check_iterations(cost.saturating_mul(block_size).saturating_mul(kdf.parallelization.into()))
+    },
+    Some(_) => Err("unsupported PBES2 key derivation".to_string()),
+    // PBES1 counts its iterations in 16 bits
+    None => Ok(()),
+  }
+}
+
+/// As `check_scheme`, for the schemes `decrypt_pbe` takes. Ones it can't read are left for
+/// decryption to refuse.
+fn check_pbe(algorithm: &pkcs8::spki::AlgorithmIdentifierOwned) -> Result<(), String> {
+  if algorithm.oid == pkcs12::PKCS_12_PBE_WITH_SHAAND3_KEY_TRIPLE_DES_CBC {
+    let parameters: Pkcs12PbeParams = algorithm.parameters.as_ref()
+      .ok_or_else(|| "missing PBE parameters".to_string())?
+      .decode_as()
+      .map_err(describe)?;
+    return check_iterations(u64::try_from(parameters.iterations).unwrap_or_default());
+  }
+  let algorithm = algorithm.to_der().map_err(describe)?;
+  match pkcs5::EncryptionScheme::try_from(algorithm.as_slice()) {
+    Ok(scheme) => check_scheme(&scheme),
+    Err(_) => Ok(()),
+  }
+}
+
+/// Reads a private key, returning its algorithm and its PKCS#8 PEM
+pub fn import(format: KeyFormat, data: &str, passphrase: Option<&str>) -> Result<(KeyAlgos, Zeroizing<String>), String> {
+  let der = match format {
+    KeyFormat::Pem => from_pem(data.trim(), passphrase)?,
+    KeyFormat::Pkcs8 => from_pkcs8(&decode_base64(data)?, passphrase)?,
+    KeyFormat::Pkcs12 => from_pkcs12(&decode_base64(data)?, passphrase.unwrap_or_default())?,
+    KeyFormat::Jwk => from_jwk(data)?,
+    KeyFormat::OpenSsh => from_openssh(data.trim(), passphrase)?,
+  };
+  normalise(&der)
+}
+
+/// Re-encodes a PKCS#8 private key the way `key_pair` would have generated it
+fn normalise(der: &[u8]) -> Result<(KeyAlgos, Zeroizing<String>), String> {
+  let info = PrivateKeyInfo::try_from(der).map_err(|err| format!("invalid private key: {}", err))?;
+  let (algorithm, curve) = info.algorithm.oids().map_err(describe)?;
+  let algo = x509::key_algo_for(algorithm, curve)?;
+  let pem = match algo {
+    KeyAlgos::RSA => {
+      let key = RsaPrivateKey::from_pkcs8_der(der).map_err(describe)?;
+      if !(key_pair::MIN_RSA_BITS..=key_pair::MAX_RSA_BITS).contains(&key.n().bits()) {
+        return Err(format!("RSA keys must be between {} and {} bits", key_pair::MIN_RSA_BITS, key_pair::MAX_RSA_BITS));
+      }
+      key.validate().map_err(describe)?;
+      key.to_pkcs8_pem(LineEnding::LF)
+    },
+    KeyAlgos::Ed25519 => SigningKey::from_pkcs8_der(der).map_err(describe)?.to_pkcs8_pem(LineEnding::LF),
+    KeyAlgos::EcdsaP256 => p256::SecretKey::from_pkcs8_der(der).map_err(describe)?.to_pkcs8_pem(LineEnding::LF),
+    KeyAlgos::EcdsaP384 => p384::SecretKey::from_pkcs8_der(der).map_err(describe)?.to_pkcs8_pem(LineEnding::LF),
+  }.map_err(describe)?;
+  Ok((algo, pem))
+}
+
+fn from_pem(data: &str, passphrase: Option<&str>) -> Result<Zeroizing<Vec<u8>>, String> {
+  if data.contains("Proc-Type:") {
+    return Err("legacy encrypted PEM is not supported, convert the key to PKCS#8 first".to_string());
+  }
+  let label = pem::decode_label(data.as_bytes()).map_err(|err| format!("invalid PEM: {}", err))?;
+  if label == "OPENSSH PRIVATE KEY" {
+    return from_openssh(data, passphrase);
+  }
+  let (_, document) = SecretDocument::from_pem(data).map_err(|err| format!("invalid PEM: {}", err))?;
+  let der = document.as_bytes();
+  match label {
+    "PRIVATE KEY" | "ENCRYPTED PRIVATE KEY" => from_pkcs8(der, passphrase),
+    "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_der(der).map_err(describe)?.to_pkcs8_der().map(secret).map_err(describe),
+    "EC PRIVATE KEY" => p256::SecretKey::from_sec1_der(der)
+      .map(|key| key.to_pkcs8_der())
+      .or_else(|_| p384::SecretKey::from_sec1_der(der).map(|key| key.to_pkcs8_der()))
+      .map_err(|_| "only P-256 and P-384 elliptic curve keys are supported".to_string())?
+      .map(secret)
+      .map_err(describe),
+    label => Err(format!("PEM {} is not a private key", label)),
+  }
+}
+
+fn from_pkcs8(der: &[u8], passphrase: Option<&str>) -> Result<Zeroizing<Vec<u8>>, String> {
+  if PrivateKeyInfo::try_from(der).is_ok() {
+    return Ok(Zeroizing::new(der.to_vec()));
+  }
+  let encrypted = EncryptedPrivateKeyInfo::try_from(der).map_err(|_| "not a PKCS#8 private key".to_string())?;
+  check_scheme(&encrypted.encryption_algorithm)?;
+  encrypted.decrypt(required(passphrase)?)
+    .map(secret)
+    .map_err(|_| "unable to decrypt the key, is the passphrase right?".to_string())
+}
+
+/// Salt and IV for PBES2 with PBKDF2-SHA256 and AES-256-CBC
+fn encrypt_pkcs8(der: &[u8], passphrase: &str) -> Result<SecretDocument, String> {
+  let mut salt = [0u8; SALT_LEN];
+  let mut iv = [0u8; 16];
+  SharedRng.fill_bytes(&mut salt);
+  SharedRng.fill_bytes(&mut iv);
+  let parameters = pkcs5::pbes2::Parameters::pbkdf2_sha256_aes256cbc(PBKDF2_ITERATIONS, &salt, &iv).map_err(describe)?;
+  PrivateKeyInfo::try_from(der).map_err(describe)?
+    .encrypt_with_params(parameters, passphrase)
+    .map_err(describe)
+}
+
+/// Decrypts with PBES2 or the PKCS#12 triple DES scheme older tools still default to
+fn decrypt_pbe(algorithm: &pkcs8::spki::AlgorithmIdentifierOwned, ciphertext: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+  check_pbe(algorithm)?;
+  if algorithm.oid == pkcs12::PKCS_12_PBE_WITH_SHAAND3_KEY_TRIPLE_DES_CBC {
+    let parameters: Pkcs12PbeParams = algorithm.parameters.as_ref()
+      .ok_or_else(|| "missing PBE parameters".to_string())?
+      .decode_as()
+      .map_err(describe)?;
+    let salt = parameters.salt.as_bytes();
+    let key = Zeroizing::new(derive_key_utf8::<Sha1>(passphrase, salt, Pkcs12KeyType::EncryptionKey, parameters.iterations, 24).map_err(describe)?);
+    let iv = derive_key_utf8::<Sha1>(passphrase, salt, Pkcs12KeyType::Iv, parameters.iterations, 8).map_err(describe)?;
+    return cbc::Decryptor::<des::TdesEde3>::new_from_slices(&key, &iv).map_err(describe)?
+      .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+      .map(Zeroizing::new)
+      .map_err(|_| "unable to decrypt, is the passphrase right?".to_string());
+  }
+  let algorithm = algorithm.to_der().map_err(describe)?;
+  let scheme = pkcs5::EncryptionScheme::try_from(algorithm.as_slice()).map_err(describe)?;
+  scheme.decrypt(passphrase, ciphertext)
+    .map(Zeroizing::new)
+    .map_err(|_| "unable to decrypt, is the passphrase right?".to_string())
+}
+
+/// HMAC keyed with the PKCS#12 KDF, fed the authenticated safe
+fn pkcs12_mac<D>(passphrase: &str, salt: &[u8], iterations: i32, content: &[u8]) -> Result<SimpleHmac<D>, String>
+where
+  D: Digest + FixedOutputReset + BlockSizeUser,
+{
+  let key = Zeroizing::new(
+    derive_key_utf8::<D>(passphrase, salt, Pkcs12KeyType::Mac, iterations, <D as Digest>::output_size()).map_err(describe)?,
+  );
+  let mut mac = <SimpleHmac<D> as Mac>::new_from_slice(&key).map_err(describe)?;
+  Mac::update(&mut mac, content);
+  Ok(mac)
+}
+
+fn verify_pkcs12_mac(mac_data: &MacData, content: &[u8], passphrase: &str) -> Result<(), String> {
+  let (salt, iterations, expected) = (mac_data.mac_salt.as_bytes(), mac_data.iterations, mac_data.mac.digest.as_bytes());
+  check_iterations(u64::try_from(iterations).unwrap_or_default())?;
+  let verified = match mac_data.mac.algorithm.oid {
+    rfc5912::ID_SHA_1 => pkcs12_mac::<Sha1>(passphrase, salt, iterations, content)?.verify_slice(expected),
+    rfc5912::ID_SHA_256 => pkcs12_mac::<Sha256>(passphrase, salt, iterations, content)?.verify_slice(expected),
+    rfc5912::ID_SHA_384 => pkcs12_mac::<Sha384>(passphrase, salt, iterations, content)?.verify_slice(expected),
+    rfc5912::ID_SHA_512 => pkcs12_mac::<Sha512>(passphrase, salt, iterations, content)?.verify_slice(expected),
+    oid => return Err(format!("unsupported PKCS#12 MAC digest {}", oid)),
+  };
+  verified.map_err(|_| "PKCS#12 MAC does not match, is the passphrase right?".to_string())
+}
+
+fn octets(content: &Any) -> Result<Vec<u8>, String> {
+  content.decode_as::<OctetString>().map(OctetString::into_bytes).map_err(describe)
+}
+
+/// The first private key in a password integrity PKCS#12 file. Certificates are ignored, as are
+/// encrypted contents the passphrase doesn't open, which are usually certificates under RC2.
+fn from_pkcs12(data: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+  let pfx = Pfx::from_der(data).map_err(|err| format!("invalid PKCS#12: {}", err))?;
+  if pfx.auth_safe.content_type != rfc5911::ID_DATA {
+    return Err("only password protected PKCS#12 files are supported".to_string());
+  }
+  let auth_safe = octets(&pfx.auth_safe.content)?;
+  if let Some(mac_data) = &pfx.mac_data {
+    verify_pkcs12_mac(mac_data, &auth_safe, passphrase)?;
+  }
+  for content in Vec::<ContentInfo>::from_der(&auth_safe).map_err(describe)? {
+    let safe_contents = match content.content_type {
+      rfc5911::ID_DATA => Zeroizing::new(octets(&content.content)?),
+      rfc5911::ID_ENCRYPTED_DATA => {
+        let encrypted = content.content.decode_as::<EncryptedData>().map_err(describe)?;
+        let info = encrypted.enc_content_info;
+        let Some(ciphertext) = info.encrypted_content else { continue };
+        // Contents that don't open are skipped, but not ones asking for too much work
+        check_pbe(&info.content_enc_alg)?;
+        match decrypt_pbe(&info.content_enc_alg, ciphertext.as_bytes(), passphrase) {
+          Ok(safe_contents) => safe_contents,
+          Err(_) => continue,
+        }
+      },
+      _ => continue,
+    };
+    for bag in SafeContents::from_der(&safe_contents).map_err(describe)? {
+      match bag.bag_id {
+        pkcs12::PKCS_12_KEY_BAG_OID => {
+          let key = ContextSpecific::<Any>::from_der(&bag.bag_value).map_err(describe)?;
+          return key.value.to_der().map(Zeroizing::new).map_err(describe);
+        },
+        pkcs12::PKCS_12_PKCS8_KEY_BAG_OID => {
+          let key = ContextSpecific::<pbe_params::EncryptedPrivateKeyInfo>::from_der(&bag.bag_value).map_err(describe)?.value;
+          return decrypt_pbe(&key.encryption_algorithm, key.encrypted_data.as_bytes(), passphrase);
+        },
+        _ => {},
+      }
+    }
+  }
+  Err("the PKCS#12 file holds no private key".to_string())
+}
+
+/// A lone shrouded key bag, with a SHA-256 MAC under the same passphrase
+fn to_pkcs12(der: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+  let shrouded = encrypt_pkcs8(der, passphrase)?;
+  let bags: SafeContents = vec![SafeBag {
+    bag_id: pkcs12::PKCS_12_PKCS8_KEY_BAG_OID,
+    bag_value: shrouded.as_bytes().to_vec(),
+    bag_attributes: None,
+  }];
+  let data = ContentInfo {
+    content_type: rfc5911::ID_DATA,
+    content: Any::new(Tag::OctetString, bags.to_der().map_err(describe)?).map_err(describe)?,
+  };
+  let auth_safe = vec![data].to_der().map_err(describe)?;
+  let mut salt = [0u8; SALT_LEN];
+  SharedRng.fill_bytes(&mut salt);
+  let mac = pkcs12_mac::<Sha256>(passphrase, &salt, PKCS12_MAC_ITERATIONS, &auth_safe)?.finalize().into_bytes();
+  let mac_data = MacData {
+    mac: DigestInfo {
+      algorithm: pkcs8::spki::AlgorithmIdentifierOwned { oid: rfc5912::ID_SHA_256, parameters: Some(Any::null()) },
+      digest: OctetString::new(mac.to_vec()).map_err(describe)?,
+    },
+    mac_salt: OctetString::new(salt.to_vec()).map_err(describe)?,
+    iterations: PKCS12_MAC_ITERATIONS,
+  };
+  Pfx {
+    version: pfx::Version::V3,
+    auth_safe: ContentInfo {
+      content_type: rfc5911::ID_DATA,
+      content: Any::new(Tag::OctetString, auth_safe).map_err(describe)?,
+    },
+    mac_data: Some(mac_data),
+  }.to_der().map_err(describe)
+}
+
+/// RFC 7517 keys, the private members are cleared when it's dropped
+#[derive(Default, Deserialize, Serialize)]
+struct Jwk {
+  kty: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  crv: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  kid: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  n: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  e: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  x: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  y: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  d: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  p: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  q: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  dp: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  dq: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  qi: Option<String>,
+}
+
+impl Drop for Jwk {
+  fn drop(&mut self) {
+    for member in [&mut self.d, &mut self.p, &mut self.q, &mut self.dp, &mut self.dq, &mut self.qi] {
+      member.zeroize();
+    }
+  }
+}
+
+fn base64url(bytes: &[u8]) -> Option<String> {
+  Some(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn member(name: &str, value: &Option<String>) -> Result<Zeroizing<Vec<u8>>, String> {
+  let value = value.as_deref().ok_or_else(|| format!("JWK has no {}", name))?;
+  URL_SAFE_NO_PAD.decode(value.trim_end_matches('='))
+    .map(Zeroizing::new)
+    .map_err(|_| format!("JWK {} must be base64url", name))
+}
+
+fn uint(name: &str, value: &Option<String>) -> Result<BigUint, String> {
+  Ok(BigUint::from_bytes_be(&member(name, value)?))
+}
+
+/// `x` and `y` as a SEC1 uncompressed point
+fn ec_point(jwk: &Jwk) -> Result<Vec<u8>, String> {
+  Ok([&[0x04][..], &member("x", &jwk.x)?, &member("y", &jwk.y)?].concat())
+}
+
+fn from_jwk(data: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+  let jwk: Jwk = serde_json::from_str(data).map_err(|err| format!("invalid JWK: {}", err))?;
+  if jwk.d.is_none() {
+    return Err("JWK has no private key".to_string());
+  }
+  let mismatched = || "JWK public and private members do not match".to_string();
+  let document = match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+    ("RSA", _) => {
+      let n = uint("n", &jwk.n)?;
+      let primes = vec![uint("p", &jwk.p)?, uint("q", &jwk.q)?];
+      let key = RsaPrivateKey::from_components(n, uint("e", &jwk.e)?, uint("d", &jwk.d)?, primes).map_err(describe)?;
+      key.to_pkcs8_der()
+    },
+    ("EC", Some("P-256")) => {
+      let key = p256::SecretKey::from_slice(&member("d", &jwk.d)?).map_err(describe)?;
+      if key.public_key() != p256::PublicKey::from_sec1_bytes(&ec_point(&jwk)?).map_err(describe)? {
+        return Err(mismatched());
+      }
+      key.to_pkcs8_der()
+    },
+    ("EC", Some("P-384")) => {
+      let key = p384::SecretKey::from_slice(&member("d", &jwk.d)?).map_err(describe)?;
+      if key.public_key() != p384::PublicKey::from_sec1_bytes(&ec_point(&jwk)?).map_err(describe)? {
+        return Err(mismatched());
+      }
+      key.to_pkcs8_der()
+    },
+    ("OKP", Some("Ed25519")) => {
+      let seed: [u8; 32] = member("d", &jwk.d)?.as_slice().try_into().map_err(|_| "Ed25519 JWK d must be 32 bytes".to_string())?;
+      let key = SigningKey::from_bytes(&seed);
+      if key.verifying_key().as_bytes().as_slice() != member("x", &jwk.x)?.as_slice() {
+        return Err(mismatched());
+      }
+      key.to_pkcs8_der()
+    },
+    (kty, crv) => return Err(format!("unsupported JWK kty {} crv {}", kty, crv.unwrap_or("-"))),
+  };
+  document.map(secret).map_err(describe)
+}
+
+impl Jwk {
+  fn new(kty: &str, crv: Option<&str>, kid: &str) -> Self {
+    let mut jwk = Jwk::default();
+    jwk.kty = kty.to_string();
+    jwk.crv = crv.map(str::to_string);
+    jwk.kid = Some(kid.to_string());
+    jwk
+  }
+}
+
+/// The public members of a key's JWK
+fn public_jwk(algo: KeyAlgos, public_key: &str, kid: &str) -> Result<Jwk, String> {
+  let point = match algo {
+    KeyAlgos::RSA => {
+      let key = RsaPublicKey::from_public_key_pem(public_key).map_err(describe)?;
+      let mut jwk = Jwk::new("RSA", None, kid);
+      jwk.n = base64url(&key.n().to_bytes_be());
+      jwk.e = base64url(&key.e().to_bytes_be());
+      return Ok(jwk);
+    },
+    KeyAlgos::Ed25519 => {
+      let key = ed25519_dalek::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?;
+      let mut jwk = Jwk::new("OKP", Some("Ed25519"), kid);
+      jwk.x = base64url(key.as_bytes());
+      return Ok(jwk);
+    },
+    KeyAlgos::EcdsaP256 => p256::PublicKey::from_public_key_pem(public_key).map_err(describe)?.to_encoded_point(false).as_bytes().to_vec(),
+    KeyAlgos::EcdsaP384 => p384::PublicKey::from_public_key_pem(public_key).map_err(describe)?.to_encoded_point(false).as_bytes().to_vec(),
+  };
+  // Uncompressed points are 0x04 followed by x and y
+  let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+  let mut jwk = Jwk::new("EC", Some(if algo == KeyAlgos::EcdsaP256 { "P-256" } else { "P-384" }), kid);
+  jwk.x = base64url(x);
+  jwk.y = base64url(y);
+  Ok(jwk)
+}
+
+fn private_jwk(algo: KeyAlgos, private_key: &str, kid: &str) -> Result<Zeroizing<String>, String> {
+  let public_key = key_pair::public_key_pem(algo, private_key)?;
+  let mut jwk = public_jwk(algo, &public_key, kid)?;
+  match algo {
+    KeyAlgos::RSA => {
+      let key = RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?;
+      let (Some(dp), Some(dq), Some(qi), [p, q]) = (key.dp(), key.dq(), key.crt_coefficient(), key.primes()) else {
+        return Err("only two prime RSA keys can be exported as a JWK".to_string());
+      };
+      jwk.d = base64url(&key.d().to_bytes_be());
+      jwk.p = base64url(&p.to_bytes_be());
+      jwk.q = base64url(&q.to_bytes_be());
+      jwk.dp = base64url(&dp.to_bytes_be());
+      jwk.dq = base64url(&dq.to_bytes_be());
+      jwk.qi = base64url(&qi.to_bytes_be());
+    },
+    KeyAlgos::Ed25519 => jwk.d = base64url(SigningKey::from_pkcs8_pem(private_key).map_err(describe)?.as_bytes()),
+    KeyAlgos::EcdsaP256 => jwk.d = base64url(&p256::SecretKey::from_pkcs8_pem(private_key).map_err(describe)?.to_bytes()),
+    KeyAlgos::EcdsaP384 => jwk.d = base64url(&p384::SecretKey::from_pkcs8_pem(private_key).map_err(describe)?.to_bytes()),
+  }
+  serde_json::to_string(&jwk).map(Zeroizing::new).map_err(describe)
+}
+
+/// `ssh-keygen`'s own format, RSA keys are rebuilt from their components
+fn from_openssh(data: &str, passphrase: Option<&str>) -> Result<Zeroizing<Vec<u8>>, String> {
+  let mut key = ssh_key::PrivateKey::from_openssh(data).map_err(|err| format!("invalid OpenSSH private key: {}", err))?;
+  if key.is_encrypted() {
+    if let ssh_key::Kdf::Bcrypt { rounds, .. } = key.kdf() {
+      if !(1..=MAX_BCRYPT_PBKDF_ROUNDS).contains(rounds) {
+        return Err(format!("bcrypt-pbkdf rounds must be between 1 and {}", MAX_BCRYPT_PBKDF_ROUNDS));
+      }
+    }
+    key = key.decrypt(required(passphrase)?).map_err(|_| "unable to decrypt the key, is the passphrase right?".to_string())?;
+  }
+  let document = match key.key_data() {
+    KeypairData::Ed25519(pair) => SigningKey::from_bytes(&pair.private.to_bytes()).to_pkcs8_der(),
+    KeypairData::Ecdsa(EcdsaKeypair::NistP256 { private, .. }) => p256::SecretKey::from_slice(private.as_slice()).map_err(describe)?.to_pkcs8_der(),
+    KeypairData::Ecdsa(EcdsaKeypair::NistP384 { private, .. }) => p384::SecretKey::from_slice(private.as_slice()).map_err(describe)?.to_pkcs8_der(),
+    KeypairData::Rsa(pair) => {
+      let uint = |mpint: &ssh_key::Mpint| mpint.as_positive_bytes().map(BigUint::from_bytes_be).ok_or_else(|| "invalid RSA key".to_string());
+      let primes = vec![uint(&pair.private.p)?, uint(&pair.private.q)?];
+      RsaPrivateKey::from_components(uint(&pair.public.n)?, uint(&pair.public.e)?, uint(&pair.private.d)?, primes)
+        .map_err(describe)?
+        .to_pkcs8_der()
+    },
+    _ => return Err(format!("{} keys are not supported", key.algorithm())),
+  };
+  document.map(secret).map_err(describe)
+}
+
+fn to_openssh(algo: KeyAlgos, private_key: &str, passphrase: Option<&str>, comment: &str) -> Result<Zeroizing<String>, String> {
+  let key_data = match algo {
+    KeyAlgos::RSA => KeypairData::from(RsaKeypair::try_from(RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?).map_err(describe)?),
+    KeyAlgos::Ed25519 => KeypairData::from(Ed25519Keypair::from(SigningKey::from_pkcs8_pem(private_key).map_err(describe)?)),
+    KeyAlgos::EcdsaP256 => {
+      let key = p256::SecretKey::from_pkcs8_pem(private_key).map_err(describe)?;
+      KeypairData::from(EcdsaKeypair::NistP256 { public: key.public_key().to_encoded_point(false), private: key.into() })
+    },
+    KeyAlgos::EcdsaP384 => {
+      let key = p384::SecretKey::from_pkcs8_pem(private_key).map_err(describe)?;
+      KeypairData::from(EcdsaKeypair::NistP384 { public: key.public_key().to_encoded_point(false), private: key.into() })
+    },
+  };
+  let mut key = ssh_key::PrivateKey::new(key_data, comment).map_err(describe)?;
+  if let Some(passphrase) = passphrase {
+    key = key.encrypt(&mut SharedRng, passphrase).map_err(describe)?;
+  }
+  key.to_openssh(ssh_key::LineEnding::LF).map_err(describe)
+}
+
+/// A public key in `format`, `kid` names it in JWKs and OpenSSH comments
+pub fn export_public(format: KeyFormat, algo: KeyAlgos, public_key: &str, kid: &str) -> Result<String, String> {
+  match format {
+    KeyFormat::Pem => Ok(public_key.to_string()),
+    KeyFormat::Pkcs8 => Ok(STANDARD.encode(x509::public_key_info(public_key)?.to_der().map_err(describe)?)),
+    KeyFormat::Pkcs12 => Err("PKCS#12 holds private keys and certificates, not bare public keys".to_string()),
+    KeyFormat::Jwk => serde_json::to_string(&public_jwk(algo, public_key, kid)?).map_err(describe),
+    KeyFormat::OpenSsh => {
+      let mut key = ssh::public_key(algo, public_key)?;
+      key.set_comment(kid);
+      key.to_openssh().map_err(describe)
+    },
+  }
+}
+
+/// A private key in `format`, encrypted under `passphrase` when there is one. PKCS#12 always is
+/// and JWKs never are.
+pub fn export_private(format: KeyFormat, algo: KeyAlgos, private_key: &str, passphrase: Option<&str>, kid: &str) -> Result<Zeroizing<String>, String> {
+  if passphrase == Some("") {
+    return Err("passphrase cannot be blank".to_string());
+  }
+  let der = || SecretDocument::from_pkcs8_pem(private_key).map_err(describe);
+  match (format, passphrase) {
+    (KeyFormat::Pem, None) => Ok(Zeroizing::new(private_key.to_string())),
+    (KeyFormat::Pem, Some(passphrase)) => encrypt_pkcs8(der()?.as_bytes(), passphrase)?
+      .to_pem("ENCRYPTED PRIVATE KEY", LineEnding::LF)
+      .map_err(describe),
+    (KeyFormat::Pkcs8, None) => Ok(Zeroizing::new(STANDARD.encode(der()?.as_bytes()))),
+    (KeyFormat::Pkcs8, Some(passphrase)) => Ok(Zeroizing::new(STANDARD.encode(encrypt_pkcs8(der()?.as_bytes(), passphrase)?.as_bytes()))),
+    (KeyFormat::Pkcs12, None) => Err("PKCS#12 exports need a passphrase".to_string()),
+    (KeyFormat::Pkcs12, Some(passphrase)) => Ok(Zeroizing::new(STANDARD.encode(to_pkcs12(der()?.as_bytes(), passphrase)?))),
+    (KeyFormat::Jwk, None) => private_jwk(algo, private_key, kid),
+    (KeyFormat::Jwk, Some(_)) => Err("JWKs cannot be encrypted, leave out the passphrase".to_string()),
+    (KeyFormat::OpenSsh, passphrase) => to_openssh(algo, private_key, passphrase, kid),
+  }
+}
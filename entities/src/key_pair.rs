@@ -0,0 +1,251 @@
+//! Key pair generation and the software signing and decryption for every `KeyAlgos`.
+//!
+//! Private keys are PKCS#8 and public keys SubjectPublicKeyInfo, both PEM encoded.
+//! ECDSA signatures are DER encoded, RSA decryption is OAEP with SHA-256.
+use ed25519_dalek::SigningKey;
+use pkcs8::{der::Document, DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::{pkcs1v15, pss, Oaep, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use signature::{
+  hazmat::{PrehashSigner, PrehashVerifier, RandomizedPrehashSigner},
+  RandomizedSigner, SignatureEncoding, Signer, Verifier,
+};
+use shared::rng::SharedRng;
+use zeroize::Zeroizing;
+use super::pki_key::KeyAlgos;
+
+/// Smallest and largest RSA modulus we generate or accept
+pub const MIN_RSA_BITS: usize = 2048;
+pub const MAX_RSA_BITS: usize = 8192;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyPair {
+  pub private_key: String,
+  pub public_key: String,
+}
+
+/// How a signature is made, each works with one kind of key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignatureScheme {
+  RsaPkcs1v15Sha256,
+  RsaPssSha256,
+  EcdsaSha256,
+  EcdsaSha384,
+  Ed25519,
+}
+
+impl SignatureScheme {
+  /// The scheme used when a caller doesn't pick one
+  pub fn default_for(algo: KeyAlgos) -> Self {
+    match algo {
+      KeyAlgos::RSA => SignatureScheme::RsaPssSha256,
+      KeyAlgos::Ed25519 => SignatureScheme::Ed25519,
+      KeyAlgos::EcdsaP256 => SignatureScheme::EcdsaSha256,
+      KeyAlgos::EcdsaP384 => SignatureScheme::EcdsaSha384,
+    }
+  }
+
+  pub fn supports(&self, algo: KeyAlgos) -> bool {
+    match self {
+      SignatureScheme::RsaPkcs1v15Sha256 | SignatureScheme::RsaPssSha256 => algo == KeyAlgos::RSA,
+      SignatureScheme::EcdsaSha256 => algo == KeyAlgos::EcdsaP256,
+      SignatureScheme::EcdsaSha384 => algo == KeyAlgos::EcdsaP384,
+      SignatureScheme::Ed25519 => algo == KeyAlgos::Ed25519,
+    }
+  }
+
+  /// Fails unless the scheme works with `algo`
+  pub fn check(&self, algo: KeyAlgos) -> Result<(), String> {
+    if !self.supports(algo) {
+      return Err(format!("{:?} keys cannot sign with {:?}", algo, self));
+    }
+    Ok(())
+  }
+
+  /// Length of the scheme's digest, Ed25519 only signs whole messages
+  pub fn digest_len(&self) -> Option<usize> {
+    match self {
+      SignatureScheme::EcdsaSha384 => Some(48),
+      SignatureScheme::Ed25519 => None,
+      _ => Some(32),
+    }
+  }
+
+  /// Hashes `message` the way the scheme would before signing it
+  pub fn digest(&self, message: &[u8]) -> Option<Vec<u8>> {
+    match self {
+      SignatureScheme::EcdsaSha384 => Some(Sha384::digest(message).to_vec()),
+      SignatureScheme::Ed25519 => None,
+      _ => Some(Sha256::digest(message).to_vec()),
+    }
+  }
+
+  /// Fails unless `digest` could have come from the scheme's hash
+  pub fn check_digest(&self, digest: &[u8]) -> Result<(), String> {
+    match self.digest_len() {
+      None => Err(format!("{:?} cannot sign a digest", self)),
+      Some(len) if len != digest.len() => Err(format!("{:?} digests are {} bytes", self, len)),
+      Some(_) => Ok(()),
+    }
+  }
+}
+
+fn describe(err: impl std::fmt::Display) -> String {
+  err.to_string()
+}
+
+/// Generates a key pair, `rsa_bits` is only used for RSA
+pub fn generate(algo: KeyAlgos, rsa_bits: usize) -> Result<KeyPair, String> {
+  let mut rng = SharedRng;
+  let private_key = match algo {
+    KeyAlgos::RSA => {
+      if !(MIN_RSA_BITS..=MAX_RSA_BITS).contains(&rsa_bits) {
+        return Err(format!("RSA keys must be between {} and {} bits", MIN_RSA_BITS, MAX_RSA_BITS));
+      }
+      RsaPrivateKey::new(&mut rng, rsa_bits).map_err(describe)?.to_pkcs8_pem(LineEnding::LF)
+    },
+    KeyAlgos::Ed25519 => SigningKey::generate(&mut rng).to_pkcs8_pem(LineEnding::LF),
+    KeyAlgos::EcdsaP256 => p256::SecretKey::random(&mut rng).to_pkcs8_pem(LineEnding::LF),
+    KeyAlgos::EcdsaP384 => p384::SecretKey::random(&mut rng).to_pkcs8_pem(LineEnding::LF),
+  }.map_err(describe)?;
+  let public_key = public_key_pem(algo, &private_key)?;
+  Ok(KeyPair { private_key: private_key.to_string(), public_key })
+}
+
+/// Derives the public key PEM from a private key PEM, which must be a key of `algo`
+pub fn public_key_pem(algo: KeyAlgos, private_key: &str) -> Result<String, String> {
+  match algo {
+    KeyAlgos::RSA => RsaPublicKey::from(&RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?)
+      .to_public_key_pem(LineEnding::LF),
+    KeyAlgos::Ed25519 => SigningKey::from_pkcs8_pem(private_key).map_err(describe)?
+      .verifying_key()
+      .to_public_key_pem(LineEnding::LF),
+    KeyAlgos::EcdsaP256 => p256::SecretKey::from_pkcs8_pem(private_key).map_err(describe)?
+      .public_key()
+      .to_public_key_pem(LineEnding::LF),
+    KeyAlgos::EcdsaP384 => p384::SecretKey::from_pkcs8_pem(private_key).map_err(describe)?
+      .public_key()
+      .to_public_key_pem(LineEnding::LF),
+  }.map_err(describe)
+}
+
+/// Checks the private key is a key of `algo` and the public key belongs to it
+pub fn check(algo: KeyAlgos, private_key: &str, public_key: &str) -> Result<(), String> {
+  let derived = public_key_pem(algo, private_key)?;
+  // Compared as DER so line endings and wrapping don't matter
+  let (_, expected) = Document::from_pem(&derived).map_err(describe)?;
+  let (label, actual) = Document::from_pem(public_key).map_err(|err| format!("invalid public key: {}", err))?;
+  if label != "PUBLIC KEY" || actual != expected {
+    return Err("public key does not match the private key".to_string());
+  }
+  Ok(())
+}
+
+/// Signs `message` with a PEM private key
+pub fn sign(algo: KeyAlgos, private_key: &str, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, String> {
+  scheme.check(algo)?;
+  let signature = match scheme {
+    SignatureScheme::RsaPkcs1v15Sha256 => pkcs1v15::SigningKey::<Sha256>::new(RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?)
+      .try_sign(message).map_err(describe)?
+      .to_vec(),
+    SignatureScheme::RsaPssSha256 => pss::BlindedSigningKey::<Sha256>::new(RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?)
+      .try_sign_with_rng(&mut SharedRng, message).map_err(describe)?
+      .to_vec(),
+    SignatureScheme::EcdsaSha256 => {
+      let signature: p256::ecdsa::Signature = p256::ecdsa::SigningKey::from_pkcs8_pem(private_key).map_err(describe)?
+        .try_sign(message).map_err(describe)?;
+      signature.to_der().to_vec()
+    },
+    SignatureScheme::EcdsaSha384 => {
+      let signature: p384::ecdsa::Signature = p384::ecdsa::SigningKey::from_pkcs8_pem(private_key).map_err(describe)?
+        .try_sign(message).map_err(describe)?;
+      signature.to_der().to_vec()
+    },
+    SignatureScheme::Ed25519 => SigningKey::from_pkcs8_pem(private_key).map_err(describe)?
+      .try_sign(message).map_err(describe)?
+      .to_vec(),
+  };
+  Ok(signature)
+}
+
+/// Checks `signature` over `message` against a PEM public key
+pub fn verify(algo: KeyAlgos, public_key: &str, scheme: SignatureScheme, message: &[u8], signature: &[u8]) -> Result<(), String> {
+  scheme.check(algo)?;
+  match scheme {
+    SignatureScheme::RsaPkcs1v15Sha256 => pkcs1v15::VerifyingKey::<Sha256>::new(RsaPublicKey::from_public_key_pem(public_key).map_err(describe)?)
+      .verify(message, &pkcs1v15::Signature::try_from(signature).map_err(describe)?),
+    SignatureScheme::RsaPssSha256 => pss::VerifyingKey::<Sha256>::new(RsaPublicKey::from_public_key_pem(public_key).map_err(describe)?)
+      .verify(message, &pss::Signature::try_from(signature).map_err(describe)?),
+    SignatureScheme::EcdsaSha256 => p256::ecdsa::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?
+      .verify(message, &p256::ecdsa::Signature::from_der(signature).map_err(describe)?),
+    SignatureScheme::EcdsaSha384 => p384::ecdsa::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?
+      .verify(message, &p384::ecdsa::Signature::from_der(signature).map_err(describe)?),
+    SignatureScheme::Ed25519 => ed25519_dalek::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?
+      .verify(message, &ed25519_dalek::Signature::from_slice(signature).map_err(describe)?),
+  }.map_err(|_| "signature does not match".to_string())
+}
+
+/// Signs a digest made with the scheme's hash, the signature is the same as signing the message
+pub fn sign_digest(algo: KeyAlgos, private_key: &str, scheme: SignatureScheme, digest: &[u8]) -> Result<Vec<u8>, String> {
+  scheme.check(algo)?;
+  scheme.check_digest(digest)?;
+  let signature = match scheme {
+    SignatureScheme::RsaPkcs1v15Sha256 => pkcs1v15::SigningKey::<Sha256>::new(RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?)
+      .sign_prehash(digest).map_err(describe)?
+      .to_vec(),
+    SignatureScheme::RsaPssSha256 => pss::BlindedSigningKey::<Sha256>::new(RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?)
+      .sign_prehash_with_rng(&mut SharedRng, digest).map_err(describe)?
+      .to_vec(),
+    SignatureScheme::EcdsaSha256 => {
+      let signature: p256::ecdsa::Signature = p256::ecdsa::SigningKey::from_pkcs8_pem(private_key).map_err(describe)?
+        .sign_prehash(digest).map_err(describe)?;
+      signature.to_der().to_vec()
+    },
+    SignatureScheme::EcdsaSha384 => {
+      let signature: p384::ecdsa::Signature = p384::ecdsa::SigningKey::from_pkcs8_pem(private_key).map_err(describe)?
+        .sign_prehash(digest).map_err(describe)?;
+      signature.to_der().to_vec()
+    },
+    SignatureScheme::Ed25519 => return Err("Ed25519 cannot sign a digest".to_string()),
+  };
+  Ok(signature)
+}
+
+/// Checks `signature` over a digest made with the scheme's hash
+pub fn verify_digest(algo: KeyAlgos, public_key: &str, scheme: SignatureScheme, digest: &[u8], signature: &[u8]) -> Result<(), String> {
+  scheme.check(algo)?;
+  scheme.check_digest(digest)?;
+  match scheme {
+    SignatureScheme::RsaPkcs1v15Sha256 => pkcs1v15::VerifyingKey::<Sha256>::new(RsaPublicKey::from_public_key_pem(public_key).map_err(describe)?)
+      .verify_prehash(digest, &pkcs1v15::Signature::try_from(signature).map_err(describe)?),
+    SignatureScheme::RsaPssSha256 => pss::VerifyingKey::<Sha256>::new(RsaPublicKey::from_public_key_pem(public_key).map_err(describe)?)
+      .verify_prehash(digest, &pss::Signature::try_from(signature).map_err(describe)?),
+    SignatureScheme::EcdsaSha256 => p256::ecdsa::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?
+      .verify_prehash(digest, &p256::ecdsa::Signature::from_der(signature).map_err(describe)?),
+    SignatureScheme::EcdsaSha384 => p384::ecdsa::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?
+      .verify_prehash(digest, &p384::ecdsa::Signature::from_der(signature).map_err(describe)?),
+    SignatureScheme::Ed25519 => return Err("Ed25519 cannot sign a digest".to_string()),
+  }.map_err(|_| "signature does not match".to_string())
+}
+
+/// Encrypts to an RSA public key, other keys can't encrypt
+pub fn encrypt(algo: KeyAlgos, public_key: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+  if algo != KeyAlgos::RSA {
+    return Err(format!("{:?} keys cannot encrypt", algo));
+  }
+  RsaPublicKey::from_public_key_pem(public_key).map_err(describe)?
+    .encrypt(&mut SharedRng, Oaep::new::<Sha256>(), plaintext)
+    .map_err(describe)
+}
+
+/// Decrypts with an RSA private key
+pub fn decrypt(algo: KeyAlgos, private_key: &str, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+  if algo != KeyAlgos::RSA {
+    return Err(format!("{:?} keys cannot decrypt", algo));
+  }
+  RsaPrivateKey::from_pkcs8_pem(private_key).map_err(describe)?
+    .decrypt(Oaep::new::<Sha256>(), ciphertext)
+    .map(Zeroizing::new)
+    .map_err(|_| "decryption failed".to_string())
+}
@@ -0,0 +1,174 @@
+//! Where a key's private half lives, signing and decryption are delegated there.
+//!
+//! Keys without an `aws_kms_url` are sealed in the database by `key_service`. A `pkcs11:` URL
+//! names an object on a PKCS#11 token, any `http(s)` URL a key in an AWS KMS compatible service.
+use async_trait::async_trait;
+use sea_orm::{entity::prelude::*, ActiveValue::Set};
+use shared::clock;
+use zeroize::Zeroizing;
+use super::{key_pair::{self, SignatureScheme}, key_service, pki_key::{self, KeyAlgos, KeyStatus, Owner}};
+
+mod aws_kms;
+mod pkcs11;
+
+pub use aws_kms::{sign_v4, AwsCredentials, AwsKmsProvider};
+pub use pkcs11::{Pkcs11Provider, Pkcs11Uri};
+
+pub const PKCS11_SCHEME: &str = "pkcs11:";
+
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+  /// The key's SubjectPublicKeyInfo PEM, as the provider holding it reports it
+  async fn public_key(&self, key: &pki_key::Model) -> Result<String, String>;
+
+  /// Signs `message`, ECDSA signatures are DER encoded
+  async fn sign(&self, key: &pki_key::Model, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, String>;
+
+  /// Signs a digest made with the scheme's hash
+  async fn sign_digest(&self, key: &pki_key::Model, scheme: SignatureScheme, digest: &[u8]) -> Result<Vec<u8>, String>;
+
+  /// Decrypts RSA-OAEP SHA-256 ciphertext
+  async fn decrypt(&self, key: &pki_key::Model, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, String>;
+}
+
+/// Keys sealed in the database, decrypted by `key_service` for each operation
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalProvider;
+
+#[async_trait]
+impl KeyProvider for LocalProvider {
+  async fn public_key(&self, key: &pki_key::Model) -> Result<String, String> {
+    key.public_key.clone().ok_or_else(|| format!("key {} has no public key", key.id))
+  }
+
+  async fn sign(&self, key: &pki_key::Model, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, String> {
+    key_pair::sign(key.algo, &key_service::open(key)?, scheme, message)
+  }
+
+  async fn sign_digest(&self, key: &pki_key::Model, scheme: SignatureScheme, digest: &[u8]) -> Result<Vec<u8>, String> {
+    key_pair::sign_digest(key.algo, &key_service::open(key)?, scheme, digest)
+  }
+
+  async fn decrypt(&self, key: &pki_key::Model, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    key_pair::decrypt(key.algo, &key_service::open(key)?, ciphertext)
+  }
+}
+
+/// Fails unless some provider understands the URL
+pub fn check_url(url: &str) -> Result<(), String> {
+  if url.starts_with(PKCS11_SCHEME) {
+    return Pkcs11Uri::parse(url).map(|_| ());
+  }
+  aws_kms::split_url(url).map(|_| ())
+}
+
+/// The provider for a key URL, set up from the configuration
+pub fn provider_for_url(url: &str) -> Result<Box<dyn KeyProvider>, String> {
+  check_url(url)?;
+  if url.starts_with(PKCS11_SCHEME) {
+    return Ok(Box::new(Pkcs11Provider::from_config()?));
+  }
+  Ok(Box::new(AwsKmsProvider::from_config()))
+}
+
+/// The provider holding the key
+pub fn provider_for(key: &pki_key::Model) -> Result<Box<dyn KeyProvider>, String> {
+  match key.aws_kms_url.as_deref() {
+    None => Ok(Box::new(LocalProvider)),
+    Some(url) => provider_for_url(url),
+  }
+}
+
+/// Only the active version of a key signs, older ones still verify and decrypt
+fn check_status(key: &pki_key::Model, sign: bool) -> Result<(), String> {
+  let usable = if sign { key.status.can_sign() } else { key.status.can_verify() };
+  match usable {
+    true => Ok(()),
+    false => Err(format!("key {} version {} is {:?}", key.id, key.version, key.status)),
+  }
+}
+
+/// Signs with the key wherever it lives
+pub async fn sign(key: &pki_key::Model, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, String> {
+  check_status(key, true)?;
+  scheme.check(key.algo)?;
+  provider_for(key)?.sign(key, scheme, message).await
+}
+
+/// Signs a digest with the key wherever it lives
+pub async fn sign_digest(key: &pki_key::Model, scheme: SignatureScheme, digest: &[u8]) -> Result<Vec<u8>, String> {
+  check_status(key, true)?;
+  scheme.check(key.algo)?;
+  scheme.check_digest(digest)?;
+  provider_for(key)?.sign_digest(key, scheme, digest).await
+}
+
+/// Decrypts with the key wherever it lives
+pub async fn decrypt(key: &pki_key::Model, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+  check_status(key, false)?;
+  provider_for(key)?.decrypt(key, ciphertext).await
+}
+
+/// The public key of the key at `url`, as its provider reports it
+async fn located_public_key(provider: &dyn KeyProvider, owner: Owner, algo: KeyAlgos, id: Uuid, url: &str) -> Result<String, DbErr> {
+  check_url(url).map_err(|err| DbErr::Custom(format!("[before_save] Invalid key url, insert: true, {}", err)))?;
+  let (user_id, organisation_id, group_id) = owner.ids();
+  let located = pki_key::Model {
+    id,
+    user_id,
+    organisation_id,
+    group_id,
+    private_key: None,
+    encrypted_data_key: None,
+    master_key_id: None,
+    public_key: None,
+    aws_kms_url: Some(url.to_string()),
+    algo,
+    key_ring_id: id,
+    version: 1,
+    status: KeyStatus::Active,
+    exportable: false,
+    created_at: clock::now(),
+    updated_at: clock::now(),
+  };
+  provider.public_key(&located).await.map_err(DbErr::Custom)
+}
+
+/// Records a key that lives in an HSM or KMS, its public key is read from there
+pub async fn register<C>(db: &C, provider: &dyn KeyProvider, owner: Owner, algo: KeyAlgos, url: &str) -> Result<pki_key::Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let (user_id, organisation_id, group_id) = owner.ids();
+  let id = Uuid::new_v4();
+  let public_key = located_public_key(provider, owner, algo, id, url).await?;
+  pki_key::ActiveModel {
+    id: Set(id),
+    key_ring_id: Set(id),
+    user_id: Set(user_id),
+    organisation_id: Set(organisation_id),
+    group_id: Set(group_id),
+    private_key: Set(None),
+    public_key: Set(Some(public_key)),
+    aws_kms_url: Set(Some(url.to_string())),
+    algo: Set(algo),
+    ..Default::default()
+  }.insert(db).await
+}
+
+/// Records the new version of a key held by a provider, once the provider has created it at `url`
+pub async fn rotate<C>(db: &C, provider: &dyn KeyProvider, key: &pki_key::Model, url: &str) -> Result<pki_key::Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let id = Uuid::new_v4();
+  let public_key = located_public_key(provider, key.owner()?, key.algo, id, url).await?;
+  pki_key::add_version(db, key, pki_key::ActiveModel {
+    id: Set(id),
+    private_key: Set(None),
+    public_key: Set(Some(public_key)),
+    aws_kms_url: Set(Some(url.to_string())),
+    algo: Set(key.algo),
+    ..Default::default()
+  }).await
+}
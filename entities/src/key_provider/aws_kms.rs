@@ -0,0 +1,223 @@
+//! Keys in AWS KMS, or anything speaking its JSON protocol such as a local KMS emulator.
+//!
+//! A key's URL is the service endpoint followed by the key id or ARN,
+//! e.g. `https://kms.eu-west-1.amazonaws.com/arn:aws:kms:eu-west-1:111122223333:key/1234abcd`.
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use pkcs8::{der::Document, LineEnding};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use shared::clock;
+use url::Url;
+use zeroize::Zeroizing;
+use crate::{config, key_pair::SignatureScheme, pki_key};
+use super::KeyProvider;
+
+/// KMS signs at most this much raw message, longer messages are sent as their digest
+const MAX_RAW_MESSAGE: usize = 4096;
+const SERVICE: &str = "kms";
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct AwsCredentials {
+  pub access_key_id: String,
+  pub secret_access_key: String,
+}
+
+impl std::fmt::Debug for AwsCredentials {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("AwsCredentials").field("access_key_id", &self.access_key_id).finish()
+  }
+}
+
+/// The endpoint and key id from a key URL, whose endpoint has to be one of `kms.aws_endpoints`
+pub(super) fn split_url(url: &str) -> Result<(Url, String), String> {
+  let parsed = Url::parse(url).map_err(|err| format!("invalid key url: {}", err))?;
+  if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+    return Err(format!("unsupported key url: {}", url));
+  }
+  // Requests are signed with the configured credentials, so they never go anywhere else
+  if !config::get().kms.allows_endpoint(&parsed) {
+    return Err(format!("key url is not on a configured kms endpoint: {}", url));
+  }
+  let key_id = parsed.path().trim_start_matches('/').to_string();
+  if key_id.is_empty() {
+    return Err(format!("key url has no key id: {}", url));
+  }
+  let mut endpoint = parsed;
+  endpoint.set_path("/");
+  Ok((endpoint, key_id))
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+  hex::encode(Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+  mac.update(data.as_bytes());
+  mac.finalize().into_bytes().to_vec()
+}
+
+/// The `Authorization` header for an AWS Signature Version 4 request. `query` must already be
+/// URI encoded, `headers` are every header to sign including `host` and `x-amz-date`.
+#[allow(clippy::too_many_arguments)]
+pub fn sign_v4(
+  credentials: &AwsCredentials,
+  region: &str,
+  service: &str,
+  method: &str,
+  path: &str,
+  query: &str,
+  headers: &[(&str, &str)],
+  body: &[u8],
+  amz_date: &str,
+) -> String {
+  let mut query_pairs: Vec<&str> = query.split('&').filter(|pair| !pair.is_empty()).collect();
+  query_pairs.sort_unstable();
+  let mut canonical_headers: Vec<(String, String)> = headers.iter()
+    .map(|(name, value)| (name.to_lowercase(), value.split_whitespace().collect::<Vec<&str>>().join(" ")))
+    .collect();
+  canonical_headers.sort();
+  let signed_headers = canonical_headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>().join(";");
+  let canonical_request = format!(
+    "{}\n{}\n{}\n{}\n{}\n{}",
+    method,
+    path,
+    query_pairs.join("&"),
+    canonical_headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect::<String>(),
+    signed_headers,
+    hex_sha256(body),
+  );
+  let date = &amz_date[..8];
+  let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+  let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex_sha256(canonical_request.as_bytes()));
+  let signing_key = [region, service, "aws4_request"].iter().fold(
+    hmac_sha256(format!("AWS4{}", credentials.secret_access_key).as_bytes(), date),
+    |key, part| hmac_sha256(&key, part),
+  );
+  format!(
+    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+    credentials.access_key_id,
+    scope,
+    signed_headers,
+    hex::encode(hmac_sha256(&signing_key, &string_to_sign)),
+  )
+}
+
+fn signing_algorithm(scheme: SignatureScheme) -> Result<&'static str, String> {
+  match scheme {
+    SignatureScheme::RsaPkcs1v15Sha256 => Ok("RSASSA_PKCS1_V1_5_SHA_256"),
+    SignatureScheme::RsaPssSha256 => Ok("RSASSA_PSS_SHA_256"),
+    SignatureScheme::EcdsaSha256 => Ok("ECDSA_SHA_256"),
+    SignatureScheme::EcdsaSha384 => Ok("ECDSA_SHA_384"),
+    SignatureScheme::Ed25519 => Err("AWS KMS keys cannot sign with Ed25519".to_string()),
+  }
+}
+
+fn decode_field(response: &Value, field: &str) -> Result<Vec<u8>, String> {
+  let encoded = response.get(field).and_then(Value::as_str).ok_or_else(|| format!("KMS response has no {}", field))?;
+  STANDARD.decode(encoded).map_err(|err| err.to_string())
+}
+
+#[derive(Clone, Debug)]
+pub struct AwsKmsProvider {
+  region: String,
+  credentials: AwsCredentials,
+  client: reqwest::Client,
+}
+
+impl AwsKmsProvider {
+  pub fn new(region: &str, credentials: AwsCredentials) -> Self {
+    Self { region: region.to_string(), credentials, client: reqwest::Client::new() }
+  }
+
+  pub fn from_config() -> Self {
+    let kms = &config::get().kms;
+    Self::new(&kms.aws_region, AwsCredentials {
+      access_key_id: kms.aws_access_key_id.clone(),
+      secret_access_key: kms.aws_secret_access_key.clone(),
+    })
+  }
+
+  /// Makes a signed `TrentService` call against the key's endpoint
+  async fn call(&self, key: &pki_key::Model, action: &str, mut body: Value) -> Result<Value, String> {
+    let url = key.aws_kms_url.as_deref().ok_or_else(|| format!("key {} is not held by KMS", key.id))?;
+    let (endpoint, key_id) = split_url(url)?;
+    body["KeyId"] = Value::String(key_id);
+    let body = serde_json::to_vec(&body).map_err(|err| err.to_string())?;
+    let host = match endpoint.port() {
+      Some(port) => format!("{}:{}", endpoint.host_str().unwrap_or_default(), port),
+      None => endpoint.host_str().unwrap_or_default().to_string(),
+    };
+    let amz_date = clock::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let target = format!("TrentService.{}", action);
+    let content_type = "application/x-amz-json-1.1";
+    let authorization = sign_v4(
+      &self.credentials,
+      &self.region,
+      SERVICE,
+      "POST",
+      "/",
+      "",
+      &[("content-type", content_type), ("host", &host), ("x-amz-date", &amz_date), ("x-amz-target", &target)],
+      &body,
+      &amz_date,
+    );
+    let response = self.client.post(endpoint)
+      .header("content-type", content_type)
+      .header("x-amz-date", amz_date)
+      .header("x-amz-target", target)
+      .header("authorization", authorization)
+      .body(body)
+      .send()
+      .await
+      .map_err(|err| format!("KMS request failed: {}", err))?;
+    let status = response.status();
+    let response: Value = response.json().await.map_err(|err| format!("invalid KMS response: {}", err))?;
+    if !status.is_success() {
+      let kind = response.get("__type").and_then(Value::as_str).unwrap_or("error");
+      let message = response.get("message").or_else(|| response.get("Message")).and_then(Value::as_str).unwrap_or_default();
+      return Err(format!("KMS {} {}: {}", action, kind, message));
+    }
+    Ok(response)
+  }
+
+  async fn sign_message(&self, key: &pki_key::Model, scheme: SignatureScheme, message_type: &str, message: &[u8]) -> Result<Vec<u8>, String> {
+    let response = self.call(key, "Sign", json!({
+      "Message": STANDARD.encode(message),
+      "MessageType": message_type,
+      "SigningAlgorithm": signing_algorithm(scheme)?,
+    })).await?;
+    decode_field(&response, "Signature")
+  }
+}
+
+#[async_trait]
+impl KeyProvider for AwsKmsProvider {
+  async fn public_key(&self, key: &pki_key::Model) -> Result<String, String> {
+    let response = self.call(key, "GetPublicKey", json!({})).await?;
+    let document = Document::try_from(decode_field(&response, "PublicKey")?).map_err(|err| err.to_string())?;
+    document.to_pem("PUBLIC KEY", LineEnding::LF).map_err(|err| err.to_string())
+  }
+
+  async fn sign(&self, key: &pki_key::Model, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, String> {
+    if message.len() > MAX_RAW_MESSAGE {
+      let digest = scheme.digest(message).ok_or_else(|| format!("{:?} cannot sign a digest", scheme))?;
+      return self.sign_digest(key, scheme, &digest).await;
+    }
+    self.sign_message(key, scheme, "RAW", message).await
+  }
+
+  async fn sign_digest(&self, key: &pki_key::Model, scheme: SignatureScheme, digest: &[u8]) -> Result<Vec<u8>, String> {
+    self.sign_message(key, scheme, "DIGEST", digest).await
+  }
+
+  async fn decrypt(&self, key: &pki_key::Model, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let response = self.call(key, "Decrypt", json!({
+      "CiphertextBlob": STANDARD.encode(ciphertext),
+      "EncryptionAlgorithm": "RSAES_OAEP_SHA_256",
+    })).await?;
+    decode_field(&response, "Plaintext").map(Zeroizing::new)
+  }
+}
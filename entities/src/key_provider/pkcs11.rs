@@ -0,0 +1,231 @@
+//! Keys on a PKCS#11 token such as an HSM or SoftHSM, named by an RFC 7512 URL,
+//! e.g. `pkcs11:token=users;object=org-signing-key`.
+use std::{collections::HashMap, sync::{Arc, Mutex, OnceLock}};
+use async_trait::async_trait;
+use cryptoki::{
+  context::{CInitializeArgs, Pkcs11},
+  error::{Error, RvError},
+  mechanism::{rsa::{PkcsMgfType, PkcsOaepParams, PkcsOaepSource, PkcsPssParams}, Mechanism, MechanismType},
+  object::{Attribute, AttributeType, ObjectClass, ObjectHandle},
+  session::{Session, UserType},
+  types::{AuthPin, Ulong},
+};
+use pkcs8::{der::{asn1::OctetString, Decode}, EncodePublicKey, LineEnding};
+use rsa::{BigUint, RsaPublicKey};
+use percent_encoding::percent_decode_str;
+use zeroize::Zeroizing;
+use crate::{config, key_pair::SignatureScheme, pki_key::{self, KeyAlgos}};
+use super::{KeyProvider, PKCS11_SCHEME};
+
+/// Each module is loaded and initialised once per process
+static CONTEXTS: OnceLock<Mutex<HashMap<String, Arc<Pkcs11>>>> = OnceLock::new();
+
+/// The DER DigestInfo header for a SHA-256 digest
+const SHA256_DIGEST_INFO: &[u8] = &[
+  0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+];
+
+fn describe(err: impl std::fmt::Display) -> String {
+  err.to_string()
+}
+
+/// The parts of a `pkcs11:` URL used to find a key, the token and its label or id
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pkcs11Uri {
+  pub token: String,
+  pub object: Option<String>,
+  pub id: Option<Vec<u8>>,
+}
+
+impl Pkcs11Uri {
+  pub fn parse(url: &str) -> Result<Self, String> {
+    let Some(path) = url.strip_prefix(PKCS11_SCHEME) else {
+      return Err(format!("not a pkcs11 url: {}", url));
+    };
+    // Query attributes like `?pin-value=` are ignored, the PIN comes from the configuration
+    let path = path.split('?').next().unwrap_or_default();
+    let (mut token, mut object, mut id) = (None, None, None);
+    for attribute in path.split(';').filter(|attribute| !attribute.is_empty()) {
+      let (name, value) = attribute.split_once('=').ok_or_else(|| format!("invalid pkcs11 attribute: {}", attribute))?;
+      let value: Vec<u8> = percent_decode_str(value).collect();
+      match name {
+        "token" => token = Some(String::from_utf8(value).map_err(describe)?),
+        "object" => object = Some(String::from_utf8(value).map_err(describe)?),
+        "id" => id = Some(value),
+        _ => {},
+      }
+    }
+    let token = token.ok_or_else(|| format!("pkcs11 url has no token: {}", url))?;
+    if object.is_none() && id.is_none() {
+      return Err(format!("pkcs11 url needs an object or id: {}", url));
+    }
+    Ok(Self { token, object, id })
+  }
+}
+
+fn context(module: &str) -> Result<Arc<Pkcs11>, String> {
+  let mut contexts = CONTEXTS.get_or_init(Default::default).lock().map_err(describe)?;
+  if let Some(context) = contexts.get(module) {
+    return Ok(context.clone());
+  }
+  let context = Pkcs11::new(module).map_err(|err| format!("unable to load {}: {}", module, err))?;
+  context.initialize(CInitializeArgs::OsThreads).map_err(describe)?;
+  let context = Arc::new(context);
+  contexts.insert(module.to_string(), context.clone());
+  Ok(context)
+}
+
+#[derive(Clone)]
+pub struct Pkcs11Provider {
+  module: String,
+  pin: String,
+}
+
+impl std::fmt::Debug for Pkcs11Provider {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Pkcs11Provider").field("module", &self.module).finish()
+  }
+}
+
+impl Pkcs11Provider {
+  pub fn new(module: &str, pin: &str) -> Self {
+    Self { module: module.to_string(), pin: pin.to_string() }
+  }
+
+  pub fn from_config() -> Result<Self, String> {
+    let kms = &config::get().kms;
+    if kms.pkcs11_module.is_empty() {
+      return Err("no PKCS#11 module is configured".to_string());
+    }
+    Ok(Self::new(&kms.pkcs11_module, &kms.pkcs11_pin))
+  }
+
+  /// A logged in session on the key's token
+  fn session(&self, uri: &Pkcs11Uri) -> Result<Session, String> {
+    let context = context(&self.module)?;
+    let slot = context.get_slots_with_token().map_err(describe)?
+      .into_iter()
+      .find(|slot| context.get_token_info(*slot).map(|info| info.label().trim() == uri.token).unwrap_or(false))
+      .ok_or_else(|| format!("no pkcs11 token labelled {}", uri.token))?;
+    let session = context.open_ro_session(slot).map_err(describe)?;
+    match session.login(UserType::User, Some(&AuthPin::new(self.pin.clone()))) {
+      // Logins are shared by every session on the token
+      Ok(()) | Err(Error::Pkcs11(RvError::UserAlreadyLoggedIn)) => Ok(session),
+      Err(err) => Err(format!("pkcs11 login failed: {}", err)),
+    }
+  }
+
+  fn find(session: &Session, uri: &Pkcs11Uri, class: ObjectClass) -> Result<ObjectHandle, String> {
+    let mut template = vec![Attribute::Class(class)];
+    if let Some(object) = &uri.object {
+      template.push(Attribute::Label(object.as_bytes().to_vec()));
+    }
+    if let Some(id) = &uri.id {
+      template.push(Attribute::Id(id.clone()));
+    }
+    session.find_objects(&template).map_err(describe)?
+      .into_iter()
+      .next()
+      .ok_or_else(|| format!("no pkcs11 object matches {:?}", uri))
+  }
+
+  fn uri(key: &pki_key::Model) -> Result<Pkcs11Uri, String> {
+    Pkcs11Uri::parse(key.aws_kms_url.as_deref().ok_or_else(|| format!("key {} is not held by pkcs11", key.id))?)
+  }
+}
+
+fn pss_params() -> PkcsPssParams {
+  PkcsPssParams { hash_alg: MechanismType::SHA256, mgf: PkcsMgfType::MGF1_SHA256, s_len: Ulong::from(32) }
+}
+
+fn attribute_bytes(attributes: &[Attribute], wanted: AttributeType) -> Result<Vec<u8>, String> {
+  attributes.iter()
+    .find_map(|attribute| match (attribute, wanted) {
+      (Attribute::Modulus(bytes), AttributeType::Modulus)
+      | (Attribute::PublicExponent(bytes), AttributeType::PublicExponent)
+      | (Attribute::EcPoint(bytes), AttributeType::EcPoint) => Some(bytes.clone()),
+      _ => None,
+    })
+    .ok_or_else(|| format!("pkcs11 key has no {:?}", wanted))
+}
+
+/// Tokens should wrap the point in an OCTET STRING, some return it bare
+fn ec_point(attributes: &[Attribute]) -> Result<Vec<u8>, String> {
+  let point = attribute_bytes(attributes, AttributeType::EcPoint)?;
+  Ok(OctetString::from_der(&point).map(|octets| octets.as_bytes().to_vec()).unwrap_or(point))
+}
+
+#[async_trait]
+impl KeyProvider for Pkcs11Provider {
+  async fn public_key(&self, key: &pki_key::Model) -> Result<String, String> {
+    let uri = Self::uri(key)?;
+    let session = self.session(&uri)?;
+    let handle = Self::find(&session, &uri, ObjectClass::PUBLIC_KEY)?;
+    let wanted = match key.algo {
+      KeyAlgos::RSA => vec![AttributeType::Modulus, AttributeType::PublicExponent],
+      _ => vec![AttributeType::EcPoint],
+    };
+    let attributes = session.get_attributes(handle, &wanted).map_err(describe)?;
+    match key.algo {
+      KeyAlgos::RSA => RsaPublicKey::new(
+        BigUint::from_bytes_be(&attribute_bytes(&attributes, AttributeType::Modulus)?),
+        BigUint::from_bytes_be(&attribute_bytes(&attributes, AttributeType::PublicExponent)?),
+      ).map_err(describe)?.to_public_key_pem(LineEnding::LF),
+      KeyAlgos::Ed25519 => {
+        let point: [u8; 32] = ec_point(&attributes)?.try_into().map_err(|_| "Ed25519 public keys are 32 bytes".to_string())?;
+        ed25519_dalek::VerifyingKey::from_bytes(&point).map_err(describe)?.to_public_key_pem(LineEnding::LF)
+      },
+      KeyAlgos::EcdsaP256 => p256::PublicKey::from_sec1_bytes(&ec_point(&attributes)?).map_err(describe)?.to_public_key_pem(LineEnding::LF),
+      KeyAlgos::EcdsaP384 => p384::PublicKey::from_sec1_bytes(&ec_point(&attributes)?).map_err(describe)?.to_public_key_pem(LineEnding::LF),
+    }.map_err(describe)
+  }
+
+  async fn sign(&self, key: &pki_key::Model, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, String> {
+    let mechanism = match scheme {
+      SignatureScheme::RsaPkcs1v15Sha256 => Mechanism::Sha256RsaPkcs,
+      SignatureScheme::RsaPssSha256 => Mechanism::Sha256RsaPkcsPss(pss_params()),
+      SignatureScheme::Ed25519 => Mechanism::Eddsa,
+      // Tokens only sign ECDSA digests
+      SignatureScheme::EcdsaSha256 | SignatureScheme::EcdsaSha384 => {
+        let digest = scheme.digest(message).ok_or_else(|| format!("{:?} cannot sign a digest", scheme))?;
+        return self.sign_digest(key, scheme, &digest).await;
+      },
+    };
+    let uri = Self::uri(key)?;
+    let session = self.session(&uri)?;
+    let handle = Self::find(&session, &uri, ObjectClass::PRIVATE_KEY)?;
+    session.sign(&mechanism, handle, message).map_err(describe)
+  }
+
+  async fn sign_digest(&self, key: &pki_key::Model, scheme: SignatureScheme, digest: &[u8]) -> Result<Vec<u8>, String> {
+    scheme.check_digest(digest)?;
+    let uri = Self::uri(key)?;
+    let session = self.session(&uri)?;
+    let handle = Self::find(&session, &uri, ObjectClass::PRIVATE_KEY)?;
+    match scheme {
+      // CKM_RSA_PKCS pads whatever it is given, so the digest goes in a DigestInfo first
+      SignatureScheme::RsaPkcs1v15Sha256 => {
+        session.sign(&Mechanism::RsaPkcs, handle, &[SHA256_DIGEST_INFO, digest].concat()).map_err(describe)
+      },
+      SignatureScheme::RsaPssSha256 => session.sign(&Mechanism::RsaPkcsPss(pss_params()), handle, digest).map_err(describe),
+      // CKM_ECDSA returns r and s back to back
+      SignatureScheme::EcdsaSha256 => {
+        let raw = session.sign(&Mechanism::Ecdsa, handle, digest).map_err(describe)?;
+        Ok(p256::ecdsa::Signature::from_slice(&raw).map_err(describe)?.to_der().as_bytes().to_vec())
+      },
+      SignatureScheme::EcdsaSha384 => {
+        let raw = session.sign(&Mechanism::Ecdsa, handle, digest).map_err(describe)?;
+        Ok(p384::ecdsa::Signature::from_slice(&raw).map_err(describe)?.to_der().as_bytes().to_vec())
+      },
+      SignatureScheme::Ed25519 => Err("Ed25519 cannot sign a digest".to_string()),
+    }
+  }
+
+  async fn decrypt(&self, key: &pki_key::Model, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let uri = Self::uri(key)?;
+    let session = self.session(&uri)?;
+    let handle = Self::find(&session, &uri, ObjectClass::PRIVATE_KEY)?;
+    let params = PkcsOaepParams::new(MechanismType::SHA256, PkcsMgfType::MGF1_SHA256, PkcsOaepSource::empty());
+    session.decrypt(&Mechanism::RsaPkcsOaep(params), handle, ciphertext).map(Zeroizing::new).map_err(describe)
+  }
+}
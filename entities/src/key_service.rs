@@ -0,0 +1,312 @@
+//! Envelope encryption of `pki_key` private keys, and the only place they are decrypted.
+//!
+//! Each private key is encrypted with its own data key under AES-256-GCM, and the data key is
+//! wrapped by a master key from the configuration. Rotating the master key only re-wraps data keys.
+//! Transit keys' material is sealed the same way.
+use std::{cell::RefCell, fmt, sync::{Arc, OnceLock}};
+use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, Condition, IntoActiveModel};
+use shared::rng::{self, SharedRng};
+use zeroize::Zeroizing;
+use super::{config, key_pair, pki_key, transit_key_version};
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+/// Names the throwaway master key tests use when none are configured
+#[cfg(any(test, feature = "test-util"))]
+const EPHEMERAL_MASTER_KEY_ID: &str = "ephemeral";
+
+thread_local! {
+  static THREAD_MASTER_KEYS: RefCell<Option<Arc<MasterKeys>>> = const { RefCell::new(None) };
+}
+
+static CONFIGURED_MASTER_KEYS: OnceLock<Arc<MasterKeys>> = OnceLock::new();
+
+/// Master key-encryption keys by id, the active one wraps new data keys
+pub struct MasterKeys {
+  active_id: String,
+  keys: Vec<(String, Zeroizing<[u8; KEY_LEN]>)>,
+}
+
+impl fmt::Debug for MasterKeys {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let ids: Vec<&str> = self.keys.iter().map(|(id, _)| id.as_str()).collect();
+    f.debug_struct("MasterKeys").field("active_id", &self.active_id).field("ids", &ids).finish()
+  }
+}
+
+impl MasterKeys {
+  /// Parses comma separated `id:base64` pairs, `active_id` defaults to the first when blank
+  pub fn parse(master_keys: &str, active_id: &str) -> Result<Self, String> {
+    let mut keys: Vec<(String, Zeroizing<[u8; KEY_LEN]>)> = Vec::new();
+    for entry in master_keys.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+      let Some((id, encoded)) = entry.split_once(':') else {
+        return Err("master keys must be written as id:base64".to_string());
+      };
+      let id = id.trim();
+      if id.is_empty() || keys.iter().any(|(existing, _)| existing == id) {
+        return Err(format!("master key ids must be unique and not blank: {}", id));
+      }
+      let decoded = Zeroizing::new(STANDARD.decode(encoded.trim()).map_err(|_| format!("master key {} is not valid base64", id))?);
+      let mut key = Zeroizing::new([0u8; KEY_LEN]);
+      if decoded.len() != KEY_LEN {
+        return Err(format!("master key {} must be {} bytes", id, KEY_LEN));
+      }
+      key.copy_from_slice(&decoded);
+      keys.push((id.to_string(), key));
+    }
+    let active_id = match (active_id.trim(), keys.first()) {
+      (_, None) => return Err("no master keys given".to_string()),
+      ("", Some((first, _))) => first.clone(),
+      (active_id, _) if keys.iter().any(|(id, _)| id == active_id) => active_id.to_string(),
+      (active_id, _) => return Err(format!("master key {} is not one of the master keys", active_id)),
+    };
+    Ok(Self { active_id, keys })
+  }
+
+  /// A single random master key, for development and tests
+  pub fn generate(id: &str) -> Self {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    rng::with_rng(|rng| rng.fill_bytes(key.as_mut()));
+    Self { active_id: id.to_string(), keys: vec![(id.to_string(), key)] }
+  }
+
+  pub fn active_id(&self) -> &str {
+    &self.active_id
+  }
+
+  fn get(&self, id: &str) -> Result<&[u8; KEY_LEN], String> {
+    self.keys.iter()
+      .find(|(key_id, _)| key_id == id)
+      .map(|(_, key)| &**key)
+      .ok_or_else(|| format!("master key {} is not configured", id))
+  }
+}
+
+/// This thread's master keys, else the configured ones
+pub fn master_keys() -> Result<Arc<MasterKeys>, String> {
+  if let Some(keys) = THREAD_MASTER_KEYS.with(|keys| keys.borrow().clone()) {
+    return Ok(keys);
+  }
+  configured_master_keys()
+}
+
+/// A generated key would leave every private key unreadable after a restart
+#[cfg(not(any(test, feature = "test-util")))]
+fn configured_master_keys() -> Result<Arc<MasterKeys>, String> {
+  let keys = &config::get().keys;
+  if keys.master_keys.is_empty() {
+    return Err("no master keys are configured, KEYS_MASTER_KEYS must be set".to_string());
+  }
+  Ok(CONFIGURED_MASTER_KEYS.get_or_init(|| {
+    Arc::new(MasterKeys::parse(&keys.master_keys, &keys.master_key_id).expect("master keys are validated with the configuration"))
+  }).clone())
+}
+
+/// Tests get a random key that lasts until the process exits when none are configured
+#[cfg(any(test, feature = "test-util"))]
+fn configured_master_keys() -> Result<Arc<MasterKeys>, String> {
+  Ok(CONFIGURED_MASTER_KEYS.get_or_init(|| {
+    let keys = &config::get().keys;
+    if keys.master_keys.is_empty() {
+      log::warn!("No master keys are configured, private keys will be unreadable after a restart");
+      return Arc::new(MasterKeys::generate(EPHEMERAL_MASTER_KEY_ID));
+    }
+    Arc::new(MasterKeys::parse(&keys.master_keys, &keys.master_key_id).expect("master keys are validated with the configuration"))
+  }).clone())
+}
+
+/// Puts the previous master keys back when dropped
+#[must_use = "the master keys are only replaced until the guard is dropped"]
+pub struct MasterKeysGuard {
+  previous: Option<Arc<MasterKeys>>,
+}
+
+impl Drop for MasterKeysGuard {
+  fn drop(&mut self) {
+    let previous = self.previous.take();
+    THREAD_MASTER_KEYS.with(|keys| *keys.borrow_mut() = previous);
+  }
+}
+
+/// Replaces the master keys for the current thread, never call this outside tests
+pub fn set_thread_master_keys(keys: MasterKeys) -> MasterKeysGuard {
+  let previous = THREAD_MASTER_KEYS.with(|current| current.borrow_mut().replace(Arc::new(keys)));
+  MasterKeysGuard { previous }
+}
+
+/// A private key is sealed when it is stored with its wrapped data key
+pub fn is_sealed(key: &pki_key::Model) -> bool {
+  key.private_key.is_some() && key.encrypted_data_key.is_some() && key.master_key_id.is_some()
+}
+
+/// The column values of a sealed private key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SealedKey {
+  pub private_key: String,
+  pub encrypted_data_key: String,
+  pub master_key_id: String,
+}
+
+/// Nonce followed by the ciphertext, `aad` ties it to the key it belongs to
+fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+  let mut nonce = [0u8; NONCE_LEN];
+  SharedRng.fill_bytes(&mut nonce);
+  let ciphertext = Aes256Gcm::new(key.into())
+    .encrypt(&Nonce::from(nonce), Payload { msg: plaintext, aad })
+    .map_err(|_| "encryption failed".to_string())?;
+  Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt(key: &[u8; KEY_LEN], sealed: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+  if sealed.len() < NONCE_LEN {
+    return Err("sealed value is too short".to_string());
+  }
+  let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+  let nonce: [u8; NONCE_LEN] = nonce.try_into().map_err(|_| "sealed value is too short".to_string())?;
+  Aes256Gcm::new(key.into())
+    .decrypt(&Nonce::from(nonce), Payload { msg: ciphertext, aad })
+    .map(Zeroizing::new)
+    .map_err(|_| "decryption failed, the wrong key or tampered data".to_string())
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+  STANDARD.decode(value).map_err(|err| err.to_string())
+}
+
+/// Encrypts a PEM private key under a new data key wrapped by the active master key
+pub fn seal(key_id: Uuid, private_key: &str) -> Result<SealedKey, String> {
+  seal_bytes(key_id, private_key.as_bytes())
+}
+
+/// Encrypts raw key material under a new data key wrapped by the active master key
+pub fn seal_bytes(key_id: Uuid, material: &[u8]) -> Result<SealedKey, String> {
+  let master_keys = master_keys()?;
+  let mut data_key = Zeroizing::new([0u8; KEY_LEN]);
+  SharedRng.fill_bytes(data_key.as_mut());
+  let ciphertext = encrypt(&data_key, material, key_id.as_bytes())?;
+  let wrapped = encrypt(master_keys.get(master_keys.active_id())?, data_key.as_ref(), key_id.as_bytes())?;
+  Ok(SealedKey {
+    private_key: STANDARD.encode(ciphertext),
+    encrypted_data_key: STANDARD.encode(wrapped),
+    master_key_id: master_keys.active_id().to_string(),
+  })
+}
+
+fn unwrap_data_key(key: &pki_key::Model, master_keys: &MasterKeys) -> Result<Zeroizing<[u8; KEY_LEN]>, String> {
+  let (Some(encrypted_data_key), Some(master_key_id)) = (&key.encrypted_data_key, &key.master_key_id) else {
+    return Err(format!("key {} has no data key", key.id));
+  };
+  unwrap_data_key_of(key.id, encrypted_data_key, master_key_id, master_keys)
+}
+
+fn unwrap_data_key_of(key_id: Uuid, encrypted_data_key: &str, master_key_id: &str, master_keys: &MasterKeys) -> Result<Zeroizing<[u8; KEY_LEN]>, String> {
+  let unwrapped = decrypt(master_keys.get(master_key_id)?, &decode(encrypted_data_key)?, key_id.as_bytes())?;
+  if unwrapped.len() != KEY_LEN {
+    return Err(format!("key {} has a malformed data key", key_id));
+  }
+  let mut data_key = Zeroizing::new([0u8; KEY_LEN]);
+  data_key.copy_from_slice(&unwrapped);
+  Ok(data_key)
+}
+
+/// The PEM private key, only ever decrypted in here
+pub(crate) fn open(key: &pki_key::Model) -> Result<Zeroizing<String>, String> {
+  let Some(private_key) = &key.private_key else {
+    return Err(format!("key {} has no private key", key.id));
+  };
+  // Keys saved before envelope encryption are refused until they are sealed
+  if !is_sealed(key) {
+    return Err(format!("key {} is stored in plaintext, seal it with `admin pki-key rewrap --seal-plaintext`", key.id));
+  }
+  let data_key = unwrap_data_key(key, &*master_keys()?)?;
+  let pem = decrypt(&data_key, &decode(private_key)?, key.id.as_bytes())?;
+  String::from_utf8(pem.to_vec()).map(Zeroizing::new).map_err(|_| format!("key {} is not valid PEM", key.id))
+}
+
+/// As `open`, for the columns of a key that isn't saved yet
+pub(crate) fn open_sealed(key_id: Uuid, sealed: &SealedKey) -> Result<Zeroizing<String>, String> {
+  let pem = open_bytes(key_id, sealed)?;
+  String::from_utf8(pem.to_vec()).map(Zeroizing::new).map_err(|_| format!("key {} is not valid PEM", key_id))
+}
+
+/// The raw key material `seal_bytes` sealed, only ever decrypted in here
+pub(crate) fn open_bytes(key_id: Uuid, sealed: &SealedKey) -> Result<Zeroizing<Vec<u8>>, String> {
+  let data_key = unwrap_data_key_of(key_id, &sealed.encrypted_data_key, &sealed.master_key_id, &*master_keys()?)?;
+  decrypt(&data_key, &decode(&sealed.private_key)?, key_id.as_bytes())
+}
+
+/// Decrypts the private key and checks it still belongs to the public key
+pub fn verify_private_key(key: &pki_key::Model) -> Result<(), String> {
+  let private_key = open(key)?;
+  let public_key = key.public_key.as_deref().ok_or_else(|| format!("key {} has no public key", key.id))?;
+  key_pair::check(key.algo, &private_key, public_key)
+}
+
+/// Re-wraps every data key not under the active master key, including transit keys', returns how
+/// many keys were updated
+pub async fn rewrap<C>(db: &C) -> Result<usize, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let master_keys = master_keys().map_err(DbErr::Custom)?;
+  let active_id = master_keys.active_id().to_string();
+  let keys = pki_key::Entity::find()
+    .filter(pki_key::Column::PrivateKey.is_not_null())
+    .filter(pki_key::Column::EncryptedDataKey.is_not_null())
+    .filter(pki_key::Column::MasterKeyId.ne(active_id.clone()))
+    .all(db)
+    .await?;
+  let count = keys.len();
+  for key in keys {
+    let data_key = unwrap_data_key(&key, &master_keys).map_err(DbErr::Custom)?;
+    let wrapped = encrypt(master_keys.get(&active_id).map_err(DbErr::Custom)?, data_key.as_ref(), key.id.as_bytes())
+      .map_err(DbErr::Custom)?;
+    let mut active_key = key.into_active_model();
+    active_key.encrypted_data_key = Set(Some(STANDARD.encode(wrapped)));
+    active_key.master_key_id = Set(Some(active_id.clone()));
+    active_key.update(db).await?;
+  }
+  let versions = transit_key_version::Entity::find()
+    .filter(transit_key_version::Column::MasterKeyId.ne(active_id.clone()))
+    .all(db)
+    .await?;
+  let count = count + versions.len();
+  for version in versions {
+    let data_key = unwrap_data_key_of(version.id, &version.encrypted_data_key, &version.master_key_id, &master_keys)
+      .map_err(DbErr::Custom)?;
+    let wrapped = encrypt(master_keys.get(&active_id).map_err(DbErr::Custom)?, data_key.as_ref(), version.id.as_bytes())
+      .map_err(DbErr::Custom)?;
+    let mut active_version = version.into_active_model();
+    active_version.encrypted_data_key = Set(STANDARD.encode(wrapped));
+    active_version.master_key_id = Set(active_id.clone());
+    active_version.update(db).await?;
+  }
+  Ok(count)
+}
+
+/// Seals private keys saved before envelope encryption, which are refused until then, returns how
+/// many keys were sealed. Only the admin tool runs this, once an operator has chosen to.
+pub async fn seal_plaintext<C>(db: &C) -> Result<usize, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let keys = pki_key::Entity::find()
+    .filter(pki_key::Column::PrivateKey.is_not_null())
+    .filter(Condition::any()
+      .add(pki_key::Column::EncryptedDataKey.is_null())
+      .add(pki_key::Column::MasterKeyId.is_null()))
+    .all(db)
+    .await?;
+  let count = keys.len();
+  for key in keys {
+    // Setting the plaintext again has `before_save` seal it
+    let private_key = key.private_key.clone();
+    let mut active_key = key.into_active_model();
+    active_key.private_key = Set(private_key);
+    active_key.update(db).await?;
+  }
+  Ok(count)
+}
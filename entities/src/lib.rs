@@ -9,8 +9,29 @@ pub mod group_access_role;
 pub mod organisation;
 pub mod organisation_access_role;
 pub mod organisation_profile;
+pub mod organisation_policy;
 pub mod users_organisations_organisations_access_roles;
 pub mod users_groups_group_access_roles;
 pub mod file;
 pub mod auth_api_key;
-pub mod pki_key;
\ No newline at end of file
+pub mod auth_token;
+pub mod pki_key;
+pub mod key_pair;
+pub mod key_service;
+pub mod key_provider;
+pub mod key_format;
+pub mod x509;
+pub mod certificate_authority;
+pub mod certificate_role;
+pub mod certificate;
+pub mod ocsp;
+pub mod ssh;
+pub mod ssh_certificate_authority;
+pub mod ssh_group_setting;
+pub mod ssh_certificate;
+pub mod transit_key;
+pub mod transit_key_version;
+pub mod invitation;
+pub mod permission;
+pub mod policy;
+pub mod config;
\ No newline at end of file
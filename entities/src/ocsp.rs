@@ -0,0 +1,127 @@
+//! An OCSP responder (RFC 6960) for the certificates a CA issued, each CA signs its own responses.
+//!
+//! Requests may be unsigned. Certificates are identified by SHA-1 or SHA-256 `CertId`s, anything
+//! that names another issuer or a serial the CA never issued is answered `unknown`.
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use shared::clock;
+use x509_cert::{
+  der::{asn1::{BitString, GeneralizedTime, OctetString}, oid::db::{rfc5912, rfc6960}, Decode, Encode},
+  Certificate,
+};
+use x509_ocsp::{
+  BasicOcspResponse, CertId, CertStatus, OcspGeneralizedTime, OcspRequest, OcspResponse, ResponderId, ResponseData,
+  RevokedInfo, SingleResponse, Version,
+};
+use super::{certificate, certificate_authority, key_provider, x509};
+
+/// More than this many certificates in one request is refused as malformed
+pub const MAX_REQUESTS: usize = 32;
+
+fn describe(err: impl std::fmt::Display) -> DbErr {
+  DbErr::Custom(err.to_string())
+}
+
+fn generalized_time(at: chrono::DateTime<chrono::Utc>) -> Result<OcspGeneralizedTime, DbErr> {
+  let since_epoch = std::time::Duration::from_secs(at.timestamp().max(0) as u64);
+  GeneralizedTime::from_unix_duration(since_epoch).map(OcspGeneralizedTime).map_err(describe)
+}
+
+/// Hashes `data` the way a `CertId` says, `None` for hashes that aren't supported
+fn hash(cert_id: &CertId, data: &[u8]) -> Option<Vec<u8>> {
+  match cert_id.hash_algorithm.oid {
+    rfc5912::ID_SHA_1 => Some(Sha1::digest(data).to_vec()),
+    rfc5912::ID_SHA_256 => Some(Sha256::digest(data).to_vec()),
+    _ => None,
+  }
+}
+
+/// Whether `cert_id` names `issuer` as the issuer
+fn names_issuer(cert_id: &CertId, issuer: &Certificate) -> Result<bool, DbErr> {
+  let name = issuer.tbs_certificate.subject.to_der().map_err(describe)?;
+  let key = issuer.tbs_certificate.subject_public_key_info.subject_public_key.raw_bytes();
+  Ok(match (hash(cert_id, &name), hash(cert_id, key)) {
+    (Some(name_hash), Some(key_hash)) => {
+      cert_id.issuer_name_hash.as_bytes() == name_hash && cert_id.issuer_key_hash.as_bytes() == key_hash
+    },
+    _ => false,
+  })
+}
+
+async fn status<C>(db: &C, authority: &certificate_authority::Model, issuer: &Certificate, cert_id: &CertId) -> Result<CertStatus, DbErr>
+where
+  C: ConnectionTrait,
+{
+  if !names_issuer(cert_id, issuer)? {
+    return Ok(CertStatus::unknown());
+  }
+  let issued = certificate::Entity::find()
+    .filter(certificate::Column::CertificateAuthorityId.eq(authority.id))
+    .filter(certificate::Column::Serial.eq(x509::serial_hex(cert_id.serial_number.as_bytes())))
+    .one(db)
+    .await?;
+  Ok(match issued {
+    None => CertStatus::unknown(),
+    Some(issued) => match issued.revoked_at {
+      None => CertStatus::good(),
+      Some(revoked_at) => CertStatus::revoked(RevokedInfo {
+        revocation_time: generalized_time(revoked_at)?,
+        revocation_reason: issued.revocation_reason.map(|reason| reason.crl_reason()),
+      }),
+    },
+  })
+}
+
+/// Answers a DER encoded OCSP request with a DER encoded response signed by `authority`
+pub async fn respond<C>(db: &C, authority: &certificate_authority::Model, request: &[u8]) -> Result<Vec<u8>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let request = match OcspRequest::from_der(request) {
+    Ok(request) if !request.tbs_request.request_list.is_empty() && request.tbs_request.request_list.len() <= MAX_REQUESTS => request,
+    _ => return OcspResponse::malformed_request().to_der().map_err(describe),
+  };
+  let (key, issuer) = certificate_authority::issuer(db, authority).await?;
+  let now = generalized_time(clock::now())?;
+  let mut responses = Vec::with_capacity(request.tbs_request.request_list.len());
+  for single in &request.tbs_request.request_list {
+    responses.push(SingleResponse {
+      cert_id: single.req_cert.clone(),
+      cert_status: status(db, authority, &issuer, &single.req_cert).await?,
+      this_update: now,
+      // Statuses come straight from the database, there's always newer information
+      next_update: None,
+      single_extensions: None,
+    });
+  }
+  // The nonce goes back unchanged so the client knows the response isn't a replay
+  let nonce = request.tbs_request.request_extensions.iter()
+    .flatten()
+    .find(|extension| extension.extn_id == rfc6960::ID_PKIX_OCSP_NONCE)
+    .cloned();
+  let key_hash = Sha1::digest(issuer.tbs_certificate.subject_public_key_info.subject_public_key.raw_bytes());
+  let tbs_response_data = ResponseData {
+    version: Version::V1,
+    responder_id: ResponderId::ByKey(OctetString::new(key_hash.to_vec()).map_err(describe)?),
+    produced_at: now,
+    responses,
+    response_extensions: nonce.map(|nonce| vec![nonce]),
+  };
+  let scheme = x509::signing_scheme(key.algo);
+  let der = tbs_response_data.to_der().map_err(describe)?;
+  let signature = key_provider::sign(&key, scheme, &der).await
+    .map_err(|err| DbErr::Custom(format!("unable to sign with key {}: {}", key.id, err)))?;
+  let response = BasicOcspResponse {
+    tbs_response_data,
+    signature_algorithm: x509::signature_algorithm(scheme).map_err(DbErr::Custom)?,
+    signature: BitString::from_bytes(&signature).map_err(describe)?,
+    certs: None,
+  };
+  OcspResponse::successful(response).map_err(describe)?.to_der().map_err(describe)
+}
+
+/// What to answer when the responder can't, e.g. because the CA's key is unreachable
+pub fn internal_error() -> Vec<u8> {
+  OcspResponse::internal_error().to_der().unwrap_or_default()
+}
@@ -1,22 +1,42 @@
-use sea_orm::{entity::prelude::*, ActiveValue::Set, ConnectionTrait};
+use sea_orm::{entity::prelude::*, ActiveValue::Set, ConnectionTrait, FromQueryResult, IntoActiveModel, Statement};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use async_trait::async_trait;
-use chrono::Utc;
+use super::organisation_access_role::OrgRolePermissions;
+use shared::{clock, OrgId, UserId};
+
+/// Guards the recursive queries against runaway hierarchies
+pub const MAX_ORGANISATION_DEPTH: i32 = 16;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "organisations", schema_name = "public")]
+#[sea_orm(table_name = "organisations")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
-  pub id: Uuid,
+  pub id: OrgId,
   pub name: String,
-  pub pki_key_id: Uuid,
+  #[sea_orm(nullable)]
+  pub parent_organisation_id: Option<OrgId>,
+  /// The organisation's own key, sub organisations without one use their nearest parent's
+  #[sea_orm(nullable)]
+  pub pki_key_id: Option<Uuid>,
+  /// Only the settings this organisation overrides, the rest are inherited from its parents
+  #[sea_orm(column_type = "JsonBinary")]
+  pub settings: Json,
   pub created_at: ChronoDateTimeUtc,
   pub updated_at: ChronoDateTimeUtc,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+  #[sea_orm(
+    belongs_to = "Entity",
+    from = "Column::ParentOrganisationId",
+    to = "Column::Id",
+    on_update = "Cascade",
+    on_delete = "Restrict"
+  )]
+  ParentOrganisation,
   #[sea_orm(has_one = "super::organisation_profile::Entity")]
   OrganisationProfile,
   #[sea_orm(has_many = "super::group::Entity")]
@@ -25,6 +45,10 @@ pub enum Relation {
   AuthApiKey,
   #[sea_orm(has_many = "super::pki_key::Entity")]
   PkiKey,
+  #[sea_orm(has_many = "super::organisation_policy::Entity")]
+  OrganisationPolicy,
+  #[sea_orm(has_many = "super::invitation::Entity")]
+  Invitation,
 }
 
 impl Related<super::organisation_profile::Entity> for Entity {
@@ -51,6 +75,18 @@ impl Related<super::pki_key::Entity> for Entity {
   }
 }
 
+impl Related<super::organisation_policy::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::OrganisationPolicy.def()
+  }
+}
+
+impl Related<super::invitation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Invitation.def()
+  }
+}
+
 impl Related<super::user::Entity> for Entity {
   fn to() -> RelationDef {
     super::users_organisations_organisations_access_roles::Relation::User.def()
@@ -69,26 +105,233 @@ impl Related<super::organisation_access_role::Entity> for Entity {
   }
 }
 
+#[derive(Debug, FromQueryResult)]
+struct OrganisationRow {
+  id: OrgId,
+}
+
+/// The organisation followed by each of its parents, nearest first
+pub async fn find_ancestor_ids<C>(db: &C, organisation_id: OrgId) -> Result<Vec<OrgId>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let ids = OrganisationRow::find_by_statement(Statement::from_sql_and_values(
+    db.get_database_backend(),
+    r#"WITH RECURSIVE ancestors (id, parent_organisation_id, depth) AS (
+      SELECT o.id, o.parent_organisation_id, 0 FROM organisations o WHERE o.id = $1
+      UNION ALL
+      SELECT o.id, o.parent_organisation_id, a.depth + 1
+      FROM ancestors a
+      JOIN organisations o ON o.id = a.parent_organisation_id
+      WHERE a.depth < $2
+    )
+    SELECT id FROM ancestors ORDER BY depth"#,
+    vec![organisation_id.into(), MAX_ORGANISATION_DEPTH.into()],
+  )).all(db).await?;
+  Ok(ids.into_iter().map(|row| row.id).collect())
+}
+
+/// The organisation and every organisation below it
+pub async fn find_descendant_ids<C>(db: &C, organisation_id: OrgId) -> Result<Vec<OrgId>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let ids = OrganisationRow::find_by_statement(Statement::from_sql_and_values(
+    db.get_database_backend(),
+    r#"WITH RECURSIVE descendants (id, depth) AS (
+      SELECT o.id, 0 FROM organisations o WHERE o.id = $1
+      UNION ALL
+      SELECT o.id, d.depth + 1
+      FROM descendants d
+      JOIN organisations o ON o.parent_organisation_id = d.id
+      WHERE d.depth < $2
+    )
+    SELECT DISTINCT id FROM descendants"#,
+    vec![organisation_id.into(), MAX_ORGANISATION_DEPTH.into()],
+  )).all(db).await?;
+  Ok(ids.into_iter().map(|row| row.id).collect())
+}
+
+/// The user's own role in the organisation. Without one, an owner or admin role held in
+/// the nearest parent organisation is delegated down to manage the child.
+pub async fn find_effective_role<C>(db: &C, user_id: UserId, organisation_id: OrgId) -> Result<Option<super::organisation_access_role::Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  for (depth, ancestor_id) in find_ancestor_ids(db, organisation_id).await?.into_iter().enumerate() {
+    let role = super::users_organisations_organisations_access_roles::find_role(db, user_id, ancestor_id).await?;
+    match role {
+      Some(role) if depth == 0 => return Ok(Some(role)),
+      Some(role) if matches!(role.org_role_permissions, OrgRolePermissions::AllowOwner | OrgRolePermissions::AllowAdmin) => {
+        return Ok(Some(role))
+      },
+      _ => {},
+    }
+  }
+  Ok(None)
+}
+
+/// Settings merged from the top of the tree down, so children override their parents
+pub async fn find_effective_settings<C>(db: &C, organisation_id: OrgId) -> Result<Map<String, Value>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let ancestor_ids = find_ancestor_ids(db, organisation_id).await?;
+  let ancestors = Entity::find()
+    .filter(Column::Id.is_in(ancestor_ids.clone()))
+    .all(db)
+    .await?;
+  let mut settings = Map::new();
+  for ancestor_id in ancestor_ids.iter().rev() {
+    if let Some(Value::Object(overrides)) = ancestors.iter().find(|org| org.id == *ancestor_id).map(|org| &org.settings) {
+      settings.extend(overrides.clone());
+    }
+  }
+  Ok(settings)
+}
+
+/// The organisation's own key, or without one the key of its nearest ancestor that has one
+pub async fn find_effective_pki_key_id<C>(db: &C, organisation_id: OrgId) -> Result<Option<Uuid>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let ancestor_ids = find_ancestor_ids(db, organisation_id).await?;
+  let ancestors = Entity::find()
+    .filter(Column::Id.is_in(ancestor_ids.clone()))
+    .all(db)
+    .await?;
+  Ok(ancestor_ids.iter()
+    .filter_map(|ancestor_id| ancestors.iter().find(|org| org.id == *ancestor_id))
+    .find_map(|org| org.pki_key_id))
+}
+
+/// Walks up from the proposed parent making sure the organisation doesn't end up as its own ancestor
+async fn check_parent<C>(db: &C, organisation_id: OrgId, parent_organisation_id: OrgId) -> Result<(), String>
+where
+  C: ConnectionTrait,
+{
+  let mut next = Some(parent_organisation_id);
+  let mut depth = 0;
+  while let Some(ancestor_id) = next {
+    if ancestor_id == organisation_id {
+      return Err("parent_organisation_id would create a cycle".to_string());
+    }
+    depth += 1;
+    if depth > MAX_ORGANISATION_DEPTH {
+      return Err(format!("organisations cannot be nested more than {} deep", MAX_ORGANISATION_DEPTH));
+    }
+    let ancestor = Entity::find_by_id(ancestor_id).one(db).await
+      .map_err(|err| err.to_string())?
+      .ok_or_else(|| format!("parent organisation {} does not exist", ancestor_id))?;
+    next = ancestor.parent_organisation_id;
+  }
+  Ok(())
+}
+
+/// An organisation may use its own key or one belonging to any of its ancestors
+async fn check_pki_key<C>(db: &C, organisation_id: OrgId, parent_organisation_id: Option<OrgId>, pki_key_id: Uuid) -> Result<(), String>
+where
+  C: ConnectionTrait,
+{
+  let key = super::pki_key::Entity::find_by_id(pki_key_id).one(db).await
+    .map_err(|err| err.to_string())?
+    .ok_or_else(|| format!("pki_key {} does not exist", pki_key_id))?;
+  let owner_id = match key.organisation_id {
+    Some(owner_id) => owner_id,
+    None => return Err("pki_key must belong to an organisation".to_string()),
+  };
+  if owner_id == organisation_id {
+    return Ok(());
+  }
+  let ancestor_ids = match parent_organisation_id {
+    Some(parent_organisation_id) => find_ancestor_ids(db, parent_organisation_id).await.map_err(|err| err.to_string())?,
+    None => vec![],
+  };
+  match ancestor_ids.contains(&owner_id) {
+    true => Ok(()),
+    false => Err("pki_key must belong to the organisation or one of its parents".to_string()),
+  }
+}
+
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
-      id: Set(Uuid::new_v4()),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      id: Set(OrgId::new()),
+      parent_organisation_id: Set(None),
+      settings: Set(Value::Object(Map::new())),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
+    }
+    if self.settings.is_set() && !self.settings.as_ref().is_object() {
+      return Err(DbErr::Custom(format!(
+        "[before_save] Settings must be an object, insert: {}",
+        insert
+      )));
+    }
+    if self.settings.is_set() {
+      if let Some(security) = self.settings.as_ref().get(super::config::SECURITY_SETTINGS_KEY) {
+        if let Err(err) = super::config::get().security.check_overrides(security) {
+          return Err(DbErr::Custom(format!(
+            "[before_save] Invalid security settings, insert: {}, {}",
+            insert, err
+          )));
+        }
+      }
+    }
+    if self.parent_organisation_id.is_set() && !self.id.is_not_set() {
+      if let Some(parent_organisation_id) = *self.parent_organisation_id.as_ref() {
+        if let Err(err) = check_parent(db, *self.id.as_ref(), parent_organisation_id).await {
+          return Err(DbErr::Custom(format!(
+            "[before_save] Invalid parent organisation, insert: {}, {}",
+            insert, err
+          )));
+        }
+      }
+    }
+    if self.pki_key_id.is_set() && !self.id.is_not_set() {
+      if let Some(pki_key_id) = *self.pki_key_id.as_ref() {
+        // Partial updates are checked against the parent the organisation is stored under
+        let parent_organisation_id = match self.parent_organisation_id.is_not_set() {
+          false => *self.parent_organisation_id.as_ref(),
+          true => Entity::find_by_id(*self.id.as_ref()).one(db).await?
+            .and_then(|stored| stored.parent_organisation_id),
+        };
+        if let Err(err) = check_pki_key(db, *self.id.as_ref(), parent_organisation_id, pki_key_id).await {
+          return Err(DbErr::Custom(format!(
+            "[before_save] Invalid pki_key, insert: {}, {}",
+            insert, err
+          )));
+        }
+      }
     }
     Ok(self)
   }
+
+  /// Gives a new top level organisation a key pair, in the algorithm its settings ask for, unless
+  /// one was assigned. Sub organisations inherit their parent's key until given their own.
+  async fn after_save<C>(model: Model, db: &C, insert: bool) -> Result<Model, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !insert || model.pki_key_id.is_some() || model.parent_organisation_id.is_some() {
+      return Ok(model);
+    }
+    let key = super::pki_key::generate(db, super::pki_key::Owner::Organisation(model.id), None).await?;
+    let mut active_organisation = model.into_active_model();
+    active_organisation.pki_key_id = Set(Some(key.id));
+    active_organisation.update(db).await
+  }
 }
\ No newline at end of file
@@ -0,0 +1,123 @@
+use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use crate::policy::{evaluate, Decision, PolicyDocument, RequestContext};
+use super::{
+  group_access_role::GroupRolePermissions,
+  organisation_access_role::OrgRolePermissions,
+};
+use shared::{clock, OrgId};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "organisation_policies")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  pub name: String,
+  #[sea_orm(nullable)]
+  pub description: Option<String>,
+  #[sea_orm(column_type = "JsonBinary")]
+  pub document: PolicyDocument,
+  pub is_enabled: bool,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+/// Whether the user's organisation role (or one delegated from a parent organisation),
+/// or their role in the requested group, grants the action.
+/// A blocked role in either denies outright.
+async fn role_allows<C>(db: &C, ctx: &RequestContext) -> Result<bool, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let org_role = super::organisation::find_effective_role(db, ctx.user_id, ctx.organisation_id).await?;
+  // Group roles only count for groups inside the organisation being checked,
+  // and are inherited from membership of any parent group
+  let group_role = match ctx.group_id {
+    Some(group_id) if ctx.group_organisation_id == Some(ctx.organisation_id) => {
+      super::group::find_effective_role(db, ctx.user_id, group_id).await?
+    },
+    _ => None,
+  };
+  let blocked = org_role.as_ref().map(|role| role.org_role_permissions == OrgRolePermissions::DeniedBlocked).unwrap_or(false)
+    || group_role.as_ref().map(|role| role.group_role_permissions == GroupRolePermissions::DeniedBlocked).unwrap_or(false);
+  if blocked {
+    return Ok(false);
+  }
+  Ok(org_role.map(|role| role.allows(&ctx.resource, &ctx.verb)).unwrap_or(false)
+    || group_role.map(|role| role.allows(&ctx.resource, &ctx.verb)).unwrap_or(false))
+}
+
+/// Evaluates the roles and enabled policies of the organisation against a request
+pub async fn decide<C>(db: &C, mut ctx: RequestContext) -> Result<Decision, DbErr>
+where
+  C: ConnectionTrait,
+{
+  if let Some(group_id) = ctx.group_id {
+    ctx.group_organisation_id = super::group::Entity::find_by_id(group_id).one(db).await?
+      .map(|group| group.organisation_id);
+  }
+  let role_allowed = role_allows(db, &ctx).await?;
+  let policies: Vec<(String, PolicyDocument)> = Entity::find()
+    .filter(Column::OrganisationId.eq(ctx.organisation_id))
+    .filter(Column::IsEnabled.eq(true))
+    .all(db)
+    .await?
+    .into_iter()
+    .map(|policy| (policy.name, policy.document))
+    .collect();
+  Ok(evaluate(&policies, role_allowed, &ctx))
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      is_enabled: Set(true),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    if self.document.is_set() {
+      if let Err(err) = self.document.as_ref().validate() {
+        return Err(DbErr::Custom(format!(
+          "[before_save] Invalid policy document, insert: {}, {}",
+          insert, err
+        )));
+      }
+    }
+    Ok(self)
+  }
+}
@@ -1,16 +1,16 @@
 use sea_orm::{ entity::prelude::*, ActiveValue::Set };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
-use chrono::Utc;
+use shared::{clock, OrgId};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "organisation_profiles", schema_name = "public")]
+#[sea_orm(table_name = "organisation_profiles")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
   #[sea_orm(unique)]
-  pub organisation_id: Uuid,
+  pub organisation_id: OrgId,
   #[sea_orm(nullable)]
   pub organisation_image_file_id: Option<Uuid>,
   // #[sea_orm(column_type = "Json")]
@@ -27,7 +27,7 @@ pub enum Relation {
   #[sea_orm(
     belongs_to = "super::organisation::Entity",
     from = "Column::OrganisationId",
-    to = "super::organisation::Column::Id"
+    to = "super::organisation::Column::Id",
     on_update = "Cascade",
     on_delete = "Cascade"
   )]
@@ -62,8 +62,8 @@ impl ActiveModelBehavior for ActiveModel {
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
@@ -74,7 +74,7 @@ impl ActiveModelBehavior for ActiveModel {
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(chrono::Utc::now());
+      self.updated_at = Set(clock::now());
     }
     Ok(self)
   }
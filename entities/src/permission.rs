@@ -0,0 +1,138 @@
+use sea_orm::{DbErr, FromJsonQueryResult};
+use serde::{Deserialize, Serialize};
+
+/// Matches any resource type or any verb
+pub const WILDCARD: &str = "*";
+
+pub const VERB_READ: &str = "read";
+pub const VERB_LIST: &str = "list";
+pub const VERB_CREATE: &str = "create";
+pub const VERB_UPDATE: &str = "update";
+pub const VERB_DELETE: &str = "delete";
+pub const VERB_MANAGE: &str = "manage";
+
+/// Whether a resource pattern (`*`, `secret.*` or `secret`) covers a resource type
+pub fn resource_matches(pattern: &str, resource: &str) -> bool {
+  match pattern.strip_suffix(WILDCARD) {
+    Some(prefix) => resource.starts_with(prefix),
+    None => pattern == resource,
+  }
+}
+
+pub fn validate_resource_pattern(pattern: &str) -> Result<(), String> {
+  if pattern.is_empty() {
+    return Err("resource cannot be blank".to_string());
+  }
+  if pattern.strip_suffix(WILDCARD).unwrap_or(pattern).contains(WILDCARD) {
+    return Err(format!("wildcard is only allowed at the end of resource '{}'", pattern));
+  }
+  Ok(())
+}
+
+pub fn verb_matches(pattern: &str, verb: &str) -> bool {
+  pattern == WILDCARD || pattern == verb
+}
+
+/// A single grant of one or more verbs on a resource type.
+///
+/// The resource can be `*` to match every resource type, or end in `*` to match
+/// everything with that prefix (e.g. `secret.*`). A verb of `*` matches every verb.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permission {
+  pub resource: String,
+  pub verbs: Vec<String>,
+}
+
+impl Permission {
+  pub fn new(resource: &str, verbs: &[&str]) -> Self {
+    Self {
+      resource: resource.to_string(),
+      verbs: verbs.iter().map(|verb| verb.to_string()).collect(),
+    }
+  }
+
+  pub fn allows(&self, resource: &str, verb: &str) -> bool {
+    resource_matches(&self.resource, resource) && self.verbs.iter().any(|allowed| verb_matches(allowed, verb))
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    validate_resource_pattern(&self.resource)?;
+    if self.verbs.is_empty() {
+      return Err(format!("permission on '{}' must have at least one verb", self.resource));
+    }
+    if self.verbs.iter().any(|verb| verb.is_empty()) {
+      return Err(format!("permission on '{}' has a blank verb", self.resource));
+    }
+    Ok(())
+  }
+}
+
+/// An explicit set of grants attached to an access role, stored as json
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct PermissionSet(pub Vec<Permission>);
+
+impl PermissionSet {
+  pub fn allows(&self, resource: &str, verb: &str) -> bool {
+    self.0.iter().any(|permission| permission.allows(resource, verb))
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    self.0.iter().try_for_each(Permission::validate)
+  }
+
+  /// Whether everything `other` grants is also granted here, wildcards included
+  pub fn covers(&self, other: &PermissionSet) -> bool {
+    other.0.iter().all(|wanted| wanted.verbs.iter().all(|verb| {
+      self.0.iter().any(|held| {
+        resource_matches(&held.resource, &wanted.resource) && held.verbs.iter().any(|allowed| verb_matches(allowed, verb))
+      })
+    }))
+  }
+
+  /// Everything, including verbs that don't exist yet
+  pub fn owner() -> Self {
+    Self(vec![Permission::new(WILDCARD, &[WILDCARD])])
+  }
+
+  /// All of the standard verbs on every resource
+  pub fn admin() -> Self {
+    Self(vec![Permission::new(
+      WILDCARD,
+      &[VERB_READ, VERB_LIST, VERB_CREATE, VERB_UPDATE, VERB_DELETE, VERB_MANAGE],
+    )])
+  }
+
+  pub fn read_write() -> Self {
+    Self(vec![Permission::new(
+      WILDCARD,
+      &[VERB_READ, VERB_LIST, VERB_CREATE, VERB_UPDATE, VERB_DELETE],
+    )])
+  }
+
+  pub fn read_only() -> Self {
+    Self(vec![Permission::new(WILDCARD, &[VERB_READ, VERB_LIST])])
+  }
+
+  pub fn none() -> Self {
+    Self(Vec::new())
+  }
+}
+
+/// Only custom roles carry their own permissions, and they must have some
+pub fn check_role_permissions(is_custom: bool, permissions: Option<&PermissionSet>, insert: bool) -> Result<(), DbErr> {
+  match permissions {
+    Some(permissions) if is_custom => permissions.validate().map_err(|err| DbErr::Custom(format!(
+      "[before_save] Invalid permissions, insert: {}, {}",
+      insert, err
+    ))),
+    None if is_custom => Err(DbErr::Custom(format!(
+      "[before_save] Custom roles must have permissions, insert: {}",
+      insert
+    ))),
+    Some(_) => Err(DbErr::Custom(format!(
+      "[before_save] Only Custom roles can have permissions, insert: {}",
+      insert
+    ))),
+    None => Ok(()),
+  }
+}
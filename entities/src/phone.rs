@@ -1,25 +1,30 @@
-use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use sea_orm::{ entity::prelude::*, sea_query::Expr, ActiveValue::Set, IntoActiveModel };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::Duration;
+use shared::{clock, otp, UserId};
+use super::config::{self, SecurityConfig};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "user_phones", schema_name = "public")]
+#[sea_orm(table_name = "user_phones")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
-  pub user_id: Option<Uuid>,
+  pub user_id: Option<UserId>,
   pub organisation_profile_id: Option<Uuid>,
   pub needs_verification: bool,
-  pub phone_country: u32,
-  pub phone_number: u32,
+  pub phone_country: i32,
+  pub phone_number: i64,
   pub is_primary: bool,
   pub is_verified: bool,
-  #[sea_orm(nullable, unique)]
+  #[serde(skip_serializing)]
+  #[sea_orm(nullable)]
   pub verification_code: Option<String>,
   #[sea_orm(nullable)]
   pub verification_code_expires_at: Option<ChronoDateTimeUtc>,
+  /// Wrong guesses against the current verification code
+  pub verification_attempts: i32,
   #[sea_orm(nullable)]
   pub verified_at: Option<ChronoDateTimeUtc>,
   pub created_at: ChronoDateTimeUtc,
@@ -60,30 +65,127 @@ impl Related<super::organisation_profile::Entity> for Entity {
   }
 }
 
+impl Model {
+  pub fn check_verification_code(&self, guess: &str, security: &SecurityConfig) -> otp::Outcome {
+    otp::check(
+      self.verification_code.as_deref(),
+      self.verification_code_expires_at,
+      self.verification_attempts,
+      security.otp_max_attempts,
+      guess,
+    )
+  }
+}
+
+/// A code to send to the phone, set it as `verification_code` to start the clock
+pub fn generate_verification_code(security: &SecurityConfig) -> String {
+  otp::numeric_code(security.otp_length)
+}
+
+/// The settings of the phone's user or organisation, the server's for neither
+async fn security_for<C>(db: &C, phone: &ActiveModel) -> Result<SecurityConfig, DbErr>
+where
+  C: ConnectionTrait,
+{
+  // Partial updates are resolved against the stored owner
+  let (user_id, organisation_profile_id) = match phone.user_id.is_not_set() || phone.organisation_profile_id.is_not_set() {
+    false => (*phone.user_id.as_ref(), *phone.organisation_profile_id.as_ref()),
+    true => match Entity::find_by_id(*phone.id.as_ref()).one(db).await? {
+      Some(stored) => (stored.user_id, stored.organisation_profile_id),
+      None => return Err(DbErr::RecordNotFound(format!("phone {}", phone.id.as_ref()))),
+    },
+  };
+  if let Some(user_id) = user_id {
+    return config::security_for_user(db, user_id).await;
+  }
+  match organisation_profile_id {
+    Some(organisation_profile_id) => {
+      let profile = super::organisation_profile::Entity::find_by_id(organisation_profile_id).one(db).await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("organisation_profile {}", organisation_profile_id)))?;
+      config::security_for_organisation(db, profile.organisation_id).await
+    },
+    None => Ok(config::get().security.clone()),
+  }
+}
+
+/// Checks a guess, verifying the phone when it matches and counting it when it doesn't.
+/// Both only apply while the code is unchanged and has attempts left, so concurrent guesses
+/// can't share an attempt or use the code twice.
+pub async fn redeem_verification_code<C>(db: &C, phone: Model, guess: &str) -> Result<(Model, otp::Outcome), DbErr>
+where
+  C: ConnectionTrait,
+{
+  let security = security_for(db, &phone.clone().into_active_model()).await?;
+  let outcome = phone.check_verification_code(guess, &security);
+  let (Some(code), otp::Outcome::Accepted | otp::Outcome::Rejected { .. }) = (phone.verification_code.clone(), outcome) else {
+    return Ok((phone, outcome));
+  };
+  let now = clock::now();
+  let update = Entity::update_many()
+    .filter(Column::Id.eq(phone.id))
+    .filter(Column::VerificationCode.eq(code))
+    .filter(Column::VerificationAttempts.lt(security.otp_max_attempts));
+  let result = match outcome {
+    otp::Outcome::Accepted => update.set(ActiveModel {
+      is_verified: Set(true),
+      verified_at: Set(Some(now)),
+      verification_code: Set(None),
+      verification_code_expires_at: Set(None),
+      updated_at: Set(now),
+      ..ActiveModelTrait::default()
+    }),
+    _ => update
+      .col_expr(Column::VerificationAttempts, Expr::col(Column::VerificationAttempts).add(1))
+      .col_expr(Column::UpdatedAt, Expr::value(now)),
+  }.exec(db).await?;
+  let updated = Entity::find_by_id(phone.id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("phone {}", phone.id)))?;
+  let attempts_left = security.otp_max_attempts - updated.verification_attempts;
+  let outcome = match (result.rows_affected, outcome) {
+    (1, otp::Outcome::Accepted) => otp::Outcome::Accepted,
+    (1, _) => otp::Outcome::Rejected { attempts_left },
+    // Another guess got there first, using the code up or its last attempt
+    _ if attempts_left <= 0 => otp::Outcome::LockedOut,
+    _ => otp::Outcome::Expired,
+  };
+  Ok((updated, outcome))
+}
+
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      created_at: Set(chrono::Utc::now()),
-      updated_at: Set(chrono::Utc::now()),
+      verification_attempts: Set(0),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(chrono::Utc::now());
+      self.updated_at = Set(clock::now());
+    }
+    // A new code gets a fresh expiry and attempt count
+    if self.verification_code.is_set() {
+      if self.verification_code.as_ref().is_some() {
+        let valid_mins = security_for(db, &self).await?.verification_code_valid_mins;
+        self.verification_code_expires_at = Set(Some(clock::now() + Duration::minutes(valid_mins)));
+        self.verification_attempts = Set(0);
+      } else {
+        self.verification_code_expires_at = Set(None);
+      }
     }
     // When a phone becomes verified, updated the verified_at date
     if self.is_verified.is_set() {
       if *self.is_verified.as_ref() {
-        self.verified_at = Set(Some(Utc::now()));
+        self.verified_at = Set(Some(clock::now()));
       } else {
         self.verified_at = Set(None);
       }
@@ -1,31 +1,85 @@
-use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use chrono::Duration;
+use sea_orm::{ entity::prelude::*, ActiveValue::{self, Set}, IntoActiveModel, QueryOrder, TransactionTrait };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
-use chrono::Utc;
-// use rand::{distributions::Alphanumeric, Rng};
+use shared::{clock, GroupId, OrgId, UserId};
+use zeroize::Zeroizing;
+use super::{
+  certificate_authority, config, group, key_format::{self, KeyFormat}, key_pair, key_provider, key_service,
+  ssh_certificate_authority,
+};
 
-#[derive(Debug, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "pki_key_algos")]
 pub enum KeyAlgos {
   #[sea_orm(string_value = "RSA")]
   RSA,
+  #[sea_orm(string_value = "Ed25519")]
+  Ed25519,
+  #[sea_orm(string_value = "EcdsaP256")]
+  EcdsaP256,
+  #[sea_orm(string_value = "EcdsaP384")]
+  EcdsaP384,
+}
+
+/// Where a version of a key is in its life. Each key has one active version, which is the only
+/// one that signs, older versions are kept to verify and decrypt until they're retired.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "pki_key_status")]
+pub enum KeyStatus {
+  #[sea_orm(string_value = "Active")]
+  Active,
+  #[sea_orm(string_value = "VerifyOnly")]
+  VerifyOnly,
+  /// Kept on record but no longer used for anything
+  #[sea_orm(string_value = "Retired")]
+  Retired,
+  /// The private key has been erased
+  #[sea_orm(string_value = "Destroyed")]
+  Destroyed,
+}
+
+impl KeyStatus {
+  pub fn can_sign(&self) -> bool {
+    *self == KeyStatus::Active
+  }
+
+  /// Verifies signatures and decrypts
+  pub fn can_verify(&self) -> bool {
+    matches!(self, KeyStatus::Active | KeyStatus::VerifyOnly)
+  }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "pki_key", schema_name = "public")]
+#[sea_orm(table_name = "pki_key")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
-  pub user_id: Option<Uuid>,
-  pub organisation_id: Option<Uuid>,
-  pub group_id: Option<Uuid>,
+  pub user_id: Option<UserId>,
+  pub organisation_id: Option<OrgId>,
+  pub group_id: Option<GroupId>,
   #[serde(skip_serializing)]
-  #[sea_orm(unique,nullable)]
+  /// Sealed by `key_service`, plaintext PEM is only ever set to have it sealed on save
+  #[sea_orm(column_type = "Text", nullable)]
   pub private_key: Option<String>,
+  /// The private key's data key, wrapped by the master key `master_key_id`
+  #[serde(skip_serializing)]
+  #[sea_orm(column_type = "Text", nullable)]
+  pub encrypted_data_key: Option<String>,
+  #[sea_orm(nullable)]
+  pub master_key_id: Option<String>,
+  #[sea_orm(column_type = "Text", nullable)]
   pub public_key: Option<String>,
+  /// Where the private key lives when it isn't sealed here, see `key_provider`
   pub aws_kms_url: Option<String>,
   pub algo: KeyAlgos,
+  /// The id of the key's first version, shared by all of its versions
+  pub key_ring_id: Uuid,
+  pub version: i32,
+  pub status: KeyStatus,
+  /// Whether the private key may ever leave, chosen when the key is created
+  pub exportable: bool,
   pub created_at: ChronoDateTimeUtc,
   pub updated_at: ChronoDateTimeUtc,
 }
@@ -49,9 +103,9 @@ pub enum Relation {
   )]
   User,
   #[sea_orm(
-    belongs_to = "super::user::Entity",
+    belongs_to = "super::group::Entity",
     from = "Column::GroupId",
-    to = "super::user::Column::Id",
+    to = "super::group::Column::Id",
     on_update = "Cascade",
     on_delete = "Cascade"
   )]
@@ -76,33 +130,544 @@ impl Related<super::group::Entity> for Entity {
   }
 }
 
+pub const DEFAULT_RSA_BITS: usize = 2048;
+
+/// Who a key belongs to, exactly one of a user, organisation or group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Owner {
+  User(UserId),
+  Organisation(OrgId),
+  Group(GroupId),
+}
+
+impl Owner {
+  /// The `user_id`, `organisation_id` and `group_id` columns
+  pub fn ids(&self) -> (Option<UserId>, Option<OrgId>, Option<GroupId>) {
+    match *self {
+      Owner::User(user_id) => (Some(user_id), None, None),
+      Owner::Organisation(organisation_id) => (None, Some(organisation_id), None),
+      Owner::Group(group_id) => (None, None, Some(group_id)),
+    }
+  }
+}
+
+impl Model {
+  pub fn owner(&self) -> Result<Owner, DbErr> {
+    match (self.user_id, self.organisation_id, self.group_id) {
+      (Some(user_id), _, _) => Ok(Owner::User(user_id)),
+      (_, Some(organisation_id), _) => Ok(Owner::Organisation(organisation_id)),
+      (_, _, Some(group_id)) => Ok(Owner::Group(group_id)),
+      _ => Err(DbErr::Custom(format!("key {} has no owner", self.id))),
+    }
+  }
+}
+
+/// Organisations and their groups may override the algorithm, RSA key size and rotation period
+async fn security_for<C>(db: &C, owner: Owner) -> Result<config::SecurityConfig, DbErr>
+where
+  C: ConnectionTrait,
+{
+  match owner {
+    Owner::User(_) => Ok(config::get().security.clone()),
+    Owner::Organisation(organisation_id) => config::security_for_organisation(db, organisation_id).await,
+    Owner::Group(group_id) => {
+      let group = group::Entity::find_by_id(group_id).one(db).await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("group {}", group_id)))?;
+      config::security_for_organisation(db, group.organisation_id).await
+    },
+  }
+}
+
+/// A key to generate, in the owner's configured algorithm when `algo` is `None`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct NewKey {
+  pub algo: Option<KeyAlgos>,
+  #[serde(default)]
+  pub exportable: bool,
+}
+
+/// A private key made elsewhere, `data` is in `format` and `passphrase` opens it if it's encrypted
+#[derive(Clone, Deserialize)]
+pub struct ImportKey {
+  pub format: KeyFormat,
+  pub data: String,
+  pub passphrase: Option<String>,
+  #[serde(default)]
+  pub exportable: bool,
+}
+
+/// Generates and stores a key pair for `owner`, in the owner's configured algorithm when `algo` is `None`
+pub async fn generate<C>(db: &C, owner: Owner, algo: Option<KeyAlgos>) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  create(db, owner, NewKey { algo, exportable: false }).await
+}
+
+pub async fn create<C>(db: &C, owner: Owner, key: NewKey) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let security = security_for(db, owner).await?;
+  let algo = key.algo.unwrap_or(security.key_algo);
+  let pair = key_pair::generate(algo, security.rsa_key_bits).map_err(DbErr::Custom)?;
+  let (user_id, organisation_id, group_id) = owner.ids();
+  ActiveModel {
+    user_id: Set(user_id),
+    organisation_id: Set(organisation_id),
+    group_id: Set(group_id),
+    private_key: Set(Some(pair.private_key)),
+    public_key: Set(Some(pair.public_key)),
+    aws_kms_url: Set(None),
+    algo: Set(algo),
+    exportable: Set(key.exportable),
+    ..Default::default()
+  }.insert(db).await
+}
+
+/// Stores an imported private key as a new key of `owner`, its public key is derived from it
+pub async fn import<C>(db: &C, owner: Owner, key: ImportKey) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let (algo, private_key) = key_format::import(key.format, &key.data, key.passphrase.as_deref())
+    .map_err(|err| DbErr::Custom(format!("[before_save] Invalid key import, insert: true, {}", err)))?;
+  let (user_id, organisation_id, group_id) = owner.ids();
+  ActiveModel {
+    user_id: Set(user_id),
+    organisation_id: Set(organisation_id),
+    group_id: Set(group_id),
+    private_key: Set(Some(private_key.to_string())),
+    aws_kms_url: Set(None),
+    algo: Set(algo),
+    exportable: Set(key.exportable),
+    ..Default::default()
+  }.insert(db).await
+}
+
+fn export_refused(err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid key export, insert: false, {}", err))
+}
+
+/// The public key in `format`
+pub fn export_public(key: &Model, format: KeyFormat) -> Result<String, DbErr> {
+  let public_key = key.public_key.as_deref().ok_or_else(|| export_refused(format!("key {} has no public key", key.id)))?;
+  key_format::export_public(format, key.algo, public_key, &key.id.to_string()).map_err(export_refused)
+}
+
+/// The private key in `format`, only for keys created exportable whose private key is still here
+pub fn export_private(key: &Model, format: KeyFormat, passphrase: Option<&str>) -> Result<Zeroizing<String>, DbErr> {
+  if !key.exportable {
+    return Err(export_refused(format!("key {} was not created exportable", key.id)));
+  }
+  if key.status == KeyStatus::Destroyed || key.aws_kms_url.is_some() {
+    return Err(export_refused(format!("key {} has no private key here", key.id)));
+  }
+  let private_key = key_service::open(key).map_err(DbErr::Custom)?;
+  let exported = key_format::export_private(format, key.algo, &private_key, passphrase, &key.id.to_string())
+    .map_err(export_refused)?;
+  log::info!("Exported private key {} version {} as {:?}", key.id, key.version, format);
+  Ok(exported)
+}
+
+/// One of the owner's keys, their current key when `key_id` is `None`. Groups have no current key
+/// so their newest active one is used, organisations without one use their nearest parent's.
+pub async fn find_for_owner<C>(db: &C, owner: Owner, key_id: Option<Uuid>) -> Result<Option<Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let key_id = match (key_id, owner) {
+    (Some(key_id), _) => Some(key_id),
+    (None, Owner::User(user_id)) => super::user::Entity::find_by_id(user_id).one(db).await?
+      .and_then(|user| user.pki_key_id),
+    // The current key may be a parent's, so it isn't filtered by owner
+    (None, Owner::Organisation(organisation_id)) => {
+      return match super::organisation::find_effective_pki_key_id(db, organisation_id).await? {
+        Some(key_id) => Entity::find_by_id(key_id).one(db).await,
+        None => Ok(None),
+      };
+    },
+    (None, Owner::Group(_)) => None,
+  };
+  let mut find = Entity::find();
+  if let Some(key_id) = key_id {
+    find = find.filter(Column::Id.eq(key_id));
+  } else if let Owner::Group(_) = owner {
+    find = find.filter(Column::Status.eq(KeyStatus::Active));
+  } else {
+    return Ok(None);
+  }
+  find
+    .filter(owner_filter(owner))
+    .order_by_desc(Column::CreatedAt)
+    .order_by_desc(Column::Version)
+    .one(db)
+    .await
+}
+
+fn owner_filter(owner: Owner) -> sea_orm::sea_query::SimpleExpr {
+  match owner {
+    Owner::User(user_id) => Column::UserId.eq(user_id),
+    Owner::Organisation(organisation_id) => Column::OrganisationId.eq(organisation_id),
+    Owner::Group(group_id) => Column::GroupId.eq(group_id),
+  }
+}
+
+/// Every version of every key the owner has, each key's newest version first
+pub async fn list_for_owner<C>(db: &C, owner: Owner) -> Result<Vec<Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  Entity::find()
+    .filter(owner_filter(owner))
+    .order_by_asc(Column::KeyRingId)
+    .order_by_desc(Column::Version)
+    .all(db)
+    .await
+}
+
+/// Every version of `key`, newest first
+pub async fn versions<C>(db: &C, key: &Model) -> Result<Vec<Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  Entity::find()
+    .filter(Column::KeyRingId.eq(key.key_ring_id))
+    .order_by_desc(Column::Version)
+    .all(db)
+    .await
+}
+
+/// The versions of `key` that still verify, the active one first
+pub async fn verification_keys<C>(db: &C, key: &Model) -> Result<Vec<Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  Ok(versions(db, key).await?.into_iter().filter(|version| version.status.can_verify()).collect())
+}
+
+fn invalid(insert: bool, err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid key version, insert: {}, {}", insert, err))
+}
+
+/// The name of a CA signing with a version of the key. Their certificates name the key, so CAs
+/// are replaced rather than having their keys rotated.
+async fn authority_using<C>(db: &C, key_ids: Vec<Uuid>) -> Result<Option<String>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let authority = certificate_authority::Entity::find()
+    .filter(certificate_authority::Column::PkiKeyId.is_in(key_ids.clone()))
+    .one(db)
+    .await?;
+  if let Some(authority) = authority {
+    return Ok(Some(authority.name));
+  }
+  Ok(ssh_certificate_authority::Entity::find()
+    .filter(ssh_certificate_authority::Column::PkiKeyId.is_in(key_ids))
+    .one(db)
+    .await?
+    .map(|authority| authority.name))
+}
+
+/// Adds `next` as the newest, active version of `key`. The versions it replaces only verify and
+/// decrypt from then on, and an owner whose current key was one of them moves to the new version.
+pub async fn add_version<C>(db: &C, key: &Model, next: ActiveModel) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let versions = versions(db, key).await?;
+  let key_ids = versions.iter().map(|version| version.id).collect::<Vec<Uuid>>();
+  if let Some(name) = authority_using(db, key_ids.clone()).await? {
+    return Err(invalid(true, format!("key {} is used by certificate authority {}, create a new authority instead", key.id, name)));
+  }
+  let owner = key.owner()?;
+  let (user_id, organisation_id, group_id) = owner.ids();
+  let latest = versions.first().map(|version| version.version).unwrap_or(key.version);
+  let next = ActiveModel {
+    user_id: Set(user_id),
+    organisation_id: Set(organisation_id),
+    group_id: Set(group_id),
+    key_ring_id: Set(key.key_ring_id),
+    version: Set(latest + 1),
+    status: Set(KeyStatus::Active),
+    ..next
+  }.insert(db).await?;
+  for version in versions.into_iter().filter(|version| version.status == KeyStatus::Active) {
+    let mut active_version = version.into_active_model();
+    active_version.status = Set(KeyStatus::VerifyOnly);
+    active_version.update(db).await?;
+  }
+  match owner {
+    Owner::User(user_id) => {
+      let user = super::user::Entity::find_by_id(user_id).one(db).await?
+        .filter(|user| user.pki_key_id.map(|id| key_ids.contains(&id)).unwrap_or(false));
+      if let Some(user) = user {
+        let mut active_user = user.into_active_model();
+        active_user.pki_key_id = Set(Some(next.id));
+        active_user.update(db).await?;
+      }
+    },
+    Owner::Organisation(organisation_id) => {
+      let organisation = super::organisation::Entity::find_by_id(organisation_id).one(db).await?
+        .filter(|organisation| organisation.pki_key_id.map(|id| key_ids.contains(&id)).unwrap_or(false));
+      if let Some(organisation) = organisation {
+        let mut active_organisation = organisation.into_active_model();
+        active_organisation.pki_key_id = Set(Some(next.id));
+        active_organisation.update(db).await?;
+      }
+    },
+    Owner::Group(_) => {},
+  }
+  Ok(next)
+}
+
+/// Generates a new version of `key` in the same algorithm. Keys held by a provider are rotated
+/// there and registered with `key_provider::rotate`.
+pub async fn rotate<C>(db: &C, key: &Model) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  if key.aws_kms_url.is_some() {
+    return Err(invalid(true, format!("key {} is held by a provider, register its new version with the admin cli instead", key.id)));
+  }
+  let security = security_for(db, key.owner()?).await?;
+  let pair = key_pair::generate(key.algo, security.rsa_key_bits).map_err(DbErr::Custom)?;
+  add_version(db, key, ActiveModel {
+    private_key: Set(Some(pair.private_key)),
+    public_key: Set(Some(pair.public_key)),
+    aws_kms_url: Set(None),
+    algo: Set(key.algo),
+    exportable: Set(key.exportable),
+    ..Default::default()
+  }).await
+}
+
+/// Moves an older version between verify-only and retired, or destroys its private key for good.
+/// The active version only changes by rotating.
+pub async fn set_status<C>(db: &C, key: &Model, status: KeyStatus) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  match (key.status, status) {
+    (current, next) if current == next => return Ok(key.clone()),
+    (KeyStatus::Active, _) | (_, KeyStatus::Active) => {
+      return Err(invalid(false, "the active version only changes by rotating".to_string()));
+    },
+    (KeyStatus::Destroyed, _) => return Err(invalid(false, format!("key {} has been destroyed", key.id))),
+    _ => {},
+  }
+  let mut active_key = key.clone().into_active_model();
+  active_key.status = Set(status);
+  if status == KeyStatus::Destroyed {
+    active_key.private_key = Set(None);
+    active_key.encrypted_data_key = Set(None);
+    active_key.master_key_id = Set(None);
+  }
+  active_key.update(db).await
+}
+
+/// Rotates the active organisation and group keys that are older than their organisation's
+/// `key_rotation_days`, returns how many were rotated. Keys held by a provider or used by a CA
+/// are left alone.
+pub async fn rotate_due<C>(db: &C) -> Result<usize, DbErr>
+where
+  C: ConnectionTrait + TransactionTrait,
+{
+  let mut count = 0;
+  for organisation in super::organisation::Entity::find().all(db).await? {
+    let rotation_days = config::security_for_organisation(db, organisation.id).await?.key_rotation_days;
+    if rotation_days <= 0 {
+      continue;
+    }
+    let group_ids = group::Entity::find()
+      .filter(group::Column::OrganisationId.eq(organisation.id))
+      .all(db)
+      .await?
+      .into_iter()
+      .map(|group| group.id)
+      .collect::<Vec<GroupId>>();
+    let keys = Entity::find()
+      .filter(sea_orm::Condition::any()
+        .add(Column::OrganisationId.eq(organisation.id))
+        .add(Column::GroupId.is_in(group_ids)))
+      .filter(Column::Status.eq(KeyStatus::Active))
+      .filter(Column::AwsKmsUrl.is_null())
+      .filter(Column::CreatedAt.lt(clock::now() - Duration::days(rotation_days)))
+      .all(db)
+      .await?;
+    for key in keys {
+      if authority_using(db, vec![key.id]).await?.is_none() {
+        let txn = db.begin().await?;
+        rotate(&txn, &key).await?;
+        txn.commit().await?;
+        count += 1;
+      }
+    }
+  }
+  Ok(count)
+}
+
+/// Not set at all or set to nothing
+fn is_blank<T>(value: &ActiveValue<Option<T>>) -> bool
+where
+  Option<T>: Into<sea_orm::Value>,
+{
+  value.is_not_set() || value.as_ref().is_none()
+}
+
+impl ActiveModel {
+  /// The private key being saved when it still has to be sealed, which is any new value that
+  /// doesn't come with its data key
+  async fn plaintext_private_key<C>(&self, db: &C, insert: bool) -> Result<Option<String>, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !self.private_key.is_set() || self.encrypted_data_key.is_set() {
+      return Ok(None);
+    }
+    let Some(private_key) = self.private_key.as_ref().clone() else {
+      return Ok(None);
+    };
+    // Saving the stored sealed value again leaves it alone
+    if !insert {
+      let stored = Entity::find_by_id(*self.id.as_ref()).one(db).await?;
+      if stored.is_some_and(|stored| key_service::is_sealed(&stored) && stored.private_key.as_ref() == Some(&private_key)) {
+        return Ok(None);
+      }
+    }
+    Ok(Some(private_key.trim().to_string()))
+  }
+
+  /// Opens a sealed private key, whether being saved or already stored, to check the public key
+  /// and algorithm against it. Plaintext private keys are checked as they're sealed.
+  async fn check_sealed_pair<C>(&self, db: &C, insert: bool) -> Result<(), DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    let stored = match insert {
+      true => None,
+      false => Entity::find_by_id(*self.id.as_ref()).one(db).await?,
+    };
+    // The value being saved, else the stored one
+    fn current<T, F>(value: &ActiveValue<T>, stored: Option<&Model>, field: F) -> Option<T>
+    where
+      T: Into<sea_orm::Value> + Clone,
+      F: FnOnce(&Model) -> T,
+    {
+      match value.is_not_set() {
+        false => Some(value.as_ref().clone()),
+        true => stored.map(field),
+      }
+    }
+    let stored = stored.as_ref();
+    let private_key = current(&self.private_key, stored, |key| key.private_key.clone()).flatten();
+    let encrypted_data_key = current(&self.encrypted_data_key, stored, |key| key.encrypted_data_key.clone()).flatten();
+    let master_key_id = current(&self.master_key_id, stored, |key| key.master_key_id.clone()).flatten();
+    let (Some(private_key), Some(encrypted_data_key), Some(master_key_id)) = (private_key, encrypted_data_key, master_key_id) else {
+      return Ok(());
+    };
+    let public_key = current(&self.public_key, stored, |key| key.public_key.clone()).flatten();
+    let (Some(algo), Some(public_key)) = (current(&self.algo, stored, |key| key.algo), public_key) else {
+      return Ok(());
+    };
+    let sealed = key_service::SealedKey { private_key, encrypted_data_key, master_key_id };
+    key_service::open_sealed(*self.id.as_ref(), &sealed)
+      .and_then(|pem| key_pair::check(algo, &pem, &public_key))
+      .map_err(|err| DbErr::Custom(format!("[before_save] Invalid key pair, insert: {}, {}", insert, err)))
+  }
+}
+
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
+    let id = Uuid::new_v4();
     Self {
-      id: Set(Uuid::new_v4()),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      id: Set(id),
+      key_ring_id: Set(id),
+      version: Set(1),
+      status: Set(KeyStatus::Active),
+      exportable: Set(false),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
-    if self.user_id.as_ref().is_none() 
-      && self.organisation_id.as_ref().is_none() 
-      && self.group_id.as_ref().is_none() {
+    // Partial updates that leave the owner alone aren't checked
+    let owner_changed = self.user_id.is_set() || self.organisation_id.is_set() || self.group_id.is_set();
+    if (insert || owner_changed)
+      && is_blank(&self.user_id)
+      && is_blank(&self.organisation_id)
+      && is_blank(&self.group_id) {
       return Err(DbErr::Custom(format!(
         "[before_save] All of user_id, organisation_id and group_id cannot be blank, insert: {}",
         insert
       )));
     }
+    if !insert && self.exportable.is_set() {
+      return Err(DbErr::Custom(
+        "[before_save] Invalid key, insert: false, whether a key is exportable is only chosen when it's created".to_string(),
+      ));
+    }
+    // Keys held by a provider never have their private key here
+    if self.aws_kms_url.is_set() {
+      if let Some(url) = self.aws_kms_url.as_ref() {
+        let has_private_key = !self.private_key.is_not_set() && self.private_key.as_ref().is_some();
+        let result = if has_private_key {
+          Err("keys held by a provider cannot have a private key".to_string())
+        } else {
+          key_provider::check_url(url)
+        };
+        if let Err(err) = result {
+          return Err(DbErr::Custom(format!(
+            "[before_save] Invalid key url, insert: {}, {}",
+            insert, err
+          )));
+        }
+      }
+    }
+    let plaintext = self.plaintext_private_key(db, insert).await?;
+    // A public key or algorithm saved next to an already sealed private key has to match it
+    if plaintext.is_none() && (self.public_key.is_set() || self.algo.is_set()) {
+      self.check_sealed_pair(db, insert).await?;
+    }
+    // A missing public key is derived, a mismatched one rejected, then the private key is sealed
+    if let Some(private_key) = plaintext {
+      if self.algo.is_not_set() {
+        return Err(DbErr::Custom(format!(
+          "[before_save] Invalid key pair, insert: {}, private keys are saved along with their algorithm",
+          insert
+        )));
+      }
+      let algo = *self.algo.as_ref();
+      let public_key = if self.public_key.is_not_set() { None } else { self.public_key.as_ref().clone() };
+      let result = match public_key {
+        Some(public_key) => key_pair::check(algo, &private_key, &public_key),
+        None => key_pair::public_key_pem(algo, &private_key).map(|public_key| {
+          self.public_key = Set(Some(public_key));
+        }),
+      }.and_then(|_| key_service::seal(*self.id.as_ref(), &private_key));
+      match result {
+        Ok(sealed) => {
+          self.private_key = Set(Some(sealed.private_key));
+          self.encrypted_data_key = Set(Some(sealed.encrypted_data_key));
+          self.master_key_id = Set(Some(sealed.master_key_id));
+        },
+        Err(err) => return Err(DbErr::Custom(format!(
+          "[before_save] Invalid key pair, insert: {}, {}",
+          insert, err
+        ))),
+      }
+    }
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
     }
     Ok(self)
   }
@@ -0,0 +1,244 @@
+use std::net::IpAddr;
+use chrono::{Datelike, Duration, FixedOffset, NaiveTime, Weekday};
+use ipnet::IpNet;
+use sea_orm::{prelude::ChronoDateTimeUtc, FromJsonQueryResult};
+use serde::{Deserialize, Serialize};
+use crate::permission::{resource_matches, validate_resource_pattern, verb_matches};
+use shared::{clock, GroupId, OrgId, UserId};
+
+pub const POLICY_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+  /// When a matching allow statement exists, at least one of them must have its conditions met
+  Allow,
+  /// A matching deny statement with its conditions met always denies
+  Deny,
+}
+
+/// Time of day window in a fixed UTC offset, e.g. business hours.
+/// If `after` is later than `before` the window wraps past midnight.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+  pub after: String,
+  pub before: String,
+  #[serde(default)]
+  pub days: Vec<Weekday>,
+  #[serde(default)]
+  pub utc_offset_mins: i32,
+}
+
+impl TimeWindow {
+  fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| format!("invalid time '{}', expected HH:MM", value))
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    Self::parse_time(&self.after)?;
+    Self::parse_time(&self.before)?;
+    FixedOffset::east_opt(self.utc_offset_mins * 60)
+      .map(|_| ())
+      .ok_or_else(|| format!("invalid utc_offset_mins {}", self.utc_offset_mins))
+  }
+
+  pub fn contains(&self, at: &ChronoDateTimeUtc) -> bool {
+    let (Ok(after), Ok(before)) = (Self::parse_time(&self.after), Self::parse_time(&self.before)) else {
+      return false;
+    };
+    let local = at.naive_utc() + Duration::minutes(self.utc_offset_mins as i64);
+    if !self.days.is_empty() && !self.days.contains(&local.weekday()) {
+      return false;
+    }
+    let time = local.time();
+    if after <= before {
+      time >= after && time < before
+    } else {
+      time >= after || time < before
+    }
+  }
+}
+
+/// Every condition that is present must hold for the statement to apply
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conditions {
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub source_ips: Vec<IpNet>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub time: Option<TimeWindow>,
+  /// Refused by `validate` until a request can show MFA was used, api keys carry no second factor
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub mfa: Option<bool>,
+  /// The group being accessed must (or must not) belong to the policy's organisation
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub group_owned_by_organisation: Option<bool>,
+}
+
+impl Conditions {
+  /// Returns the names of the conditions that were not met
+  pub fn failed(&self, ctx: &RequestContext) -> Vec<String> {
+    let mut failed = Vec::new();
+    if !self.source_ips.is_empty() {
+      let in_range = ctx.source_ip
+        .map(|ip| self.source_ips.iter().any(|net| net.contains(&ip)))
+        .unwrap_or(false);
+      if !in_range {
+        failed.push("source_ips".to_string());
+      }
+    }
+    if let Some(time) = &self.time {
+      if !time.contains(&ctx.at) {
+        failed.push("time".to_string());
+      }
+    }
+    if let Some(mfa) = self.mfa {
+      if ctx.mfa != mfa {
+        failed.push("mfa".to_string());
+      }
+    }
+    if let Some(owned) = self.group_owned_by_organisation {
+      let is_owned = ctx.group_organisation_id.is_some() && ctx.group_organisation_id == Some(ctx.organisation_id);
+      if is_owned != owned {
+        failed.push("group_owned_by_organisation".to_string());
+      }
+    }
+    failed
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Statement {
+  pub id: String,
+  pub effect: Effect,
+  pub resources: Vec<String>,
+  pub verbs: Vec<String>,
+  #[serde(default)]
+  pub conditions: Conditions,
+}
+
+impl Statement {
+  pub fn matches(&self, resource: &str, verb: &str) -> bool {
+    self.resources.iter().any(|pattern| resource_matches(pattern, resource))
+      && self.verbs.iter().any(|pattern| verb_matches(pattern, verb))
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    if self.id.is_empty() {
+      return Err("statement id cannot be blank".to_string());
+    }
+    if self.resources.is_empty() || self.verbs.is_empty() {
+      return Err(format!("statement '{}' must have at least one resource and verb", self.id));
+    }
+    self.resources.iter().try_for_each(|resource| validate_resource_pattern(resource))
+      .map_err(|err| format!("statement '{}': {}", self.id, err))?;
+    if let Some(time) = &self.conditions.time {
+      time.validate().map_err(|err| format!("statement '{}': {}", self.id, err))?;
+    }
+    if self.conditions.mfa.is_some() {
+      return Err(format!("statement '{}': mfa conditions are not supported yet, no request can show MFA was used", self.id));
+    }
+    Ok(())
+  }
+}
+
+/// A declarative policy document stored per organisation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct PolicyDocument {
+  pub version: u32,
+  pub statements: Vec<Statement>,
+}
+
+impl PolicyDocument {
+  pub fn parse(document: &str) -> Result<Self, String> {
+    let document: Self = serde_json::from_str(document).map_err(|err| err.to_string())?;
+    document.validate()?;
+    Ok(document)
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    if self.version != POLICY_VERSION {
+      return Err(format!("unsupported policy version {}", self.version));
+    }
+    self.statements.iter().try_for_each(Statement::validate)?;
+    let mut ids: Vec<&str> = self.statements.iter().map(|statement| statement.id.as_str()).collect();
+    ids.sort_unstable();
+    match ids.windows(2).find(|pair| pair[0] == pair[1]) {
+      Some(pair) => Err(format!("duplicate statement id '{}'", pair[0])),
+      None => Ok(()),
+    }
+  }
+}
+
+/// The attributes of a single access request that policies are evaluated against
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestContext {
+  pub user_id: UserId,
+  pub organisation_id: OrgId,
+  pub resource: String,
+  pub verb: String,
+  #[serde(default)]
+  pub group_id: Option<GroupId>,
+  #[serde(default)]
+  pub source_ip: Option<IpAddr>,
+  #[serde(default = "clock::now")]
+  pub at: ChronoDateTimeUtc,
+  #[serde(default)]
+  pub mfa: bool,
+  /// Looked up from `group_id` rather than supplied by the caller
+  #[serde(skip)]
+  pub group_organisation_id: Option<OrgId>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatementTrace {
+  pub policy: String,
+  pub statement: String,
+  pub effect: Effect,
+  pub conditions_met: bool,
+  pub failed_conditions: Vec<String>,
+}
+
+/// The outcome of an access check along with why it was reached
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+  pub allowed: bool,
+  pub role_allowed: bool,
+  pub reason: String,
+  pub statements: Vec<StatementTrace>,
+}
+
+/// Combines the role decision with every statement matching the request
+pub fn evaluate(policies: &[(String, PolicyDocument)], role_allowed: bool, ctx: &RequestContext) -> Decision {
+  let statements: Vec<StatementTrace> = policies.iter()
+    .flat_map(|(name, document)| document.statements.iter().map(move |statement| (name, statement)))
+    .filter(|(_, statement)| statement.matches(&ctx.resource, &ctx.verb))
+    .map(|(name, statement)| {
+      let failed_conditions = statement.conditions.failed(ctx);
+      StatementTrace {
+        policy: name.clone(),
+        statement: statement.id.clone(),
+        effect: statement.effect,
+        conditions_met: failed_conditions.is_empty(),
+        failed_conditions,
+      }
+    })
+    .collect();
+
+  let (allowed, reason) = if !role_allowed {
+    (false, format!("no role grants '{}' on '{}'", ctx.verb, ctx.resource))
+  } else if let Some(deny) = statements.iter().find(|trace| trace.effect == Effect::Deny && trace.conditions_met) {
+    (false, format!("denied by statement '{}' of policy '{}'", deny.statement, deny.policy))
+  } else {
+    let mut allows = statements.iter().filter(|trace| trace.effect == Effect::Allow).peekable();
+    if allows.peek().is_none() {
+      (true, "allowed by role".to_string())
+    } else if let Some(allow) = allows.clone().find(|trace| trace.conditions_met) {
+      (true, format!("allowed by statement '{}' of policy '{}'", allow.statement, allow.policy))
+    } else {
+      let unmet: Vec<String> = allows.map(|trace| format!("{}/{}", trace.policy, trace.statement)).collect();
+      (false, format!("conditions not met for {}", unmet.join(", ")))
+    }
+  };
+
+  Decision { allowed, role_allowed, reason, statements }
+}
@@ -0,0 +1,177 @@
+//! OpenSSH user certificates (PROTOCOL.certkeys) for the public keys users bring.
+//!
+//! Public keys and certificates are in the one line `authorized_keys` format `ssh-keygen` uses.
+//! The CA's key is a `pki_key`, so the certificate is built once to learn what to sign and again
+//! with the signature `key_provider` made.
+use std::{cell::RefCell, collections::BTreeMap};
+use chrono::{DateTime, Utc};
+use pkcs8::DecodePublicKey;
+use rand::RngCore;
+use shared::rng::SharedRng;
+use ssh_key::{
+  certificate::{Builder, CertType},
+  public::{EcdsaPublicKey, Ed25519PublicKey, KeyData, RsaPublicKey},
+  Algorithm, Certificate, HashAlg, PublicKey, Signature,
+};
+use super::{key_pair, pki_key::KeyAlgos};
+
+/// Bytes of randomness in a certificate nonce
+const NONCE_LEN: usize = 32;
+
+fn describe(err: impl std::fmt::Display) -> String {
+  err.to_string()
+}
+
+/// What a user certificate says, everything but the CA's signature
+#[derive(Clone, Debug)]
+pub struct CertificateTemplate {
+  pub nonce: Vec<u8>,
+  pub public_key: PublicKey,
+  pub serial: u64,
+  /// Logged by sshd when the certificate is used
+  pub key_id: String,
+  pub principals: Vec<String>,
+  pub valid_after: DateTime<Utc>,
+  pub valid_before: DateTime<Utc>,
+  pub critical_options: BTreeMap<String, String>,
+  pub extensions: BTreeMap<String, String>,
+}
+
+pub fn random_nonce() -> Vec<u8> {
+  let mut nonce = vec![0u8; NONCE_LEN];
+  SharedRng.fill_bytes(&mut nonce);
+  nonce
+}
+
+/// A random serial that also fits a signed 64 bit column
+pub fn random_serial() -> u64 {
+  SharedRng.next_u64() >> 1
+}
+
+/// Parses a user's public key, DSA and RSA keys below the minimum size are refused
+pub fn parse_public_key(public_key: &str) -> Result<PublicKey, String> {
+  let public_key = PublicKey::from_openssh(public_key.trim()).map_err(|err| format!("invalid SSH public key: {}", err))?;
+  match public_key.key_data() {
+    KeyData::Rsa(rsa) => {
+      let bits = rsa.n.as_positive_bytes().map(|n| n.len() * 8).unwrap_or_default();
+      if bits < key_pair::MIN_RSA_BITS {
+        return Err(format!("RSA keys must have at least {} bits", key_pair::MIN_RSA_BITS));
+      }
+    },
+    KeyData::Ed25519(_) | KeyData::Ecdsa(_) | KeyData::SkEd25519(_) | KeyData::SkEcdsaSha2NistP256(_) => {},
+    _ => return Err(format!("{} keys are not supported", public_key.algorithm())),
+  }
+  Ok(public_key)
+}
+
+/// `SHA256:...` as `ssh-keygen -l` prints it
+pub fn fingerprint(public_key: &PublicKey) -> String {
+  public_key.fingerprint(HashAlg::Sha256).to_string()
+}
+
+/// The SSH form of a `pki_key`'s PEM public key
+pub fn public_key(algo: KeyAlgos, public_key: &str) -> Result<PublicKey, String> {
+  let key_data = match algo {
+    KeyAlgos::RSA => KeyData::Rsa(
+      RsaPublicKey::try_from(rsa::RsaPublicKey::from_public_key_pem(public_key).map_err(describe)?).map_err(describe)?,
+    ),
+    KeyAlgos::Ed25519 => KeyData::Ed25519(Ed25519PublicKey::from(
+      ed25519_dalek::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?,
+    )),
+    KeyAlgos::EcdsaP256 => KeyData::Ecdsa(EcdsaPublicKey::from(
+      p256::ecdsa::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?,
+    )),
+    KeyAlgos::EcdsaP384 => KeyData::Ecdsa(EcdsaPublicKey::from(
+      p384::ecdsa::VerifyingKey::from_public_key_pem(public_key).map_err(describe)?,
+    )),
+  };
+  Ok(PublicKey::new(key_data, ""))
+}
+
+/// Turns a signature made with `x509::signing_scheme(algo)` into its SSH encoding, RSA is `rsa-sha2-256`
+fn ssh_signature(algo: KeyAlgos, signature: &[u8]) -> Result<Signature, String> {
+  match algo {
+    KeyAlgos::RSA => Signature::new(Algorithm::Rsa { hash: Some(HashAlg::Sha256) }, signature).map_err(describe),
+    KeyAlgos::Ed25519 => Signature::new(Algorithm::Ed25519, signature).map_err(describe),
+    KeyAlgos::EcdsaP256 => Signature::try_from(p256::ecdsa::Signature::from_der(signature).map_err(describe)?).map_err(describe),
+    KeyAlgos::EcdsaP384 => Signature::try_from(p384::ecdsa::Signature::from_der(signature).map_err(describe)?).map_err(describe),
+  }
+}
+
+fn builder(template: &CertificateTemplate) -> Result<Builder, String> {
+  let valid_after = template.valid_after.timestamp().max(0) as u64;
+  let valid_before = template.valid_before.timestamp().max(0) as u64;
+  let mut builder = Builder::new(template.nonce.clone(), template.public_key.key_data().clone(), valid_after, valid_before)
+    .map_err(describe)?;
+  builder.serial(template.serial).map_err(describe)?;
+  builder.cert_type(CertType::User).map_err(describe)?;
+  builder.key_id(template.key_id.clone()).map_err(describe)?;
+  for principal in &template.principals {
+    builder.valid_principal(principal.clone()).map_err(describe)?;
+  }
+  for (name, data) in &template.critical_options {
+    builder.critical_option(name.clone(), data.clone()).map_err(describe)?;
+  }
+  for (name, data) in &template.extensions {
+    builder.extension(name.clone(), data.clone()).map_err(describe)?;
+  }
+  Ok(builder)
+}
+
+/// Hands the builder the signature made elsewhere, after remembering what it was asked to sign
+struct DeferredSigner {
+  key: KeyData,
+  signature: Option<Signature>,
+  signed: RefCell<Option<Vec<u8>>>,
+}
+
+impl signature::Signer<Signature> for DeferredSigner {
+  fn try_sign(&self, message: &[u8]) -> Result<Signature, signature::Error> {
+    let mut signed = self.signed.borrow_mut();
+    match (&self.signature, signed.as_deref()) {
+      (Some(signature), Some(expected)) if expected == message => Ok(signature.clone()),
+      (None, _) => {
+        *signed = Some(message.to_vec());
+        Err(signature::Error::new())
+      },
+      _ => Err(signature::Error::new()),
+    }
+  }
+}
+
+impl From<&DeferredSigner> for KeyData {
+  fn from(signer: &DeferredSigner) -> Self {
+    signer.key.clone()
+  }
+}
+
+/// The bytes the CA's key signs for `template`
+pub fn tbs_certificate(template: &CertificateTemplate, ca_key: &PublicKey) -> Result<Vec<u8>, String> {
+  let signer = DeferredSigner { key: ca_key.key_data().clone(), signature: None, signed: RefCell::new(None) };
+  let result = builder(template)?.sign(&signer);
+  match signer.signed.into_inner() {
+    Some(tbs) => Ok(tbs),
+    None => Err(result.err().map(describe).unwrap_or_else(|| "certificate was not signed".to_string())),
+  }
+}
+
+/// Puts the CA's signature over `tbs` on the certificate and checks it
+pub fn assemble(template: &CertificateTemplate, ca_key: &PublicKey, algo: KeyAlgos, tbs: Vec<u8>, signature: &[u8]) -> Result<String, String> {
+  let signer = DeferredSigner {
+    key: ca_key.key_data().clone(),
+    signature: Some(ssh_signature(algo, signature)?),
+    signed: RefCell::new(Some(tbs)),
+  };
+  let certificate = builder(template)?.sign(&signer).map_err(describe)?;
+  verify_issued_by(&certificate, ca_key)?;
+  certificate.to_openssh().map_err(describe)
+}
+
+pub fn parse_certificate(certificate: &str) -> Result<Certificate, String> {
+  Certificate::from_openssh(certificate.trim()).map_err(|err| format!("invalid SSH certificate: {}", err))
+}
+
+/// Checks `certificate` was signed by `ca_key`, whether or not it's currently valid
+pub fn verify_issued_by(certificate: &Certificate, ca_key: &PublicKey) -> Result<(), String> {
+  certificate.validate_at(certificate.valid_after(), [&ca_key.fingerprint(HashAlg::Sha256)]).map_err(describe)
+}
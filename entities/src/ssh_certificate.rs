@@ -0,0 +1,192 @@
+use sea_orm::{ entity::prelude::*, ActiveValue::Set, FromJsonQueryResult };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use chrono::Duration;
+use shared::{clock, GroupId, OrgId, UserId};
+use super::{
+  group,
+  group_access_role::GroupRolePermissions,
+  ssh,
+  ssh_certificate_authority,
+  ssh_group_setting,
+  users_groups_group_access_roles,
+};
+
+/// How far back certificates are dated, for servers whose clocks run a little behind
+pub const CLOCK_SKEW_MINUTES: i64 = 5;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct Principals(pub Vec<String>);
+
+/// What is kept about every SSH certificate signed, for auditing who could log in where
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "ssh_certificates")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  pub ssh_certificate_authority_id: Uuid,
+  pub user_id: UserId,
+  pub group_id: GroupId,
+  pub serial: i64,
+  pub key_id: String,
+  #[sea_orm(column_type = "JsonBinary")]
+  pub principals: Principals,
+  /// Of the user's public key, `SHA256:...`
+  pub fingerprint: String,
+  pub valid_after: ChronoDateTimeUtc,
+  pub valid_before: ChronoDateTimeUtc,
+  #[sea_orm(column_type = "Text")]
+  pub certificate: String,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "super::ssh_certificate_authority::Entity",
+    from = "Column::SshCertificateAuthorityId",
+    to = "super::ssh_certificate_authority::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  SshCertificateAuthority,
+  #[sea_orm(
+    belongs_to = "super::user::Entity",
+    from = "Column::UserId",
+    to = "super::user::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  User,
+  #[sea_orm(
+    belongs_to = "super::group::Entity",
+    from = "Column::GroupId",
+    to = "super::group::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Group,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::ssh_certificate_authority::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::SshCertificateAuthority.def()
+  }
+}
+
+impl Related<super::user::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::User.def()
+  }
+}
+
+impl Related<super::group::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Group.def()
+  }
+}
+
+/// A user's public key to certify for logging in as a group's principals
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SignRequest {
+  /// `authorized_keys` format, e.g. `ssh-ed25519 AAAA... me@laptop`
+  pub public_key: String,
+  pub ttl_minutes: Option<i64>,
+}
+
+fn invalid(err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid SSH certificate request, insert: true, {}", err))
+}
+
+/// Signs `user_id`'s key with the CA the group uses, as long as they're a member who isn't denied
+pub async fn sign<C>(db: &C, user_id: UserId, group: &group::Model, request: SignRequest) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let setting = ssh_group_setting::Entity::find()
+    .filter(ssh_group_setting::Column::GroupId.eq(group.id))
+    .one(db)
+    .await?
+    .ok_or_else(|| invalid(format!("group {} does not issue SSH certificates", group.name)))?;
+  let member = match users_groups_group_access_roles::find_role(db, user_id, group.id).await? {
+    Some(role) => !matches!(role.group_role_permissions, GroupRolePermissions::Denied | GroupRolePermissions::DeniedBlocked),
+    None => false,
+  };
+  if !member {
+    return Err(invalid(format!("user {} is not a member of group {}", user_id, group.name)));
+  }
+  let authority = ssh_certificate_authority::Entity::find_by_id(setting.ssh_certificate_authority_id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("SSH certificate authority {}", setting.ssh_certificate_authority_id)))?;
+
+  let template = &setting.template;
+  let public_key = ssh::parse_public_key(&request.public_key).map_err(invalid)?;
+  let ttl = template.ttl(request.ttl_minutes).map_err(invalid)?;
+  let now = clock::now();
+  let certificate_template = ssh::CertificateTemplate {
+    nonce: ssh::random_nonce(),
+    serial: ssh::random_serial(),
+    key_id: format!("user={} group={}", user_id, group.id),
+    principals: template.principals(&group.name).map_err(invalid)?,
+    valid_after: now - Duration::minutes(CLOCK_SKEW_MINUTES),
+    valid_before: now + Duration::minutes(ttl),
+    critical_options: template.critical_options.clone(),
+    extensions: template.extensions.clone(),
+    public_key,
+  };
+  let certificate = ssh_certificate_authority::sign(db, &authority, &certificate_template).await?;
+  ActiveModel {
+    organisation_id: Set(group.organisation_id),
+    ssh_certificate_authority_id: Set(authority.id),
+    user_id: Set(user_id),
+    group_id: Set(group.id),
+    serial: Set(certificate_template.serial as i64),
+    key_id: Set(certificate_template.key_id.clone()),
+    principals: Set(Principals(certificate_template.principals.clone())),
+    fingerprint: Set(ssh::fingerprint(&certificate_template.public_key)),
+    valid_after: Set(certificate_template.valid_after),
+    valid_before: Set(certificate_template.valid_before),
+    certificate: Set(certificate),
+    ..Default::default()
+  }.insert(db).await
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
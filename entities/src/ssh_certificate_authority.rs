@@ -0,0 +1,150 @@
+use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use shared::{clock, OrgId};
+use super::{key_provider, pki_key::{self, KeyAlgos, Owner}, ssh, x509};
+
+/// An organisation's SSH user CA, its key is a `pki_key` owned by the organisation
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "ssh_certificate_authorities")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  pub pki_key_id: Uuid,
+  pub name: String,
+  /// OpenSSH format, what servers list in `TrustedUserCAKeys`
+  #[sea_orm(column_type = "Text")]
+  pub public_key: String,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "super::pki_key::Entity",
+    from = "Column::PkiKeyId",
+    to = "super::pki_key::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  PkiKey,
+  #[sea_orm(has_many = "super::ssh_group_setting::Entity")]
+  SshGroupSetting,
+  #[sea_orm(has_many = "super::ssh_certificate::Entity")]
+  SshCertificate,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::pki_key::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::PkiKey.def()
+  }
+}
+
+impl Related<super::ssh_group_setting::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::SshGroupSetting.def()
+  }
+}
+
+impl Related<super::ssh_certificate::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::SshCertificate.def()
+  }
+}
+
+/// An SSH CA to create, its key is generated unless an existing organisation key is named
+#[derive(Clone, Debug, Deserialize)]
+pub struct NewSshAuthority {
+  pub name: String,
+  pub key_id: Option<Uuid>,
+  pub algo: Option<KeyAlgos>,
+}
+
+fn invalid(err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid SSH certificate authority, insert: true, {}", err))
+}
+
+pub async fn create<C>(db: &C, organisation_id: OrgId, new: NewSshAuthority) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let key = match new.key_id {
+    Some(key_id) => pki_key::find_for_owner(db, Owner::Organisation(organisation_id), Some(key_id)).await?
+      .ok_or_else(|| invalid(format!("key {} does not belong to the organisation", key_id)))
+      .and_then(|key| match key.status.can_sign() {
+        true => Ok(key),
+        false => Err(invalid(format!("key {} is not an active version", key_id))),
+      })?,
+    None => pki_key::generate(db, Owner::Organisation(organisation_id), new.algo).await?,
+  };
+  let public_key = key.public_key.as_deref().ok_or_else(|| invalid(format!("key {} has no public key", key.id)))?;
+  let public_key = ssh::public_key(key.algo, public_key).map_err(invalid)?;
+  ActiveModel {
+    organisation_id: Set(organisation_id),
+    pki_key_id: Set(key.id),
+    name: Set(new.name),
+    public_key: Set(public_key.to_openssh().map_err(|err| DbErr::Custom(err.to_string()))?),
+    ..Default::default()
+  }.insert(db).await
+}
+
+/// Signs a user certificate with the CA's key, wherever that lives
+pub async fn sign<C>(db: &C, authority: &Model, template: &ssh::CertificateTemplate) -> Result<String, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let key = pki_key::Entity::find_by_id(authority.pki_key_id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("pki_key {}", authority.pki_key_id)))?;
+  let ca_key = ssh_key::PublicKey::from_openssh(&authority.public_key).map_err(|err| DbErr::Custom(err.to_string()))?;
+  let tbs = ssh::tbs_certificate(template, &ca_key).map_err(invalid)?;
+  let signature = key_provider::sign(&key, x509::signing_scheme(key.algo), &tbs).await
+    .map_err(|err| DbErr::Custom(format!("unable to sign with key {}: {}", key.id, err)))?;
+  ssh::assemble(template, &ca_key, key.algo, tbs, &signature).map_err(DbErr::Custom)
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if self.name.is_set() && self.name.as_ref().trim().is_empty() {
+      return Err(DbErr::Custom(format!(
+        "[before_save] SSH certificate authority name cannot be blank, insert: {}",
+        insert
+      )));
+    }
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
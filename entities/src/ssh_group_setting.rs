@@ -0,0 +1,252 @@
+use std::{collections::BTreeMap, net::IpAddr};
+use ipnet::IpNet;
+use sea_orm::{ entity::prelude::*, ActiveValue::Set, FromJsonQueryResult };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use shared::{clock, GroupId, OrgId};
+
+/// Certificates are short lived, no group may ask for more than a day
+pub const MAX_TTL_MINUTES: i64 = 24 * 60;
+
+/// The critical options OpenSSH understands, anything else must be `name@domain`
+const CRITICAL_OPTIONS: [&str; 3] = ["force-command", "source-address", "verify-required"];
+/// The extensions OpenSSH understands, all of them flags without data
+const EXTENSIONS: [&str; 6] = [
+  "no-touch-required",
+  "permit-X11-forwarding",
+  "permit-agent-forwarding",
+  "permit-port-forwarding",
+  "permit-pty",
+  "permit-user-rc",
+];
+
+/// What the SSH certificates of a group's members say, beyond who they are
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+#[serde(default)]
+pub struct SshTemplate {
+  /// The names members may log in as, the group's name when empty
+  pub principals: Vec<String>,
+  /// e.g. `force-command` or `source-address`, servers refuse certificates with options they don't know
+  pub critical_options: BTreeMap<String, String>,
+  /// e.g. `permit-pty` or `permit-port-forwarding`
+  pub extensions: BTreeMap<String, String>,
+  /// Validity when a request doesn't ask for one
+  pub ttl_minutes: i64,
+  pub max_ttl_minutes: i64,
+}
+
+impl Default for SshTemplate {
+  fn default() -> Self {
+    Self {
+      principals: Vec::new(),
+      critical_options: BTreeMap::new(),
+      extensions: BTreeMap::from([("permit-pty".to_string(), String::new())]),
+      ttl_minutes: 60,
+      max_ttl_minutes: 8 * 60,
+    }
+  }
+}
+
+impl SshTemplate {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.ttl_minutes <= 0 || self.max_ttl_minutes <= 0 {
+      return Err("ttl_minutes and max_ttl_minutes must be positive".to_string());
+    }
+    if self.ttl_minutes > self.max_ttl_minutes {
+      return Err("ttl_minutes cannot exceed max_ttl_minutes".to_string());
+    }
+    if self.max_ttl_minutes > MAX_TTL_MINUTES {
+      return Err(format!("max_ttl_minutes cannot exceed {}", MAX_TTL_MINUTES));
+    }
+    if let Some(principal) = self.principals.iter().find(|principal| !valid_principal(principal)) {
+      return Err(format!("invalid principal {:?}", principal));
+    }
+    for (name, data) in &self.critical_options {
+      match name.as_str() {
+        "force-command" if data.trim().is_empty() => return Err("force-command needs a command".to_string()),
+        "source-address" => validate_source_address(data)?,
+        "verify-required" if !data.is_empty() => return Err("verify-required takes no data".to_string()),
+        name if !CRITICAL_OPTIONS.contains(&name) && !custom_name(name) => {
+          return Err(format!("unknown critical option {}", name));
+        },
+        _ => {},
+      }
+    }
+    for (name, data) in &self.extensions {
+      if EXTENSIONS.contains(&name.as_str()) {
+        if !data.is_empty() {
+          return Err(format!("{} takes no data", name));
+        }
+      } else if !custom_name(name) {
+        return Err(format!("unknown extension {}", name));
+      }
+    }
+    Ok(())
+  }
+
+  /// Who a member of `group` may log in as
+  pub fn principals(&self, group_name: &str) -> Result<Vec<String>, String> {
+    if !self.principals.is_empty() {
+      return Ok(self.principals.clone());
+    }
+    if !valid_principal(group_name) {
+      return Err(format!("group name {:?} is not a valid principal, set principals explicitly", group_name));
+    }
+    Ok(vec![group_name.to_string()])
+  }
+
+  /// The requested validity in minutes, or the group's default, as long as it's within the maximum
+  pub fn ttl(&self, requested: Option<i64>) -> Result<i64, String> {
+    match requested {
+      Some(minutes) if minutes <= 0 => Err("ttl_minutes must be positive".to_string()),
+      Some(minutes) if minutes > self.max_ttl_minutes => Err(format!("ttl_minutes cannot exceed {}", self.max_ttl_minutes)),
+      Some(minutes) => Ok(minutes),
+      None => Ok(self.ttl_minutes),
+    }
+  }
+}
+
+/// Principals end up in comma separated lists and `AuthorizedPrincipalsFile`s
+fn valid_principal(principal: &str) -> bool {
+  !principal.is_empty()
+    && principal.len() <= 255
+    && principal.chars().all(|c| c.is_ascii_graphic() && c != ',')
+}
+
+/// Vendor options are named like `name@example.com`
+fn custom_name(name: &str) -> bool {
+  matches!(name.split_once('@'), Some((name, domain)) if !name.is_empty() && !domain.is_empty())
+}
+
+/// A comma separated list of addresses and CIDR ranges
+fn validate_source_address(data: &str) -> Result<(), String> {
+  if data.trim().is_empty() {
+    return Err("source-address needs at least one address".to_string());
+  }
+  match data.split(',').map(str::trim).find(|address| address.parse::<IpNet>().is_err() && address.parse::<IpAddr>().is_err()) {
+    Some(address) => Err(format!("invalid source-address {}", address)),
+    None => Ok(()),
+  }
+}
+
+/// How a group's members get SSH certificates, and from which CA
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "ssh_group_settings")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  #[sea_orm(unique)]
+  pub group_id: GroupId,
+  pub ssh_certificate_authority_id: Uuid,
+  #[sea_orm(column_type = "JsonBinary")]
+  pub template: SshTemplate,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "super::group::Entity",
+    from = "Column::GroupId",
+    to = "super::group::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Group,
+  #[sea_orm(
+    belongs_to = "super::ssh_certificate_authority::Entity",
+    from = "Column::SshCertificateAuthorityId",
+    to = "super::ssh_certificate_authority::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  SshCertificateAuthority,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::group::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Group.def()
+  }
+}
+
+impl Related<super::ssh_certificate_authority::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::SshCertificateAuthority.def()
+  }
+}
+
+/// Creates or replaces a group's settings, the CA must belong to the group's organisation
+pub async fn put<C>(db: &C, group: &super::group::Model, authority_id: Uuid, template: SshTemplate) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let authority = super::ssh_certificate_authority::Entity::find_by_id(authority_id)
+    .filter(super::ssh_certificate_authority::Column::OrganisationId.eq(group.organisation_id))
+    .one(db)
+    .await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("SSH certificate authority {}", authority_id)))?;
+  match Entity::find().filter(Column::GroupId.eq(group.id)).one(db).await? {
+    Some(existing) => {
+      let mut setting: ActiveModel = existing.into();
+      setting.ssh_certificate_authority_id = Set(authority.id);
+      setting.template = Set(template);
+      setting.update(db).await
+    },
+    None => ActiveModel {
+      organisation_id: Set(group.organisation_id),
+      group_id: Set(group.id),
+      ssh_certificate_authority_id: Set(authority.id),
+      template: Set(template),
+      ..Default::default()
+    }.insert(db).await,
+  }
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if self.template.is_set() {
+      if let Err(err) = self.template.as_ref().validate() {
+        return Err(DbErr::Custom(format!(
+          "[before_save] Invalid SSH group settings, insert: {}, {}",
+          insert, err
+        )));
+      }
+    }
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
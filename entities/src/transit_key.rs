@@ -0,0 +1,325 @@
+//! Named encryption keys that organisations and groups encrypt with without ever seeing them.
+//!
+//! Ciphertexts are `vault:v<version>:<base64 nonce and ciphertext>`, so each names the version
+//! that encrypted it. Rotating adds a version that new ciphertexts use, older versions still
+//! decrypt until `min_decryption_version` is raised past them.
+use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::ChaCha20Poly1305;
+use rand::RngCore;
+use sea_orm::{ entity::prelude::*, ActiveValue::Set, IntoActiveModel, QueryOrder };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use shared::{clock, rng::SharedRng, Error, GroupId, OrgId};
+use zeroize::Zeroizing;
+use super::{key_service::KEY_LEN, transit_key_version};
+
+const CIPHERTEXT_PREFIX: &str = "vault:v";
+const NONCE_LEN: usize = 12;
+/// The sizes of data keys that can be asked for, in bits
+pub const DATA_KEY_BITS: [usize; 3] = [128, 256, 512];
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "transit_key_algos")]
+pub enum TransitKeyAlgos {
+  #[default]
+  #[sea_orm(string_value = "Aes256Gcm")]
+  Aes256Gcm,
+  #[sea_orm(string_value = "ChaCha20Poly1305")]
+  ChaCha20Poly1305,
+}
+
+/// An organisation's key, or one of its groups' when `group_id` is set. Names are unique per owner.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "transit_keys")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub organisation_id: OrgId,
+  pub group_id: Option<GroupId>,
+  pub name: String,
+  pub algo: TransitKeyAlgos,
+  /// The version new ciphertexts are encrypted with
+  pub latest_version: i32,
+  /// Ciphertexts from older versions are refused
+  pub min_decryption_version: i32,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::organisation::Entity",
+    from = "Column::OrganisationId",
+    to = "super::organisation::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Organisation,
+  #[sea_orm(
+    belongs_to = "super::group::Entity",
+    from = "Column::GroupId",
+    to = "super::group::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  Group,
+  #[sea_orm(has_many = "super::transit_key_version::Entity")]
+  TransitKeyVersion,
+}
+
+impl Related<super::organisation::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Organisation.def()
+  }
+}
+
+impl Related<super::group::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Group.def()
+  }
+}
+
+impl Related<super::transit_key_version::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::TransitKeyVersion.def()
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NewTransitKey {
+  pub name: String,
+  #[serde(default)]
+  pub algo: TransitKeyAlgos,
+}
+
+fn invalid(insert: bool, err: String) -> DbErr {
+  DbErr::Custom(format!("[before_save] Invalid transit key, insert: {}, {}", insert, err))
+}
+
+fn invalid_ciphertext(err: String) -> Error {
+  Error::InvalidCiphertext(err)
+}
+
+fn owner_filter(organisation_id: OrgId, group_id: Option<GroupId>) -> sea_orm::Condition {
+  let condition = sea_orm::Condition::all().add(Column::OrganisationId.eq(organisation_id));
+  match group_id {
+    Some(group_id) => condition.add(Column::GroupId.eq(group_id)),
+    None => condition.add(Column::GroupId.is_null()),
+  }
+}
+
+/// The organisation's key called `name`, or its group's when `group_id` is set
+pub async fn find<C>(db: &C, organisation_id: OrgId, group_id: Option<GroupId>, name: &str) -> Result<Option<Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  Entity::find()
+    .filter(owner_filter(organisation_id, group_id))
+    .filter(Column::Name.eq(name))
+    .one(db)
+    .await
+}
+
+pub async fn list<C>(db: &C, organisation_id: OrgId, group_id: Option<GroupId>) -> Result<Vec<Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  Entity::find()
+    .filter(owner_filter(organisation_id, group_id))
+    .order_by_asc(Column::Name)
+    .all(db)
+    .await
+}
+
+/// Creates the key with its first version, run it in a transaction
+pub async fn create<C>(db: &C, organisation_id: OrgId, group_id: Option<GroupId>, new: NewTransitKey) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  if find(db, organisation_id, group_id, &new.name).await?.is_some() {
+    return Err(invalid(true, format!("there is already a key called {}", new.name)));
+  }
+  let key = ActiveModel {
+    organisation_id: Set(organisation_id),
+    group_id: Set(group_id),
+    name: Set(new.name),
+    algo: Set(new.algo),
+    ..Default::default()
+  }.insert(db).await?;
+  transit_key_version::generate(db, key.id, key.latest_version).await?;
+  Ok(key)
+}
+
+/// Adds a version that encrypts from then on, run it in a transaction
+pub async fn rotate<C>(db: &C, key: &Model) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let key = Entity::find_by_id(key.id).one(db).await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("transit key {}", key.id)))?;
+  let version = transit_key_version::generate(db, key.id, key.latest_version + 1).await?;
+  let mut active_key = key.into_active_model();
+  active_key.latest_version = Set(version.version);
+  active_key.update(db).await
+}
+
+/// Refuses ciphertexts older than `version` from then on, lowering it lets them decrypt again
+pub async fn set_min_decryption_version<C>(db: &C, key: &Model, version: i32) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let mut active_key = key.clone().into_active_model();
+  active_key.min_decryption_version = Set(version);
+  active_key.update(db).await
+}
+
+async fn find_version<C>(db: &C, key: &Model, version: i32) -> Result<transit_key_version::Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  transit_key_version::Entity::find()
+    .filter(transit_key_version::Column::TransitKeyId.eq(key.id))
+    .filter(transit_key_version::Column::Version.eq(version))
+    .one(db)
+    .await?
+    .ok_or_else(|| DbErr::RecordNotFound(format!("transit key {} version {}", key.id, version)))
+}
+
+fn open(version: &transit_key_version::Model) -> Result<Zeroizing<[u8; KEY_LEN]>, DbErr> {
+  version.open().map_err(DbErr::Custom)
+}
+
+fn seal(algo: TransitKeyAlgos, material: &[u8; KEY_LEN], plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, DbErr> {
+  let mut nonce = [0u8; NONCE_LEN];
+  SharedRng.fill_bytes(&mut nonce);
+  let payload = Payload { msg: plaintext, aad: associated_data };
+  let ciphertext = match algo {
+    TransitKeyAlgos::Aes256Gcm => Aes256Gcm::new(material.into()).encrypt(&nonce.into(), payload),
+    TransitKeyAlgos::ChaCha20Poly1305 => ChaCha20Poly1305::new(material.into()).encrypt(&nonce.into(), payload),
+  }.map_err(|_| DbErr::Custom("encryption failed".to_string()))?;
+  Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn unseal(algo: TransitKeyAlgos, material: &[u8; KEY_LEN], sealed: &[u8], associated_data: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+  if sealed.len() < NONCE_LEN {
+    return Err(invalid_ciphertext("the ciphertext is too short".to_string()));
+  }
+  let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+  let nonce: [u8; NONCE_LEN] = nonce.try_into().map_err(|_| invalid_ciphertext("the ciphertext is too short".to_string()))?;
+  let payload = Payload { msg: ciphertext, aad: associated_data };
+  match algo {
+    TransitKeyAlgos::Aes256Gcm => Aes256Gcm::new(material.into()).decrypt(&nonce.into(), payload),
+    TransitKeyAlgos::ChaCha20Poly1305 => ChaCha20Poly1305::new(material.into()).decrypt(&nonce.into(), payload),
+  }
+    .map(Zeroizing::new)
+    .map_err(|_| invalid_ciphertext("decryption failed, the wrong key, associated data or tampered data".to_string()))
+}
+
+/// The version a ciphertext names and its sealed bytes
+fn parse(ciphertext: &str) -> Result<(i32, Vec<u8>), Error> {
+  let (version, encoded) = ciphertext.strip_prefix(CIPHERTEXT_PREFIX)
+    .and_then(|rest| rest.split_once(':'))
+    .ok_or_else(|| invalid_ciphertext(format!("ciphertexts start with {}<version>:", CIPHERTEXT_PREFIX)))?;
+  let version = version.parse::<i32>().ok()
+    .filter(|version| *version > 0)
+    .ok_or_else(|| invalid_ciphertext(format!("{} is not a key version", version)))?;
+  let sealed = STANDARD.decode(encoded).map_err(|_| invalid_ciphertext("the ciphertext is not base64".to_string()))?;
+  Ok((version, sealed))
+}
+
+/// Encrypts with the latest version, `associated_data` must be given again to decrypt
+pub async fn encrypt<C>(db: &C, key: &Model, plaintext: &[u8], associated_data: &[u8]) -> Result<String, Error>
+where
+  C: ConnectionTrait,
+{
+  let version = find_version(db, key, key.latest_version).await?;
+  let material = open(&version)?;
+  let sealed = seal(key.algo, &material, plaintext, associated_data)?;
+  Ok(format!("{}{}:{}", CIPHERTEXT_PREFIX, version.version, STANDARD.encode(sealed)))
+}
+
+/// Decrypts with the version the ciphertext names, unless it's below `min_decryption_version`
+pub async fn decrypt<C>(db: &C, key: &Model, ciphertext: &str, associated_data: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error>
+where
+  C: ConnectionTrait,
+{
+  let (version, sealed) = parse(ciphertext)?;
+  if version < key.min_decryption_version {
+    return Err(invalid_ciphertext(format!(
+      "version {} of key {} is below its minimum decryption version {}, rewrap ciphertexts before raising it",
+      version, key.name, key.min_decryption_version
+    )));
+  }
+  if version > key.latest_version {
+    return Err(invalid_ciphertext(format!("key {} has no version {}", key.name, version)));
+  }
+  let version = find_version(db, key, version).await?;
+  let material = open(&version)?;
+  unseal(key.algo, &material, &sealed, associated_data)
+}
+
+/// Decrypts and encrypts again with the latest version, without the plaintext leaving
+pub async fn rewrap<C>(db: &C, key: &Model, ciphertext: &str, associated_data: &[u8]) -> Result<String, Error>
+where
+  C: ConnectionTrait,
+{
+  let plaintext = decrypt(db, key, ciphertext, associated_data).await?;
+  encrypt(db, key, &plaintext, associated_data).await
+}
+
+/// A random key of `bits` for the caller to encrypt with themselves, and it encrypted with the
+/// latest version to store alongside their data
+pub async fn data_key<C>(db: &C, key: &Model, bits: usize, associated_data: &[u8]) -> Result<(Zeroizing<Vec<u8>>, String), Error>
+where
+  C: ConnectionTrait,
+{
+  if !DATA_KEY_BITS.contains(&bits) {
+    return Err(Error::Validation(format!("data keys are one of {:?} bits", DATA_KEY_BITS)));
+  }
+  let mut plaintext = Zeroizing::new(vec![0u8; bits / 8]);
+  SharedRng.fill_bytes(&mut plaintext);
+  let ciphertext = encrypt(db, key, &plaintext, associated_data).await?;
+  Ok((plaintext, ciphertext))
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      latest_version: Set(1),
+      min_decryption_version: Set(1),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    // Names go in URLs
+    if self.name.is_set() {
+      let name = self.name.as_ref();
+      if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return Err(invalid(insert, "names are letters, digits, '-', '_' and '.'".to_string()));
+      }
+    }
+    if self.min_decryption_version.is_set() || self.latest_version.is_set() {
+      let (min, latest) = (*self.min_decryption_version.as_ref(), *self.latest_version.as_ref());
+      if min < 1 || min > latest {
+        return Err(invalid(insert, format!("the minimum decryption version must be between 1 and the latest version {}", latest)));
+      }
+    }
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
@@ -0,0 +1,106 @@
+use sea_orm::{ entity::prelude::*, ActiveValue::Set };
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use rand::RngCore;
+use shared::{clock, rng::SharedRng};
+use zeroize::Zeroizing;
+use super::key_service::{self, SealedKey, KEY_LEN};
+
+/// One version of a transit key, its material sealed by `key_service` like a private key
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "transit_key_versions")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  #[serde(skip_deserializing)]
+  pub id: Uuid,
+  pub transit_key_id: Uuid,
+  pub version: i32,
+  #[serde(skip_serializing)]
+  #[sea_orm(column_type = "Text")]
+  pub key_material: String,
+  #[serde(skip_serializing)]
+  #[sea_orm(column_type = "Text")]
+  pub encrypted_data_key: String,
+  pub master_key_id: String,
+  pub created_at: ChronoDateTimeUtc,
+  pub updated_at: ChronoDateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+  #[sea_orm(
+    belongs_to = "super::transit_key::Entity",
+    from = "Column::TransitKeyId",
+    to = "super::transit_key::Column::Id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  TransitKey,
+}
+
+impl Related<super::transit_key::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::TransitKey.def()
+  }
+}
+
+/// Generates and seals the material for `version` of a transit key
+pub async fn generate<C>(db: &C, transit_key_id: Uuid, version: i32) -> Result<Model, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let id = Uuid::new_v4();
+  let mut material = Zeroizing::new([0u8; KEY_LEN]);
+  SharedRng.fill_bytes(material.as_mut());
+  let sealed = key_service::seal_bytes(id, material.as_ref()).map_err(DbErr::Custom)?;
+  ActiveModel {
+    id: Set(id),
+    transit_key_id: Set(transit_key_id),
+    version: Set(version),
+    key_material: Set(sealed.private_key),
+    encrypted_data_key: Set(sealed.encrypted_data_key),
+    master_key_id: Set(sealed.master_key_id),
+    ..Default::default()
+  }.insert(db).await
+}
+
+impl Model {
+  /// The key material, only ever decrypted by `key_service`
+  pub(crate) fn open(&self) -> Result<Zeroizing<[u8; KEY_LEN]>, String> {
+    let opened = key_service::open_bytes(self.id, &SealedKey {
+      private_key: self.key_material.clone(),
+      encrypted_data_key: self.encrypted_data_key.clone(),
+      master_key_id: self.master_key_id.clone(),
+    })?;
+    let mut material = Zeroizing::new([0u8; KEY_LEN]);
+    if opened.len() != KEY_LEN {
+      return Err(format!("transit key version {} has malformed key material", self.id));
+    }
+    material.copy_from_slice(&opened);
+    Ok(material)
+  }
+}
+
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+  /// Create a new ActiveModel with default values. Also used by `Default::default()`.
+  fn new() -> Self {
+    Self {
+      id: Set(Uuid::new_v4()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
+      ..ActiveModelTrait::default()
+    }
+  }
+
+  /// Will be triggered before insert / update
+  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !insert {
+      self.updated_at = Set(clock::now());
+    }
+    Ok(self)
+  }
+}
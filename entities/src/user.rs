@@ -1,7 +1,8 @@
-use sea_orm::{entity::prelude::*, ActiveValue::Set, ConnectionTrait};
+use sea_orm::{entity::prelude::*, ActiveValue::Set, ConnectionTrait, IntoActiveModel};
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
+use chrono::Duration;
+use shared::{clock, UserId};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "locked_state")]
@@ -15,20 +16,22 @@ pub enum LockedState {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "users", schema_name = "public")]
+#[sea_orm(table_name = "users")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
-  pub id: Uuid,
+  pub id: UserId,
   //pub auth_pass_id: Uuid,
-  pub invalid_login_attempts: u16,
+  pub invalid_login_attempts: i32,
   pub locked_state: LockedState,
   pub locked_state_updated_at: ChronoDateTimeUtc,
   #[sea_orm(nullable)]
   pub locked_state_expires_at: Option<ChronoDateTimeUtc>,
   #[sea_orm(nullable)]
   pub last_login_at: Option<ChronoDateTimeUtc>,
-  pub pki_key_id: Uuid,
+  /// Not set until a key has been generated for the user
+  #[sea_orm(nullable)]
+  pub pki_key_id: Option<Uuid>,
   pub created_at: ChronoDateTimeUtc,
   pub updated_at: ChronoDateTimeUtc,
 }
@@ -116,49 +119,75 @@ impl Related<super::group_access_role::Entity> for Entity {
 //   find.one(_db).await
 // }
 
+impl Model {
+  /// Temporary locks lapse once `locked_state_expires_at` has passed
+  pub fn is_locked(&self) -> bool {
+    match self.locked_state {
+      LockedState::Unlocked => false,
+      LockedState::PermanentlyLocked => true,
+      LockedState::TemporarilyLocked => self.locked_state_expires_at.map(|expires_at| expires_at > clock::now()).unwrap_or(true),
+    }
+  }
+}
+
 #[async_trait]
 impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
-      id: Set(Uuid::new_v4()),
+      id: Set(UserId::new()),
       last_login_at: Set(None),
       invalid_login_attempts: Set(0),
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
 
   /// Will be triggered before insert / update
-  async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+  async fn before_save<C>(mut self, db: &C, insert: bool) -> Result<Self, DbErr>
   where
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
       let locked_state = *self.locked_state.as_ref();
       // If invalid_login_attempts is changed and we are not permanently locked
       if self.invalid_login_attempts.is_set() && locked_state != LockedState::PermanentlyLocked {
         let invalid_lock_attempts = *self.invalid_login_attempts.as_ref();
         // If invalid_login_attempts is greater than 0 and we are not already temporarily locked
         if invalid_lock_attempts > 0 && locked_state != LockedState::TemporarilyLocked {  
-          let max_login_attempts: u16 = 10;
-          let locked_duration_mins: i64 = 60;
+          let security = super::config::security_for_user(db, *self.id.as_ref()).await?;
+          let max_login_attempts: i32 = security.max_login_attempts;
+          let locked_duration_mins: i64 = security.locked_duration_mins;
           // If login attempts exceeds our max then temporarily lock the account
           if invalid_lock_attempts > max_login_attempts && locked_state == LockedState::Unlocked {
             self.locked_state = Set(LockedState::TemporarilyLocked);
-            self.locked_state_expires_at = Set(Some(Utc::now() + Duration::minutes(locked_duration_mins)));
-            self.locked_state_updated_at = Set(Utc::now());
+            self.locked_state_expires_at = Set(Some(clock::now() + Duration::minutes(locked_duration_mins)));
+            self.locked_state_updated_at = Set(clock::now());
           }
         // If invalid_login_attempts is 0 and we are not currently unlocked then unlock
         } else if invalid_lock_attempts == 0 && locked_state != LockedState::Unlocked {
           self.locked_state = Set(LockedState::Unlocked);
           self.locked_state_expires_at = Set(None);
-          self.locked_state_updated_at = Set(Utc::now());
+          self.locked_state_updated_at = Set(clock::now());
         }
       }
     }
     Ok(self)
   }
+
+  /// Gives a new user a key pair unless one was assigned
+  async fn after_save<C>(model: Model, db: &C, insert: bool) -> Result<Model, DbErr>
+  where
+    C: ConnectionTrait,
+  {
+    if !insert || model.pki_key_id.is_some() {
+      return Ok(model);
+    }
+    let key = super::pki_key::generate(db, super::pki_key::Owner::User(model.id), None).await?;
+    let mut active_user = model.into_active_model();
+    active_user.pki_key_id = Set(Some(key.id));
+    active_user.update(db).await
+  }
 }
\ No newline at end of file
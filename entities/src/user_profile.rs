@@ -2,15 +2,16 @@ use sea_orm::{ entity::prelude::*, ActiveValue::Set };
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
 use names::{Generator, Name};
+use shared::{clock, UserId};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
-#[sea_orm(table_name = "user_profiles", schema_name = "public")]
+#[sea_orm(table_name = "user_profiles")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   #[serde(skip_deserializing)]
   pub id: Uuid,
   #[sea_orm(unique)]
-  pub user_id: Uuid,
+  pub user_id: UserId,
   #[sea_orm(unique)]
   pub username: Option<String>,
   #[sea_orm(nullable)]
@@ -29,7 +30,7 @@ pub enum Relation {
   #[sea_orm(
     belongs_to = "super::user::Entity",
     from = "Column::UserId",
-    to = "super::user::Column::Id"
+    to = "super::user::Column::Id",
     on_update = "Cascade",
     on_delete = "Cascade"
   )]
@@ -56,8 +57,8 @@ impl ActiveModelBehavior for ActiveModel {
   fn new() -> Self {
     Self {
       id: Set(Uuid::new_v4()),
-      created_at: Set(chrono::Utc::now()),
-      updated_at: Set(chrono::Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
@@ -68,7 +69,7 @@ impl ActiveModelBehavior for ActiveModel {
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(chrono::Utc::now());
+      self.updated_at = Set(clock::now());
     }
     // If no username is set, generate something random
     if self.username.is_set() && self.username.as_ref().is_none() {
@@ -1,14 +1,14 @@
 use sea_orm::{ entity::prelude::*, ActiveValue::Set };
 use async_trait::async_trait;
-use chrono::Utc;
+use shared::{clock, GroupId, UserId};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
-#[sea_orm(table_name = "users_groups_group_access_roles", schema_name = "public")]
+#[sea_orm(table_name = "users_groups_group_access_roles")]
 pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
-  pub user_id: Uuid,
+  pub user_id: UserId,
   #[sea_orm(primary_key, auto_increment = false)]
-  pub group_id: Uuid,
+  pub group_id: GroupId,
   pub group_access_role_id: Uuid,
   pub created_at: ChronoDateTimeUtc,
   pub updated_at: ChronoDateTimeUtc,
@@ -47,8 +47,8 @@ impl ActiveModelBehavior for ActiveModel {
   /// Create a new ActiveModel with default values. Also used by `Default::default()`.
   fn new() -> Self {
     Self {
-      created_at: Set(Utc::now()),
-      updated_at: Set(Utc::now()),
+      created_at: Set(clock::now()),
+      updated_at: Set(clock::now()),
       ..ActiveModelTrait::default()
     }
   }
@@ -59,8 +59,19 @@ impl ActiveModelBehavior for ActiveModel {
     C: ConnectionTrait,
   {
     if !insert {
-      self.updated_at = Set(Utc::now());
+      self.updated_at = Set(clock::now());
     }
     Ok(self)
   }
-}
\ No newline at end of file
+}
+
+/// The access role a user holds in a group, if they are a member
+pub async fn find_role<C>(db: &C, user_id: UserId, group_id: GroupId) -> Result<Option<super::group_access_role::Model>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  match Entity::find_by_id((user_id, group_id)).one(db).await? {
+    Some(membership) => super::group_access_role::Entity::find_by_id(membership.group_access_role_id).one(db).await,
+    None => Ok(None),
+  }
+}
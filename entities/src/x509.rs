@@ -0,0 +1,443 @@
+//! Building, parsing and checking X.509 certificates, CRLs and PKCS#10 requests.
+//!
+//! Certificates are built here but signed by whoever holds the issuer's key, see `key_provider`.
+//! Key identifiers are the leftmost 160 bits of the SHA-256 of the public key (RFC 7093).
+use std::{net::IpAddr, str::FromStr, time::Duration};
+use chrono::{DateTime, TimeZone, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use shared::rng::SharedRng;
+use x509_cert::{
+  certificate::{TbsCertificate, Version},
+  crl::{CertificateList, RevokedCert, TbsCertList},
+  der::{
+    asn1::{BitString, GeneralizedTime, Ia5String, Ia5StringRef, ObjectIdentifier, OctetString, PrintableStringRef, Uint, UtcTime, Utf8StringRef},
+    flagset::FlagSet,
+    oid::{db::{rfc4519, rfc5280, rfc5912, rfc8410}, AssociatedOid},
+    pem, Any, Decode, DecodePem, Encode, EncodePem, Tag, Tagged,
+  },
+  ext::{
+    pkix::{
+      crl::dp::DistributionPoint, name::{DistributionPointName, GeneralName}, AccessDescription, AuthorityInfoAccessSyntax,
+      AuthorityKeyIdentifier, BasicConstraints, CrlDistributionPoints, CrlNumber, ExtendedKeyUsage as ExtendedKeyUsageExt,
+      KeyUsage as KeyUsageExt, KeyUsages, SubjectAltName, SubjectKeyIdentifier,
+    },
+    Extension,
+  },
+  name::Name,
+  request::CertReq,
+  serial_number::SerialNumber,
+  spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned},
+  time::{Time, Validity},
+  Certificate,
+};
+use pkcs8::LineEnding;
+use super::{key_pair::{self, SignatureScheme}, pki_key::KeyAlgos};
+
+pub use x509_cert::ext::pkix::crl::CrlReason;
+
+/// Bytes of randomness in a serial number
+const SERIAL_LEN: usize = 16;
+const KEY_IDENTIFIER_LEN: usize = 20;
+const CRL_PEM_LABEL: &str = "X509 CRL";
+
+/// What a certificate's key may be used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyUsage {
+  DigitalSignature,
+  NonRepudiation,
+  KeyEncipherment,
+  DataEncipherment,
+  KeyAgreement,
+}
+
+impl KeyUsage {
+  fn flag(&self) -> KeyUsages {
+    match self {
+      KeyUsage::DigitalSignature => KeyUsages::DigitalSignature,
+      KeyUsage::NonRepudiation => KeyUsages::NonRepudiation,
+      KeyUsage::KeyEncipherment => KeyUsages::KeyEncipherment,
+      KeyUsage::DataEncipherment => KeyUsages::DataEncipherment,
+      KeyUsage::KeyAgreement => KeyUsages::KeyAgreement,
+    }
+  }
+}
+
+/// The purposes a certificate is for, mTLS needs `ServerAuth` and `ClientAuth`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExtendedKeyUsage {
+  ServerAuth,
+  ClientAuth,
+  CodeSigning,
+  EmailProtection,
+}
+
+impl ExtendedKeyUsage {
+  fn oid(&self) -> ObjectIdentifier {
+    match self {
+      ExtendedKeyUsage::ServerAuth => rfc5280::ID_KP_SERVER_AUTH,
+      ExtendedKeyUsage::ClientAuth => rfc5280::ID_KP_CLIENT_AUTH,
+      ExtendedKeyUsage::CodeSigning => rfc5280::ID_KP_CODE_SIGNING,
+      ExtendedKeyUsage::EmailProtection => rfc5280::ID_KP_EMAIL_PROTECTION,
+    }
+  }
+}
+
+/// Everything that goes into a certificate apart from the issuer's signature
+#[derive(Clone, Debug)]
+pub struct CertificateTemplate {
+  pub serial: Vec<u8>,
+  pub issuer: Name,
+  pub subject: Name,
+  pub not_before: DateTime<Utc>,
+  pub not_after: DateTime<Utc>,
+  pub public_key: SubjectPublicKeyInfoOwned,
+  /// The issuer's key identifier, `None` for a self-signed certificate
+  pub authority_key_id: Option<Vec<u8>>,
+  /// `Some` for a CA, holding its path length constraint
+  pub ca: Option<Option<u8>>,
+  pub key_usage: Vec<KeyUsage>,
+  pub extended_key_usage: Vec<ExtendedKeyUsage>,
+  pub dns_names: Vec<String>,
+  pub ip_addresses: Vec<IpAddr>,
+  /// Where the issuer's CRL and OCSP responder are, told to relying parties
+  pub crl_url: Option<String>,
+  pub ocsp_url: Option<String>,
+}
+
+/// A revoked certificate as it's listed on a CRL
+#[derive(Clone, Debug)]
+pub struct RevokedEntry {
+  pub serial: Vec<u8>,
+  pub revoked_at: DateTime<Utc>,
+  pub reason: CrlReason,
+}
+
+/// A certificate request whose signature checked out
+#[derive(Clone, Debug)]
+pub struct CertificateRequest {
+  pub subject: Name,
+  pub common_name: Option<String>,
+  pub public_key: SubjectPublicKeyInfoOwned,
+  pub algo: KeyAlgos,
+}
+
+fn describe(err: impl std::fmt::Display) -> String {
+  err.to_string()
+}
+
+/// Parses an RFC 4514 name such as `CN=Issuing CA,O=Example`
+pub fn parse_name(name: &str) -> Result<Name, String> {
+  let parsed = Name::from_str(name).map_err(|err| format!("invalid name {}: {}", name, err))?;
+  if parsed.0.is_empty() {
+    return Err("name cannot be blank".to_string());
+  }
+  Ok(parsed)
+}
+
+/// A name holding just a common name
+pub fn common_name_only(common_name: &str) -> Result<Name, String> {
+  // Commas and the like have to be escaped in RFC 4514
+  let escaped: String = common_name.chars().flat_map(|c| match c {
+    ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' | '#' => vec!['\\', c],
+    _ => vec![c],
+  }).collect();
+  parse_name(&format!("CN={}", escaped))
+}
+
+/// The first common name in `name`
+pub fn common_name(name: &Name) -> Option<String> {
+  name.0.iter()
+    .flat_map(|rdn| rdn.0.iter())
+    .find(|atv| atv.oid == rfc4519::CN)
+    .and_then(|atv| match atv.value.tag() {
+      Tag::Utf8String => atv.value.decode_as::<Utf8StringRef>().ok().map(|value| value.to_string()),
+      Tag::PrintableString => atv.value.decode_as::<PrintableStringRef>().ok().map(|value| value.to_string()),
+      Tag::Ia5String => atv.value.decode_as::<Ia5StringRef>().ok().map(|value| value.to_string()),
+      _ => None,
+    })
+}
+
+/// A random positive serial number
+pub fn random_serial() -> Vec<u8> {
+  let mut serial = [0u8; SERIAL_LEN];
+  SharedRng.fill_bytes(&mut serial);
+  // The high bit would make it negative, a leading zero byte would be dropped from the encoding
+  serial[0] = (serial[0] & 0x7f) | 0x01;
+  serial.to_vec()
+}
+
+/// How serial numbers are written down, lowercase hex
+pub fn serial_hex(serial: &[u8]) -> String {
+  hex::encode(serial)
+}
+
+pub fn key_identifier(public_key: &SubjectPublicKeyInfoOwned) -> Vec<u8> {
+  Sha256::digest(public_key.subject_public_key.raw_bytes())[..KEY_IDENTIFIER_LEN].to_vec()
+}
+
+pub fn public_key_info(public_key: &str) -> Result<SubjectPublicKeyInfoOwned, String> {
+  SubjectPublicKeyInfoOwned::from_pem(public_key).map_err(|err| format!("invalid public key: {}", err))
+}
+
+pub fn public_key_pem(public_key: &SubjectPublicKeyInfoOwned) -> Result<String, String> {
+  public_key.to_pem(LineEnding::LF).map_err(describe)
+}
+
+/// The kind of key in a SubjectPublicKeyInfo
+pub fn key_algo(public_key: &SubjectPublicKeyInfoOwned) -> Result<KeyAlgos, String> {
+  let algorithm = &public_key.algorithm;
+  let curve = algorithm.parameters.as_ref()
+    .and_then(|parameters| parameters.decode_as::<ObjectIdentifier>().ok());
+  key_algo_for(algorithm.oid, curve)
+}
+
+/// The kind of key a key algorithm and, for elliptic curve keys, its curve name
+pub fn key_algo_for(algorithm: ObjectIdentifier, curve: Option<ObjectIdentifier>) -> Result<KeyAlgos, String> {
+  match algorithm {
+    rfc5912::RSA_ENCRYPTION => Ok(KeyAlgos::RSA),
+    rfc8410::ID_ED_25519 => Ok(KeyAlgos::Ed25519),
+    rfc5912::ID_EC_PUBLIC_KEY => match curve {
+      Some(rfc5912::SECP_256_R_1) => Ok(KeyAlgos::EcdsaP256),
+      Some(rfc5912::SECP_384_R_1) => Ok(KeyAlgos::EcdsaP384),
+      _ => Err("only P-256 and P-384 elliptic curve keys are supported".to_string()),
+    },
+    oid => Err(format!("unsupported key algorithm {}", oid)),
+  }
+}
+
+/// How a CA with a key of `algo` signs what it issues
+pub fn signing_scheme(algo: KeyAlgos) -> SignatureScheme {
+  match algo {
+    // PKCS#1 v1.5 rather than PSS, which too many TLS stacks still reject in certificates
+    KeyAlgos::RSA => SignatureScheme::RsaPkcs1v15Sha256,
+    KeyAlgos::Ed25519 => SignatureScheme::Ed25519,
+    KeyAlgos::EcdsaP256 => SignatureScheme::EcdsaSha256,
+    KeyAlgos::EcdsaP384 => SignatureScheme::EcdsaSha384,
+  }
+}
+
+pub fn signature_algorithm(scheme: SignatureScheme) -> Result<AlgorithmIdentifierOwned, String> {
+  let (oid, parameters) = match scheme {
+    SignatureScheme::RsaPkcs1v15Sha256 => (rfc5912::SHA_256_WITH_RSA_ENCRYPTION, Some(Any::null())),
+    SignatureScheme::EcdsaSha256 => (rfc5912::ECDSA_WITH_SHA_256, None),
+    SignatureScheme::EcdsaSha384 => (rfc5912::ECDSA_WITH_SHA_384, None),
+    SignatureScheme::Ed25519 => (rfc8410::ID_ED_25519, None),
+    SignatureScheme::RsaPssSha256 => return Err("certificates are not signed with RSA-PSS".to_string()),
+  };
+  Ok(AlgorithmIdentifierOwned { oid, parameters })
+}
+
+/// The scheme behind a signature algorithm, RSA-PSS is taken to use SHA-256 throughout
+fn scheme_for_algorithm(algorithm: &AlgorithmIdentifierOwned) -> Result<SignatureScheme, String> {
+  match algorithm.oid {
+    rfc5912::SHA_256_WITH_RSA_ENCRYPTION => Ok(SignatureScheme::RsaPkcs1v15Sha256),
+    rfc5912::ID_RSASSA_PSS => Ok(SignatureScheme::RsaPssSha256),
+    rfc5912::ECDSA_WITH_SHA_256 => Ok(SignatureScheme::EcdsaSha256),
+    rfc5912::ECDSA_WITH_SHA_384 => Ok(SignatureScheme::EcdsaSha384),
+    rfc8410::ID_ED_25519 => Ok(SignatureScheme::Ed25519),
+    oid => Err(format!("unsupported signature algorithm {}", oid)),
+  }
+}
+
+/// RFC 5280 wants UTCTime up to 2049 and GeneralizedTime after
+pub fn time(at: DateTime<Utc>) -> Result<Time, String> {
+  let since_epoch = Duration::from_secs(at.timestamp().max(0) as u64);
+  match UtcTime::from_unix_duration(since_epoch) {
+    Ok(utc_time) => Ok(Time::UtcTime(utc_time)),
+    Err(_) => GeneralizedTime::from_unix_duration(since_epoch).map(Time::GeneralTime).map_err(describe),
+  }
+}
+
+pub fn to_date_time(time: Time) -> DateTime<Utc> {
+  Utc.timestamp_opt(time.to_unix_duration().as_secs() as i64, 0).single().unwrap_or_default()
+}
+
+fn extension<T: AssociatedOid + Encode>(value: &T, critical: bool) -> Result<Extension, String> {
+  Ok(Extension {
+    extn_id: T::OID,
+    critical,
+    extn_value: OctetString::new(value.to_der().map_err(describe)?).map_err(describe)?,
+  })
+}
+
+/// The to-be-signed part of a certificate, signed by an issuer using `scheme`
+pub fn tbs_certificate(template: &CertificateTemplate, scheme: SignatureScheme) -> Result<TbsCertificate, String> {
+  let subject_key_id = key_identifier(&template.public_key);
+  let mut extensions = vec![
+    extension(&BasicConstraints { ca: template.ca.is_some(), path_len_constraint: template.ca.flatten() }, true)?,
+    extension(&SubjectKeyIdentifier(OctetString::new(subject_key_id.clone()).map_err(describe)?), false)?,
+    extension(&AuthorityKeyIdentifier {
+      key_identifier: Some(OctetString::new(template.authority_key_id.clone().unwrap_or(subject_key_id)).map_err(describe)?),
+      ..Default::default()
+    }, false)?,
+  ];
+  let mut key_usage = template.key_usage.iter().fold(FlagSet::<KeyUsages>::default(), |usages, usage| usages | usage.flag());
+  if template.ca.is_some() {
+    key_usage |= KeyUsages::KeyCertSign | KeyUsages::CRLSign;
+  }
+  if !key_usage.is_empty() {
+    extensions.push(extension(&KeyUsageExt(key_usage), true)?);
+  }
+  if !template.extended_key_usage.is_empty() {
+    let usages = template.extended_key_usage.iter().map(ExtendedKeyUsage::oid).collect();
+    extensions.push(extension(&ExtendedKeyUsageExt(usages), false)?);
+  }
+  let mut names = Vec::new();
+  for dns_name in &template.dns_names {
+    names.push(GeneralName::DnsName(Ia5String::new(dns_name).map_err(|_| format!("invalid DNS name {}", dns_name))?));
+  }
+  names.extend(template.ip_addresses.iter().map(|ip| GeneralName::from(*ip)));
+  if !names.is_empty() {
+    // An empty subject makes the alternative names critical
+    extensions.push(extension(&SubjectAltName(names), template.subject.0.is_empty())?);
+  }
+  if let Some(crl_url) = &template.crl_url {
+    extensions.push(extension(&CrlDistributionPoints(vec![DistributionPoint {
+      distribution_point: Some(DistributionPointName::FullName(vec![uri(crl_url)?])),
+      reasons: None,
+      crl_issuer: None,
+    }]), false)?);
+  }
+  if let Some(ocsp_url) = &template.ocsp_url {
+    extensions.push(extension(&AuthorityInfoAccessSyntax(vec![AccessDescription {
+      access_method: rfc5280::ID_AD_OCSP,
+      access_location: uri(ocsp_url)?,
+    }]), false)?);
+  }
+  Ok(TbsCertificate {
+    version: Version::V3,
+    serial_number: SerialNumber::new(&template.serial).map_err(describe)?,
+    signature: signature_algorithm(scheme)?,
+    issuer: template.issuer.clone(),
+    validity: Validity { not_before: time(template.not_before)?, not_after: time(template.not_after)? },
+    subject: template.subject.clone(),
+    subject_public_key_info: template.public_key.clone(),
+    issuer_unique_id: None,
+    subject_unique_id: None,
+    extensions: Some(extensions),
+  })
+}
+
+fn uri(url: &str) -> Result<GeneralName, String> {
+  Ok(GeneralName::UniformResourceIdentifier(Ia5String::new(url).map_err(|_| format!("invalid URL {}", url))?))
+}
+
+/// The to-be-signed part of a v2 CRL from `issuer` listing `revoked`
+pub fn tbs_cert_list(
+  issuer: &Certificate,
+  revoked: &[RevokedEntry],
+  this_update: DateTime<Utc>,
+  next_update: DateTime<Utc>,
+  crl_number: u64,
+  scheme: SignatureScheme,
+) -> Result<TbsCertList, String> {
+  let mut revoked_certificates = Vec::with_capacity(revoked.len());
+  for entry in revoked {
+    revoked_certificates.push(RevokedCert {
+      serial_number: SerialNumber::new(&entry.serial).map_err(describe)?,
+      revocation_date: time(entry.revoked_at)?,
+      crl_entry_extensions: Some(vec![extension(&entry.reason, false)?]),
+    });
+  }
+  let authority_key_id = key_identifier(&issuer.tbs_certificate.subject_public_key_info);
+  Ok(TbsCertList {
+    version: Version::V2,
+    signature: signature_algorithm(scheme)?,
+    issuer: issuer.tbs_certificate.subject.clone(),
+    this_update: time(this_update)?,
+    next_update: Some(time(next_update)?),
+    // An empty list has to be left out altogether
+    revoked_certificates: Some(revoked_certificates).filter(|revoked| !revoked.is_empty()),
+    crl_extensions: Some(vec![
+      extension(&CrlNumber(Uint::new(&crl_number.to_be_bytes()).map_err(describe)?), false)?,
+      extension(&AuthorityKeyIdentifier {
+        key_identifier: Some(OctetString::new(authority_key_id).map_err(describe)?),
+        ..Default::default()
+      }, false)?,
+    ]),
+  })
+}
+
+/// Puts the issuer's signature over the DER of `tbs` on it and PEM encodes the CRL
+pub fn assemble_crl(tbs: TbsCertList, signature: &[u8]) -> Result<String, String> {
+  let crl = CertificateList {
+    signature_algorithm: tbs.signature.clone(),
+    tbs_cert_list: tbs,
+    signature: BitString::from_bytes(signature).map_err(describe)?,
+  };
+  pem::encode_string(CRL_PEM_LABEL, LineEnding::LF, &crl.to_der().map_err(describe)?).map_err(describe)
+}
+
+/// The DER of a PEM CRL, how it's served
+pub fn crl_der(crl: &str) -> Result<Vec<u8>, String> {
+  match pem::decode_vec(crl.as_bytes()) {
+    Ok((CRL_PEM_LABEL, der)) => Ok(der),
+    Ok((label, _)) => Err(format!("expected a CRL, found {}", label)),
+    Err(err) => Err(format!("invalid CRL: {}", err)),
+  }
+}
+
+pub fn parse_crl(crl: &str) -> Result<CertificateList, String> {
+  CertificateList::from_der(&crl_der(crl)?).map_err(|err| format!("invalid CRL: {}", err))
+}
+
+/// Checks `crl` was signed by the key in `issuer`
+pub fn verify_crl_issued_by(crl: &CertificateList, issuer: &Certificate) -> Result<(), String> {
+  if crl.tbs_cert_list.issuer != issuer.tbs_certificate.subject {
+    return Err("CRL was issued by someone else".to_string());
+  }
+  let tbs = crl.tbs_cert_list.to_der().map_err(describe)?;
+  verify_signature(&issuer.tbs_certificate.subject_public_key_info, &crl.signature_algorithm, &tbs, &crl.signature)
+}
+
+/// Puts the issuer's signature over the DER of `tbs` on it and PEM encodes the certificate
+pub fn assemble(tbs: TbsCertificate, signature: &[u8]) -> Result<String, String> {
+  let certificate = Certificate {
+    signature_algorithm: tbs.signature.clone(),
+    tbs_certificate: tbs,
+    signature: BitString::from_bytes(signature).map_err(describe)?,
+  };
+  certificate.to_pem(LineEnding::LF).map_err(describe)
+}
+
+pub fn parse_certificate(certificate: &str) -> Result<Certificate, String> {
+  Certificate::from_pem(certificate).map_err(|err| format!("invalid certificate: {}", err))
+}
+
+/// Checks `signature` over `data` with a SubjectPublicKeyInfo
+fn verify_signature(
+  public_key: &SubjectPublicKeyInfoOwned,
+  algorithm: &AlgorithmIdentifierOwned,
+  data: &[u8],
+  signature: &BitString,
+) -> Result<(), String> {
+  let algo = key_algo(public_key)?;
+  let signature = signature.as_bytes().ok_or_else(|| "signature is not a whole number of bytes".to_string())?;
+  key_pair::verify(algo, &public_key_pem(public_key)?, scheme_for_algorithm(algorithm)?, data, signature)
+}
+
+/// Checks `certificate` was signed by the key in `issuer`
+pub fn verify_issued_by(certificate: &Certificate, issuer: &Certificate) -> Result<(), String> {
+  if certificate.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+    return Err("certificate was issued by someone else".to_string());
+  }
+  let tbs = certificate.tbs_certificate.to_der().map_err(describe)?;
+  verify_signature(&issuer.tbs_certificate.subject_public_key_info, &certificate.signature_algorithm, &tbs, &certificate.signature)
+}
+
+/// Parses a PEM PKCS#10 request and checks it was signed by the key it asks a certificate for
+pub fn parse_request(request: &str) -> Result<CertificateRequest, String> {
+  let parsed = CertReq::from_pem(request).map_err(|err| format!("invalid certificate request: {}", err))?;
+  let info = parsed.info.to_der().map_err(describe)?;
+  verify_signature(&parsed.info.public_key, &parsed.algorithm, &info, &parsed.signature)
+    .map_err(|err| format!("certificate request signature is invalid: {}", err))?;
+  Ok(CertificateRequest {
+    common_name: common_name(&parsed.info.subject),
+    algo: key_algo(&parsed.info.public_key)?,
+    subject: parsed.info.subject,
+    public_key: parsed.info.public_key,
+  })
+}
+
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use chrono::{Duration, TimeZone, Utc};
+use entities::{auth_api_key, config};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, DbErr, EntityTrait, IntoActiveModel};
+use shared::clock::{set_thread_clock, FixedClock};
+use test_support::{factory, TestDb};
+
+#[async_std::test]
+async fn keys_are_generated_at_the_configured_length() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let (first, first_secret) = factory::api_key(&*db, user.id).await;
+  let (second, _) = factory::api_key(&*db, user.id).await;
+
+  let length = config::get().security.api_key_length;
+  assert_eq!((first.api_access_key.len(), first_secret.len()), (length, length));
+  assert_ne!(first.api_access_key, first_secret);
+  assert_ne!(first.api_access_key, second.api_access_key);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn only_a_hash_of_the_secret_is_stored() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let (key, secret) = factory::api_key(&*db, user.id).await;
+  let stored = auth_api_key::Entity::find_by_id(key.id).one(&*db).await.unwrap().unwrap();
+  assert_eq!(stored.api_secret_key, auth_api_key::hash_secret(&secret));
+  assert!(stored.check_secret(&secret));
+  assert!(!stored.check_secret(&stored.api_secret_key));
+
+  // Saving the key again leaves the hash alone
+  let mut active_key = stored.into_active_model();
+  active_key.ip_address_last_used = Set(Some("192.0.2.1".to_string()));
+  assert!(active_key.update(&*db).await.unwrap().check_secret(&secret));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn keys_are_only_inserted_through_issue() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  match factory::new_api_key(user.id).insert(&*db).await {
+    Err(DbErr::Custom(message)) => assert!(message.contains("auth_api_key::issue")),
+    other => panic!("expected a before_save error, got {:?}", other),
+  }
+  db.close().await;
+}
+
+#[async_std::test]
+async fn keys_need_a_user_or_organisation() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let result = auth_api_key::ActiveModel { user_id: Set(None), ..factory::new_api_key(user.id) }.insert(&*db).await;
+  match result {
+    Err(DbErr::Custom(message)) => assert!(message.contains("Both user_id and organisation_id cannot be blank")),
+    other => panic!("expected a before_save error, got {:?}", other),
+  }
+  db.close().await;
+}
+
+#[async_std::test]
+async fn using_a_key_records_when() {
+  let clock = Arc::new(FixedClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+  let _clock_guard = set_thread_clock(clock.clone());
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let (key, _) = factory::api_key(&*db, user.id).await;
+  assert_eq!(key.key_last_used_at, None);
+
+  clock.advance(Duration::minutes(5));
+  let mut active_key = key.into_active_model();
+  active_key.ip_address_last_used = Set(Some("192.0.2.1".to_string()));
+  let key = active_key.update(&*db).await.unwrap();
+  assert_eq!(key.key_last_used_at, Some(Utc.timestamp_opt(1_700_000_300, 0).unwrap()));
+  db.close().await;
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use entities::{auth_method_magiclink, config};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, IntoActiveModel};
+use shared::{clock::{set_thread_clock, FixedClock}, rng};
+use test_support::{factory, TestDb};
+
+#[async_std::test]
+async fn using_link_invalidates_it() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let link = factory::magiclink(&*db, user.id, None).await;
+  assert!(link.link_hash.is_some());
+  assert!(link.link_hash_expires_at.unwrap() > Utc::now());
+
+  let mut active_link = link.into_active_model();
+  active_link.link_used_at = Set(Some(Utc::now()));
+  let link = active_link.update(&*db).await.unwrap();
+  assert_eq!(link.link_hash, None);
+  assert_eq!(link.link_hash_expires_at, None);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn link_expires_once_the_clock_passes_it() {
+  let clock = Arc::new(FixedClock::default());
+  let _guard = set_thread_clock(clock.clone());
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let link = factory::magiclink(&*db, user.id, None).await;
+  let link_hash = link.link_hash.clone().unwrap();
+  assert!(link.is_valid(&link_hash));
+  assert!(!link.is_valid("guess"));
+
+  clock.advance(Duration::minutes(config::get().security.magic_link_valid_mins) + Duration::seconds(1));
+  assert!(!link.is_valid(&link_hash));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn seeded_links_are_reproducible() {
+  let first = {
+    let _guard = rng::seed_thread_rng(7);
+    auth_method_magiclink::generate_login_link_hash(&config::get().security)
+  };
+  let _guard = rng::seed_thread_rng(7);
+  assert_eq!(auth_method_magiclink::generate_login_link_hash(&config::get().security), first);
+}
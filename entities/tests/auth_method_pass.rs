@@ -0,0 +1,108 @@
+use chrono::{Duration, Utc};
+use entities::{auth_method_pass::{self, verify_pass_hash, PassHashCipher}, config};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, IntoActiveModel};
+use shared::otp::Outcome;
+use test_support::{factory, TestDb};
+
+#[async_std::test]
+async fn password_is_stored_hashed() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let pass = factory::password(&*db, user.id, "correct horse").await;
+
+  assert_ne!(pass.pass_hash.as_deref(), Some("correct horse"));
+  assert!(verify_pass_hash(Some("correct horse".to_string()), pass.pass_hash.as_ref(), &pass.pass_hash_cipher));
+  assert!(!verify_pass_hash(Some("battery staple".to_string()), pass.pass_hash.as_ref(), &pass.pass_hash_cipher));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn reset_code_expires_in_a_day() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let pass = factory::password(&*db, user.id, "correct horse").await;
+  assert_eq!(pass.pass_reset_code_expires_at, None);
+
+  let mut active_pass = pass.into_active_model();
+  active_pass.pass_reset_code = Set(Some("12345678".to_string()));
+  active_pass.pass_reset_str = Set(Some("reset-string".to_string()));
+  let pass = active_pass.update(&*db).await.unwrap();
+  let expires_at = pass.pass_reset_code_expires_at.unwrap();
+  assert!(expires_at > Utc::now() + Duration::hours(23));
+  assert!(expires_at <= Utc::now() + Duration::hours(24));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn changing_cipher_without_hash_clears_password() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let pass = factory::password(&*db, user.id, "correct horse").await;
+
+  let mut active_pass = pass.into_active_model();
+  active_pass.pass_hash_cipher = Set(PassHashCipher::Bcrypt);
+  let pass = active_pass.update(&*db).await.unwrap();
+  assert_eq!(pass.pass_hash, None);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn reset_code_is_used_up_once_redeemed() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let pass = factory::password(&*db, user.id, "correct horse").await;
+
+  let (reset_str, reset_code) = auth_method_pass::gen_pass_reset_codes(&config::get().security);
+  assert_eq!(reset_code.len(), config::get().security.otp_length);
+  let mut active_pass = pass.into_active_model();
+  active_pass.pass_reset_code = Set(Some(reset_code.clone()));
+  active_pass.pass_reset_str = Set(Some(reset_str.clone()));
+  let pass = active_pass.update(&*db).await.unwrap();
+
+  let (pass, outcome) = auth_method_pass::redeem_reset_code(&*db, pass, "00000000000").await.unwrap();
+  assert_eq!(outcome, Outcome::Rejected { attempts_left: config::get().security.otp_max_attempts - 1 });
+  assert_eq!(pass.pass_reset_attempts, 1);
+  let (pass, outcome) = auth_method_pass::redeem_reset_code(&*db, pass, &reset_code).await.unwrap();
+  assert_eq!(outcome, Outcome::Accepted);
+  assert_eq!(pass.pass_reset_code, None);
+  assert_eq!(pass.pass_reset_code_expires_at, None);
+  let (_, outcome) = auth_method_pass::redeem_reset_str(&*db, pass, &reset_str).await.unwrap();
+  assert_eq!(outcome, Outcome::Expired);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn stale_copies_cannot_reuse_attempts_or_codes() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let pass = factory::password(&*db, user.id, "correct horse").await;
+  let max_attempts = config::get().security.otp_max_attempts;
+
+  let (reset_str, reset_code) = auth_method_pass::gen_pass_reset_codes(&config::get().security);
+  let mut active_pass = pass.into_active_model();
+  active_pass.pass_reset_code = Set(Some(reset_code.clone()));
+  active_pass.pass_reset_str = Set(Some(reset_str.clone()));
+  let mut pass = active_pass.update(&*db).await.unwrap();
+  for _ in 1..max_attempts {
+    pass = auth_method_pass::redeem_reset_code(&*db, pass, "wrong").await.unwrap().0;
+  }
+  // Two requests holding the same copy race for the last attempt
+  let (_, outcome) = auth_method_pass::redeem_reset_code(&*db, pass.clone(), "wrong").await.unwrap();
+  assert_eq!(outcome, Outcome::Rejected { attempts_left: 0 });
+  let (updated, outcome) = auth_method_pass::redeem_reset_str(&*db, pass, &reset_str).await.unwrap();
+  assert_eq!(outcome, Outcome::LockedOut);
+  assert_eq!(updated.pass_reset_attempts, max_attempts);
+  assert_eq!(updated.pass_reset_str, Some(reset_str));
+
+  // And for the code itself
+  let (reset_str, reset_code) = auth_method_pass::gen_pass_reset_codes(&config::get().security);
+  let mut active_pass = updated.into_active_model();
+  active_pass.pass_reset_code = Set(Some(reset_code.clone()));
+  active_pass.pass_reset_str = Set(Some(reset_str));
+  let pass = active_pass.update(&*db).await.unwrap();
+  let (_, outcome) = auth_method_pass::redeem_reset_code(&*db, pass.clone(), &reset_code).await.unwrap();
+  assert_eq!(outcome, Outcome::Accepted);
+  let (_, outcome) = auth_method_pass::redeem_reset_code(&*db, pass, &reset_code).await.unwrap();
+  assert_eq!(outcome, Outcome::Expired);
+  db.close().await;
+}
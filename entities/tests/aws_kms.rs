@@ -0,0 +1,43 @@
+use entities::{
+  config::{self, Config, KmsConfig},
+  key_pair::{self, SignatureScheme},
+  key_provider::{self, AwsCredentials, AwsKmsProvider, KeyProvider},
+  key_service::{self, MasterKeys},
+  pki_key::{KeyAlgos, Owner},
+};
+use test_support::{factory, TestDb};
+use url::Url;
+
+const MESSAGE: &[u8] = b"a message to sign";
+
+/// Runs against a KMS emulator, e.g. local-kms. `TEST_KMS_URL` must name an ECC_NIST_P256 key,
+/// run it with `cargo test -- --include-ignored`. Kept apart so its endpoint can be configured
+/// before anything reads the configuration.
+#[async_std::test]
+#[ignore = "needs a KMS emulator and TEST_KMS_URL"]
+async fn kms_keys_sign() {
+  let url = std::env::var("TEST_KMS_URL").expect("TEST_KMS_URL must name an ECC_NIST_P256 key");
+  let region = std::env::var("TEST_KMS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+  let endpoint = Url::parse(&url).unwrap().origin().ascii_serialization();
+  config::init(Config {
+    kms: KmsConfig { aws_region: region.clone(), aws_endpoints: endpoint, ..KmsConfig::default() },
+    ..Config::default()
+  }).unwrap();
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let provider = AwsKmsProvider::new(
+    &region,
+    AwsCredentials {
+      access_key_id: std::env::var("TEST_KMS_ACCESS_KEY_ID").unwrap_or_else(|_| "test".to_string()),
+      secret_access_key: std::env::var("TEST_KMS_SECRET_ACCESS_KEY").unwrap_or_else(|_| "test".to_string()),
+    },
+  );
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let key = key_provider::register(&*db, &provider, Owner::User(user.id), KeyAlgos::EcdsaP256, &url).await.unwrap();
+
+  let public_key = key.public_key.as_deref().unwrap();
+  let scheme = SignatureScheme::default_for(key.algo);
+  let signature = provider.sign(&key, scheme, MESSAGE).await.unwrap();
+  key_pair::verify(key.algo, public_key, scheme, MESSAGE, &signature).unwrap();
+  db.close().await;
+}
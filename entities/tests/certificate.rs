@@ -0,0 +1,262 @@
+use entities::{
+  certificate::{self, IssueRequest},
+  certificate_authority::{self, NewAuthority},
+  certificate_role::{self, RoleTemplate},
+  key_pair::{self, SignatureScheme},
+  key_service::{self, MasterKeys},
+  pki_key::KeyAlgos,
+  x509,
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter};
+use shared::OrgId;
+use test_support::{factory, TestDb};
+use x509_cert::{
+  der::{asn1::BitString, EncodePem, Encode},
+  ext::pkix::SubjectAltName,
+  request::{CertReq, CertReqInfo, Version},
+};
+
+fn new_authority(name: &str, ttl_days: i64) -> NewAuthority {
+  NewAuthority {
+    name: name.to_string(),
+    subject: format!("CN={},O=Example", name),
+    ttl_days,
+    key_id: None,
+    algo: Some(KeyAlgos::EcdsaP256),
+    max_path_length: None,
+  }
+}
+
+async fn role<C: sea_orm::ConnectionTrait>(
+  db: &C,
+  organisation_id: OrgId,
+  authority: &certificate_authority::Model,
+  template: RoleTemplate,
+) -> certificate_role::Model {
+  certificate_role::ActiveModel {
+    organisation_id: Set(organisation_id),
+    certificate_authority_id: Set(authority.id),
+    name: Set("web-server".to_string()),
+    template: Set(template),
+    ..Default::default()
+  }.insert(db).await.unwrap()
+}
+
+fn example_template() -> RoleTemplate {
+  RoleTemplate {
+    allowed_domains: vec!["example.com".to_string()],
+    allow_subdomains: true,
+    ..Default::default()
+  }
+}
+
+/// A PKCS#10 request for `common_name`, signed by `private_key` unless another key is given
+fn csr(algo: KeyAlgos, common_name: &str, private_key: &str, signing_key: Option<&str>) -> String {
+  let info = CertReqInfo {
+    version: Version::V1,
+    subject: x509::common_name_only(common_name).unwrap(),
+    public_key: x509::public_key_info(&key_pair::public_key_pem(algo, private_key).unwrap()).unwrap(),
+    attributes: Default::default(),
+  };
+  let scheme = SignatureScheme::default_for(algo);
+  let signature = key_pair::sign(algo, signing_key.unwrap_or(private_key), scheme, &info.to_der().unwrap()).unwrap();
+  CertReq {
+    info,
+    algorithm: x509::signature_algorithm(scheme).unwrap(),
+    signature: BitString::from_bytes(&signature).unwrap(),
+  }.to_pem(pkcs8::LineEnding::LF).unwrap()
+}
+
+fn names(pem: &str) -> Vec<String> {
+  let certificate = x509::parse_certificate(pem).unwrap();
+  let (_, san) = certificate.tbs_certificate.get::<SubjectAltName>().unwrap().unwrap();
+  san.0.iter().map(|name| format!("{:?}", name)).collect()
+}
+
+#[async_std::test]
+async fn roots_and_intermediates_form_a_chain() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+
+  let root = certificate_authority::create_root(&*db, organisation.id, new_authority("Root", 3650)).await.unwrap();
+  assert_eq!(root.parent_id, None);
+  assert_eq!(root.max_path_length, 1);
+  let root_certificate = x509::parse_certificate(&root.certificate).unwrap();
+  x509::verify_issued_by(&root_certificate, &root_certificate).unwrap();
+
+  // Asked to outlive its parent, the intermediate is cut short
+  let intermediate = certificate_authority::create_intermediate(&*db, &root, new_authority("Issuing", 7300)).await.unwrap();
+  assert_eq!(intermediate.parent_id, Some(root.id));
+  assert_eq!(intermediate.max_path_length, 0);
+  assert_eq!(intermediate.not_after, root.not_after);
+  let intermediate_certificate = x509::parse_certificate(&intermediate.certificate).unwrap();
+  x509::verify_issued_by(&intermediate_certificate, &root_certificate).unwrap();
+  assert!(x509::verify_issued_by(&root_certificate, &intermediate_certificate).is_err());
+
+  let chain = certificate_authority::chain(&*db, &intermediate).await.unwrap();
+  assert_eq!(chain, vec![intermediate.certificate.clone(), root.certificate.clone()]);
+
+  // Path lengths run out
+  let below = certificate_authority::create_intermediate(&*db, &intermediate, new_authority("Too deep", 30)).await;
+  assert!(below.unwrap_err().to_string().contains("[before_save]"));
+
+  // Both CA certificates are on record, the intermediate's against the root
+  let recorded = certificate::Entity::find()
+    .filter(certificate::Column::IsCa.eq(true))
+    .all(&*db).await.unwrap();
+  assert_eq!(recorded.len(), 2);
+  assert!(recorded.iter().all(|certificate| certificate.certificate_authority_id == root.id));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn bad_authorities_are_rejected() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let other = factory::organisation(&*db).await;
+
+  let mut bad_subject = new_authority("Root", 365);
+  bad_subject.subject = "not a name".to_string();
+  assert!(certificate_authority::create_root(&*db, organisation.id, bad_subject).await.is_err());
+  assert!(certificate_authority::create_root(&*db, organisation.id, new_authority("Root", 0)).await.is_err());
+
+  // Another organisation's key can't be borrowed
+  let theirs = certificate_authority::create_root(&*db, other.id, new_authority("Theirs", 365)).await.unwrap();
+  let mut borrowed = new_authority("Borrowed", 365);
+  borrowed.key_id = Some(theirs.pki_key_id);
+  let err = certificate_authority::create_root(&*db, organisation.id, borrowed).await.unwrap_err();
+  assert!(err.to_string().contains("does not belong"));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn leaf_certificates_are_issued_with_generated_keys() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let root = certificate_authority::create_root(&*db, organisation.id, new_authority("Root", 365)).await.unwrap();
+  let intermediate = certificate_authority::create_intermediate(&*db, &root, new_authority("Issuing", 90)).await.unwrap();
+  let mut template = example_template();
+  template.allow_ip_sans = true;
+  template.key_algo = Some(KeyAlgos::Ed25519);
+  let role = role(&*db, organisation.id, &intermediate, template).await;
+
+  let issued = certificate::issue(&*db, &role, IssueRequest {
+    common_name: Some("api.example.com".to_string()),
+    sans: vec!["example.com".to_string(), "10.0.0.1".to_string()],
+    ttl_hours: Some(48),
+    csr: None,
+  }).await.unwrap();
+  assert_eq!(issued.chain, vec![intermediate.certificate.clone(), root.certificate.clone()]);
+
+  let leaf = x509::parse_certificate(&issued.certificate.certificate).unwrap();
+  x509::verify_issued_by(&leaf, &x509::parse_certificate(&intermediate.certificate).unwrap()).unwrap();
+  assert_eq!(x509::common_name(&leaf.tbs_certificate.subject).as_deref(), Some("api.example.com"));
+  let names = names(&issued.certificate.certificate);
+  assert_eq!(names.len(), 3, "{:?}", names);
+
+  // The generated private key goes with the certificate
+  let private_key = issued.private_key.unwrap();
+  let public_key = x509::public_key_pem(&leaf.tbs_certificate.subject_public_key_info).unwrap();
+  key_pair::check(KeyAlgos::Ed25519, &private_key, &public_key).unwrap();
+
+  let recorded = certificate::Entity::find_by_id(issued.certificate.id).one(&*db).await.unwrap().unwrap();
+  assert_eq!(recorded.serial, x509::serial_hex(leaf.tbs_certificate.serial_number.as_bytes()));
+  assert_eq!(recorded.subject, "CN=api.example.com");
+  assert_eq!(recorded.certificate_authority_id, intermediate.id);
+  assert_eq!(recorded.certificate_role_id, Some(role.id));
+  assert_eq!(recorded.not_after - recorded.not_before, chrono::Duration::hours(48));
+  assert!(!recorded.is_ca);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn leaf_certificates_are_issued_for_requests() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let root = certificate_authority::create_root(&*db, organisation.id, new_authority("Root", 365)).await.unwrap();
+  let role = role(&*db, organisation.id, &root, example_template()).await;
+  let pair = key_pair::generate(KeyAlgos::EcdsaP256, 2048).unwrap();
+
+  // The common name comes from the request when none is given
+  let issued = certificate::issue(&*db, &role, IssueRequest {
+    csr: Some(csr(KeyAlgos::EcdsaP256, "svc.example.com", &pair.private_key, None)),
+    ..Default::default()
+  }).await.unwrap();
+  assert!(issued.private_key.is_none());
+  let leaf = x509::parse_certificate(&issued.certificate.certificate).unwrap();
+  assert_eq!(x509::public_key_pem(&leaf.tbs_certificate.subject_public_key_info).unwrap(), pair.public_key);
+  assert_eq!(issued.certificate.not_after - issued.certificate.not_before, chrono::Duration::hours(24));
+
+  // A request signed by some other key proves nothing
+  let other = key_pair::generate(KeyAlgos::EcdsaP256, 2048).unwrap();
+  let forged = certificate::issue(&*db, &role, IssueRequest {
+    csr: Some(csr(KeyAlgos::EcdsaP256, "svc.example.com", &pair.private_key, Some(&other.private_key))),
+    ..Default::default()
+  }).await;
+  assert!(forged.unwrap_err().to_string().contains("signature is invalid"));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn roles_limit_names_and_validity() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let root = certificate_authority::create_root(&*db, organisation.id, new_authority("Root", 365)).await.unwrap();
+  let role = role(&*db, organisation.id, &root, RoleTemplate { allow_subdomains: false, ..example_template() }).await;
+
+  let issue = |common_name: &str, sans: Vec<&str>, ttl_hours: Option<i64>| IssueRequest {
+    common_name: Some(common_name.to_string()),
+    sans: sans.into_iter().map(String::from).collect(),
+    ttl_hours,
+    csr: None,
+  };
+  certificate::issue(&*db, &role, issue("example.com", vec![], None)).await.unwrap();
+  for request in [
+    issue("api.example.com", vec![], None),
+    issue("example.com", vec!["evil.com"], None),
+    issue("example.com", vec!["notexample.com"], None),
+    issue("example.com", vec!["10.0.0.1"], None),
+    issue("example.com", vec![], Some(24 * 365)),
+    issue("example.com", vec![], Some(0)),
+  ] {
+    let err = certificate::issue(&*db, &role, request).await.unwrap_err();
+    assert!(err.to_string().starts_with("Custom Error: [before_save]"), "{}", err);
+  }
+
+  let template = example_template();
+  assert!(template.allows_name("a.b.example.com"));
+  assert!(template.allows_name("EXAMPLE.com"));
+  assert!(!template.allows_name("example.com.evil.com"));
+  assert!(!template.allows_name("bad_name.example.com"));
+
+  let invalid = certificate_role::ActiveModel {
+    organisation_id: Set(organisation.id),
+    certificate_authority_id: Set(root.id),
+    name: Set("broken".to_string()),
+    template: Set(RoleTemplate { ttl_hours: 48, max_ttl_hours: 24, ..Default::default() }),
+    ..Default::default()
+  }.insert(&*db).await;
+  assert!(invalid.unwrap_err().to_string().contains("[before_save]"));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn leaves_never_outlive_their_authority() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let root = certificate_authority::create_root(&*db, organisation.id, new_authority("Root", 1)).await.unwrap();
+  let role = role(&*db, organisation.id, &root, example_template()).await;
+  let issued = certificate::issue(&*db, &role, IssueRequest {
+    common_name: Some("example.com".to_string()),
+    ttl_hours: Some(72),
+    ..Default::default()
+  }).await.unwrap();
+  assert_eq!(issued.certificate.not_after, root.not_after);
+  db.close().await;
+}
@@ -0,0 +1,158 @@
+use std::sync::Arc;
+use chrono::{Duration, TimeZone, Utc};
+use entities::{config::{self, Config, KmsConfig, SecurityConfig}, pki_key::KeyAlgos, organisation, organisation_access_role::OrgRolePermissions, user::LockedState};
+use sea_orm::{sea_query::Expr, ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+use serde_json::json;
+use shared::clock::{self, set_thread_clock, FixedClock};
+use test_support::{factory, TestDb};
+use url::Url;
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+  pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+#[test]
+fn defaults_match_the_previous_constants() {
+  let config = Config::from_sources(None, vars(&[])).unwrap();
+  assert_eq!(config.security.max_login_attempts, 10);
+  assert_eq!(config.security.locked_duration_mins, 60);
+  assert_eq!(config.security.api_key_length, 64);
+  assert_eq!(config.security.magic_link_hash_length, 256);
+}
+
+#[test]
+fn environment_overrides_the_file() {
+  let file = "bind_address = \"0.0.0.0:80\"\n[security]\nmax_login_attempts = 3\nlocked_duration_mins = 5\n";
+  let config = Config::from_sources(Some(file), vars(&[
+    ("SECURITY_MAX_LOGIN_ATTEMPTS", "5"),
+    ("DATABASE_URL", "sqlite::memory:"),
+  ])).unwrap();
+  assert_eq!(config.bind_address, "0.0.0.0:80");
+  assert_eq!(config.database_url.as_deref(), Some("sqlite::memory:"));
+  assert_eq!(config.security.max_login_attempts, 5);
+  assert_eq!(config.security.locked_duration_mins, 5);
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+  assert!(Config::from_sources(None, vars(&[("SECURITY_BCRYPT_COST", "32")])).is_err());
+  assert!(Config::from_sources(None, vars(&[("SECURITY_API_KEY_LENGTH", "lots")])).is_err());
+  assert!(Config::from_sources(Some("[security]\nmagic_link_valid_mins = 0\n"), vars(&[])).is_err());
+  assert!(Config::from_sources(Some("[security]\nunknown = 1\n"), vars(&[])).is_err());
+  assert!(Config::from_sources(None, vars(&[("KEYS_MASTER_KEYS", "2023:not-a-key")])).is_err());
+  assert!(Config::from_sources(None, vars(&[("PKI_PUBLIC_URL", "pki.example.com")])).is_err());
+  assert!(Config::from_sources(None, vars(&[("PKI_CRL_VALID_HOURS", "1")])).is_err());
+  assert!(Config::from_sources(None, vars(&[("KMS_AWS_ENDPOINTS", "ftp://kms.example.com")])).is_err());
+  assert!(Config::from_sources(None, vars(&[("KMS_AWS_ENDPOINTS", "http://localhost:4566/keys")])).is_err());
+}
+
+#[test]
+fn overrides_are_validated() {
+  let security = SecurityConfig::default();
+  assert_eq!(security.with_overrides(&json!({ "max_login_attempts": 3 })).unwrap().max_login_attempts, 3);
+  assert!(security.with_overrides(&json!({ "max_login_attempts": -1 })).is_err());
+  assert!(security.with_overrides(&json!("strict")).is_err());
+
+  // Organisations can only tighten the server's settings
+  assert_eq!(security.check_overrides(&json!({ "max_login_attempts": 3, "bcrypt_cost": 14 })).unwrap().bcrypt_cost, 14);
+  assert_eq!(security.check_overrides(&json!({ "key_algo": "EcdsaP256" })).unwrap().key_algo, KeyAlgos::EcdsaP256);
+  for (overrides, expected) in [
+    (json!({ "max_login_attempts": 50 }), "max_login_attempts cannot be looser"),
+    (json!({ "locked_duration_mins": 1 }), "locked_duration_mins cannot be looser"),
+    (json!({ "magic_link_valid_mins": 600 }), "magic_link_valid_mins cannot be looser"),
+    (json!({ "pass_reset_valid_hours": 100 }), "pass_reset_valid_hours cannot be looser"),
+    (json!({ "bcrypt_cost": 4 }), "bcrypt_cost cannot be looser"),
+    (json!({ "bcrypt_cost": 31 }), "bcrypt_cost cannot be above 16"),
+  ] {
+    let err = security.check_overrides(&overrides).unwrap_err();
+    assert!(err.contains(expected), "{} does not mention {}", err, expected);
+  }
+}
+
+#[test]
+fn kms_endpoints_are_limited_to_the_config() {
+  let endpoint = |url: &str| Url::parse(url).unwrap();
+  let kms = KmsConfig::default();
+  assert!(kms.allows_endpoint(&endpoint("https://kms.us-east-1.amazonaws.com/key")));
+  assert!(!kms.allows_endpoint(&endpoint("http://kms.us-east-1.amazonaws.com/key")));
+  assert!(!kms.allows_endpoint(&endpoint("http://localhost:4566/key")));
+
+  let kms = KmsConfig { aws_endpoints: "http://localhost:4566, https://kms.example.com".to_string(), ..KmsConfig::default() };
+  assert!(kms.allows_endpoint(&endpoint("http://localhost:4566/alias/signing")));
+  assert!(kms.allows_endpoint(&endpoint("https://kms.example.com/key")));
+  assert!(!kms.allows_endpoint(&endpoint("http://localhost:4567/key")));
+  assert!(!kms.allows_endpoint(&endpoint("https://kms.us-east-1.amazonaws.com/key")));
+}
+
+#[async_std::test]
+async fn organisations_inherit_security_overrides() {
+  let db = TestDb::new().await;
+  let parent = organisation::ActiveModel {
+    settings: Set(json!({ "security": { "max_login_attempts": 3, "invitation_valid_days": 2 } })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+  let child = organisation::ActiveModel {
+    parent_organisation_id: Set(Some(parent.id)),
+    settings: Set(json!({ "security": { "invitation_valid_days": 1 } })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+
+  let security = config::security_for_organisation(&*db, child.id).await.unwrap();
+  assert_eq!(security.max_login_attempts, 3);
+  assert_eq!(security.invitation_valid_days, 1);
+  assert_eq!(security.locked_duration_mins, config::get().security.locked_duration_mins);
+
+  let invalid = organisation::ActiveModel {
+    settings: Set(json!({ "security": { "bcrypt_cost": 40 } })),
+    ..factory::new_organisation()
+  }.insert(&*db).await;
+  assert!(invalid.is_err());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn users_follow_their_strictest_organisation() {
+  let clock = Arc::new(FixedClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+  let _clock_guard = set_thread_clock(clock);
+  let db = TestDb::new().await;
+  let lenient = organisation::ActiveModel {
+    settings: Set(json!({ "security": { "max_login_attempts": 5, "otp_length": 8 } })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+  let strict = organisation::ActiveModel {
+    settings: Set(json!({ "security": { "max_login_attempts": 2, "magic_link_valid_mins": 5, "api_key_length": 96 } })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+  let user = factory::user(&*db).await;
+  let role = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+  factory::assign_organisation_role(&*db, user.id, lenient.id, role.id).await;
+  factory::assign_organisation_role(&*db, user.id, strict.id, role.id).await;
+
+  let security = config::security_for_user(&*db, user.id).await.unwrap();
+  assert_eq!((security.max_login_attempts, security.otp_length), (2, 8));
+  assert_eq!((security.magic_link_valid_mins, security.api_key_length), (5, 96));
+  let loner = factory::user(&*db).await;
+  assert_eq!(config::security_for_user(&*db, loner.id).await.unwrap(), config::get().security);
+
+  // Settings stored before they had to tighten the server's are held to it anyway
+  let legacy = factory::organisation(&*db).await;
+  organisation::Entity::update_many()
+    .col_expr(organisation::Column::Settings, Expr::value(json!({ "security": { "max_login_attempts": 50, "pass_reset_valid_hours": 100 } })))
+    .filter(organisation::Column::Id.eq(legacy.id))
+    .exec(&*db)
+    .await
+    .unwrap();
+  factory::assign_organisation_role(&*db, loner.id, legacy.id, role.id).await;
+  assert_eq!(config::security_for_user(&*db, loner.id).await.unwrap(), config::get().security);
+  assert_eq!(config::security_for_organisation(&*db, legacy.id).await.unwrap(), config::get().security);
+
+  // Lockout, links and keys are all held to it
+  let mut active_user = user.into_active_model();
+  active_user.invalid_login_attempts = Set(3);
+  let user = active_user.update(&*db).await.unwrap();
+  assert_eq!(user.locked_state, LockedState::TemporarilyLocked);
+  let link = factory::magiclink(&*db, user.id, None).await;
+  assert_eq!(link.link_hash_expires_at, Some(clock::now() + Duration::minutes(5)));
+  assert_eq!(factory::api_key(&*db, user.id).await.1.len(), 96);
+  db.close().await;
+}
@@ -0,0 +1,81 @@
+use entities::{config, email};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, IntoActiveModel};
+use shared::otp::Outcome;
+use test_support::{factory, TestDb};
+
+#[async_std::test]
+async fn verifying_stamps_verified_at() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let email = factory::email(&*db, user.id).await;
+  assert_eq!(email.verified_at, None);
+
+  let mut active_email = email.into_active_model();
+  active_email.is_verified = Set(true);
+  let email = active_email.update(&*db).await.unwrap();
+  assert!(email.verified_at.is_some());
+
+  let mut active_email = email.into_active_model();
+  active_email.is_verified = Set(false);
+  let email = active_email.update(&*db).await.unwrap();
+  assert_eq!(email.verified_at, None);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn verification_code_locks_out_after_too_many_guesses() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let code = email::generate_verification_code(&config::get().security);
+  let email = email::ActiveModel {
+    verification_code: Set(Some(code.clone())),
+    ..factory::new_email(user.id)
+  }.insert(&*db).await.unwrap();
+  assert!(email.verification_code_expires_at.is_some());
+
+  let max_attempts = config::get().security.otp_max_attempts;
+  let mut email = email;
+  for attempt in 1..=max_attempts {
+    let (updated, outcome) = email::redeem_verification_code(&*db, email, "not the code").await.unwrap();
+    assert_eq!(outcome, Outcome::Rejected { attempts_left: max_attempts - attempt });
+    email = updated;
+  }
+  let (email, outcome) = email::redeem_verification_code(&*db, email, &code).await.unwrap();
+  assert_eq!(outcome, Outcome::LockedOut);
+  assert!(!email.is_verified);
+
+  // Sending a new code starts the count again
+  let code = email::generate_verification_code(&config::get().security);
+  let mut active_email = email.into_active_model();
+  active_email.verification_code = Set(Some(code.clone()));
+  let email = active_email.update(&*db).await.unwrap();
+  let (email, outcome) = email::redeem_verification_code(&*db, email, &code).await.unwrap();
+  assert_eq!(outcome, Outcome::Accepted);
+  assert!(email.is_verified);
+  assert_eq!(email.verification_code, None);
+  assert_eq!(email.verification_code_expires_at, None);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn stale_copies_cannot_share_the_last_attempt() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let code = email::generate_verification_code(&config::get().security);
+  let mut email = email::ActiveModel {
+    verification_code: Set(Some(code.clone())),
+    ..factory::new_email(user.id)
+  }.insert(&*db).await.unwrap();
+  let max_attempts = config::get().security.otp_max_attempts;
+  for _ in 1..max_attempts {
+    email = email::redeem_verification_code(&*db, email, "not the code").await.unwrap().0;
+  }
+
+  let (_, outcome) = email::redeem_verification_code(&*db, email.clone(), "not the code").await.unwrap();
+  assert_eq!(outcome, Outcome::Rejected { attempts_left: 0 });
+  let (email, outcome) = email::redeem_verification_code(&*db, email, &code).await.unwrap();
+  assert_eq!(outcome, Outcome::LockedOut);
+  assert_eq!(email.verification_attempts, max_attempts);
+  assert!(!email.is_verified);
+  db.close().await;
+}
@@ -0,0 +1,116 @@
+use entities::group::{self, MAX_GROUP_DEPTH};
+use entities::group_access_role::GroupRolePermissions;
+use sea_orm::{ActiveModelTrait, ActiveValue::{Set, Unchanged}, DbErr, IntoActiveModel};
+use test_support::{factory, TestDb};
+
+fn rejected(result: Result<impl std::fmt::Debug, DbErr>, expected: &str) {
+  match result {
+    Err(DbErr::Custom(message)) => assert!(message.contains(expected), "{} does not mention {}", message, expected),
+    other => panic!("expected an error mentioning {}, got {:?}", expected, other),
+  }
+}
+
+#[async_std::test]
+async fn roles_are_inherited_down_the_tree_only() {
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let parent = factory::group(&*db, organisation.id).await;
+  let child = factory::child_group(&*db, &parent).await;
+  let grandchild = factory::child_group(&*db, &child).await;
+  let owner = factory::group_role(&*db, GroupRolePermissions::AllowOwner).await;
+  let read_only = factory::group_role(&*db, GroupRolePermissions::AllowReadOnly).await;
+
+  // An owner of the child gains nothing in the parent
+  let user = factory::user(&*db).await;
+  factory::assign_group_role(&*db, user.id, child.id, owner.id).await;
+  assert_eq!(group::find_effective_role(&*db, user.id, parent.id).await.unwrap(), None);
+  assert_eq!(group::find_effective_role(&*db, user.id, child.id).await.unwrap(), Some(owner.clone()));
+  assert_eq!(group::find_effective_role(&*db, user.id, grandchild.id).await.unwrap(), Some(owner.clone()));
+  let memberships = group::find_effective_memberships(&*db, user.id).await.unwrap();
+  assert_eq!(
+    memberships.iter().map(|membership| (membership.group_id, membership.via_group_id, membership.depth)).collect::<Vec<_>>(),
+    vec![(child.id, child.id, 0), (grandchild.id, child.id, 1)],
+  );
+
+  // The closest membership wins over one inherited from further up
+  let reader = factory::user(&*db).await;
+  factory::assign_group_role(&*db, reader.id, parent.id, read_only.id).await;
+  factory::assign_group_role(&*db, reader.id, grandchild.id, owner.id).await;
+  assert_eq!(group::find_effective_role(&*db, reader.id, child.id).await.unwrap(), Some(read_only.clone()));
+  assert_eq!(group::find_effective_role(&*db, reader.id, grandchild.id).await.unwrap(), Some(owner));
+
+  let mut member_ids = vec![user.id, reader.id];
+  member_ids.sort_unstable();
+  assert_eq!(group::find_effective_member_ids(&*db, grandchild.id).await.unwrap(), member_ids);
+  assert_eq!(group::find_effective_member_ids(&*db, parent.id).await.unwrap(), vec![reader.id]);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn descendants_and_ancestors_follow_the_tree() {
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let parent = factory::group(&*db, organisation.id).await;
+  let child = factory::child_group(&*db, &parent).await;
+  let sibling = factory::child_group(&*db, &parent).await;
+  let grandchild = factory::child_group(&*db, &child).await;
+
+  let mut descendant_ids = group::find_descendant_ids(&*db, parent.id).await.unwrap();
+  descendant_ids.sort_unstable();
+  let mut expected = vec![parent.id, child.id, sibling.id, grandchild.id];
+  expected.sort_unstable();
+  assert_eq!(descendant_ids, expected);
+  assert_eq!(group::find_descendant_ids(&*db, sibling.id).await.unwrap(), vec![sibling.id]);
+  assert_eq!(group::find_ancestor_ids(&*db, grandchild.id).await.unwrap(), vec![grandchild.id, child.id, parent.id]);
+
+  // Removing the parent detaches its children
+  let mut active_child = child.clone().into_active_model();
+  active_child.parent_group_id = Set(None);
+  active_child.update(&*db).await.unwrap();
+  assert_eq!(group::find_ancestor_ids(&*db, grandchild.id).await.unwrap(), vec![grandchild.id, child.id]);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn parents_must_keep_the_tree_acyclic_and_in_one_organisation() {
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let parent = factory::group(&*db, organisation.id).await;
+  let child = factory::child_group(&*db, &parent).await;
+  let grandchild = factory::child_group(&*db, &child).await;
+
+  let mut active_parent = parent.clone().into_active_model();
+  active_parent.parent_group_id = Set(Some(grandchild.id));
+  rejected(active_parent.update(&*db).await, "parent_group_id would create a cycle");
+  // Partial updates are checked the same way
+  rejected(group::ActiveModel {
+    id: Unchanged(parent.id),
+    parent_group_id: Set(Some(parent.id)),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "parent_group_id would create a cycle");
+
+  let other = factory::group(&*db, factory::organisation(&*db).await.id).await;
+  rejected(group::ActiveModel {
+    id: Unchanged(child.id),
+    parent_group_id: Set(Some(other.id)),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "parent group must belong to the same organisation");
+
+  // Nor can a group leave its organisation, its parent and children would stay behind
+  let mut moved = child.clone().into_active_model();
+  moved.organisation_id = Set(other.organisation_id);
+  rejected(moved.update(&*db).await, "groups cannot move to another organisation");
+  let mut unmoved = child.clone().into_active_model();
+  unmoved.organisation_id = Set(organisation.id);
+  unmoved.update(&*db).await.unwrap();
+
+  let mut deepest = grandchild;
+  for _ in 2..MAX_GROUP_DEPTH {
+    deepest = factory::child_group(&*db, &deepest).await;
+  }
+  rejected(group::ActiveModel {
+    parent_group_id: Set(Some(deepest.id)),
+    ..factory::new_group(organisation.id)
+  }.insert(&*db).await, "groups cannot be nested more than");
+  db.close().await;
+}
@@ -0,0 +1,230 @@
+use std::sync::Arc;
+use chrono::{Duration, TimeZone, Utc};
+use entities::{
+  email, group_access_role::GroupRolePermissions, user,
+  invitation::{self, InvitationStatus, NewInvitation},
+  key_service::{self, MasterKeys},
+  organisation_access_role::OrgRolePermissions,
+  users_groups_group_access_roles, users_organisations_organisations_access_roles,
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, DbErr, EntityTrait, QueryFilter};
+use serde_email::Email;
+use shared::{clock::{self, set_thread_clock, FixedClock}, OrgId, UserId};
+use test_support::{factory, TestDb};
+use uuid::Uuid;
+
+fn rejected(result: Result<impl std::fmt::Debug, DbErr>, expected: &str) {
+  match result {
+    Err(DbErr::Custom(message)) => assert!(message.contains(expected), "{} does not mention {}", message, expected),
+    other => panic!("expected an error mentioning {}, got {:?}", expected, other),
+  }
+}
+
+fn new_invitation(email_address: &str, organisation_access_role_id: Uuid) -> NewInvitation {
+  NewInvitation {
+    email_address: Email::new(email_address.to_string()).unwrap(),
+    organisation_access_role_id,
+    group_id: None,
+    group_access_role_id: None,
+    expires_in_hours: None,
+  }
+}
+
+/// A user with a single address and nothing else
+async fn invitee<C: sea_orm::ConnectionTrait>(db: &C, verified: bool) -> (UserId, email::Model) {
+  let user = factory::user(db).await;
+  let address = email::ActiveModel { is_verified: Set(verified), ..factory::new_email(user.id) }.insert(db).await.unwrap();
+  (user.id, address)
+}
+
+/// An organisation and a member of it holding `permissions`
+async fn inviter<C: sea_orm::ConnectionTrait>(db: &C, permissions: OrgRolePermissions) -> (OrgId, UserId) {
+  let organisation = factory::organisation(db).await;
+  let user = factory::user(db).await;
+  let role = factory::organisation_role(db, permissions).await;
+  factory::assign_organisation_role(db, user.id, organisation.id, role.id).await;
+  (organisation.id, user.id)
+}
+
+#[async_std::test]
+async fn inviters_cannot_hand_out_more_than_they_hold() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let (organisation_id, admin_id) = inviter(&*db, OrgRolePermissions::AllowAdmin).await;
+  let owner = factory::organisation_role(&*db, OrgRolePermissions::AllowOwner).await;
+  let read_write = factory::organisation_role(&*db, OrgRolePermissions::AllowReadWrite).await;
+
+  rejected(
+    invitation::create(&*db, organisation_id, admin_id, new_invitation("new@example.com", owner.id)).await,
+    "the organisation role grants more than your own",
+  );
+  let invitation = invitation::create(&*db, organisation_id, admin_id, new_invitation("New@Example.com", read_write.id)).await.unwrap();
+  assert_eq!(invitation.email_address, "new@example.com");
+  // Roles held in another organisation don't count
+  let (other_id, _) = inviter(&*db, OrgRolePermissions::AllowOwner).await;
+  rejected(
+    invitation::create(&*db, other_id, admin_id, new_invitation("new@example.com", read_write.id)).await,
+    "the organisation role grants more than your own",
+  );
+
+  // Groups have to be in the organisation and their role covered by one of the inviter's
+  let group = factory::group(&*db, organisation_id).await;
+  let group_owner = factory::group_role(&*db, GroupRolePermissions::AllowOwner).await;
+  let group_reader = factory::group_role(&*db, GroupRolePermissions::AllowReadOnly).await;
+  let in_group = |group_id, group_access_role_id| NewInvitation {
+    group_id: Some(group_id),
+    group_access_role_id: Some(group_access_role_id),
+    ..new_invitation("new@example.com", read_write.id)
+  };
+  rejected(invitation::create(&*db, organisation_id, admin_id, in_group(group.id, group_owner.id)).await, "the group role grants more than your own");
+  invitation::create(&*db, organisation_id, admin_id, in_group(group.id, group_reader.id)).await.unwrap();
+  factory::assign_group_role(&*db, admin_id, group.id, group_owner.id).await;
+  invitation::create(&*db, organisation_id, admin_id, in_group(group.id, group_owner.id)).await.unwrap();
+  let foreign = factory::group(&*db, other_id).await;
+  rejected(invitation::create(&*db, organisation_id, admin_id, in_group(foreign.id, group_reader.id)).await, "group_id does not belong to the organisation");
+  rejected(invitation::create(&*db, organisation_id, admin_id, NewInvitation {
+    group_id: Some(group.id),
+    ..new_invitation("new@example.com", read_write.id)
+  }).await, "must be set together");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn expiry_is_capped_by_the_organisation() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let clock = Arc::new(FixedClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+  let _clock_guard = set_thread_clock(clock.clone());
+  let db = TestDb::new().await;
+  let (organisation_id, owner_id) = inviter(&*db, OrgRolePermissions::AllowOwner).await;
+  let role = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+
+  let invitation = invitation::create(&*db, organisation_id, owner_id, new_invitation("a@example.com", role.id)).await.unwrap();
+  assert_eq!(invitation.expires_at, clock::now() + Duration::days(7));
+  let invitation = invitation::create(&*db, organisation_id, owner_id, NewInvitation {
+    expires_in_hours: Some(7 * 24),
+    ..new_invitation("a@example.com", role.id)
+  }).await.unwrap();
+  assert_eq!(invitation.expires_at, clock::now() + Duration::days(7));
+  for hours in [0, 7 * 24 + 1] {
+    rejected(invitation::create(&*db, organisation_id, owner_id, NewInvitation {
+      expires_in_hours: Some(hours),
+      ..new_invitation("a@example.com", role.id)
+    }).await, "expires_in_hours must be between 1 and 168");
+  }
+
+  // Expired invitations can't be accepted
+  let (user_id, address) = invitee(&*db, true).await;
+  let invitation = invitation::create(&*db, organisation_id, owner_id, NewInvitation {
+    expires_in_hours: Some(1),
+    ..new_invitation(&address.email_address, role.id)
+  }).await.unwrap();
+  clock.advance(Duration::hours(2));
+  rejected(invitation::accept(&*db, &invitation.token, Some(user_id)).await, "invitation is no longer open");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn only_the_verified_owner_of_the_address_can_accept() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let (organisation_id, owner_id) = inviter(&*db, OrgRolePermissions::AllowOwner).await;
+  let role = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+  let (user_id, address) = invitee(&*db, false).await;
+  let invitation = invitation::create(&*db, organisation_id, owner_id, new_invitation(&address.email_address.to_uppercase(), role.id)).await.unwrap();
+
+  let (stranger_id, _) = invitee(&*db, true).await;
+  rejected(invitation::accept(&*db, &invitation.token, Some(stranger_id)).await, "the invitation is for another email address");
+  rejected(invitation::accept(&*db, &invitation.token, Some(user_id)).await, "before accepting the invitation");
+  assert!(!email::Entity::find_by_id(address.id).one(&*db).await.unwrap().unwrap().is_verified);
+  assert!(matches!(invitation::accept(&*db, "nonsense", Some(user_id)).await, Err(DbErr::RecordNotFound(_))));
+
+  email::ActiveModel { is_verified: Set(true), ..address.clone().into() }.update(&*db).await.unwrap();
+  let accepted = invitation::accept(&*db, &invitation.token, Some(user_id)).await.unwrap();
+  assert!(!accepted.user_created);
+  let accepted = accepted.invitation;
+  assert_eq!(accepted.status, InvitationStatus::Accepted);
+  assert_eq!((accepted.accepted_user_id, accepted.email_id), (Some(user_id), Some(address.id)));
+  assert!(accepted.responded_at.is_some());
+  let membership = users_organisations_organisations_access_roles::Entity::find_by_id((user_id, organisation_id)).one(&*db).await.unwrap().unwrap();
+  assert_eq!(membership.organisation_access_role_id, role.id);
+
+  // The token only works once, whoever tries
+  rejected(invitation::accept(&*db, &invitation.token, Some(user_id)).await, "invitation is no longer open");
+  rejected(invitation::decline(&*db, &invitation.token).await, "invitation is no longer open");
+  rejected(invitation::revoke(&*db, &accepted).await, "only pending invitations can be revoked");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn accepting_without_an_account_creates_the_user() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let (organisation_id, owner_id) = inviter(&*db, OrgRolePermissions::AllowOwner).await;
+  let role = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+
+  // An unverified claim on the address doesn't stop the invitee
+  let (squatter_id, _) = invitee(&*db, false).await;
+  let squatted = email::Entity::find().filter(email::Column::UserId.eq(squatter_id)).one(&*db).await.unwrap().unwrap();
+  let invitation = invitation::create(&*db, organisation_id, owner_id, new_invitation(&squatted.email_address, role.id)).await.unwrap();
+  let accepted = invitation::accept(&*db, &invitation.token, None).await.unwrap();
+  assert!(accepted.user_created);
+  let user_id = accepted.invitation.accepted_user_id.unwrap();
+  assert_ne!(user_id, squatter_id);
+  let address = email::Entity::find_by_id(accepted.invitation.email_id.unwrap()).one(&*db).await.unwrap().unwrap();
+  assert_eq!((address.user_id, address.email_address.as_str()), (user_id, invitation.email_address.as_str()));
+  assert!(address.is_verified && address.is_primary && address.verified_at.is_some());
+  let membership = users_organisations_organisations_access_roles::Entity::find_by_id((user_id, organisation_id)).one(&*db).await.unwrap().unwrap();
+  assert_eq!(membership.organisation_access_role_id, role.id);
+
+  // Once someone has verified the address they have to sign in to accept
+  let invitation = invitation::create(&*db, organisation_id, owner_id, new_invitation(&address.email_address, role.id)).await.unwrap();
+  rejected(invitation::accept(&*db, &invitation.token, None).await, "sign in as");
+  assert_eq!(user::Entity::find().all(&*db).await.unwrap().len(), 3);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn accepting_keeps_the_roles_already_held() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let (organisation_id, owner_id) = inviter(&*db, OrgRolePermissions::AllowOwner).await;
+  let admin = factory::organisation_role(&*db, OrgRolePermissions::AllowAdmin).await;
+  let read_only = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+  let group = factory::group(&*db, organisation_id).await;
+  let group_admin = factory::group_role(&*db, GroupRolePermissions::AllowAdmin).await;
+  let (user_id, address) = invitee(&*db, true).await;
+  factory::assign_organisation_role(&*db, user_id, organisation_id, read_only.id).await;
+
+  let invitation = invitation::create(&*db, organisation_id, owner_id, NewInvitation {
+    group_id: Some(group.id),
+    group_access_role_id: Some(group_admin.id),
+    ..new_invitation(&address.email_address, admin.id)
+  }).await.unwrap();
+  invitation::accept(&*db, &invitation.token, Some(user_id)).await.unwrap();
+  let membership = users_organisations_organisations_access_roles::Entity::find_by_id((user_id, organisation_id)).one(&*db).await.unwrap().unwrap();
+  assert_eq!(membership.organisation_access_role_id, read_only.id);
+  let group_membership = users_groups_group_access_roles::Entity::find_by_id((user_id, group.id)).one(&*db).await.unwrap().unwrap();
+  assert_eq!(group_membership.group_access_role_id, group_admin.id);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn declined_and_revoked_invitations_close() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let (organisation_id, owner_id) = inviter(&*db, OrgRolePermissions::AllowOwner).await;
+  let role = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+  let (user_id, address) = invitee(&*db, true).await;
+
+  let declined = invitation::create(&*db, organisation_id, owner_id, new_invitation(&address.email_address, role.id)).await.unwrap();
+  assert_eq!(invitation::decline(&*db, &declined.token).await.unwrap().status, InvitationStatus::Declined);
+  rejected(invitation::accept(&*db, &declined.token, Some(user_id)).await, "invitation is no longer open");
+
+  let revoked = invitation::create(&*db, organisation_id, owner_id, new_invitation(&address.email_address, role.id)).await.unwrap();
+  assert_eq!(invitation::revoke(&*db, &revoked).await.unwrap().status, InvitationStatus::Revoked);
+  rejected(invitation::accept(&*db, &revoked.token, Some(user_id)).await, "invitation is no longer open");
+  // A stale copy still pending can't reopen it either
+  rejected(invitation::revoke(&*db, &revoked).await, "invitation is no longer open");
+  assert!(users_organisations_organisations_access_roles::Entity::find_by_id((user_id, organisation_id)).one(&*db).await.unwrap().is_none());
+  db.close().await;
+}
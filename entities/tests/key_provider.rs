@@ -0,0 +1,234 @@
+use async_trait::async_trait;
+use entities::{
+  key_pair::{self, SignatureScheme},
+  key_provider::{self, AwsCredentials, KeyProvider, LocalProvider, Pkcs11Provider, Pkcs11Uri},
+  key_service::{self, MasterKeys},
+  pki_key::{self, KeyAlgos, Owner},
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, EntityTrait};
+use zeroize::Zeroizing;
+use test_support::{factory, TestDb};
+
+const MESSAGE: &[u8] = b"a message to sign";
+
+/// Holds a generated key pair in memory, standing in for an HSM
+struct FakeHsm(key_pair::KeyPair);
+
+#[async_trait]
+impl KeyProvider for FakeHsm {
+  async fn public_key(&self, _key: &pki_key::Model) -> Result<String, String> {
+    Ok(self.0.public_key.clone())
+  }
+
+  async fn sign(&self, key: &pki_key::Model, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, String> {
+    key_pair::sign(key.algo, &self.0.private_key, scheme, message)
+  }
+
+  async fn sign_digest(&self, key: &pki_key::Model, scheme: SignatureScheme, digest: &[u8]) -> Result<Vec<u8>, String> {
+    key_pair::sign_digest(key.algo, &self.0.private_key, scheme, digest)
+  }
+
+  async fn decrypt(&self, key: &pki_key::Model, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    key_pair::decrypt(key.algo, &self.0.private_key, ciphertext)
+  }
+}
+
+async fn sign_and_verify(provider: &dyn KeyProvider, key: &pki_key::Model) {
+  let public_key = key.public_key.as_deref().unwrap();
+  let scheme = SignatureScheme::default_for(key.algo);
+  let signature = provider.sign(key, scheme, MESSAGE).await.unwrap();
+  key_pair::verify(key.algo, public_key, scheme, MESSAGE, &signature).unwrap();
+  assert!(key_pair::verify(key.algo, public_key, scheme, b"another message", &signature).is_err());
+}
+
+#[async_std::test]
+async fn local_keys_sign_with_every_scheme() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  for algo in [KeyAlgos::RSA, KeyAlgos::Ed25519, KeyAlgos::EcdsaP256, KeyAlgos::EcdsaP384] {
+    let key = pki_key::generate(&*db, Owner::User(user.id), Some(algo)).await.unwrap();
+    let schemes: Vec<SignatureScheme> = [
+      SignatureScheme::RsaPkcs1v15Sha256,
+      SignatureScheme::RsaPssSha256,
+      SignatureScheme::EcdsaSha256,
+      SignatureScheme::EcdsaSha384,
+      SignatureScheme::Ed25519,
+    ].into_iter().filter(|scheme| scheme.supports(algo)).collect();
+    assert!(!schemes.is_empty());
+    for scheme in schemes {
+      let signature = key_provider::sign(&key, scheme, MESSAGE).await.unwrap();
+      key_pair::verify(algo, key.public_key.as_deref().unwrap(), scheme, MESSAGE, &signature).unwrap();
+    }
+    sign_and_verify(&LocalProvider, &key).await;
+  }
+  db.close().await;
+}
+
+#[async_std::test]
+async fn digests_sign_like_their_messages() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let cases = [
+    (KeyAlgos::RSA, SignatureScheme::RsaPkcs1v15Sha256),
+    (KeyAlgos::RSA, SignatureScheme::RsaPssSha256),
+    (KeyAlgos::EcdsaP256, SignatureScheme::EcdsaSha256),
+    (KeyAlgos::EcdsaP384, SignatureScheme::EcdsaSha384),
+  ];
+  for (algo, scheme) in cases {
+    let key = pki_key::generate(&*db, Owner::User(user.id), Some(algo)).await.unwrap();
+    let public_key = key.public_key.as_deref().unwrap();
+    let digest = scheme.digest(MESSAGE).unwrap();
+    let signature = key_provider::sign_digest(&key, scheme, &digest).await.unwrap();
+    key_pair::verify(algo, public_key, scheme, MESSAGE, &signature).unwrap();
+    key_pair::verify_digest(algo, public_key, scheme, &digest, &signature).unwrap();
+    let signature = key_provider::sign(&key, scheme, MESSAGE).await.unwrap();
+    key_pair::verify_digest(algo, public_key, scheme, &digest, &signature).unwrap();
+    assert!(key_pair::verify_digest(algo, public_key, scheme, &scheme.digest(b"another message").unwrap(), &signature).is_err());
+    // Digests must be the length of the scheme's hash
+    assert!(key_provider::sign_digest(&key, scheme, &digest[1..]).await.is_err());
+  }
+  let key = pki_key::generate(&*db, Owner::User(user.id), Some(KeyAlgos::Ed25519)).await.unwrap();
+  assert!(key_provider::sign_digest(&key, SignatureScheme::Ed25519, &[0; 32]).await.is_err());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn schemes_must_match_the_key() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let key = pki_key::generate(&*db, Owner::User(user.id), Some(KeyAlgos::Ed25519)).await.unwrap();
+  assert!(key_provider::sign(&key, SignatureScheme::RsaPssSha256, MESSAGE).await.is_err());
+  assert!(key_provider::sign(&key, SignatureScheme::EcdsaSha256, MESSAGE).await.is_err());
+  // Only RSA keys decrypt
+  assert!(key_provider::decrypt(&key, b"ciphertext").await.is_err());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn rsa_keys_decrypt() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let key = pki_key::generate(&*db, Owner::User(user.id), Some(KeyAlgos::RSA)).await.unwrap();
+  let ciphertext = key_pair::encrypt(key.algo, key.public_key.as_deref().unwrap(), b"secret").unwrap();
+  assert_eq!(key_provider::decrypt(&key, &ciphertext).await.unwrap().as_slice(), b"secret");
+  assert!(key_provider::decrypt(&key, b"not ciphertext").await.is_err());
+  db.close().await;
+}
+
+#[test]
+fn key_urls_are_checked() {
+  key_provider::check_url("pkcs11:token=users;object=signing").unwrap();
+  key_provider::check_url("https://kms.us-east-1.amazonaws.com/1234abcd-12ab-34cd-56ef-1234567890ab").unwrap();
+  assert!(key_provider::check_url("pkcs11:object=signing").is_err());
+  assert!(key_provider::check_url("pkcs11:token=users").is_err());
+  assert!(key_provider::check_url("https://kms.us-east-1.amazonaws.com/").is_err());
+  // Signed requests only go to the configured endpoints
+  assert!(key_provider::check_url("https://kms.eu-west-1.amazonaws.com/1234abcd-12ab-34cd-56ef-1234567890ab").is_err());
+  assert!(key_provider::check_url("http://localhost:4566/alias/signing").is_err());
+  assert!(key_provider::check_url("http://169.254.169.254/latest").is_err());
+  assert!(key_provider::check_url("ftp://kms.example.com/key").is_err());
+  assert!(key_provider::check_url("not a url").is_err());
+}
+
+#[test]
+fn pkcs11_urls_are_parsed() {
+  let uri = Pkcs11Uri::parse("pkcs11:token=My%20Token;object=signing;id=%01%02?pin-value=1234").unwrap();
+  assert_eq!(uri, Pkcs11Uri { token: "My Token".to_string(), object: Some("signing".to_string()), id: Some(vec![1, 2]) });
+  let uri = Pkcs11Uri::parse("pkcs11:id=%A0;token=users").unwrap();
+  assert_eq!(uri.object, None);
+  assert_eq!(uri.id, Some(vec![0xa0]));
+}
+
+/// The example request from the AWS Signature Version 4 documentation
+#[test]
+fn aws_requests_are_signed() {
+  let credentials = AwsCredentials {
+    access_key_id: "AKIDEXAMPLE".to_string(),
+    secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+  };
+  let authorization = key_provider::sign_v4(
+    &credentials,
+    "us-east-1",
+    "iam",
+    "GET",
+    "/",
+    "Version=2010-05-08&Action=ListUsers",
+    &[
+      ("Content-Type", "application/x-www-form-urlencoded; charset=utf-8"),
+      ("Host", "iam.amazonaws.com"),
+      ("X-Amz-Date", "20150830T123600Z"),
+    ],
+    b"",
+    "20150830T123600Z",
+  );
+  assert_eq!(
+    authorization,
+    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+     SignedHeaders=content-type;host;x-amz-date, \
+     Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7",
+  );
+}
+
+#[async_std::test]
+async fn registered_keys_sign_through_their_provider() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let hsm = FakeHsm(key_pair::generate(KeyAlgos::EcdsaP256, 0).unwrap());
+  let key = key_provider::register(&*db, &hsm, Owner::Organisation(organisation.id), KeyAlgos::EcdsaP256, "pkcs11:token=users;object=org")
+    .await
+    .unwrap();
+  assert_eq!(key.private_key, None);
+  assert_eq!(key.public_key.as_deref(), Some(hsm.0.public_key.as_str()));
+  assert_eq!(key.organisation_id, Some(organisation.id));
+  sign_and_verify(&hsm, &key).await;
+  // Nothing is sealed in the database to sign with
+  assert!(LocalProvider.sign(&key, SignatureScheme::EcdsaSha256, MESSAGE).await.is_err());
+
+  assert!(key_provider::register(&*db, &hsm, Owner::Organisation(organisation.id), KeyAlgos::EcdsaP256, "pkcs11:object=org").await.is_err());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn keys_with_a_url_cannot_have_a_private_key() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let pair = key_pair::generate(KeyAlgos::Ed25519, 0).unwrap();
+  let result = pki_key::ActiveModel {
+    id: Set(uuid::Uuid::new_v4()),
+    user_id: Set(Some(user.id)),
+    private_key: Set(Some(pair.private_key)),
+    aws_kms_url: Set(Some("pkcs11:token=users;object=user".to_string())),
+    algo: Set(KeyAlgos::Ed25519),
+    ..Default::default()
+  }.insert(&*db).await;
+  assert!(result.is_err());
+
+  let key = pki_key::Entity::find_by_id(user.pki_key_id.unwrap()).one(&*db).await.unwrap().unwrap();
+  let mut active_key: pki_key::ActiveModel = key.into();
+  active_key.aws_kms_url = Set(Some("not a url".to_string()));
+  assert!(active_key.update(&*db).await.is_err());
+  db.close().await;
+}
+
+/// Runs against SoftHSM or another token. `TEST_PKCS11_MODULE`, `TEST_PKCS11_PIN` and
+/// `TEST_PKCS11_URI` must name an EC P-256 key pair, run it with `cargo test -- --include-ignored`.
+#[async_std::test]
+#[ignore = "needs SoftHSM and TEST_PKCS11_MODULE, TEST_PKCS11_PIN and TEST_PKCS11_URI"]
+async fn pkcs11_keys_sign() {
+  let module = std::env::var("TEST_PKCS11_MODULE").expect("TEST_PKCS11_MODULE must name the PKCS#11 module");
+  let pin = std::env::var("TEST_PKCS11_PIN").expect("TEST_PKCS11_PIN must be the token's user PIN");
+  let url = std::env::var("TEST_PKCS11_URI").expect("TEST_PKCS11_URI must name an EC P-256 key pair");
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let provider = Pkcs11Provider::new(&module, &pin);
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let key = key_provider::register(&*db, &provider, Owner::User(user.id), KeyAlgos::EcdsaP256, &url).await.unwrap();
+  sign_and_verify(&provider, &key).await;
+  db.close().await;
+}
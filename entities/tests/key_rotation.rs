@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use chrono::Duration;
+use entities::{
+  certificate_authority::{self, NewAuthority},
+  key_pair::{self, SignatureScheme},
+  key_provider,
+  key_service::{self, MasterKeys},
+  organisation,
+  pki_key::{self, KeyAlgos, KeyStatus, Owner},
+  user,
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, DbErr, EntityTrait, IntoActiveModel};
+use serde_json::json;
+use shared::clock::{set_thread_clock, FixedClock};
+use test_support::{factory, TestDb};
+
+fn new_authority(name: &str, key_id: Option<uuid::Uuid>) -> NewAuthority {
+  NewAuthority {
+    name: name.to_string(),
+    subject: format!("CN={}", name),
+    ttl_days: 365,
+    key_id,
+    algo: None,
+    max_path_length: None,
+  }
+}
+
+fn rejected(result: Result<impl std::fmt::Debug, DbErr>, expected: &str) {
+  match result {
+    Err(DbErr::Custom(message)) => assert!(message.contains(expected), "{} does not mention {}", message, expected),
+    other => panic!("expected an error mentioning {}, got {:?}", expected, other),
+  }
+}
+
+async fn reload<C: sea_orm::ConnectionTrait>(db: &C, key: &pki_key::Model) -> pki_key::Model {
+  pki_key::Entity::find_by_id(key.id).one(db).await.unwrap().unwrap()
+}
+
+#[async_std::test]
+async fn rotating_keeps_old_versions_for_verification() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let first = pki_key::find_for_owner(&*db, Owner::User(user.id), None).await.unwrap().unwrap();
+  assert_eq!((first.key_ring_id, first.version, first.status), (first.id, 1, KeyStatus::Active));
+  let scheme = SignatureScheme::default_for(first.algo);
+  let old_signature = key_provider::sign(&first, scheme, b"before").await.unwrap();
+
+  let second = pki_key::rotate(&*db, &first).await.unwrap();
+  assert_eq!((second.key_ring_id, second.version, second.status), (first.id, 2, KeyStatus::Active));
+  assert_eq!(second.algo, first.algo);
+  assert_ne!(second.public_key, first.public_key);
+  let first = reload(&*db, &first).await;
+  assert_eq!(first.status, KeyStatus::VerifyOnly);
+  // The user's current key follows the rotation
+  let user = user::Entity::find_by_id(user.id).one(&*db).await.unwrap().unwrap();
+  assert_eq!(user.pki_key_id, Some(second.id));
+
+  // Only the new version signs, the old one still verifies
+  assert!(key_provider::sign(&first, scheme, b"after").await.is_err());
+  key_provider::sign(&second, scheme, b"after").await.unwrap();
+  key_pair::verify(first.algo, first.public_key.as_deref().unwrap(), scheme, b"before", &old_signature).unwrap();
+  let verifying = pki_key::verification_keys(&*db, &second).await.unwrap();
+  assert_eq!(verifying.iter().map(|key| key.version).collect::<Vec<_>>(), [2, 1]);
+
+  // Rotating from any version continues the same key
+  let third = pki_key::rotate(&*db, &first).await.unwrap();
+  assert_eq!((third.key_ring_id, third.version), (first.id, 3));
+  assert_eq!(reload(&*db, &second).await.status, KeyStatus::VerifyOnly);
+  let versions = pki_key::versions(&*db, &third).await.unwrap();
+  assert_eq!(versions.iter().map(|key| (key.version, key.status)).collect::<Vec<_>>(), [
+    (3, KeyStatus::Active),
+    (2, KeyStatus::VerifyOnly),
+    (1, KeyStatus::VerifyOnly),
+  ]);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn older_versions_are_retired_and_destroyed() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let first = pki_key::find_for_owner(&*db, Owner::Organisation(organisation.id), None).await.unwrap().unwrap();
+  let second = pki_key::rotate(&*db, &first).await.unwrap();
+  let organisation = organisation::Entity::find_by_id(organisation.id).one(&*db).await.unwrap().unwrap();
+  assert_eq!(organisation.pki_key_id, Some(second.id));
+
+  // The active version only changes by rotating
+  rejected(pki_key::set_status(&*db, &second, KeyStatus::Retired).await, "only changes by rotating");
+  let first = reload(&*db, &first).await;
+  rejected(pki_key::set_status(&*db, &first, KeyStatus::Active).await, "only changes by rotating");
+
+  let retired = pki_key::set_status(&*db, &first, KeyStatus::Retired).await.unwrap();
+  assert_eq!(retired.status, KeyStatus::Retired);
+  assert_eq!(pki_key::verification_keys(&*db, &second).await.unwrap().len(), 1);
+  assert!(key_provider::decrypt(&retired, b"ciphertext").await.unwrap_err().contains("Retired"));
+  let verify_only = pki_key::set_status(&*db, &retired, KeyStatus::VerifyOnly).await.unwrap();
+  assert_eq!(verify_only.status, KeyStatus::VerifyOnly);
+
+  let destroyed = pki_key::set_status(&*db, &verify_only, KeyStatus::Destroyed).await.unwrap();
+  assert_eq!(destroyed.status, KeyStatus::Destroyed);
+  assert_eq!((&destroyed.private_key, &destroyed.encrypted_data_key), (&None, &None));
+  assert!(destroyed.public_key.is_some());
+  rejected(pki_key::set_status(&*db, &destroyed, KeyStatus::VerifyOnly).await, "has been destroyed");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn groups_use_their_active_version() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let group = factory::group(&*db, organisation.id).await;
+  let first = pki_key::generate(&*db, Owner::Group(group.id), Some(KeyAlgos::EcdsaP256)).await.unwrap();
+  let second = pki_key::rotate(&*db, &first).await.unwrap();
+  let found = pki_key::find_for_owner(&*db, Owner::Group(group.id), None).await.unwrap().unwrap();
+  assert_eq!(found.id, second.id);
+  let keys = pki_key::list_for_owner(&*db, Owner::Group(group.id)).await.unwrap();
+  assert_eq!(keys.iter().map(|key| key.id).collect::<Vec<_>>(), [second.id, first.id]);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn authority_keys_are_not_rotated() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let authority = certificate_authority::create_root(&*db, organisation.id, new_authority("Root", None)).await.unwrap();
+  let key = pki_key::Entity::find_by_id(authority.pki_key_id).one(&*db).await.unwrap().unwrap();
+  rejected(pki_key::rotate(&*db, &key).await, "create a new authority instead");
+
+  // Nor do new CAs sign with a version that has been replaced
+  let first = pki_key::find_for_owner(&*db, Owner::Organisation(organisation.id), None).await.unwrap().unwrap();
+  pki_key::rotate(&*db, &first).await.unwrap();
+  let result = certificate_authority::create_root(&*db, organisation.id, new_authority("Old", Some(first.id))).await;
+  rejected(result, "is not an active version");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn organisations_rotate_on_a_schedule() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let clock = Arc::new(FixedClock::default());
+  let _clock_guard = set_thread_clock(clock.clone());
+  let db = TestDb::new().await;
+  let rotating = organisation::ActiveModel {
+    settings: Set(json!({ "security": { "key_rotation_days": 30 } })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+  let group = factory::group(&*db, rotating.id).await;
+  let group_key = pki_key::generate(&*db, Owner::Group(group.id), None).await.unwrap();
+  // A sub organisation with a key of its own still inherits the setting
+  let child = factory::child_organisation(&*db, rotating.id).await;
+  let child_key = pki_key::generate(&*db, Owner::Organisation(child.id), None).await.unwrap();
+  let mut active_child = child.clone().into_active_model();
+  active_child.pki_key_id = Set(Some(child_key.id));
+  active_child.update(&*db).await.unwrap();
+  let fixed = factory::organisation(&*db).await;
+
+  clock.advance(Duration::days(29));
+  assert_eq!(pki_key::rotate_due(&*db).await.unwrap(), 0);
+  clock.advance(Duration::days(2));
+  // The organisation, its group and the child organisation inheriting the setting
+  assert_eq!(pki_key::rotate_due(&*db).await.unwrap(), 3);
+  assert_eq!(pki_key::rotate_due(&*db).await.unwrap(), 0);
+
+  for organisation_id in [rotating.id, child.id] {
+    let current = pki_key::find_for_owner(&*db, Owner::Organisation(organisation_id), None).await.unwrap().unwrap();
+    assert_eq!(current.version, 2);
+  }
+  let current = pki_key::find_for_owner(&*db, Owner::Organisation(child.id), None).await.unwrap().unwrap();
+  assert_eq!(current.key_ring_id, child_key.id);
+  let current = pki_key::find_for_owner(&*db, Owner::Group(group.id), None).await.unwrap().unwrap();
+  assert_eq!((current.key_ring_id, current.version), (group_key.id, 2));
+  let current = pki_key::find_for_owner(&*db, Owner::Organisation(fixed.id), None).await.unwrap().unwrap();
+  assert_eq!(current.version, 1);
+  db.close().await;
+}
@@ -0,0 +1,124 @@
+use entities::{key_pair, key_service::{self, MasterKeys}, pki_key::{self, KeyAlgos}};
+use sea_orm::{sea_query::Expr, ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+use test_support::{factory, TestDb};
+
+/// A master key of 32 bytes built from one base64 digit
+fn master_key(id: &str, digit: char) -> String {
+  format!("{}:{}A=", id, digit.to_string().repeat(42))
+}
+
+async fn find_key(db: &TestDb, id: uuid::Uuid) -> pki_key::Model {
+  pki_key::Entity::find_by_id(id).one(&**db).await.unwrap().unwrap()
+}
+
+#[test]
+fn master_keys_are_validated() {
+  assert_eq!(MasterKeys::parse(&master_key("first", 'B'), "").unwrap().active_id(), "first");
+  let both = format!("{},{}", master_key("first", 'B'), master_key("second", 'C'));
+  assert_eq!(MasterKeys::parse(&both, "second").unwrap().active_id(), "second");
+  assert!(MasterKeys::parse(&both, "third").is_err());
+  assert!(MasterKeys::parse("first:c2hvcnQ=", "").is_err());
+  assert!(MasterKeys::parse(&format!("{},{}", master_key("first", 'B'), master_key("first", 'C')), "").is_err());
+}
+
+#[async_std::test]
+async fn private_keys_are_sealed() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let key = find_key(&db, user.pki_key_id.unwrap()).await;
+  let private_key = key.private_key.clone().unwrap();
+  assert!(!private_key.contains("PRIVATE KEY"));
+  assert!(key_service::is_sealed(&key));
+  assert_eq!(key.master_key_id.as_deref(), Some("test"));
+  key_service::verify_private_key(&key).unwrap();
+
+  // Data keys are tied to their own key
+  let other = find_key(&db, factory::user(&*db).await.pki_key_id.unwrap()).await;
+  let swapped = pki_key::Model { encrypted_data_key: other.encrypted_data_key, ..key };
+  assert!(key_service::verify_private_key(&swapped).is_err());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn rotating_the_master_key_rewraps_data_keys() {
+  let db = TestDb::new().await;
+  let old = master_key("old", 'B');
+  let new = master_key("new", 'C');
+  let key_id = {
+    let _guard = key_service::set_thread_master_keys(MasterKeys::parse(&old, "").unwrap());
+    factory::user(&*db).await.pki_key_id.unwrap()
+  };
+
+  let _guard = key_service::set_thread_master_keys(MasterKeys::parse(&format!("{},{}", old, new), "new").unwrap());
+  key_service::verify_private_key(&find_key(&db, key_id).await).unwrap();
+  let before = find_key(&db, key_id).await;
+  assert_eq!(key_service::rewrap(&*db).await.unwrap(), 1);
+  assert_eq!(key_service::rewrap(&*db).await.unwrap(), 0);
+  let after = find_key(&db, key_id).await;
+  assert_eq!(after.master_key_id.as_deref(), Some("new"));
+  // Only the data key is re-wrapped
+  assert_eq!(after.private_key, before.private_key);
+  assert_ne!(after.encrypted_data_key, before.encrypted_data_key);
+
+  // The old master key can now be dropped
+  let _guard = key_service::set_thread_master_keys(MasterKeys::parse(&new, "").unwrap());
+  key_service::verify_private_key(&find_key(&db, key_id).await).unwrap();
+  db.close().await;
+}
+
+#[async_std::test]
+async fn plaintext_private_keys_are_refused_until_sealed() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let pair = key_pair::generate(KeyAlgos::EcdsaP256, 2048).unwrap();
+  let key = pki_key::ActiveModel {
+    user_id: Set(Some(user.id)),
+    private_key: Set(None),
+    public_key: Set(Some(pair.public_key)),
+    aws_kms_url: Set(None),
+    algo: Set(KeyAlgos::EcdsaP256),
+    ..Default::default()
+  }.insert(&*db).await.unwrap();
+  // As a key saved before envelope encryption would be
+  pki_key::Entity::update_many()
+    .col_expr(pki_key::Column::PrivateKey, Expr::value(pair.private_key.clone()))
+    .filter(pki_key::Column::Id.eq(key.id))
+    .exec(&*db)
+    .await
+    .unwrap();
+  let plaintext = find_key(&db, key.id).await;
+  assert!(!key_service::is_sealed(&plaintext));
+  assert!(key_service::verify_private_key(&plaintext).unwrap_err().contains("stored in plaintext"));
+
+  // Only the admin tool's opt-in seals them, a rewrap leaves them be
+  assert_eq!(key_service::rewrap(&*db).await.unwrap(), 0);
+  assert_eq!(key_service::seal_plaintext(&*db).await.unwrap(), 1);
+  assert_eq!(key_service::seal_plaintext(&*db).await.unwrap(), 0);
+  let sealed = find_key(&db, key.id).await;
+  assert!(key_service::is_sealed(&sealed));
+  key_service::verify_private_key(&sealed).unwrap();
+
+  // Saving a key again leaves it sealed
+  let mut active_key = sealed.clone().into_active_model();
+  active_key.aws_kms_url = Set(None);
+  active_key.private_key = Set(sealed.private_key.clone());
+  let saved = active_key.update(&*db).await.unwrap();
+  assert_eq!(saved.private_key, sealed.private_key);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn only_keys_with_a_data_key_count_as_sealed() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let key = find_key(&db, user.pki_key_id.unwrap()).await;
+  assert!(key_service::is_sealed(&key));
+  // Base64 alone doesn't make a private key sealed, nor is it used as plaintext
+  let stripped = pki_key::Model { encrypted_data_key: None, master_key_id: None, ..key };
+  assert!(!key_service::is_sealed(&stripped));
+  assert!(key_service::verify_private_key(&stripped).is_err());
+  db.close().await;
+}
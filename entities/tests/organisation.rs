@@ -0,0 +1,138 @@
+use entities::{
+  key_service::{self, MasterKeys},
+  organisation,
+  organisation_access_role::OrgRolePermissions,
+  pki_key::{self, Owner},
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::{Set, Unchanged}, DbErr, IntoActiveModel};
+use serde_json::json;
+use test_support::{factory, TestDb};
+
+fn rejected(result: Result<impl std::fmt::Debug, DbErr>, expected: &str) {
+  match result {
+    Err(DbErr::Custom(message)) => assert!(message.contains(expected), "{} does not mention {}", message, expected),
+    other => panic!("expected an error mentioning {}, got {:?}", expected, other),
+  }
+}
+
+#[async_std::test]
+async fn only_owners_and_admins_of_a_parent_manage_its_children() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let parent = factory::organisation(&*db).await;
+  let child = factory::child_organisation(&*db, parent.id).await;
+  let grandchild = factory::child_organisation(&*db, child.id).await;
+  let admin = factory::organisation_role(&*db, OrgRolePermissions::AllowAdmin).await;
+  let read_only = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+
+  let user = factory::user(&*db).await;
+  factory::assign_organisation_role(&*db, user.id, parent.id, admin.id).await;
+  for organisation_id in [parent.id, child.id, grandchild.id] {
+    assert_eq!(organisation::find_effective_role(&*db, user.id, organisation_id).await.unwrap(), Some(admin.clone()));
+  }
+
+  // Other roles stay where they were given, and a child role never reaches the parent
+  let reader = factory::user(&*db).await;
+  factory::assign_organisation_role(&*db, reader.id, parent.id, read_only.id).await;
+  factory::assign_organisation_role(&*db, reader.id, grandchild.id, admin.id).await;
+  assert_eq!(organisation::find_effective_role(&*db, reader.id, parent.id).await.unwrap(), Some(read_only.clone()));
+  assert_eq!(organisation::find_effective_role(&*db, reader.id, child.id).await.unwrap(), None);
+  assert_eq!(organisation::find_effective_role(&*db, reader.id, grandchild.id).await.unwrap(), Some(admin));
+
+  // The user's own role in the child comes before a delegated one
+  factory::assign_organisation_role(&*db, user.id, child.id, read_only.id).await;
+  assert_eq!(organisation::find_effective_role(&*db, user.id, child.id).await.unwrap(), Some(read_only));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn children_override_the_settings_they_inherit() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let parent = organisation::ActiveModel {
+    settings: Set(json!({ "locale": "en-GB", "theme": "dark" })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+  let child = organisation::ActiveModel {
+    parent_organisation_id: Set(Some(parent.id)),
+    settings: Set(json!({ "theme": "light" })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+  let grandchild = organisation::ActiveModel {
+    parent_organisation_id: Set(Some(child.id)),
+    settings: Set(json!({ "locale": "de-DE" })),
+    ..factory::new_organisation()
+  }.insert(&*db).await.unwrap();
+
+  let settings = organisation::find_effective_settings(&*db, child.id).await.unwrap();
+  assert_eq!(json!(settings), json!({ "locale": "en-GB", "theme": "light" }));
+  let settings = organisation::find_effective_settings(&*db, grandchild.id).await.unwrap();
+  assert_eq!(json!(settings), json!({ "locale": "de-DE", "theme": "light" }));
+  let settings = organisation::find_effective_settings(&*db, parent.id).await.unwrap();
+  assert_eq!(json!(settings), json!({ "locale": "en-GB", "theme": "dark" }));
+
+  rejected(organisation::ActiveModel {
+    settings: Set(json!(["theme"])),
+    ..factory::new_organisation()
+  }.insert(&*db).await, "Settings must be an object");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn children_use_the_nearest_parent_key_until_given_their_own() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let parent = factory::organisation(&*db).await;
+  let child = factory::child_organisation(&*db, parent.id).await;
+  let grandchild = factory::child_organisation(&*db, child.id).await;
+  assert!(parent.pki_key_id.is_some());
+  assert_eq!((child.pki_key_id, grandchild.pki_key_id), (None, None));
+  for organisation_id in [parent.id, child.id, grandchild.id] {
+    assert_eq!(organisation::find_effective_pki_key_id(&*db, organisation_id).await.unwrap(), parent.pki_key_id);
+    let key = pki_key::find_for_owner(&*db, Owner::Organisation(organisation_id), None).await.unwrap().unwrap();
+    assert_eq!(Some(key.id), parent.pki_key_id);
+  }
+
+  // A child's own key is inherited by its children in turn
+  let child_key = pki_key::generate(&*db, Owner::Organisation(child.id), None).await.unwrap();
+  let mut active_child = child.clone().into_active_model();
+  active_child.pki_key_id = Set(Some(child_key.id));
+  active_child.update(&*db).await.unwrap();
+  assert_eq!(organisation::find_effective_pki_key_id(&*db, grandchild.id).await.unwrap(), Some(child_key.id));
+  assert_eq!(organisation::find_effective_pki_key_id(&*db, parent.id).await.unwrap(), parent.pki_key_id);
+
+  // Children may point at a parent's key, but not at another organisation's
+  organisation::ActiveModel {
+    id: Unchanged(grandchild.id),
+    pki_key_id: Set(parent.pki_key_id),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await.unwrap();
+  let other = factory::organisation(&*db).await;
+  rejected(organisation::ActiveModel {
+    id: Unchanged(grandchild.id),
+    pki_key_id: Set(other.pki_key_id),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "pki_key must belong to the organisation or one of its parents");
+  rejected(organisation::ActiveModel {
+    id: Unchanged(parent.id),
+    pki_key_id: Set(Some(child_key.id)),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "pki_key must belong to the organisation or one of its parents");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn parents_must_keep_the_tree_acyclic() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let parent = factory::organisation(&*db).await;
+  let child = factory::child_organisation(&*db, parent.id).await;
+  let grandchild = factory::child_organisation(&*db, child.id).await;
+  rejected(organisation::ActiveModel {
+    id: Unchanged(parent.id),
+    parent_organisation_id: Set(Some(grandchild.id)),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "parent_organisation_id would create a cycle");
+  assert_eq!(organisation::find_ancestor_ids(&*db, grandchild.id).await.unwrap(), vec![grandchild.id, child.id, parent.id]);
+  db.close().await;
+}
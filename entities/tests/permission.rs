@@ -0,0 +1,125 @@
+use entities::{
+  group_access_role::{self, GroupRolePermissions},
+  organisation_access_role::{self, OrgRolePermissions},
+  permission::{self, Permission, PermissionSet},
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::{Set, Unchanged}, DbErr};
+use test_support::{factory, TestDb};
+
+fn rejected(result: Result<impl std::fmt::Debug, DbErr>, expected: &str) {
+  match result {
+    Err(DbErr::Custom(message)) => assert!(message.contains(expected), "{} does not mention {}", message, expected),
+    other => panic!("expected an error mentioning {}, got {:?}", expected, other),
+  }
+}
+
+fn custom(resource: &str, verbs: &[&str]) -> PermissionSet {
+  PermissionSet(vec![Permission::new(resource, verbs)])
+}
+
+#[test]
+fn resources_and_verbs_match_wildcards() {
+  assert!(permission::resource_matches("*", "secret"));
+  assert!(permission::resource_matches("secret", "secret"));
+  assert!(permission::resource_matches("secret.*", "secret.value"));
+  assert!(!permission::resource_matches("secret.*", "secret"));
+  assert!(!permission::resource_matches("secret", "secret.value"));
+  assert!(!permission::resource_matches("pki_key", "secret"));
+  assert!(permission::verb_matches("*", "export"));
+  assert!(permission::verb_matches("read", "read"));
+  assert!(!permission::verb_matches("read", "update"));
+
+  let permission = Permission::new("secret.*", &["read", "list"]);
+  assert!(permission.allows("secret.value", "list"));
+  assert!(!permission.allows("secret.value", "delete"));
+  assert!(!permission.allows("pki_key", "read"));
+}
+
+#[test]
+fn permissions_are_validated() {
+  permission::validate_resource_pattern("secret.*").unwrap();
+  assert!(permission::validate_resource_pattern("").is_err());
+  assert!(permission::validate_resource_pattern("se*cret").is_err());
+  assert!(permission::validate_resource_pattern("*.*").is_err());
+  custom("secret", &["read"]).validate().unwrap();
+  assert!(custom("secret", &[]).validate().is_err());
+  assert!(custom("secret", &["read", ""]).validate().is_err());
+}
+
+#[test]
+fn presets_grant_their_level() {
+  // Only owners get verbs outside the standard ones
+  assert!(PermissionSet::owner().allows("pki_key", "export"));
+  assert!(!PermissionSet::admin().allows("pki_key", "export"));
+  assert!(PermissionSet::admin().allows("pki_key", "manage"));
+  assert!(!PermissionSet::read_write().allows("pki_key", "manage"));
+  assert!(PermissionSet::read_write().allows("pki_key", "delete"));
+  assert!(PermissionSet::read_only().allows("pki_key", "list"));
+  assert!(!PermissionSet::read_only().allows("pki_key", "create"));
+  assert!(!PermissionSet::none().allows("pki_key", "read"));
+  assert_eq!(OrgRolePermissions::Custom.preset(), None);
+  assert_eq!(GroupRolePermissions::DeniedBlocked.preset(), Some(PermissionSet::none()));
+}
+
+#[async_std::test]
+async fn only_custom_roles_carry_permissions() {
+  let db = TestDb::new().await;
+  let role = organisation_access_role::ActiveModel {
+    org_role_permissions: Set(OrgRolePermissions::Custom),
+    permissions: Set(Some(custom("secret", &["read"]))),
+    ..factory::new_organisation_role(OrgRolePermissions::Custom)
+  }.insert(&*db).await.unwrap();
+  assert!(role.allows("secret", "read"));
+  assert!(!role.allows("secret", "update"));
+
+  rejected(factory::new_organisation_role(OrgRolePermissions::Custom).insert(&*db).await, "Custom roles must have permissions");
+  rejected(organisation_access_role::ActiveModel {
+    permissions: Set(Some(custom("secret", &["read"]))),
+    ..factory::new_organisation_role(OrgRolePermissions::AllowAdmin)
+  }.insert(&*db).await, "Only Custom roles can have permissions");
+  rejected(organisation_access_role::ActiveModel {
+    permissions: Set(Some(custom("", &["read"]))),
+    ..factory::new_organisation_role(OrgRolePermissions::Custom)
+  }.insert(&*db).await, "Invalid permissions");
+  rejected(group_access_role::ActiveModel {
+    permissions: Set(Some(custom("secret", &["read"]))),
+    ..factory::new_group_role(GroupRolePermissions::AllowReadOnly)
+  }.insert(&*db).await, "Only Custom roles can have permissions");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn partial_updates_are_checked_against_the_stored_role() {
+  let db = TestDb::new().await;
+  let preset = factory::organisation_role(&*db, OrgRolePermissions::AllowReadOnly).await;
+  // Becoming custom without permissions
+  rejected(organisation_access_role::ActiveModel {
+    id: Unchanged(preset.id),
+    org_role_permissions: Set(OrgRolePermissions::Custom),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "Custom roles must have permissions");
+  // Giving a preset role permissions
+  rejected(organisation_access_role::ActiveModel {
+    id: Unchanged(preset.id),
+    permissions: Set(Some(custom("secret", &["read"]))),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "Only Custom roles can have permissions");
+
+  let role = group_access_role::ActiveModel {
+    permissions: Set(Some(custom("secret", &["read"]))),
+    ..factory::new_group_role(GroupRolePermissions::Custom)
+  }.insert(&*db).await.unwrap();
+  // Clearing a custom role's permissions
+  rejected(group_access_role::ActiveModel {
+    id: Unchanged(role.id),
+    permissions: Set(None),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await, "Custom roles must have permissions");
+  let role = group_access_role::ActiveModel {
+    id: Unchanged(role.id),
+    permissions: Set(Some(custom("secret", &["read", "update"]))),
+    ..ActiveModelTrait::default()
+  }.update(&*db).await.unwrap();
+  assert!(role.allows("secret", "update"));
+  db.close().await;
+}
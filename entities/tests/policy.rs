@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use chrono::{TimeZone, Utc, Weekday};
+use entities::policy::{self, Conditions, Effect, PolicyDocument, RequestContext, Statement, TimeWindow};
+use shared::{clock::{set_thread_clock, FixedClock}, GroupId, OrgId, UserId};
+
+fn statement(id: &str, effect: Effect, conditions: Conditions) -> Statement {
+  Statement {
+    id: id.to_string(),
+    effect,
+    resources: vec!["secret.*".to_string()],
+    verbs: vec!["read".to_string()],
+    conditions,
+  }
+}
+
+fn policies(statements: Vec<Statement>) -> Vec<(String, PolicyDocument)> {
+  vec![("office".to_string(), PolicyDocument { version: policy::POLICY_VERSION, statements })]
+}
+
+/// Reading a secret from the office network on Monday 2023-05-01 at 10:00 UTC
+fn context() -> RequestContext {
+  RequestContext {
+    user_id: UserId::new(),
+    organisation_id: OrgId::new(),
+    resource: "secret.value".to_string(),
+    verb: "read".to_string(),
+    group_id: None,
+    source_ip: Some("10.0.0.5".parse().unwrap()),
+    at: Utc.with_ymd_and_hms(2023, 5, 1, 10, 0, 0).unwrap(),
+    mfa: false,
+    group_organisation_id: None,
+  }
+}
+
+fn office_ips() -> Conditions {
+  Conditions { source_ips: vec!["10.0.0.0/8".parse().unwrap()], ..Default::default() }
+}
+
+#[test]
+fn roles_decide_when_no_statement_matches() {
+  let ctx = context();
+  let decision = policy::evaluate(&[], true, &ctx);
+  assert!(decision.allowed);
+  assert_eq!(decision.reason, "allowed by role");
+
+  let decision = policy::evaluate(&[], false, &ctx);
+  assert!(!decision.allowed && !decision.role_allowed);
+
+  // Policies never grant what the role doesn't
+  let decision = policy::evaluate(&policies(vec![statement("allow", Effect::Allow, Conditions::default())]), false, &ctx);
+  assert!(!decision.allowed);
+
+  let other = RequestContext { verb: "delete".to_string(), ..context() };
+  let decision = policy::evaluate(&policies(vec![statement("deny", Effect::Deny, Conditions::default())]), true, &other);
+  assert!(decision.allowed);
+  assert!(decision.statements.is_empty());
+}
+
+#[test]
+fn allow_statements_need_one_with_its_conditions_met() {
+  let documents = policies(vec![statement("office", Effect::Allow, office_ips())]);
+  let decision = policy::evaluate(&documents, true, &context());
+  assert!(decision.allowed);
+  assert_eq!(decision.reason, "allowed by statement 'office' of policy 'office'");
+
+  let away = RequestContext { source_ip: Some("192.0.2.1".parse().unwrap()), ..context() };
+  let decision = policy::evaluate(&documents, true, &away);
+  assert!(!decision.allowed);
+  assert_eq!(decision.reason, "conditions not met for office/office");
+  assert_eq!(decision.statements[0].failed_conditions, ["source_ips"]);
+
+  // Requests without a source address don't match an address range
+  let unknown = RequestContext { source_ip: None, ..context() };
+  assert!(!policy::evaluate(&documents, true, &unknown).allowed);
+}
+
+#[test]
+fn deny_statements_win_when_their_conditions_are_met() {
+  let kiosk = Conditions { source_ips: vec!["10.0.0.5/32".parse().unwrap()], ..Default::default() };
+  let documents = policies(vec![
+    statement("office", Effect::Allow, office_ips()),
+    statement("kiosk", Effect::Deny, kiosk),
+  ]);
+  let decision = policy::evaluate(&documents, true, &context());
+  assert!(!decision.allowed);
+  assert_eq!(decision.reason, "denied by statement 'kiosk' of policy 'office'");
+
+  let desk = RequestContext { source_ip: Some("10.0.0.6".parse().unwrap()), ..context() };
+  assert!(policy::evaluate(&documents, true, &desk).allowed);
+}
+
+#[test]
+fn time_windows_respect_days_offsets_and_midnight() {
+  let business_hours = TimeWindow {
+    after: "09:00".to_string(),
+    before: "17:00".to_string(),
+    days: vec![Weekday::Mon, Weekday::Tue],
+    utc_offset_mins: 0,
+  };
+  let monday = Utc.with_ymd_and_hms(2023, 5, 1, 10, 0, 0).unwrap();
+  assert!(business_hours.contains(&monday));
+  assert!(!business_hours.contains(&Utc.with_ymd_and_hms(2023, 5, 1, 17, 0, 0).unwrap()));
+  assert!(!business_hours.contains(&Utc.with_ymd_and_hms(2023, 5, 3, 10, 0, 0).unwrap()));
+  // 10:00 UTC is 20:00 at +10:00
+  let sydney = TimeWindow { utc_offset_mins: 600, ..business_hours.clone() };
+  assert!(!sydney.contains(&monday));
+
+  let overnight = TimeWindow { after: "22:00".to_string(), before: "06:00".to_string(), days: vec![], utc_offset_mins: 0 };
+  assert!(overnight.contains(&Utc.with_ymd_and_hms(2023, 5, 1, 23, 0, 0).unwrap()));
+  assert!(overnight.contains(&Utc.with_ymd_and_hms(2023, 5, 1, 5, 59, 0).unwrap()));
+  assert!(!overnight.contains(&monday));
+
+  let documents = policies(vec![statement("hours", Effect::Allow, Conditions { time: Some(business_hours), ..Default::default() })]);
+  let evening = RequestContext { at: Utc.with_ymd_and_hms(2023, 5, 1, 20, 0, 0).unwrap(), ..context() };
+  assert!(policy::evaluate(&documents, true, &context()).allowed);
+  assert_eq!(policy::evaluate(&documents, true, &evening).statements[0].failed_conditions, ["time"]);
+}
+
+#[test]
+fn groups_must_belong_to_the_organisation_when_required() {
+  let documents = policies(vec![statement("own", Effect::Allow, Conditions {
+    group_owned_by_organisation: Some(true),
+    ..Default::default()
+  })]);
+  let ctx = context();
+  let own = RequestContext { group_id: Some(GroupId::new()), group_organisation_id: Some(ctx.organisation_id), ..ctx.clone() };
+  assert!(policy::evaluate(&documents, true, &own).allowed);
+  let foreign = RequestContext { group_organisation_id: Some(OrgId::new()), ..own.clone() };
+  assert!(!policy::evaluate(&documents, true, &foreign).allowed);
+  assert!(!policy::evaluate(&documents, true, &ctx).allowed);
+}
+
+#[test]
+fn documents_are_validated() {
+  let valid = r#"{"version": 1, "statements": [
+    {"id": "office", "effect": "allow", "resources": ["secret.*"], "verbs": ["read"],
+     "conditions": {"source_ips": ["10.0.0.0/8"], "time": {"after": "09:00", "before": "17:00"}}}
+  ]}"#;
+  let document = PolicyDocument::parse(valid).unwrap();
+  assert_eq!(document.statements[0].conditions.source_ips.len(), 1);
+
+  let invalid = [
+    (r#"{"version": 2, "statements": []}"#, "unsupported policy version 2"),
+    (r#"{"version": 1, "statements": [{"id": "", "effect": "deny", "resources": ["*"], "verbs": ["*"]}]}"#, "id cannot be blank"),
+    (r#"{"version": 1, "statements": [{"id": "a", "effect": "deny", "resources": [], "verbs": ["*"]}]}"#, "at least one resource"),
+    (r#"{"version": 1, "statements": [{"id": "a", "effect": "deny", "resources": ["a*b"], "verbs": ["*"]}]}"#, "wildcard"),
+    (r#"{"version": 1, "statements": [
+      {"id": "a", "effect": "deny", "resources": ["*"], "verbs": ["*"]},
+      {"id": "a", "effect": "allow", "resources": ["*"], "verbs": ["*"]}
+    ]}"#, "duplicate statement id 'a'"),
+    (r#"{"version": 1, "statements": [{"id": "a", "effect": "deny", "resources": ["*"], "verbs": ["*"],
+      "conditions": {"time": {"after": "9am", "before": "17:00"}}}]}"#, "invalid time '9am'"),
+    // Nothing can show MFA was used yet, so a rule needing it could never match
+    (r#"{"version": 1, "statements": [{"id": "a", "effect": "allow", "resources": ["*"], "verbs": ["*"],
+      "conditions": {"mfa": true}}]}"#, "mfa conditions are not supported"),
+  ];
+  for (document, expected) in invalid {
+    let err = PolicyDocument::parse(document).unwrap_err();
+    assert!(err.contains(expected), "{} does not mention {}", err, expected);
+  }
+}
+
+#[test]
+fn simulated_requests_default_to_the_clock() {
+  let now = Utc.with_ymd_and_hms(2023, 5, 1, 10, 0, 0).unwrap();
+  let _clock_guard = set_thread_clock(Arc::new(FixedClock::new(now)));
+  let request = serde_json::json!({
+    "user_id": UserId::new(),
+    "organisation_id": OrgId::new(),
+    "resource": "secret.value",
+    "verb": "read",
+  });
+  let ctx: RequestContext = serde_json::from_value(request).unwrap();
+  assert_eq!(ctx.at, now);
+}
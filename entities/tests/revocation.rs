@@ -0,0 +1,330 @@
+use std::sync::Arc;
+use chrono::Duration;
+use entities::{
+  certificate::{self, IssueRequest, RevocationReason},
+  certificate_authority::{self, NewAuthority},
+  certificate_role::{self, RoleTemplate},
+  key_pair,
+  key_service::{self, MasterKeys},
+  ocsp,
+  pki_key::KeyAlgos,
+  x509::{self, CrlReason},
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, DbErr, EntityTrait};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use shared::{clock::{set_thread_clock, Clock, FixedClock}, OrgId};
+use test_support::{factory, TestDb};
+use x509_cert::{
+  der::{asn1::{Null, OctetString}, oid::{db::{rfc5280, rfc5912, rfc6960}, AssociatedOid}, Any, Decode, Encode},
+  ext::{pkix::{AuthorityInfoAccessSyntax, CrlDistributionPoints}, Extension},
+  serial_number::SerialNumber,
+  spki::AlgorithmIdentifierOwned,
+  Certificate,
+};
+use x509_ocsp::{BasicOcspResponse, CertId, CertStatus, OcspRequest, OcspResponse, OcspResponseStatus, Request, TbsRequest};
+
+fn new_authority(name: &str) -> NewAuthority {
+  NewAuthority {
+    name: name.to_string(),
+    subject: format!("CN={},O=Example", name),
+    ttl_days: 365,
+    key_id: None,
+    algo: Some(KeyAlgos::EcdsaP256),
+    max_path_length: None,
+  }
+}
+
+async fn role<C: sea_orm::ConnectionTrait>(
+  db: &C,
+  organisation_id: OrgId,
+  authority: &certificate_authority::Model,
+) -> certificate_role::Model {
+  certificate_role::ActiveModel {
+    organisation_id: Set(organisation_id),
+    certificate_authority_id: Set(authority.id),
+    name: Set("web-server".to_string()),
+    template: Set(RoleTemplate {
+      allowed_domains: vec!["example.com".to_string()],
+      allow_subdomains: true,
+      ..Default::default()
+    }),
+    ..Default::default()
+  }.insert(db).await.unwrap()
+}
+
+async fn issue<C: sea_orm::ConnectionTrait>(db: &C, role: &certificate_role::Model, common_name: &str) -> certificate::Model {
+  certificate::issue(db, role, IssueRequest {
+    common_name: Some(common_name.to_string()),
+    ..Default::default()
+  }).await.unwrap().certificate
+}
+
+async fn reload<C: sea_orm::ConnectionTrait>(db: &C, authority: &certificate_authority::Model) -> certificate_authority::Model {
+  certificate_authority::Entity::find_by_id(authority.id).one(db).await.unwrap().unwrap()
+}
+
+/// A `CertId` for `serial` under `issuer`, built the way clients do
+fn cert_id(issuer: &Certificate, serial: &str, sha256: bool) -> CertId {
+  let name = issuer.tbs_certificate.subject.to_der().unwrap();
+  let key = issuer.tbs_certificate.subject_public_key_info.subject_public_key.raw_bytes();
+  let (oid, name_hash, key_hash) = if sha256 {
+    (rfc5912::ID_SHA_256, Sha256::digest(&name).to_vec(), Sha256::digest(key).to_vec())
+  } else {
+    (rfc5912::ID_SHA_1, Sha1::digest(&name).to_vec(), Sha1::digest(key).to_vec())
+  };
+  CertId {
+    hash_algorithm: AlgorithmIdentifierOwned { oid, parameters: Some(Any::from(Null)) },
+    issuer_name_hash: OctetString::new(name_hash).unwrap(),
+    issuer_key_hash: OctetString::new(key_hash).unwrap(),
+    serial_number: SerialNumber::new(&hex::decode(serial).unwrap()).unwrap(),
+  }
+}
+
+fn ocsp_request(cert_ids: Vec<CertId>, nonce: Option<&[u8]>) -> Vec<u8> {
+  let request_extensions = nonce.map(|nonce| vec![Extension {
+    extn_id: rfc6960::ID_PKIX_OCSP_NONCE,
+    critical: false,
+    extn_value: OctetString::new(OctetString::new(nonce).unwrap().to_der().unwrap()).unwrap(),
+  }]);
+  OcspRequest {
+    tbs_request: TbsRequest {
+      request_list: cert_ids.into_iter().map(|req_cert| Request { req_cert, single_request_extensions: None }).collect(),
+      request_extensions,
+      ..Default::default()
+    },
+    optional_signature: None,
+  }.to_der().unwrap()
+}
+
+/// Decodes a successful response and checks the CA signed it
+fn basic_response(der: &[u8], issuer: &Certificate) -> BasicOcspResponse {
+  let response = OcspResponse::from_der(der).unwrap();
+  assert_eq!(response.response_status, OcspResponseStatus::Successful);
+  let bytes = response.response_bytes.unwrap();
+  assert_eq!(bytes.response_type, BasicOcspResponse::OID);
+  let basic = BasicOcspResponse::from_der(bytes.response.as_bytes()).unwrap();
+  let public_key = x509::public_key_pem(&issuer.tbs_certificate.subject_public_key_info).unwrap();
+  key_pair::verify(
+    KeyAlgos::EcdsaP256,
+    &public_key,
+    x509::signing_scheme(KeyAlgos::EcdsaP256),
+    &basic.tbs_response_data.to_der().unwrap(),
+    basic.signature.raw_bytes(),
+  ).unwrap();
+  basic
+}
+
+#[async_std::test]
+async fn revoking_publishes_a_signed_crl() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let authority = certificate_authority::create_root(&*db, organisation.id, new_authority("Root")).await.unwrap();
+  assert_eq!(authority.crl_number, 1);
+  assert!(authority.crl_next_update.is_some());
+  let ca_certificate = x509::parse_certificate(&authority.certificate).unwrap();
+  let crl = x509::parse_crl(authority.crl.as_deref().unwrap()).unwrap();
+  x509::verify_crl_issued_by(&crl, &ca_certificate).unwrap();
+  assert!(crl.tbs_cert_list.revoked_certificates.is_none());
+
+  let role = role(&*db, organisation.id, &authority).await;
+  let leaf = issue(&*db, &role, "www.example.com").await;
+  let kept = issue(&*db, &role, "api.example.com").await;
+  let revoked = certificate::revoke(&*db, organisation.id, &leaf.serial, RevocationReason::KeyCompromise).await.unwrap();
+  assert!(revoked.revoked_at.is_some());
+  assert_eq!(revoked.revocation_reason, Some(RevocationReason::KeyCompromise));
+
+  let authority = reload(&*db, &authority).await;
+  assert_eq!(authority.crl_number, 2);
+  let crl = x509::parse_crl(authority.crl.as_deref().unwrap()).unwrap();
+  x509::verify_crl_issued_by(&crl, &ca_certificate).unwrap();
+  let entries = crl.tbs_cert_list.revoked_certificates.as_ref().unwrap();
+  assert_eq!(entries.len(), 1);
+  assert_eq!(x509::serial_hex(entries[0].serial_number.as_bytes()), leaf.serial);
+  assert!(entries.iter().all(|entry| x509::serial_hex(entry.serial_number.as_bytes()) != kept.serial));
+  let (_, reason) = entries[0].crl_entry_extensions.as_ref().unwrap().iter()
+    .find(|extension| extension.extn_id == CrlReason::OID)
+    .map(|extension| (extension, CrlReason::from_der(extension.extn_value.as_bytes()).unwrap()))
+    .unwrap();
+  assert_eq!(reason, CrlReason::KeyCompromise);
+
+  // A CRL signed by another CA doesn't verify
+  let other = certificate_authority::create_root(&*db, organisation.id, new_authority("Other")).await.unwrap();
+  assert!(x509::verify_crl_issued_by(&crl, &x509::parse_certificate(&other.certificate).unwrap()).is_err());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn revoking_checks_the_serial() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let stranger = factory::organisation(&*db).await;
+  let authority = certificate_authority::create_root(&*db, organisation.id, new_authority("Root")).await.unwrap();
+  let role = role(&*db, organisation.id, &authority).await;
+  let leaf = issue(&*db, &role, "www.example.com").await;
+
+  // Serials as openssl prints them
+  let printed = hex::decode(&leaf.serial).unwrap().iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(":");
+  assert_eq!(certificate::normalise_serial(&printed), leaf.serial);
+
+  let result = certificate::revoke(&*db, stranger.id, &leaf.serial, RevocationReason::Superseded).await;
+  assert!(matches!(result, Err(DbErr::RecordNotFound(_))));
+  let result = certificate::revoke(&*db, organisation.id, "00ff", RevocationReason::Superseded).await;
+  assert!(matches!(result, Err(DbErr::RecordNotFound(_))));
+
+  certificate::revoke(&*db, organisation.id, &printed, RevocationReason::Superseded).await.unwrap();
+  let result = certificate::revoke(&*db, organisation.id, &leaf.serial, RevocationReason::KeyCompromise).await;
+  assert!(matches!(result, Err(DbErr::Custom(message)) if message.contains("already revoked")));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn revoked_intermediates_cannot_issue() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let root = NewAuthority { max_path_length: Some(2), ..new_authority("Root") };
+  let root = certificate_authority::create_root(&*db, organisation.id, root).await.unwrap();
+  let intermediate = certificate_authority::create_intermediate(&*db, &root, new_authority("Issuing")).await.unwrap();
+  let role = role(&*db, organisation.id, &intermediate).await;
+  issue(&*db, &role, "www.example.com").await;
+
+  certificate::revoke(&*db, organisation.id, &intermediate.serial, RevocationReason::CaCompromise).await.unwrap();
+  // It shows up on the root's CRL
+  let root = reload(&*db, &root).await;
+  let crl = x509::parse_crl(root.crl.as_deref().unwrap()).unwrap();
+  let entries = crl.tbs_cert_list.revoked_certificates.unwrap();
+  assert_eq!(x509::serial_hex(entries[0].serial_number.as_bytes()), intermediate.serial);
+
+  let result = certificate::issue(&*db, &role, IssueRequest {
+    common_name: Some("api.example.com".to_string()),
+    ..Default::default()
+  }).await;
+  assert!(matches!(result, Err(DbErr::Custom(message)) if message.contains("has been revoked")));
+  let result = certificate_authority::create_intermediate(&*db, &intermediate, new_authority("Below")).await;
+  assert!(matches!(result, Err(DbErr::Custom(message)) if message.contains("has been revoked")));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn only_stale_crls_are_refreshed() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let clock = Arc::new(FixedClock::default());
+  let _clock_guard = set_thread_clock(clock.clone());
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let authority = certificate_authority::create_root(&*db, organisation.id, new_authority("Root")).await.unwrap();
+  assert_eq!(certificate_authority::refresh_crls(&*db).await.unwrap(), 0);
+
+  // Past half of the default 24 hours
+  clock.advance(Duration::hours(13));
+  let fresh = certificate_authority::create_root(&*db, organisation.id, new_authority("Fresh")).await.unwrap();
+  assert_eq!(certificate_authority::refresh_crls(&*db).await.unwrap(), 1);
+  let authority = reload(&*db, &authority).await;
+  assert_eq!(authority.crl_number, 2);
+  assert_eq!(authority.crl_next_update.unwrap().timestamp(), (clock.now() + Duration::hours(24)).timestamp());
+  assert_eq!(reload(&*db, &fresh).await.crl_number, 1);
+  assert_eq!(certificate_authority::refresh_crls(&*db).await.unwrap(), 0);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn certificates_point_at_the_revocation_endpoints() {
+  assert_eq!(certificate_authority::revocation_urls("", uuid::Uuid::nil()), (None, None));
+  let id = uuid::Uuid::new_v4();
+  let (crl_url, ocsp_url) = certificate_authority::revocation_urls("https://pki.example.com/", id);
+  assert_eq!(crl_url.unwrap(), format!("https://pki.example.com/pki/cas/{}/crl", id));
+  assert_eq!(ocsp_url.unwrap(), format!("https://pki.example.com/pki/cas/{}/ocsp", id));
+
+  let pair = key_pair::generate(KeyAlgos::Ed25519, 2048).unwrap();
+  let public_key = x509::public_key_info(&pair.public_key).unwrap();
+  let now = chrono::Utc::now();
+  let template = x509::CertificateTemplate {
+    serial: x509::random_serial(),
+    issuer: x509::common_name_only("Root").unwrap(),
+    subject: x509::common_name_only("www.example.com").unwrap(),
+    not_before: now,
+    not_after: now + Duration::hours(1),
+    public_key,
+    authority_key_id: None,
+    ca: None,
+    key_usage: vec![x509::KeyUsage::DigitalSignature],
+    extended_key_usage: vec![],
+    dns_names: vec!["www.example.com".to_string()],
+    ip_addresses: vec![],
+    crl_url: Some(format!("https://pki.example.com/pki/cas/{}/crl", id)),
+    ocsp_url: Some(format!("https://pki.example.com/pki/cas/{}/ocsp", id)),
+  };
+  let tbs = x509::tbs_certificate(&template, x509::signing_scheme(KeyAlgos::Ed25519)).unwrap();
+  let (_, distribution_points) = tbs.get::<CrlDistributionPoints>().unwrap().unwrap();
+  assert_eq!(distribution_points.0.len(), 1);
+  let (_, access) = tbs.get::<AuthorityInfoAccessSyntax>().unwrap().unwrap();
+  assert_eq!(access.0[0].access_method, rfc5280::ID_AD_OCSP);
+
+  let template = x509::CertificateTemplate { crl_url: None, ocsp_url: None, ..template };
+  let tbs = x509::tbs_certificate(&template, x509::signing_scheme(KeyAlgos::Ed25519)).unwrap();
+  assert!(tbs.get::<CrlDistributionPoints>().unwrap().is_none());
+  assert!(tbs.get::<AuthorityInfoAccessSyntax>().unwrap().is_none());
+}
+
+#[async_std::test]
+async fn ocsp_answers_with_each_certificates_status() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let authority = certificate_authority::create_root(&*db, organisation.id, new_authority("Root")).await.unwrap();
+  let other = certificate_authority::create_root(&*db, organisation.id, new_authority("Other")).await.unwrap();
+  let issuer = x509::parse_certificate(&authority.certificate).unwrap();
+  let role = role(&*db, organisation.id, &authority).await;
+  let good = issue(&*db, &role, "www.example.com").await;
+  let revoked = issue(&*db, &role, "api.example.com").await;
+  certificate::revoke(&*db, organisation.id, &revoked.serial, RevocationReason::Superseded).await.unwrap();
+
+  let request = ocsp_request(vec![
+    cert_id(&issuer, &good.serial, false),
+    cert_id(&issuer, &revoked.serial, true),
+    cert_id(&issuer, "00ff", false),
+    // Right serial, wrong issuer
+    cert_id(&x509::parse_certificate(&other.certificate).unwrap(), &good.serial, false),
+  ], Some(b"0123456789abcdef"));
+  let basic = basic_response(&ocsp::respond(&*db, &authority, &request).await.unwrap(), &issuer);
+  let responses = &basic.tbs_response_data.responses;
+  assert_eq!(responses.len(), 4);
+  assert_eq!(responses[0].cert_status, CertStatus::good());
+  match responses[1].cert_status {
+    CertStatus::Revoked(info) => assert_eq!(info.revocation_reason, Some(CrlReason::Superseded)),
+    status => panic!("expected revoked, got {:?}", status),
+  }
+  assert_eq!(responses[2].cert_status, CertStatus::unknown());
+  assert_eq!(responses[3].cert_status, CertStatus::unknown());
+  assert_eq!(basic.nonce().unwrap().0.as_bytes(), b"0123456789abcdef");
+
+  // Another CA doesn't know about the certificate
+  let request = ocsp_request(vec![cert_id(&issuer, &good.serial, false)], None);
+  let other_issuer = x509::parse_certificate(&other.certificate).unwrap();
+  let basic = basic_response(&ocsp::respond(&*db, &other, &request).await.unwrap(), &other_issuer);
+  assert_eq!(basic.tbs_response_data.responses[0].cert_status, CertStatus::unknown());
+  assert!(basic.nonce().is_none());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn ocsp_refuses_malformed_requests() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let authority = certificate_authority::create_root(&*db, organisation.id, new_authority("Root")).await.unwrap();
+  let issuer = x509::parse_certificate(&authority.certificate).unwrap();
+
+  let too_many = ocsp_request((0..=ocsp::MAX_REQUESTS).map(|_| cert_id(&issuer, "01", false)).collect(), None);
+  for request in [b"not der".to_vec(), Vec::new(), ocsp_request(vec![], None), too_many] {
+    let response = OcspResponse::from_der(&ocsp::respond(&*db, &authority, &request).await.unwrap()).unwrap();
+    assert_eq!(response.response_status, OcspResponseStatus::MalformedRequest);
+    assert!(response.response_bytes.is_none());
+  }
+  let response = OcspResponse::from_der(&ocsp::internal_error()).unwrap();
+  assert_eq!(response.response_status, OcspResponseStatus::InternalError);
+  db.close().await;
+}
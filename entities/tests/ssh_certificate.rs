@@ -0,0 +1,220 @@
+use std::{collections::BTreeMap, sync::Arc};
+use chrono::{Duration, TimeZone, Utc};
+use entities::{
+  group,
+  group_access_role::GroupRolePermissions,
+  key_pair,
+  key_service::{self, MasterKeys},
+  pki_key::KeyAlgos,
+  ssh,
+  ssh_certificate::{self, SignRequest},
+  ssh_certificate_authority::{self, NewSshAuthority},
+  ssh_group_setting::{self, SshTemplate},
+};
+use sea_orm::DbErr;
+use shared::{clock::{set_thread_clock, FixedClock}, UserId};
+use ssh_key::{certificate::CertType, public::RsaPublicKey, PublicKey};
+use test_support::{factory, TestDb};
+
+fn new_authority(algo: KeyAlgos) -> NewSshAuthority {
+  NewSshAuthority {
+    name: "Fleet".to_string(),
+    key_id: None,
+    algo: Some(algo),
+  }
+}
+
+/// A user's key in `authorized_keys` format
+fn user_key(algo: KeyAlgos) -> String {
+  let pair = key_pair::generate(algo, key_pair::MIN_RSA_BITS).unwrap();
+  ssh::public_key(algo, &pair.public_key).unwrap().to_openssh().unwrap()
+}
+
+async fn member<C: sea_orm::ConnectionTrait>(db: &C, group: &group::Model, permissions: GroupRolePermissions) -> UserId {
+  let user = factory::user(db).await;
+  let role = factory::group_role(db, permissions).await;
+  factory::assign_group_role(db, user.id, group.id, role.id).await;
+  user.id
+}
+
+fn request(public_key: String) -> SignRequest {
+  SignRequest { public_key, ttl_minutes: None }
+}
+
+fn rejected(result: Result<impl std::fmt::Debug, DbErr>, expected: &str) {
+  match result {
+    Err(DbErr::Custom(message)) => assert!(message.contains(expected), "{} does not mention {}", message, expected),
+    other => panic!("expected an error mentioning {}, got {:?}", expected, other),
+  }
+}
+
+#[async_std::test]
+async fn signs_members_keys_for_the_groups_principals() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let clock = Arc::new(FixedClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+  let _clock_guard = set_thread_clock(clock.clone());
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let group = factory::group(&*db, organisation.id).await;
+  let user_id = member(&*db, &group, GroupRolePermissions::AllowReadWrite).await;
+
+  for algo in [KeyAlgos::Ed25519, KeyAlgos::EcdsaP256, KeyAlgos::EcdsaP384, KeyAlgos::RSA] {
+    let authority = ssh_certificate_authority::create(&*db, organisation.id, new_authority(algo)).await.unwrap();
+    let ca_key = PublicKey::from_openssh(&authority.public_key).unwrap();
+    ssh_group_setting::put(&*db, &group, authority.id, SshTemplate::default()).await.unwrap();
+
+    let public_key = user_key(KeyAlgos::Ed25519);
+    let signed = ssh_certificate::sign(&*db, user_id, &group, request(public_key.clone())).await.unwrap();
+    let certificate = ssh::parse_certificate(&signed.certificate).unwrap();
+    ssh::verify_issued_by(&certificate, &ca_key).unwrap();
+    assert_eq!(certificate.public_key(), PublicKey::from_openssh(&public_key).unwrap().key_data());
+    assert_eq!(certificate.cert_type(), CertType::User);
+    assert_eq!(certificate.valid_principals(), [group.name.as_str()]);
+    assert_eq!(certificate.serial() as i64, signed.serial);
+    assert_eq!(certificate.key_id(), signed.key_id);
+    assert_eq!(certificate.valid_after() as i64, 1_700_000_000 - 5 * 60);
+    assert_eq!(certificate.valid_before() as i64, 1_700_000_000 + 60 * 60);
+    assert!(certificate.critical_options().is_empty());
+    assert_eq!(certificate.extensions().keys().collect::<Vec<_>>(), ["permit-pty"]);
+    assert_eq!(signed.principals.0, [group.name.as_str()]);
+    assert_eq!(signed.fingerprint, ssh::fingerprint(&PublicKey::from_openssh(&public_key).unwrap()));
+
+    // Another CA's key doesn't verify it
+    let other = ssh_certificate_authority::create(&*db, organisation.id, new_authority(KeyAlgos::Ed25519)).await.unwrap();
+    assert!(ssh::verify_issued_by(&certificate, &PublicKey::from_openssh(&other.public_key).unwrap()).is_err());
+  }
+  db.close().await;
+}
+
+#[async_std::test]
+async fn group_settings_shape_the_certificate() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let group = factory::group(&*db, organisation.id).await;
+  let user_id = member(&*db, &group, GroupRolePermissions::AllowReadOnly).await;
+  let authority = ssh_certificate_authority::create(&*db, organisation.id, new_authority(KeyAlgos::Ed25519)).await.unwrap();
+  let template = SshTemplate {
+    principals: vec!["deploy".to_string(), "ops".to_string()],
+    critical_options: BTreeMap::from([
+      ("force-command".to_string(), "/usr/bin/deploy".to_string()),
+      ("source-address".to_string(), "10.0.0.0/8, 192.168.1.1".to_string()),
+    ]),
+    extensions: BTreeMap::from([
+      ("permit-port-forwarding".to_string(), String::new()),
+      ("login@example.com".to_string(), "audit".to_string()),
+    ]),
+    ttl_minutes: 15,
+    max_ttl_minutes: 30,
+  };
+  let setting = ssh_group_setting::put(&*db, &group, authority.id, template.clone()).await.unwrap();
+  // Putting again replaces the settings
+  let replaced = ssh_group_setting::put(&*db, &group, authority.id, template).await.unwrap();
+  assert_eq!(replaced.id, setting.id);
+
+  let signed = ssh_certificate::sign(&*db, user_id, &group, request(user_key(KeyAlgos::EcdsaP256))).await.unwrap();
+  let certificate = ssh::parse_certificate(&signed.certificate).unwrap();
+  assert_eq!(certificate.valid_principals(), ["deploy", "ops"]);
+  assert_eq!(certificate.critical_options().get("force-command").unwrap(), "/usr/bin/deploy");
+  assert_eq!(certificate.critical_options().get("source-address").unwrap(), "10.0.0.0/8, 192.168.1.1");
+  assert_eq!(certificate.extensions().keys().collect::<Vec<_>>(), ["login@example.com", "permit-port-forwarding"]);
+  assert_eq!(certificate.valid_before() - certificate.valid_after(), (15 + 5) * 60);
+
+  let shorter = ssh_certificate::sign(&*db, user_id, &group, SignRequest {
+    ttl_minutes: Some(20),
+    ..request(user_key(KeyAlgos::Ed25519))
+  }).await.unwrap();
+  assert_eq!(shorter.valid_before - shorter.valid_after, Duration::minutes(20 + 5));
+  let result = ssh_certificate::sign(&*db, user_id, &group, SignRequest {
+    ttl_minutes: Some(31),
+    ..request(user_key(KeyAlgos::Ed25519))
+  }).await;
+  rejected(result, "ttl_minutes cannot exceed 30");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn only_members_get_certificates() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let group = factory::group(&*db, organisation.id).await;
+  let other_group = factory::group(&*db, organisation.id).await;
+  let user_id = member(&*db, &group, GroupRolePermissions::AllowAdmin).await;
+  let denied = member(&*db, &group, GroupRolePermissions::Denied).await;
+  let outsider = factory::user(&*db).await;
+  let authority = ssh_certificate_authority::create(&*db, organisation.id, new_authority(KeyAlgos::Ed25519)).await.unwrap();
+
+  // The group has to opt in first
+  rejected(ssh_certificate::sign(&*db, user_id, &group, request(user_key(KeyAlgos::Ed25519))).await, "does not issue SSH certificates");
+  ssh_group_setting::put(&*db, &group, authority.id, SshTemplate::default()).await.unwrap();
+  ssh_group_setting::put(&*db, &other_group, authority.id, SshTemplate::default()).await.unwrap();
+
+  rejected(ssh_certificate::sign(&*db, denied, &group, request(user_key(KeyAlgos::Ed25519))).await, "not a member");
+  rejected(ssh_certificate::sign(&*db, outsider.id, &group, request(user_key(KeyAlgos::Ed25519))).await, "not a member");
+  // Membership of one group says nothing about another
+  rejected(ssh_certificate::sign(&*db, user_id, &other_group, request(user_key(KeyAlgos::Ed25519))).await, "not a member");
+  ssh_certificate::sign(&*db, user_id, &group, request(user_key(KeyAlgos::Ed25519))).await.unwrap();
+  db.close().await;
+}
+
+#[async_std::test]
+async fn user_keys_are_checked() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let group = factory::group(&*db, organisation.id).await;
+  let user_id = member(&*db, &group, GroupRolePermissions::AllowOwner).await;
+  let authority = ssh_certificate_authority::create(&*db, organisation.id, new_authority(KeyAlgos::EcdsaP256)).await.unwrap();
+  ssh_group_setting::put(&*db, &group, authority.id, SshTemplate::default()).await.unwrap();
+
+  rejected(ssh_certificate::sign(&*db, user_id, &group, request("ssh-ed25519 not-base64".to_string())).await, "invalid SSH public key");
+  let weak = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+  let weak = PublicKey::new(RsaPublicKey::try_from(weak.to_public_key()).unwrap().into(), "");
+  rejected(ssh_certificate::sign(&*db, user_id, &group, request(weak.to_openssh().unwrap())).await, "at least 2048 bits");
+
+  // Comments and surrounding whitespace are fine
+  let public_key = format!("  {} me@laptop\n", user_key(KeyAlgos::RSA));
+  ssh_certificate::sign(&*db, user_id, &group, request(public_key)).await.unwrap();
+  db.close().await;
+}
+
+#[async_std::test]
+async fn templates_are_validated() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let other_organisation = factory::organisation(&*db).await;
+  let group = factory::group(&*db, organisation.id).await;
+  let authority = ssh_certificate_authority::create(&*db, organisation.id, new_authority(KeyAlgos::Ed25519)).await.unwrap();
+  let foreign = ssh_certificate_authority::create(&*db, other_organisation.id, new_authority(KeyAlgos::Ed25519)).await.unwrap();
+
+  let result = ssh_group_setting::put(&*db, &group, foreign.id, SshTemplate::default()).await;
+  assert!(matches!(result, Err(DbErr::RecordNotFound(_))));
+
+  let option = |name: &str, data: &str| BTreeMap::from([(name.to_string(), data.to_string())]);
+  let cases = [
+    (SshTemplate { ttl_minutes: 0, ..Default::default() }, "must be positive"),
+    (SshTemplate { ttl_minutes: 120, max_ttl_minutes: 60, ..Default::default() }, "cannot exceed max_ttl_minutes"),
+    (SshTemplate { max_ttl_minutes: 24 * 60 + 1, ..Default::default() }, "max_ttl_minutes cannot exceed 1440"),
+    (SshTemplate { principals: vec!["root,admin".to_string()], ..Default::default() }, "invalid principal"),
+    (SshTemplate { principals: vec!["".to_string()], ..Default::default() }, "invalid principal"),
+    (SshTemplate { critical_options: option("no-such-option", ""), ..Default::default() }, "unknown critical option"),
+    (SshTemplate { critical_options: option("source-address", "10.0.0.0/8,nowhere"), ..Default::default() }, "invalid source-address nowhere"),
+    (SshTemplate { critical_options: option("force-command", " "), ..Default::default() }, "needs a command"),
+    (SshTemplate { extensions: option("permit-pty", "yes"), ..Default::default() }, "takes no data"),
+    (SshTemplate { extensions: option("permit-everything", ""), ..Default::default() }, "unknown extension"),
+  ];
+  for (template, expected) in cases {
+    rejected(ssh_group_setting::put(&*db, &group, authority.id, template).await, expected);
+  }
+
+  let template = SshTemplate {
+    critical_options: option("verify-required", ""),
+    extensions: option("permit-user-rc", ""),
+    max_ttl_minutes: 24 * 60,
+    ..Default::default()
+  };
+  ssh_group_setting::put(&*db, &group, authority.id, template).await.unwrap();
+  db.close().await;
+}
@@ -0,0 +1,197 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use entities::{
+  key_service::{self, MasterKeys},
+  transit_key::{self, NewTransitKey, TransitKeyAlgos},
+  transit_key_version,
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter, TransactionTrait};
+use shared::Error;
+use test_support::{factory, TestDb};
+
+fn new_key(name: &str, algo: TransitKeyAlgos) -> NewTransitKey {
+  NewTransitKey { name: name.to_string(), algo }
+}
+
+/// Invalid keys and ciphertexts are both the caller's fault
+fn rejected<E: Into<Error>>(result: Result<impl std::fmt::Debug, E>, expected: &str) {
+  match result.map_err(Into::into) {
+    Err(Error::Validation(message) | Error::InvalidCiphertext(message)) => {
+      assert!(message.contains(expected), "{} does not mention {}", message, expected)
+    },
+    other => panic!("expected an error mentioning {}, got {:?}", expected, other),
+  }
+}
+
+/// Swaps the version a ciphertext names, leaving its bytes alone
+fn with_version(ciphertext: &str, version: i32) -> String {
+  let (_, sealed) = ciphertext.rsplit_once(':').unwrap();
+  format!("vault:v{}:{}", version, sealed)
+}
+
+#[async_std::test]
+async fn ciphertexts_decrypt_with_their_associated_data() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  for algo in [TransitKeyAlgos::Aes256Gcm, TransitKeyAlgos::ChaCha20Poly1305] {
+    let key = transit_key::create(&*db, organisation.id, None, new_key(&format!("{:?}", algo), algo)).await.unwrap();
+    assert_eq!((key.algo, key.latest_version, key.min_decryption_version), (algo, 1, 1));
+
+    let ciphertext = transit_key::encrypt(&*db, &key, b"card number", b"customer 1").await.unwrap();
+    assert!(ciphertext.starts_with("vault:v1:"), "{}", ciphertext);
+    assert!(!ciphertext.contains(&STANDARD.encode(b"card number")));
+    // Every encryption gets its own nonce
+    assert_ne!(transit_key::encrypt(&*db, &key, b"card number", b"customer 1").await.unwrap(), ciphertext);
+    let plaintext = transit_key::decrypt(&*db, &key, &ciphertext, b"customer 1").await.unwrap();
+    assert_eq!(plaintext.as_slice(), b"card number");
+
+    rejected(transit_key::decrypt(&*db, &key, &ciphertext, b"customer 2").await, "decryption failed");
+    let mut sealed = STANDARD.decode(ciphertext.rsplit_once(':').unwrap().1).unwrap();
+    sealed[20] ^= 1;
+    let tampered = format!("vault:v1:{}", STANDARD.encode(sealed));
+    rejected(transit_key::decrypt(&*db, &key, &tampered, b"customer 1").await, "decryption failed");
+    rejected(transit_key::decrypt(&*db, &key, "v1:abc", b"").await, "ciphertexts start with vault:v");
+    rejected(transit_key::decrypt(&*db, &key, "vault:vx:abc", b"").await, "x is not a key version");
+    rejected(transit_key::decrypt(&*db, &key, "vault:v1:!!", b"").await, "not base64");
+  }
+
+  // Keys can't read each other's ciphertexts
+  let first = transit_key::find(&*db, organisation.id, None, "Aes256Gcm").await.unwrap().unwrap();
+  let second = transit_key::create(&*db, organisation.id, None, new_key("other", TransitKeyAlgos::Aes256Gcm)).await.unwrap();
+  let ciphertext = transit_key::encrypt(&*db, &first, b"secret", b"").await.unwrap();
+  rejected(transit_key::decrypt(&*db, &second, &ciphertext, b"").await, "decryption failed");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn rotation_keeps_old_ciphertexts_until_the_minimum_version_passes_them() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let key = transit_key::create(&*db, organisation.id, None, new_key("payments", TransitKeyAlgos::Aes256Gcm)).await.unwrap();
+  let old = transit_key::encrypt(&*db, &key, b"old", b"").await.unwrap();
+
+  let key = transit_key::rotate(&*db, &key).await.unwrap();
+  assert_eq!((key.latest_version, key.min_decryption_version), (2, 1));
+  let new = transit_key::encrypt(&*db, &key, b"new", b"").await.unwrap();
+  assert!(new.starts_with("vault:v2:"), "{}", new);
+  assert_eq!(transit_key::decrypt(&*db, &key, &old, b"").await.unwrap().as_slice(), b"old");
+  // The version a ciphertext names is the one that decrypts it
+  rejected(transit_key::decrypt(&*db, &key, &with_version(&old, 2), b"").await, "decryption failed");
+  rejected(transit_key::decrypt(&*db, &key, &with_version(&old, 3), b"").await, "has no version 3");
+
+  let rewrapped = transit_key::rewrap(&*db, &key, &old, b"").await.unwrap();
+  assert!(rewrapped.starts_with("vault:v2:"), "{}", rewrapped);
+  assert_eq!(transit_key::decrypt(&*db, &key, &rewrapped, b"").await.unwrap().as_slice(), b"old");
+
+  let key = transit_key::set_min_decryption_version(&*db, &key, 2).await.unwrap();
+  rejected(transit_key::decrypt(&*db, &key, &old, b"").await, "below its minimum decryption version 2");
+  rejected(transit_key::rewrap(&*db, &key, &old, b"").await, "below its minimum decryption version 2");
+  assert_eq!(transit_key::decrypt(&*db, &key, &rewrapped, b"").await.unwrap().as_slice(), b"old");
+  rejected(transit_key::set_min_decryption_version(&*db, &key, 3).await, "between 1 and the latest version 2");
+  rejected(transit_key::set_min_decryption_version(&*db, &key, 0).await, "between 1 and the latest version 2");
+
+  // Lowering it again lets old ciphertexts back in
+  let key = transit_key::set_min_decryption_version(&*db, &key, 1).await.unwrap();
+  assert_eq!(transit_key::decrypt(&*db, &key, &old, b"").await.unwrap().as_slice(), b"old");
+
+  // Rotating from a stale copy still adds the next version
+  let stale = key.clone();
+  transit_key::rotate(&*db, &key).await.unwrap();
+  let txn = db.begin().await.unwrap();
+  let key = transit_key::rotate(&txn, &stale).await.unwrap();
+  txn.commit().await.unwrap();
+  assert_eq!(key.latest_version, 4);
+  let versions = transit_key_version::Entity::find()
+    .filter(transit_key_version::Column::TransitKeyId.eq(key.id))
+    .all(&*db)
+    .await
+    .unwrap();
+  assert_eq!(versions.len(), 4);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn data_keys_come_with_their_ciphertext() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let key = transit_key::create(&*db, organisation.id, None, new_key("files", TransitKeyAlgos::ChaCha20Poly1305)).await.unwrap();
+  for bits in transit_key::DATA_KEY_BITS {
+    let (plaintext, ciphertext) = transit_key::data_key(&*db, &key, bits, b"file 1").await.unwrap();
+    assert_eq!(plaintext.len(), bits / 8);
+    assert!(ciphertext.starts_with("vault:v1:"), "{}", ciphertext);
+    assert_eq!(transit_key::decrypt(&*db, &key, &ciphertext, b"file 1").await.unwrap(), plaintext);
+  }
+  rejected(transit_key::data_key(&*db, &key, 100, b"").await, "data keys are one of [128, 256, 512] bits");
+  db.close().await;
+}
+
+#[async_std::test]
+async fn names_are_unique_to_their_owner() {
+  let _guard = key_service::set_thread_master_keys(MasterKeys::generate("test"));
+  let db = TestDb::new().await;
+  let organisation = factory::organisation(&*db).await;
+  let other = factory::organisation(&*db).await;
+  let group = factory::group(&*db, organisation.id).await;
+  let key = transit_key::create(&*db, organisation.id, None, new_key("orders", TransitKeyAlgos::Aes256Gcm)).await.unwrap();
+  rejected(
+    transit_key::create(&*db, organisation.id, None, new_key("orders", TransitKeyAlgos::Aes256Gcm)).await,
+    "there is already a key called orders",
+  );
+  let group_key = transit_key::create(&*db, organisation.id, Some(group.id), new_key("orders", TransitKeyAlgos::Aes256Gcm)).await.unwrap();
+  transit_key::create(&*db, other.id, None, new_key("orders", TransitKeyAlgos::Aes256Gcm)).await.unwrap();
+  for name in ["", "has space", "a/b"] {
+    rejected(transit_key::create(&*db, organisation.id, None, new_key(name, TransitKeyAlgos::Aes256Gcm)).await, "names are letters");
+  }
+  // The database holds them to it too, whatever gets past the check in `create`
+  for group_id in [None, Some(group.id)] {
+    let duplicate = transit_key::ActiveModel {
+      organisation_id: Set(organisation.id),
+      group_id: Set(group_id),
+      name: Set("orders".to_string()),
+      algo: Set(TransitKeyAlgos::Aes256Gcm),
+      ..Default::default()
+    }.insert(&*db).await;
+    assert!(duplicate.is_err(), "{:?}", duplicate);
+  }
+
+  assert_eq!(transit_key::find(&*db, organisation.id, None, "orders").await.unwrap(), Some(key.clone()));
+  assert_eq!(transit_key::find(&*db, organisation.id, Some(group.id), "orders").await.unwrap(), Some(group_key.clone()));
+  assert_eq!(transit_key::list(&*db, organisation.id, None).await.unwrap(), vec![key]);
+  assert_eq!(transit_key::list(&*db, organisation.id, Some(group.id)).await.unwrap(), vec![group_key]);
+  assert_eq!(transit_key::find(&*db, other.id, Some(group.id), "orders").await.unwrap(), None);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn rotating_the_master_key_rewraps_transit_keys() {
+  let db = TestDb::new().await;
+  let old = "old:BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBA=";
+  let new = "new:CCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCA=";
+  let (key, ciphertext) = {
+    let _guard = key_service::set_thread_master_keys(MasterKeys::parse(old, "").unwrap());
+    let organisation = factory::organisation(&*db).await;
+    let key = transit_key::create(&*db, organisation.id, None, new_key("ledger", TransitKeyAlgos::Aes256Gcm)).await.unwrap();
+    let ciphertext = transit_key::encrypt(&*db, &key, b"balance", b"").await.unwrap();
+    (key, ciphertext)
+  };
+
+  {
+    let _guard = key_service::set_thread_master_keys(MasterKeys::parse(&format!("{},{}", old, new), "new").unwrap());
+    key_service::rewrap(&*db).await.unwrap();
+    assert_eq!(key_service::rewrap(&*db).await.unwrap(), 0);
+  }
+  let version = transit_key_version::Entity::find()
+    .filter(transit_key_version::Column::TransitKeyId.eq(key.id))
+    .one(&*db)
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(version.master_key_id, "new");
+
+  // The old master key is no longer needed
+  let _guard = key_service::set_thread_master_keys(MasterKeys::parse(new, "").unwrap());
+  assert_eq!(transit_key::decrypt(&*db, &key, &ciphertext, b"").await.unwrap().as_slice(), b"balance");
+  db.close().await;
+}
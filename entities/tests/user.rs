@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use chrono::{Duration, TimeZone, Utc};
+use entities::user::LockedState;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, IntoActiveModel};
+use shared::clock::{set_thread_clock, Clock, FixedClock};
+use test_support::{factory, TestDb};
+
+#[async_std::test]
+async fn too_many_invalid_logins_locks_temporarily() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+
+  let mut active_user = user.into_active_model();
+  active_user.invalid_login_attempts = Set(10);
+  let user = active_user.update(&*db).await.unwrap();
+  assert_eq!(user.locked_state, LockedState::Unlocked);
+
+  let mut active_user = user.into_active_model();
+  active_user.invalid_login_attempts = Set(11);
+  let user = active_user.update(&*db).await.unwrap();
+  assert_eq!(user.locked_state, LockedState::TemporarilyLocked);
+  assert!(user.locked_state_expires_at.unwrap() > Utc::now());
+  db.close().await;
+}
+
+#[async_std::test]
+async fn clearing_invalid_logins_unlocks() {
+  let db = TestDb::new().await;
+  let mut active_user = factory::new_user();
+  active_user.locked_state = Set(LockedState::TemporarilyLocked);
+  active_user.locked_state_expires_at = Set(Some(Utc::now()));
+  let user = active_user.insert(&*db).await.unwrap();
+
+  let mut active_user = user.into_active_model();
+  active_user.invalid_login_attempts = Set(0);
+  let user = active_user.update(&*db).await.unwrap();
+  assert_eq!(user.locked_state, LockedState::Unlocked);
+  assert_eq!(user.locked_state_expires_at, None);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn permanent_lock_is_kept() {
+  let db = TestDb::new().await;
+  let mut active_user = factory::new_user();
+  active_user.locked_state = Set(LockedState::PermanentlyLocked);
+  let user = active_user.insert(&*db).await.unwrap();
+
+  let mut active_user = user.into_active_model();
+  active_user.invalid_login_attempts = Set(0);
+  let user = active_user.update(&*db).await.unwrap();
+  assert_eq!(user.locked_state, LockedState::PermanentlyLocked);
+  db.close().await;
+}
+
+#[async_std::test]
+async fn temporary_lock_lapses_when_it_expires() {
+  // Whole seconds so the expiry survives the round trip through the database
+  let clock = Arc::new(FixedClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+  let _guard = set_thread_clock(clock.clone());
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+
+  let mut active_user = user.into_active_model();
+  active_user.invalid_login_attempts = Set(11);
+  let user = active_user.update(&*db).await.unwrap();
+  assert!(user.is_locked());
+  assert_eq!(user.locked_state_expires_at, Some(clock.now() + Duration::minutes(60)));
+
+  clock.advance(Duration::minutes(61));
+  assert!(!user.is_locked());
+  db.close().await;
+}
@@ -0,0 +1,22 @@
+use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+use test_support::{factory, TestDb};
+
+#[async_std::test]
+async fn missing_username_is_generated() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let profile = factory::user_profile(&*db, user.id).await;
+  assert!(profile.username.map(|username| !username.is_empty()).unwrap_or(false));
+  db.close().await;
+}
+
+#[async_std::test]
+async fn chosen_username_is_kept() {
+  let db = TestDb::new().await;
+  let user = factory::user(&*db).await;
+  let mut active_profile = factory::new_user_profile(user.id);
+  active_profile.username = Set(Some("chosen".to_string()));
+  let profile = active_profile.insert(&*db).await.unwrap();
+  assert_eq!(profile.username.as_deref(), Some("chosen"));
+  db.close().await;
+}
@@ -0,0 +1,395 @@
+//! Compares the schema built by the migrations against the entity definitions, so the
+//! two can't silently drift apart.
+use sea_orm_migration::{
+  prelude::*,
+  sea_orm::{ConnectionTrait, DbBackend, EntityTrait, FromQueryResult, Schema, Statement},
+};
+
+use entities::*;
+
+pub const SCHEMA: &str = "public";
+
+/// Tables owned by the migrator rather than an entity
+const IGNORED_TABLES: &[&str] = &["seaql_migrations"];
+
+/// Entities can't declare a unique key over several columns, so those the migrations create are listed here
+const COMPOSITE_UNIQUE_KEYS: &[(&str, &[&str])] = &[
+  ("organisation_policies", &["organisation_id", "name"]),
+  ("pki_key", &["key_ring_id", "version"]),
+  ("transit_key_versions", &["transit_key_id", "version"]),
+  // Partial, one for organisation keys and one for group keys
+  ("transit_keys", &["organisation_id", "name"]),
+  ("transit_keys", &["organisation_id", "group_id", "name"]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSchema {
+  pub name: String,
+  pub data_type: String,
+  pub nullable: bool,
+  pub unique: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ForeignKeySchema {
+  pub column: String,
+  pub ref_table: String,
+  pub ref_column: String,
+  pub on_update: String,
+  pub on_delete: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableSchema {
+  pub name: String,
+  pub columns: Vec<ColumnSchema>,
+  pub primary_key: Vec<String>,
+  pub foreign_keys: Vec<ForeignKeySchema>,
+  /// Unique indexes over more than one column, each one's columns sorted
+  pub unique_keys: Vec<Vec<String>>,
+}
+
+/// The Postgres `udt_name` a column of this type ends up as
+fn postgres_type(column_type: Option<&ColumnType>) -> String {
+  match column_type {
+    Some(ColumnType::Uuid) => "uuid".to_string(),
+    Some(ColumnType::String(_)) | Some(ColumnType::Char(_)) => "varchar".to_string(),
+    Some(ColumnType::Text) => "text".to_string(),
+    Some(ColumnType::SmallInteger) => "int2".to_string(),
+    Some(ColumnType::Integer) => "int4".to_string(),
+    Some(ColumnType::BigInteger) => "int8".to_string(),
+    Some(ColumnType::Float) => "float4".to_string(),
+    Some(ColumnType::Double) => "float8".to_string(),
+    Some(ColumnType::Boolean) => "bool".to_string(),
+    Some(ColumnType::Date) => "date".to_string(),
+    Some(ColumnType::DateTime) | Some(ColumnType::Timestamp) => "timestamp".to_string(),
+    Some(ColumnType::TimestampWithTimeZone) => "timestamptz".to_string(),
+    Some(ColumnType::Json) => "json".to_string(),
+    Some(ColumnType::JsonBinary) => "jsonb".to_string(),
+    Some(ColumnType::Binary(_)) | Some(ColumnType::VarBinary(_)) => "bytea".to_string(),
+    // Enums become their named Postgres type
+    Some(ColumnType::Custom(name)) => name.to_string(),
+    other => format!("{:?}", other),
+  }
+}
+
+fn foreign_key_action(action: Option<ForeignKeyAction>) -> String {
+  match action {
+    Some(ForeignKeyAction::Cascade) => "CASCADE",
+    Some(ForeignKeyAction::SetNull) => "SET NULL",
+    Some(ForeignKeyAction::SetDefault) => "SET DEFAULT",
+    Some(ForeignKeyAction::Restrict) => "RESTRICT",
+    Some(ForeignKeyAction::NoAction) | None => "NO ACTION",
+  }.to_string()
+}
+
+fn table_ref_name(table_ref: Option<&TableRef>) -> String {
+  match table_ref {
+    Some(TableRef::Table(table)) | Some(TableRef::SchemaTable(_, table)) => table.to_string(),
+    other => format!("{:?}", other),
+  }
+}
+
+/// What the entity expects its table to look like
+fn entity_table<E>(entity: E) -> TableSchema
+where
+  E: EntityTrait,
+{
+  let stmt = Schema::new(DbBackend::Postgres).create_table_from_entity(entity);
+  let mut primary_key: Vec<String> = stmt.get_indexes().iter()
+    .filter(|index| index.is_primary_key())
+    .flat_map(|index| index.get_index_spec().get_column_names())
+    .collect();
+  let columns = stmt.get_columns().iter().map(|column| {
+    let spec = column.get_column_spec();
+    if spec.iter().any(|spec| matches!(spec, ColumnSpec::PrimaryKey)) {
+      primary_key.push(column.get_column_name());
+    }
+    ColumnSchema {
+      name: column.get_column_name(),
+      data_type: postgres_type(column.get_column_type()),
+      nullable: !spec.iter().any(|spec| matches!(spec, ColumnSpec::NotNull)),
+      unique: spec.iter().any(|spec| matches!(spec, ColumnSpec::UniqueKey)),
+    }
+  }).collect();
+  let mut foreign_keys: Vec<ForeignKeySchema> = stmt.get_foreign_key_create_stmts().iter().map(|stmt| {
+    let foreign_key = stmt.get_foreign_key();
+    ForeignKeySchema {
+      column: foreign_key.get_columns().join(","),
+      ref_table: table_ref_name(foreign_key.get_ref_table()),
+      ref_column: foreign_key.get_ref_columns().join(","),
+      on_update: foreign_key_action(foreign_key.get_on_update()),
+      on_delete: foreign_key_action(foreign_key.get_on_delete()),
+    }
+  }).collect();
+  let mut unique_keys: Vec<Vec<String>> = COMPOSITE_UNIQUE_KEYS.iter()
+    .filter(|(table, _)| *table == entity.table_name())
+    .map(|(_, columns)| {
+      let mut columns: Vec<String> = columns.iter().map(|column| column.to_string()).collect();
+      columns.sort();
+      columns
+    })
+    .collect();
+  primary_key.sort();
+  foreign_keys.sort();
+  unique_keys.sort();
+  TableSchema { name: entity.table_name().to_string(), columns, primary_key, foreign_keys, unique_keys }
+}
+
+/// Every entity in the workspace, a table in the database without one here is reported too
+pub fn entity_tables() -> Vec<TableSchema> {
+  vec![
+    entity_table(user::Entity),
+    entity_table(email::Entity),
+    entity_table(phone::Entity),
+    entity_table(user_profile::Entity),
+    entity_table(auth_method_pass::Entity),
+    entity_table(auth_method_magiclink::Entity),
+    entity_table(group::Entity),
+    entity_table(group_access_role::Entity),
+    entity_table(organisation::Entity),
+    entity_table(organisation_access_role::Entity),
+    entity_table(organisation_profile::Entity),
+    entity_table(organisation_policy::Entity),
+    entity_table(users_organisations_organisations_access_roles::Entity),
+    entity_table(users_groups_group_access_roles::Entity),
+    entity_table(file::Entity),
+    entity_table(auth_api_key::Entity),
+    entity_table(auth_token::Entity),
+    entity_table(pki_key::Entity),
+    entity_table(invitation::Entity),
+    entity_table(certificate_authority::Entity),
+    entity_table(certificate_role::Entity),
+    entity_table(certificate::Entity),
+    entity_table(ssh_certificate_authority::Entity),
+    entity_table(ssh_group_setting::Entity),
+    entity_table(ssh_certificate::Entity),
+    entity_table(transit_key::Entity),
+    entity_table(transit_key_version::Entity),
+  ]
+}
+
+#[derive(Debug, FromQueryResult)]
+struct IntrospectedColumn {
+  table_name: String,
+  column_name: String,
+  udt_name: String,
+  is_nullable: String,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct IntrospectedIndex {
+  table_name: String,
+  column_name: String,
+  is_primary: bool,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct IntrospectedUniqueKey {
+  table_name: String,
+  index_name: String,
+  column_name: String,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct IntrospectedForeignKey {
+  table_name: String,
+  column_name: String,
+  ref_table: String,
+  ref_column: String,
+  update_rule: String,
+  delete_rule: String,
+}
+
+/// Reads the tables back out of a Postgres database
+pub async fn introspect_tables<C>(db: &C) -> Result<Vec<TableSchema>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let backend = db.get_database_backend();
+  if backend != DbBackend::Postgres {
+    return Err(DbErr::Custom("schema drift can only be checked against Postgres".to_string()));
+  }
+  let columns = IntrospectedColumn::find_by_statement(Statement::from_sql_and_values(
+    backend,
+    r#"SELECT c.table_name::text AS table_name, c.column_name::text AS column_name,
+        c.udt_name::text AS udt_name, c.is_nullable::text AS is_nullable
+      FROM information_schema.columns c
+      JOIN information_schema.tables t ON t.table_schema = c.table_schema AND t.table_name = c.table_name
+      WHERE c.table_schema = $1 AND t.table_type = 'BASE TABLE'
+      ORDER BY c.table_name, c.ordinal_position"#,
+    vec![SCHEMA.into()],
+  )).all(db).await?;
+  // Primary keys plus any unique index covering exactly one column, wider ones are unique keys
+  let indexes = IntrospectedIndex::find_by_statement(Statement::from_sql_and_values(
+    backend,
+    r#"SELECT t.relname::text AS table_name, a.attname::text AS column_name, i.indisprimary AS is_primary
+      FROM pg_index i
+      JOIN pg_class t ON t.oid = i.indrelid
+      JOIN pg_namespace n ON n.oid = t.relnamespace
+      JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = ANY(i.indkey)
+      WHERE n.nspname = $1 AND (i.indisprimary OR (i.indisunique AND i.indnatts = 1))"#,
+    vec![SCHEMA.into()],
+  )).all(db).await?;
+  let unique_keys = IntrospectedUniqueKey::find_by_statement(Statement::from_sql_and_values(
+    backend,
+    r#"SELECT t.relname::text AS table_name, ic.relname::text AS index_name, a.attname::text AS column_name
+      FROM pg_index i
+      JOIN pg_class t ON t.oid = i.indrelid
+      JOIN pg_class ic ON ic.oid = i.indexrelid
+      JOIN pg_namespace n ON n.oid = t.relnamespace
+      JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = ANY(i.indkey)
+      WHERE n.nspname = $1 AND i.indisunique AND NOT i.indisprimary AND i.indnatts > 1"#,
+    vec![SCHEMA.into()],
+  )).all(db).await?;
+  let foreign_keys = IntrospectedForeignKey::find_by_statement(Statement::from_sql_and_values(
+    backend,
+    r#"SELECT tc.table_name::text AS table_name, kcu.column_name::text AS column_name,
+        ccu.table_name::text AS ref_table, ccu.column_name::text AS ref_column,
+        rc.update_rule::text AS update_rule, rc.delete_rule::text AS delete_rule
+      FROM information_schema.table_constraints tc
+      JOIN information_schema.key_column_usage kcu
+        ON kcu.constraint_name = tc.constraint_name AND kcu.table_schema = tc.table_schema
+      JOIN information_schema.constraint_column_usage ccu
+        ON ccu.constraint_name = tc.constraint_name AND ccu.table_schema = tc.table_schema
+      JOIN information_schema.referential_constraints rc
+        ON rc.constraint_name = tc.constraint_name AND rc.constraint_schema = tc.table_schema
+      WHERE tc.constraint_type = 'FOREIGN KEY' AND tc.table_schema = $1"#,
+    vec![SCHEMA.into()],
+  )).all(db).await?;
+
+  let mut tables: Vec<TableSchema> = Vec::new();
+  for column in columns {
+    if IGNORED_TABLES.contains(&column.table_name.as_str()) {
+      continue;
+    }
+    if tables.last().map(|table| table.name != column.table_name).unwrap_or(true) {
+      tables.push(TableSchema { name: column.table_name.clone(), ..Default::default() });
+    }
+    let unique = indexes.iter().any(|index| {
+      !index.is_primary && index.table_name == column.table_name && index.column_name == column.column_name
+    });
+    if let Some(table) = tables.last_mut() {
+      table.columns.push(ColumnSchema {
+        name: column.column_name,
+        data_type: column.udt_name,
+        nullable: column.is_nullable == "YES",
+        unique,
+      });
+    }
+  }
+  for table in tables.iter_mut() {
+    table.primary_key = indexes.iter()
+      .filter(|index| index.is_primary && index.table_name == table.name)
+      .map(|index| index.column_name.clone())
+      .collect();
+    table.primary_key.sort();
+    table.foreign_keys = foreign_keys.iter()
+      .filter(|foreign_key| foreign_key.table_name == table.name)
+      .map(|foreign_key| ForeignKeySchema {
+        column: foreign_key.column_name.clone(),
+        ref_table: foreign_key.ref_table.clone(),
+        ref_column: foreign_key.ref_column.clone(),
+        on_update: foreign_key.update_rule.clone(),
+        on_delete: foreign_key.delete_rule.clone(),
+      })
+      .collect();
+    table.foreign_keys.sort();
+    let mut index_names: Vec<&String> = unique_keys.iter()
+      .filter(|key| key.table_name == table.name)
+      .map(|key| &key.index_name)
+      .collect();
+    index_names.sort();
+    index_names.dedup();
+    table.unique_keys = index_names.into_iter().map(|index_name| {
+      let mut columns: Vec<String> = unique_keys.iter()
+        .filter(|key| key.table_name == table.name && key.index_name == *index_name)
+        .map(|key| key.column_name.clone())
+        .collect();
+      columns.sort();
+      columns
+    }).collect();
+    table.unique_keys.sort();
+  }
+  Ok(tables)
+}
+
+/// Describes every difference between what the entities expect and what the database has
+pub fn compare(expected: &[TableSchema], actual: &[TableSchema]) -> Vec<String> {
+  let mut mismatches = Vec::new();
+  for table in actual {
+    if !expected.iter().any(|expected| expected.name == table.name) {
+      mismatches.push(format!("{}: table has no entity", table.name));
+    }
+  }
+  for expected in expected {
+    let Some(table) = actual.iter().find(|table| table.name == expected.name) else {
+      mismatches.push(format!("{}: table is missing", expected.name));
+      continue;
+    };
+    for column in &table.columns {
+      if !expected.columns.iter().any(|expected| expected.name == column.name) {
+        mismatches.push(format!("{}.{}: column has no entity field", table.name, column.name));
+      }
+    }
+    for expected_column in &expected.columns {
+      let Some(column) = table.columns.iter().find(|column| column.name == expected_column.name) else {
+        mismatches.push(format!("{}.{}: column is missing", table.name, expected_column.name));
+        continue;
+      };
+      if column.data_type != expected_column.data_type {
+        mismatches.push(format!(
+          "{}.{}: type is {} but the entity expects {}",
+          table.name, column.name, column.data_type, expected_column.data_type
+        ));
+      }
+      if column.nullable != expected_column.nullable {
+        mismatches.push(format!(
+          "{}.{}: nullable is {} but the entity expects {}",
+          table.name, column.name, column.nullable, expected_column.nullable
+        ));
+      }
+      if column.unique != expected_column.unique {
+        mismatches.push(format!(
+          "{}.{}: unique is {} but the entity expects {}",
+          table.name, column.name, column.unique, expected_column.unique
+        ));
+      }
+    }
+    if table.primary_key != expected.primary_key {
+      mismatches.push(format!(
+        "{}: primary key is ({}) but the entity expects ({})",
+        table.name, table.primary_key.join(", "), expected.primary_key.join(", ")
+      ));
+    }
+    for foreign_key in &expected.foreign_keys {
+      if !table.foreign_keys.contains(foreign_key) {
+        mismatches.push(format!("{}: missing foreign key {:?}", table.name, foreign_key));
+      }
+    }
+    for foreign_key in &table.foreign_keys {
+      if !expected.foreign_keys.contains(foreign_key) {
+        mismatches.push(format!("{}: unexpected foreign key {:?}", table.name, foreign_key));
+      }
+    }
+    for unique_key in &expected.unique_keys {
+      if !table.unique_keys.contains(unique_key) {
+        mismatches.push(format!("{}: missing unique key ({})", table.name, unique_key.join(", ")));
+      }
+    }
+    for unique_key in &table.unique_keys {
+      if !expected.unique_keys.contains(unique_key) {
+        mismatches.push(format!("{}: unexpected unique key ({})", table.name, unique_key.join(", ")));
+      }
+    }
+  }
+  mismatches
+}
+
+/// Checks an already migrated database against the entities
+pub async fn check<C>(db: &C) -> Result<Vec<String>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  Ok(compare(&entity_tables(), &introspect_tables(db).await?))
+}
@@ -1,7 +1,25 @@
 pub use sea_orm_migration::prelude::*;
-// use entities::models::create_connection;
+use sea_orm_migration::sea_orm::DatabaseConnection;
+
+pub mod drift;
+pub mod runner;
 
 mod m20230315_143439_create_tables;
+mod m20230401_120000_create_organisation_policies;
+mod m20230405_120000_create_invitations;
+mod m20230410_120000_add_one_time_code_attempts;
+mod m20230415_120000_add_pki_key_algos;
+mod m20230420_120000_add_pki_key_envelope;
+mod m20230425_120000_create_certificate_authorities;
+mod m20230430_120000_add_certificate_revocation;
+mod m20230505_120000_create_ssh_certificate_authorities;
+mod m20230510_120000_add_pki_key_versions;
+mod m20230515_120000_add_pki_key_exportable;
+mod m20230520_120000_create_transit_keys;
+mod m20230525_120000_add_access_role_permissions;
+mod m20230530_120000_add_group_parents;
+mod m20230601_120000_hash_api_secret_keys;
+mod m20230605_120000_make_transit_key_names_unique;
 
 pub struct Migrator;
 
@@ -10,29 +28,28 @@ impl MigratorTrait for Migrator {
   fn migrations() -> Vec<Box<dyn MigrationTrait>> {
     vec![
         Box::new(m20230315_143439_create_tables::Migration),
+        Box::new(m20230401_120000_create_organisation_policies::Migration),
+        Box::new(m20230405_120000_create_invitations::Migration),
+        Box::new(m20230410_120000_add_one_time_code_attempts::Migration),
+        Box::new(m20230415_120000_add_pki_key_algos::Migration),
+        Box::new(m20230420_120000_add_pki_key_envelope::Migration),
+        Box::new(m20230425_120000_create_certificate_authorities::Migration),
+        Box::new(m20230430_120000_add_certificate_revocation::Migration),
+        Box::new(m20230505_120000_create_ssh_certificate_authorities::Migration),
+        Box::new(m20230510_120000_add_pki_key_versions::Migration),
+        Box::new(m20230515_120000_add_pki_key_exportable::Migration),
+        Box::new(m20230520_120000_create_transit_keys::Migration),
+        Box::new(m20230525_120000_add_access_role_permissions::Migration),
+        Box::new(m20230530_120000_add_group_parents::Migration),
+        Box::new(m20230601_120000_hash_api_secret_keys::Migration),
+        Box::new(m20230605_120000_make_transit_key_names_unique::Migration),
     ]
   }
 }
 
-// impl Migrator {
-//   pub async fn run_migrations() -> Result<(), DbErr> {
-//       let config = Config::new();
-
-//       let db = create_connection(&config, false)
-//           .await
-//           .expect("Unable to connect to db");
-
-//       match Migrator::up(&db, None).await {
-//           Ok(_) => Ok(()),
-//           Err(e) => {
-//               let msg = e.to_string();
-//               // This is ok, just the migrator being funky
-//               if !msg.contains("been applied but its file is missing") {
-//                   return Err(e);
-//               }
-
-//               Ok(())
-//           }
-//       }
-//   }
-// }
+impl Migrator {
+  /// Applies every pending migration, safe to call from several instances starting at once
+  pub async fn run_migrations(db: &DatabaseConnection) -> Result<(), DbErr> {
+    runner::run::<Self>(db, runner::Action::Up, None).await.map(|_| ())
+  }
+}
@@ -1,8 +1,7 @@
 use sea_orm_migration::{
   prelude::*,
-  sea_orm::{prelude::ChronoDateTimeUtc},
-  // sea_orm::{ConnectionTrait, DbBackend, Statement},
-  // sea_query::extension::postgres::Type,
+  sea_orm::DbBackend,
+  sea_query::extension::postgres::Type,
 };
 
 use entities::*;
@@ -10,35 +9,44 @@ use entities::*;
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
-// #[derive(Iden)]
-// enum AuthMethod {
-//     #[iden = "auth_method"]
-//     Type,
-//     Password,
-//     Google,
-//     Github,
-//     Discord,
-// }
-
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
   async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
-    // if manager.get_database_backend() == DbBackend::Postgres {
-    //   manager
-    //     .create_type(Type::create()
-    //       .as_enum(AuthMethod::Type)
-    //       .values(vec![
-    //         AuthMethod::Password,
-    //         AuthMethod::Google,
-    //         AuthMethod::Github,
-    //         AuthMethod::Discord,
-    //       ])
-    //       .to_owned(),
-    //     )
-    //     .await?;
-    // }
-
-    // User Table
+    // Postgres needs the enum types to exist before any table uses them
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .create_type(Type::create()
+        .as_enum(user::LockedStateEnum)
+        .values(user::LockedState::iden_values())
+        .to_owned())
+        .await?;
+      manager
+        .create_type(Type::create()
+        .as_enum(auth_method_pass::PassHashCipherEnum)
+        .values(auth_method_pass::PassHashCipher::iden_values())
+        .to_owned())
+        .await?;
+      manager
+        .create_type(Type::create()
+        .as_enum(organisation_access_role::OrgRolePermissionsEnum)
+        .values(organisation_access_role::OrgRolePermissions::iden_values())
+        .to_owned())
+        .await?;
+      manager
+        .create_type(Type::create()
+        .as_enum(group_access_role::GroupRolePermissionsEnum)
+        .values(group_access_role::GroupRolePermissions::iden_values())
+        .to_owned())
+        .await?;
+      manager
+        .create_type(Type::create()
+        .as_enum(pki_key::KeyAlgosEnum)
+        .values(pki_key::KeyAlgos::iden_values())
+        .to_owned())
+        .await?;
+    }
+
+    // Users
     manager
       .create_table(Table::create()
       .table(user::Entity)
@@ -60,12 +68,13 @@ impl MigrationTrait for Migration {
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
       .col(
         ColumnDef::new(user::Column::LockedStateExpiresAt)
-        .timestamp_with_time_zone()
-        .default(Option::<ChronoDateTimeUtc>::None).null())
+        .timestamp_with_time_zone().null())
       .col(
         ColumnDef::new(user::Column::LastLoginAt)
-        .timestamp_with_time_zone()
-        .default(Option::<ChronoDateTimeUtc>::None).null())
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(user::Column::PkiKeyId)
+        .uuid().null())
       .col(
         ColumnDef::new(user::Column::CreatedAt)
         .timestamp_with_time_zone().not_null()
@@ -77,6 +86,382 @@ impl MigrationTrait for Migration {
       .to_owned())
       .await?;
 
+    // Organisations
+    manager
+      .create_table(Table::create()
+      .table(organisation::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(organisation::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(organisation::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(organisation::Column::ParentOrganisationId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(organisation::Column::PkiKeyId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(organisation::Column::Settings)
+        .json_binary().not_null()
+        .extra("DEFAULT '{}'".into()))
+      .col(
+        ColumnDef::new(organisation::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(organisation::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-organisations-parent_organisation_id")
+        .from(organisation::Entity, organisation::Column::ParentOrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Restrict))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-organisations-parent_organisation_id")
+      .table(organisation::Entity)
+      .col(organisation::Column::ParentOrganisationId)
+      .to_owned())
+      .await?;
+
+    // Organisation Access Roles
+    manager
+      .create_table(Table::create()
+      .table(organisation_access_role::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(organisation_access_role::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(organisation_access_role::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(organisation_access_role::Column::Description)
+        .string().null())
+      .col(
+        ColumnDef::new(organisation_access_role::Column::OrgRolePermissions)
+        .enumeration(organisation_access_role::OrgRolePermissionsEnum, organisation_access_role::OrgRolePermissions::iden_values())
+        .not_null())
+      .col(
+        ColumnDef::new(organisation_access_role::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(organisation_access_role::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .to_owned())
+      .await?;
+
+    // Group Access Roles
+    manager
+      .create_table(Table::create()
+      .table(group_access_role::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(group_access_role::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(group_access_role::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(group_access_role::Column::Description)
+        .string().null())
+      .col(
+        ColumnDef::new(group_access_role::Column::GroupRolePermissions)
+        .enumeration(group_access_role::GroupRolePermissionsEnum, group_access_role::GroupRolePermissions::iden_values())
+        .not_null())
+      .col(
+        ColumnDef::new(group_access_role::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(group_access_role::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .to_owned())
+      .await?;
+
+    // Groups
+    manager
+      .create_table(Table::create()
+      .table(group::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(group::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(group::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(group::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(group::Column::GroupImageFileId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(group::Column::Description)
+        .string().null())
+      .col(
+        ColumnDef::new(group::Column::Icon)
+        .string().null())
+      .col(
+        ColumnDef::new(group::Column::ColorRgb)
+        .string().null())
+      .col(
+        ColumnDef::new(group::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(group::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-groups-organisation_id")
+        .from(group::Entity, group::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-groups-organisation_id")
+      .table(group::Entity)
+      .col(group::Column::OrganisationId)
+      .to_owned())
+      .await?;
+
+    // User Profiles
+    manager
+      .create_table(Table::create()
+      .table(user_profile::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(user_profile::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(user_profile::Column::UserId)
+        .uuid().not_null().unique_key())
+      .col(
+        ColumnDef::new(user_profile::Column::Username)
+        .string().null().unique_key())
+      .col(
+        ColumnDef::new(user_profile::Column::ProfileImageFileId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(user_profile::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(user_profile::Column::ContactDetails)
+        .json().not_null())
+      .col(
+        ColumnDef::new(user_profile::Column::Notes)
+        .string().null())
+      .col(
+        ColumnDef::new(user_profile::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(user_profile::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-user_profiles-user_id")
+        .from(user_profile::Entity, user_profile::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    // Organisation Profiles
+    manager
+      .create_table(Table::create()
+      .table(organisation_profile::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(organisation_profile::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(organisation_profile::Column::OrganisationId)
+        .uuid().not_null().unique_key())
+      .col(
+        ColumnDef::new(organisation_profile::Column::OrganisationImageFileId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(organisation_profile::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(organisation_profile::Column::ContactDetails)
+        .json().not_null())
+      .col(
+        ColumnDef::new(organisation_profile::Column::Notes)
+        .string().null())
+      .col(
+        ColumnDef::new(organisation_profile::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(organisation_profile::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-organisation_profiles-organisation_id")
+        .from(organisation_profile::Entity, organisation_profile::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    // User Emails
+    manager
+      .create_table(Table::create()
+      .table(email::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(email::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(email::Column::UserId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(email::Column::EmailAddress)
+        .string().not_null())
+      .col(
+        ColumnDef::new(email::Column::VerificationCode)
+        .string().null().unique_key())
+      .col(
+        ColumnDef::new(email::Column::VerificationCodeExpiresAt)
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(email::Column::IsPrimary)
+        .boolean().default(false).not_null())
+      .col(
+        ColumnDef::new(email::Column::IsVerified)
+        .boolean().default(false).not_null())
+      .col(
+        ColumnDef::new(email::Column::VerifiedAt)
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(email::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(email::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-user_emails-user_id")
+        .from(email::Entity, email::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-user_emails-user_id")
+      .table(email::Entity)
+      .col(email::Column::UserId)
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-user_emails-email_address")
+      .table(email::Entity)
+      .col(email::Column::EmailAddress)
+      .to_owned())
+      .await?;
+
+    // User Phones
+    manager
+      .create_table(Table::create()
+      .table(phone::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(phone::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(phone::Column::UserId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(phone::Column::OrganisationProfileId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(phone::Column::NeedsVerification)
+        .boolean().default(false).not_null())
+      .col(
+        ColumnDef::new(phone::Column::PhoneCountry)
+        .integer().not_null())
+      .col(
+        ColumnDef::new(phone::Column::PhoneNumber)
+        .big_integer().not_null())
+      .col(
+        ColumnDef::new(phone::Column::IsPrimary)
+        .boolean().default(false).not_null())
+      .col(
+        ColumnDef::new(phone::Column::IsVerified)
+        .boolean().default(false).not_null())
+      .col(
+        ColumnDef::new(phone::Column::VerificationCode)
+        .string().null().unique_key())
+      .col(
+        ColumnDef::new(phone::Column::VerificationCodeExpiresAt)
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(phone::Column::VerifiedAt)
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(phone::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(phone::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-user_phones-user_id")
+        .from(phone::Entity, phone::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-user_phones-organisation_profile_id")
+        .from(phone::Entity, phone::Column::OrganisationProfileId)
+        .to(organisation_profile::Entity, organisation_profile::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-user_phones-user_id")
+      .table(phone::Entity)
+      .col(phone::Column::UserId)
+      .to_owned())
+      .await?;
+
     // Auth Method Pass
     manager
       .create_table(Table::create()
@@ -105,11 +490,13 @@ impl MigrationTrait for Migration {
         .boolean().default(false).not_null())
       .col(
         ColumnDef::new(auth_method_pass::Column::PassResetCode)
-        .string().null())
+        .string().null().unique_key())
+      .col(
+        ColumnDef::new(auth_method_pass::Column::PassResetStr)
+        .string().null().unique_key())
       .col(
         ColumnDef::new(auth_method_pass::Column::PassResetCodeExpiresAt)
-        .timestamp_with_time_zone()
-        .default(Option::<ChronoDateTimeUtc>::None).null())
+        .timestamp_with_time_zone().null())
       .col(
         ColumnDef::new(auth_method_pass::Column::CreatedAt)
         .timestamp_with_time_zone().not_null()
@@ -118,188 +505,436 @@ impl MigrationTrait for Migration {
         ColumnDef::new(auth_method_pass::Column::UpdatedAt)
         .timestamp_with_time_zone().not_null()
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-auth_method_passes-user_id")
+        .from(auth_method_pass::Entity, auth_method_pass::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-auth_method_passes-user_id")
+      .table(auth_method_pass::Entity)
+      .col(auth_method_pass::Column::UserId)
       .to_owned())
       .await?;
 
-    // User Email
+    // Auth Method Magic Link
     manager
       .create_table(Table::create()
-      .table(user_email::Entity)
+      .table(auth_method_magiclink::Entity)
       .if_not_exists()
       .col(
-        ColumnDef::new(user_email::Column::Id)
+        ColumnDef::new(auth_method_magiclink::Column::Id)
         .uuid().not_null().primary_key())
       .col(
-        ColumnDef::new(user_email::Column::UserId)
-        .uuid().not_null())
+        ColumnDef::new(auth_method_magiclink::Column::UserId)
+        .uuid().not_null().unique_key())
       .col(
-        ColumnDef::new(user_email::Column::EmailAddress)
-        .string().not_null())
+        ColumnDef::new(auth_method_magiclink::Column::EmailId)
+        .uuid().null())
       .col(
-        ColumnDef::new(user_email::Column::VerificationCode)
-        .string().null())    
+        ColumnDef::new(auth_method_magiclink::Column::PhoneId)
+        .uuid().null())
       .col(
-        ColumnDef::new(user_email::Column::VerificationCodeExpiresAt)
-        .timestamp_with_time_zone()
-        .default(Option::<ChronoDateTimeUtc>::None).null())
+        ColumnDef::new(auth_method_magiclink::Column::LinkHash)
+        .string().null().unique_key())
       .col(
-        ColumnDef::new(user_email::Column::IsPrimary)
-        .boolean().default(false).not_null())
+        ColumnDef::new(auth_method_magiclink::Column::LinkHashExpiresAt)
+        .timestamp_with_time_zone().null())
       .col(
-        ColumnDef::new(user_email::Column::IsVerified)
-        .boolean().default(false).not_null())
-      .col(
-        ColumnDef::new(user_email::Column::VerifiedAt)
-        .timestamp_with_time_zone()
-        .default(Option::<ChronoDateTimeUtc>::None).null())
+        ColumnDef::new(auth_method_magiclink::Column::LinkUsedAt)
+        .timestamp_with_time_zone().null())
       .col(
-        ColumnDef::new(user_email::Column::CreatedAt)
+        ColumnDef::new(auth_method_magiclink::Column::CreatedAt)
         .timestamp_with_time_zone().not_null()
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
       .col(
-        ColumnDef::new(user_email::Column::UpdatedAt)
+        ColumnDef::new(auth_method_magiclink::Column::UpdatedAt)
         .timestamp_with_time_zone().not_null()
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-auth_method_magiclinks-user_id")
+        .from(auth_method_magiclink::Entity, auth_method_magiclink::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-auth_method_magiclinks-email_id")
+        .from(auth_method_magiclink::Entity, auth_method_magiclink::Column::EmailId)
+        .to(email::Entity, email::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-auth_method_magiclinks-phone_id")
+        .from(auth_method_magiclink::Entity, auth_method_magiclink::Column::PhoneId)
+        .to(phone::Entity, phone::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
       .to_owned())
       .await?;
 
-    // User Phone
+    // Files
     manager
       .create_table(Table::create()
-      .table(user_phone::Entity)
+      .table(file::Entity)
       .if_not_exists()
       .col(
-        ColumnDef::new(user_phone::Column::Id)
+        ColumnDef::new(file::Column::Id)
         .uuid().not_null().primary_key())
       .col(
-        ColumnDef::new(user_phone::Column::UserId)
-        .uuid().not_null())
+        ColumnDef::new(file::Column::OrganisationProfileId)
+        .uuid().null())
       .col(
-        ColumnDef::new(user_phone::Column::PhoneCountry)
-        .integer().unsigned().not_null())
+        ColumnDef::new(file::Column::UserProfileId)
+        .uuid().null())
       .col(
-        ColumnDef::new(user_phone::Column::PhoneNumber)
-        .integer().unsigned().not_null())
+        ColumnDef::new(file::Column::GroupId)
+        .uuid().null())
       .col(
-        ColumnDef::new(user_phone::Column::IsPrimary)
-        .boolean().default(false).not_null())
+        ColumnDef::new(file::Column::S3FileUrl)
+        .string().not_null())
       .col(
-        ColumnDef::new(user_phone::Column::IsVerified)
-        .boolean().default(false).not_null())
+        ColumnDef::new(file::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
       .col(
-        ColumnDef::new(user_email::Column::VerificationCode)
-        .string().null())    
+        ColumnDef::new(file::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-files-organisation_profile_id")
+        .from(file::Entity, file::Column::OrganisationProfileId)
+        .to(organisation_profile::Entity, organisation_profile::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-files-user_profile_id")
+        .from(file::Entity, file::Column::UserProfileId)
+        .to(user_profile::Entity, user_profile::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-files-group_id")
+        .from(file::Entity, file::Column::GroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    // Api Keys
+    manager
+      .create_table(Table::create()
+      .table(auth_api_key::Entity)
+      .if_not_exists()
       .col(
-        ColumnDef::new(user_email::Column::VerificationCodeExpiresAt)
-        .timestamp_with_time_zone()
-        .default(Option::<ChronoDateTimeUtc>::None).null())
+        ColumnDef::new(auth_api_key::Column::Id)
+        .uuid().not_null().primary_key())
       .col(
-        ColumnDef::new(user_email::Column::VerifiedAt)
-        .timestamp_with_time_zone()
-        .default(Option::<ChronoDateTimeUtc>::None).null())
+        ColumnDef::new(auth_api_key::Column::UserId)
+        .uuid().null())
       .col(
-        ColumnDef::new(user_phone::Column::VerifiedAt)
-        .timestamp_with_time_zone().default(Option::<ChronoDateTimeUtc>::None))
+        ColumnDef::new(auth_api_key::Column::OrganisationId)
+        .uuid().null())
       .col(
-        ColumnDef::new(user_phone::Column::CreatedAt)
+        ColumnDef::new(auth_api_key::Column::ApiAccessKey)
+        .string().not_null().unique_key())
+      .col(
+        ColumnDef::new(auth_api_key::Column::ApiSecretKey)
+        .string().not_null())
+      .col(
+        ColumnDef::new(auth_api_key::Column::KeyIssuedAt)
         .timestamp_with_time_zone().not_null()
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
-      .col(ColumnDef::new(user_phone::Column::UpdatedAt)
-      .timestamp_with_time_zone().not_null()
+      .col(
+        ColumnDef::new(auth_api_key::Column::ExpiresOn)
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(auth_api_key::Column::IpAddressLastUsed)
+        .string().null())
+      .col(
+        ColumnDef::new(auth_api_key::Column::KeyLastUsedAt)
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(auth_api_key::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(auth_api_key::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-api_keys-user_id")
+        .from(auth_api_key::Entity, auth_api_key::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-api_keys-organisation_id")
+        .from(auth_api_key::Entity, auth_api_key::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
       .to_owned())
       .await?;
 
-    // Group
+    // PKI Keys
     manager
       .create_table(Table::create()
-      .table(group::Entity)
+      .table(pki_key::Entity)
       .if_not_exists()
-      .col(ColumnDef::new(group::Column::Id).uuid().not_null().primary_key())
-      .col(ColumnDef::new(group::Column::Name).string().not_null())
-      .col(ColumnDef::new(group::Column::Description).string().null())
-      .col(ColumnDef::new(group::Column::CreatedAt).timestamp_with_time_zone().not_null()
-      .extra("DEFAULT CURRENT_TIMESTAMP".into()))
-      .col(ColumnDef::new(group::Column::UpdatedAt).timestamp_with_time_zone().not_null()
-      .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(pki_key::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(pki_key::Column::UserId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(pki_key::Column::OrganisationId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(pki_key::Column::GroupId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(pki_key::Column::PrivateKey)
+        .text().null())
+      .col(
+        ColumnDef::new(pki_key::Column::PublicKey)
+        .text().null())
+      .col(
+        ColumnDef::new(pki_key::Column::AwsKmsUrl)
+        .string().null())
+      .col(
+        ColumnDef::new(pki_key::Column::Algo)
+        .enumeration(pki_key::KeyAlgosEnum, pki_key::KeyAlgos::iden_values())
+        .not_null())
+      .col(
+        ColumnDef::new(pki_key::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(pki_key::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-pki_key-organisation_id")
+        .from(pki_key::Entity, pki_key::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-pki_key-user_id")
+        .from(pki_key::Entity, pki_key::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-pki_key-group_id")
+        .from(pki_key::Entity, pki_key::Column::GroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
       .to_owned())
       .await?;
-    
-    // Group Auth Role
+
+    // Users - Organisations - Organisation Access Roles
     manager
       .create_table(Table::create()
-      .table(group_access_role::Entity)
+      .table(users_organisations_organisations_access_roles::Entity)
       .if_not_exists()
       .col(
-        ColumnDef::new(group_access_role::Column::Id)
-        .uuid().not_null().primary_key())
-      .col(
-        ColumnDef::new(group_access_role::Column::Name)
-        .string().not_null())
+        ColumnDef::new(users_organisations_organisations_access_roles::Column::UserId)
+        .uuid().not_null())
       .col(
-        ColumnDef::new(group_access_role::Column::Description)
-        .string().null())
+        ColumnDef::new(users_organisations_organisations_access_roles::Column::OrganisationId)
+        .uuid().not_null())
       .col(
-        ColumnDef::new(group_access_role::Column::AuthRole)
-        .enumeration(group_access_role::AuthRoleEnum, group_access_role::AuthRole::iden_values())
-        .not_null())
+        ColumnDef::new(users_organisations_organisations_access_roles::Column::OrganisationAccessRoleId)
+        .uuid().not_null())
       .col(
-        ColumnDef::new(group_access_role::Column::CreatedAt)
+        ColumnDef::new(users_organisations_organisations_access_roles::Column::CreatedAt)
         .timestamp_with_time_zone().not_null()
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
       .col(
-        ColumnDef::new(group_access_role::Column::UpdatedAt)
+        ColumnDef::new(users_organisations_organisations_access_roles::Column::UpdatedAt)
         .timestamp_with_time_zone().not_null()
         .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-users_organisations_organisation_access_roles-user_id")
+        .from(users_organisations_organisations_access_roles::Entity, users_organisations_organisations_access_roles::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-users_organisations_organisation_access_roles-organisation_id")
+        .from(users_organisations_organisations_access_roles::Entity, users_organisations_organisations_access_roles::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-users_organisations_organisation_access_roles-organisation_access_role_id")
+        .from(users_organisations_organisations_access_roles::Entity, users_organisations_organisations_access_roles::Column::OrganisationAccessRoleId)
+        .to(organisation_access_role::Entity, organisation_access_role::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .primary_key(
+        Index::create()
+        .col(users_organisations_organisations_access_roles::Column::UserId)
+        .col(users_organisations_organisations_access_roles::Column::OrganisationId))
       .to_owned())
       .await?;
 
-    // Users - Groups - Group Auth Roles
+    // Users - Groups - Group Access Roles
     manager
       .create_table(Table::create()
       .table(users_groups_group_access_roles::Entity)
       .if_not_exists()
       .col(
         ColumnDef::new(users_groups_group_access_roles::Column::UserId)
-        .uuid().not_null().primary_key())
+        .uuid().not_null())
       .col(
         ColumnDef::new(users_groups_group_access_roles::Column::GroupId)
-        .uuid().not_null().primary_key())
+        .uuid().not_null())
       .col(
-        ColumnDef::new(users_groups_group_access_roles::Column::GroupAuthRoleId)
+        ColumnDef::new(users_groups_group_access_roles::Column::GroupAccessRoleId)
         .uuid().not_null())
       .col(
         ColumnDef::new(users_groups_group_access_roles::Column::CreatedAt)
         .timestamp_with_time_zone().not_null()
-      .extra("DEFAULT CURRENT_TIMESTAMP".into()))
-      .col(ColumnDef::new(users_groups_group_access_roles::Column::UpdatedAt).timestamp_with_time_zone().not_null()
-      .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(users_groups_group_access_roles::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-users_groups_group_access_roles-user_id")
+        .from(users_groups_group_access_roles::Entity, users_groups_group_access_roles::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-users_groups_group_access_roles-group_id")
+        .from(users_groups_group_access_roles::Entity, users_groups_group_access_roles::Column::GroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-users_groups_group_access_roles-group_access_role_id")
+        .from(users_groups_group_access_roles::Entity, users_groups_group_access_roles::Column::GroupAccessRoleId)
+        .to(group_access_role::Entity, group_access_role::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .primary_key(
+        Index::create()
+        .col(users_groups_group_access_roles::Column::UserId)
+        .col(users_groups_group_access_roles::Column::GroupId))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-users_groups_group_access_roles-group_id")
+      .table(users_groups_group_access_roles::Entity)
+      .col(users_groups_group_access_roles::Column::GroupId)
+      .to_owned())
+      .await?;
+
+    // Auth Tokens
+    manager
+      .create_table(Table::create()
+      .table(auth_token::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(auth_token::Column::Token)
+        .string().not_null().primary_key())
+      .col(
+        ColumnDef::new(auth_token::Column::Refresh)
+        .string().null())
+      .col(
+        ColumnDef::new(auth_token::Column::TokenType)
+        .string().not_null())
+      .col(
+        ColumnDef::new(auth_token::Column::OwnerId)
+        .big_integer().not_null())
+      .col(
+        ColumnDef::new(auth_token::Column::Expire)
+        .timestamp_with_time_zone().not_null())
+      .col(
+        ColumnDef::new(auth_token::Column::ClientId)
+        .big_integer().null())
+      .col(
+        ColumnDef::new(auth_token::Column::Scope)
+        .string().not_null())
+      .col(
+        ColumnDef::new(auth_token::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(auth_token::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
       .to_owned())
       .await?;
 
     Ok(())
   }
 
-  // .col(ColumnDef::new(Chef::BakeryId).integer().not_null())
-  // .foreign_key(
-  //     ForeignKey::create()
-  //         .name("fk-chef-bakery_id")
-  //         .from(Chef::Table, Chef::BakeryId)
-  //         .to(Bakery::Table, Bakery::Id),
-  // )
-
+  // Tables are dropped in reverse so nothing is still referenced by a foreign key
   async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
     manager
-      .drop_table(Table::drop().table(user::Entity).to_owned())
+      .drop_table(Table::drop().table(auth_token::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(users_groups_group_access_roles::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(users_organisations_organisations_access_roles::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(pki_key::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(auth_api_key::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(file::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(auth_method_magiclink::Entity).to_owned())
       .await?;
     manager
       .drop_table(Table::drop().table(auth_method_pass::Entity).to_owned())
       .await?;
     manager
-      .drop_table(Table::drop().table(user_email::Entity).to_owned())
+      .drop_table(Table::drop().table(phone::Entity).to_owned())
       .await?;
     manager
-      .drop_table(Table::drop().table(user_phone::Entity).to_owned())
+      .drop_table(Table::drop().table(email::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(organisation_profile::Entity).to_owned())
       .await?;
     manager
       .drop_table(Table::drop().table(user_profile::Entity).to_owned())
@@ -311,213 +946,31 @@ impl MigrationTrait for Migration {
       .drop_table(Table::drop().table(group_access_role::Entity).to_owned())
       .await?;
     manager
-      .drop_table(Table::drop().table(users_groups_group_access_roles::Entity).to_owned())
+      .drop_table(Table::drop().table(organisation_access_role::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(organisation::Entity).to_owned())
       .await?;
+    manager
+      .drop_table(Table::drop().table(user::Entity).to_owned())
+      .await?;
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .drop_type(Type::drop().name(pki_key::KeyAlgosEnum).to_owned())
+        .await?;
+      manager
+        .drop_type(Type::drop().name(group_access_role::GroupRolePermissionsEnum).to_owned())
+        .await?;
+      manager
+        .drop_type(Type::drop().name(organisation_access_role::OrgRolePermissionsEnum).to_owned())
+        .await?;
+      manager
+        .drop_type(Type::drop().name(auth_method_pass::PassHashCipherEnum).to_owned())
+        .await?;
+      manager
+        .drop_type(Type::drop().name(user::LockedStateEnum).to_owned())
+        .await?;
+    }
     Ok(())
   }
 }
-
-// #[derive(Iden, EnumIter)]
-// pub enum GroupAuthRoleEnum {
-//   Table,
-//   #[iden = "Owner"]
-//   Owner,
-//   #[iden = "AllowOwner"]
-//   AllowOwner,
-//   #[iden = "AllowAdmin"]
-//   AllowAdmin,
-//   #[iden = "AllowReadWrite"]
-//   AllowReadWrite,
-//   #[iden = "AllowReadOnly"]
-//   AllowReadOnly,
-//   #[iden = "DeniedVisible"]
-//   DeniedVisible,
-//   #[iden = "DeniedBlocked"]
-//   DeniedBlocked,
-// }
-
-// #[derive(Iden, EnumIter)]
-// pub enum UserLockedState {
-//   Table,
-//   #[iden = "Unlocked"]
-//   Unlocked,
-//   #[iden = "TemporarilyLocked"]
-//   TemporarilyLocked,
-//   #[iden = "PermanentlyLocked"]
-//   PermanentlyLocked,
-// }
-
-// #[derive(Iden)]
-// pub enum User {
-//   Table,
-//   Id,
-//   LockedState,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(User::Table.to_string(), "user");
-// assert_eq!(User::Id.to_string(), "id");
-// assert_eq!(User::CreatedAt.to_string(), "created_at");
-// assert_eq!(User::UpdatedAt.to_string(), "updated_at");
-
-// #[derive(Iden)]
-// pub enum Group {
-//   Table,
-//   Id,
-//   Name,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(Group::Table.to_string(), "group");
-// assert_eq!(Group::Id.to_string(), "id");
-// assert_eq!(Group::Name.to_string(), "name");
-// assert_eq!(Group::CreatedAt.to_string(), "created_at");
-// assert_eq!(Group::UpdatedAt.to_string(), "updated_at");
-
-// #[derive(Iden)]
-// pub enum UserAuthPass {
-//   Table,
-//   Id,
-//   UserId,
-//   Password,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(UserAuthPass::Table.to_string(), "user_auth_pass");
-// assert_eq!(UserAuthPass::Id.to_string(), "id");
-// assert_eq!(UserAuthPass::UserId.to_string(), "user_id");
-// assert_eq!(UserAuthPass::Password.to_string(), "password");
-// assert_eq!(UserAuthPass::CreatedAt.to_string(), "created_at");
-// assert_eq!(UserAuthPass::UpdatedAt.to_string(), "updated_at");
-
-// #[derive(Iden)]
-// pub enum UserEmail {
-//   Table,
-//   Id,
-//   UserId,
-//   EmailAddress,
-//   IsPrimary,
-//   IsVerified,
-//   VerifiedAt,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(UserEmail::Table.to_string(), "user_email");
-// assert_eq!(UserEmail::Id.to_string(), "id");
-// assert_eq!(UserEmail::UserId.to_string(), "user_id");
-// assert_eq!(UserEmail::EmailAddress.to_string(), "email_address");
-// assert_eq!(UserEmail::IsVerified.to_string(), "is_primary");
-// assert_eq!(UserEmail::IsVerified.to_string(), "is_verified");
-// assert_eq!(UserEmail::VerifiedAt.to_string(), "verified_at");
-// assert_eq!(UserEmail::CreatedAt.to_string(), "created_at");
-// assert_eq!(UserEmail::UpdatedAt.to_string(), "updated_at");
-
-// #[derive(Iden)]
-// pub enum UserPhone {
-//   Table,
-//   Id,
-//   UserId,
-//   PhoneCountry,
-//   PhoneNumber,
-//   IsPrimary,
-//   IsVerified,
-//   VerifiedAt,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(UserPhone::Table.to_string(), "user_phone");
-// assert_eq!(UserPhone::Id.to_string(), "id");
-// assert_eq!(UserPhone::UserId.to_string(), "user_id");
-// assert_eq!(UserPhone::PhoneCountry.to_string(), "phone_country");
-// assert_eq!(UserPhone::PhoneNumber.to_string(), "phone_number");
-// assert_eq!(UserPhone::IsPrimary.to_string(), "is_primary");
-// assert_eq!(UserPhone::IsVerified.to_string(), "is_verified");
-// assert_eq!(UserPhone::VerifiedAt.to_string(), "verified_at");
-// assert_eq!(UserPhone::CreatedAt.to_string(), "created_at");
-// assert_eq!(UserPhone::UpdatedAt.to_string(), "updated_at");
-
-// #[derive(Iden)]
-// pub enum UserProfile {
-//   Table,
-//   Id,
-//   UserId,
-//   Name,
-//   ContactDetails,
-//   IsVerified,
-//   VerifiedAt,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(UserProfile::Table.to_string(), "user_profile");
-// assert_eq!(UserProfile::Id.to_string(), "id");
-// assert_eq!(UserProfile::UserId.to_string(), "user_id");
-// assert_eq!(UserProfile::Name.to_string(), "name");
-// assert_eq!(UserProfile::ContactDetails.to_string(), "contact_details");
-// assert_eq!(UserProfile::IsVerified.to_string(), "is_verified");
-// assert_eq!(UserProfile::VerifiedAt.to_string(), "verified_at");
-// assert_eq!(UserProfile::CreatedAt.to_string(), "created_at");
-// assert_eq!(UserProfile::UpdatedAt.to_string(), "updated_at");
-
-// #[derive(Iden)]
-// pub enum GroupAuthRole {
-//   Table,
-//   Id,
-//   Name,
-//   Description,
-//   AuthRole,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(GroupAuthRole::Table.to_string(), "group_auth_role");
-// assert_eq!(GroupAuthRole::Id.to_string(), "id");
-// assert_eq!(GroupAuthRole::Name.to_string(), "name");
-// assert_eq!(GroupAuthRole::Description.to_string(), "description");
-// assert_eq!(GroupAuthRole::AuthRole.to_string(), "group_auth_role");
-// assert_eq!(GroupAuthRole::CreatedAt.to_string(), "created_at");
-// assert_eq!(GroupAuthRole::UpdatedAt.to_string(), "updated_at");
-
-// #[derive(Iden)]
-// pub enum UsersGroupsGroupAuthRoles {
-//   Table,
-//   Id,
-//   UserId,
-//   GroupId,
-//   GroupAuthRoleId,
-//   CreatedAt,
-//   UpdatedAt,
-// }
-
-// assert_eq!(UsersGroupsGroupAuthRoles::Table.to_string(), "users_groups_group_role_roles");
-// assert_eq!(UsersGroupsGroupAuthRoles::Id.to_string(), "id");
-// assert_eq!(UsersGroupsGroupAuthRoles::UserId.to_string(), "user_id");
-// assert_eq!(UsersGroupsGroupAuthRoles::GroupId.to_string(), "group_id");
-// assert_eq!(UsersGroupsGroupAuthRoles::GroupAuthRoleId.to_string(), "group_auth_role_id");
-// assert_eq!(UsersGroupsGroupAuthRoles::CreatedAt.to_string(), "created_at");
-// assert_eq!(UsersGroupsGroupAuthRoles::UpdatedAt.to_string(), "updated_at");
-
-// impl Migrator {
-//   pub async fn run_migrations() -> Result<(), DbErr> {
-//     let config = Config::new();
-//     let db = create_connection(&config, false)
-//         .await
-//         .expect("Unable to connect to db");
-//     match Migrator::up(&db, None).await {
-//         Ok(_) => Ok(()),
-//         Err(e) => {
-//             let msg = e.to_string();
-//             // This is ok, just the migrator being funky
-//             if !msg.contains("been applied but its file is missing") {
-//                 return Err(e);
-//             }
-//             Ok(())
-//         }
-//     }
-//   }
-// }
\ No newline at end of file
@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    // Organisation Policies
+    manager
+      .create_table(Table::create()
+      .table(organisation_policy::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(organisation_policy::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(organisation_policy::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(organisation_policy::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(organisation_policy::Column::Description)
+        .string().null())
+      .col(
+        ColumnDef::new(organisation_policy::Column::Document)
+        .json_binary().not_null())
+      .col(
+        ColumnDef::new(organisation_policy::Column::IsEnabled)
+        .boolean().default(true).not_null())
+      .col(
+        ColumnDef::new(organisation_policy::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(organisation_policy::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-organisation_policies-organisation_id")
+        .from(organisation_policy::Entity, organisation_policy::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .index(
+        Index::create()
+        .name("idx-organisation_policies-organisation_id-name")
+        .col(organisation_policy::Column::OrganisationId)
+        .col(organisation_policy::Column::Name)
+        .unique())
+      .to_owned())
+      .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(organisation_policy::Entity).to_owned())
+      .await?;
+    Ok(())
+  }
+}
@@ -0,0 +1,152 @@
+use sea_orm_migration::{
+  prelude::*,
+  sea_orm::DbBackend,
+  sea_query::extension::postgres::Type,
+};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .create_type(Type::create()
+        .as_enum(invitation::InvitationStatusEnum)
+        .values(invitation::InvitationStatus::iden_values())
+        .to_owned())
+        .await?;
+    }
+
+    // Invitations
+    manager
+      .create_table(Table::create()
+      .table(invitation::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(invitation::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(invitation::Column::EmailAddress)
+        .string().not_null())
+      .col(
+        ColumnDef::new(invitation::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(invitation::Column::OrganisationAccessRoleId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(invitation::Column::GroupId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(invitation::Column::GroupAccessRoleId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(invitation::Column::InviterUserId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(invitation::Column::Token)
+        .string().not_null().unique_key())
+      .col(
+        ColumnDef::new(invitation::Column::Status)
+        .enumeration(invitation::InvitationStatusEnum, invitation::InvitationStatus::iden_values())
+        .default(invitation::InvitationStatus::Pending)
+        .not_null())
+      .col(
+        ColumnDef::new(invitation::Column::ExpiresAt)
+        .timestamp_with_time_zone().not_null())
+      .col(
+        ColumnDef::new(invitation::Column::RespondedAt)
+        .timestamp_with_time_zone().null())
+      .col(
+        ColumnDef::new(invitation::Column::AcceptedUserId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(invitation::Column::EmailId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(invitation::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(invitation::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-invitations-organisation_id")
+        .from(invitation::Entity, invitation::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-invitations-organisation_access_role_id")
+        .from(invitation::Entity, invitation::Column::OrganisationAccessRoleId)
+        .to(organisation_access_role::Entity, organisation_access_role::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-invitations-group_id")
+        .from(invitation::Entity, invitation::Column::GroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-invitations-group_access_role_id")
+        .from(invitation::Entity, invitation::Column::GroupAccessRoleId)
+        .to(group_access_role::Entity, group_access_role::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-invitations-inviter_user_id")
+        .from(invitation::Entity, invitation::Column::InviterUserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-invitations-accepted_user_id")
+        .from(invitation::Entity, invitation::Column::AcceptedUserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::SetNull))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-invitations-email_id")
+        .from(invitation::Entity, invitation::Column::EmailId)
+        .to(email::Entity, email::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::SetNull))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-invitations-organisation_id")
+      .table(invitation::Entity)
+      .col(invitation::Column::OrganisationId)
+      .to_owned())
+      .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(invitation::Entity).to_owned())
+      .await?;
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .drop_type(Type::drop().name(invitation::InvitationStatusEnum).to_owned())
+        .await?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,82 @@
+use sea_orm_migration::{prelude::*, sea_orm::{ConnectionTrait, DbBackend}};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Short numeric codes collide, so they're looked up through their owner rather than being unique.
+/// SQLite can't drop a column constraint, its databases keep them.
+const UNIQUE_CODE_CONSTRAINTS: [(&str, &str); 3] = [
+  ("user_emails", "verification_code"),
+  ("user_phones", "verification_code"),
+  ("auth_method_passes", "pass_reset_code"),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(Table::alter()
+      .table(email::Entity)
+      .add_column(
+        ColumnDef::new(email::Column::VerificationAttempts)
+        .integer().not_null().default(0))
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(phone::Entity)
+      .add_column(
+        ColumnDef::new(phone::Column::VerificationAttempts)
+        .integer().not_null().default(0))
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(auth_method_pass::Entity)
+      .add_column(
+        ColumnDef::new(auth_method_pass::Column::PassResetAttempts)
+        .integer().not_null().default(0))
+      .to_owned())
+      .await?;
+
+    if manager.get_database_backend() == DbBackend::Postgres {
+      for (table, column) in UNIQUE_CODE_CONSTRAINTS {
+        manager.get_connection()
+          .execute_unprepared(&format!("ALTER TABLE {0} DROP CONSTRAINT IF EXISTS {0}_{1}_key", table, column))
+          .await?;
+      }
+    }
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    if manager.get_database_backend() == DbBackend::Postgres {
+      for (table, column) in UNIQUE_CODE_CONSTRAINTS {
+        manager.get_connection()
+          .execute_unprepared(&format!("ALTER TABLE {0} ADD CONSTRAINT {0}_{1}_key UNIQUE ({1})", table, column))
+          .await?;
+      }
+    }
+
+    manager
+      .alter_table(Table::alter()
+      .table(auth_method_pass::Entity)
+      .drop_column(auth_method_pass::Column::PassResetAttempts)
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(phone::Entity)
+      .drop_column(phone::Column::VerificationAttempts)
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(email::Entity)
+      .drop_column(email::Column::VerificationAttempts)
+      .to_owned())
+      .await
+  }
+}
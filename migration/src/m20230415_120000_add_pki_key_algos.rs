@@ -0,0 +1,40 @@
+use sea_orm_migration::{prelude::*, sea_orm::{ConnectionTrait, DbBackend}};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Listed here rather than read from the entity, which keeps growing
+const ADDED_ALGOS: [&str; 3] = ["Ed25519", "EcdsaP256", "EcdsaP384"];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    // SQLite stores enums as text, only Postgres has a type to extend
+    if manager.get_database_backend() == DbBackend::Postgres {
+      for algo in ADDED_ALGOS {
+        manager.get_connection()
+          .execute_unprepared(&format!("ALTER TYPE pki_key_algos ADD VALUE IF NOT EXISTS '{}'", algo))
+          .await?;
+      }
+    }
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+    let added = ADDED_ALGOS.iter().map(|algo| format!("'{}'", algo)).collect::<Vec<String>>().join(", ");
+    let removed_keys = format!("SELECT id FROM pki_key WHERE algo IN ({})", added);
+    db.execute_unprepared(&format!("UPDATE users SET pki_key_id = NULL WHERE pki_key_id IN ({})", removed_keys)).await?;
+    db.execute_unprepared(&format!("UPDATE organisations SET pki_key_id = NULL WHERE pki_key_id IN ({})", removed_keys)).await?;
+    db.execute_unprepared(&format!("DELETE FROM pki_key WHERE algo IN ({})", added)).await?;
+
+    // Postgres can't drop enum values, the type is rebuilt without them
+    if manager.get_database_backend() == DbBackend::Postgres {
+      db.execute_unprepared("ALTER TYPE pki_key_algos RENAME TO pki_key_algos_old").await?;
+      db.execute_unprepared("CREATE TYPE pki_key_algos AS ENUM ('RSA')").await?;
+      db.execute_unprepared("ALTER TABLE pki_key ALTER COLUMN algo TYPE pki_key_algos USING algo::text::pki_key_algos").await?;
+      db.execute_unprepared("DROP TYPE pki_key_algos_old").await?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Existing plaintext private keys are sealed by the server's re-wrap job
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .add_column(
+        ColumnDef::new(pki_key::Column::EncryptedDataKey)
+        .text().null())
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .add_column(
+        ColumnDef::new(pki_key::Column::MasterKeyId)
+        .string().null())
+      .to_owned())
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .drop_column(pki_key::Column::MasterKeyId)
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .drop_column(pki_key::Column::EncryptedDataKey)
+      .to_owned())
+      .await
+  }
+}
@@ -0,0 +1,229 @@
+use sea_orm_migration::prelude::*;
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    // Certificate authorities
+    manager
+      .create_table(Table::create()
+      .table(certificate_authority::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(certificate_authority::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(certificate_authority::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::ParentId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::PkiKeyId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::Subject)
+        .string().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::MaxPathLength)
+        .integer().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::Certificate)
+        .text().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::Serial)
+        .string().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::NotAfter)
+        .timestamp_with_time_zone().not_null())
+      .col(
+        ColumnDef::new(certificate_authority::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(certificate_authority::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificate_authorities-organisation_id")
+        .from(certificate_authority::Entity, certificate_authority::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificate_authorities-parent_id")
+        .from(certificate_authority::Entity, certificate_authority::Column::ParentId)
+        .to(certificate_authority::Entity, certificate_authority::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificate_authorities-pki_key_id")
+        .from(certificate_authority::Entity, certificate_authority::Column::PkiKeyId)
+        .to(pki_key::Entity, pki_key::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-certificate_authorities-organisation_id")
+      .table(certificate_authority::Entity)
+      .col(certificate_authority::Column::OrganisationId)
+      .to_owned())
+      .await?;
+
+    // Certificate roles
+    manager
+      .create_table(Table::create()
+      .table(certificate_role::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(certificate_role::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(certificate_role::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(certificate_role::Column::CertificateAuthorityId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(certificate_role::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(certificate_role::Column::Template)
+        .json_binary().not_null())
+      .col(
+        ColumnDef::new(certificate_role::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(certificate_role::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificate_roles-organisation_id")
+        .from(certificate_role::Entity, certificate_role::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificate_roles-certificate_authority_id")
+        .from(certificate_role::Entity, certificate_role::Column::CertificateAuthorityId)
+        .to(certificate_authority::Entity, certificate_authority::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-certificate_roles-certificate_authority_id")
+      .table(certificate_role::Entity)
+      .col(certificate_role::Column::CertificateAuthorityId)
+      .to_owned())
+      .await?;
+
+    // Issued certificates
+    manager
+      .create_table(Table::create()
+      .table(certificate::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(certificate::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(certificate::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(certificate::Column::CertificateAuthorityId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(certificate::Column::CertificateRoleId)
+        .uuid().null())
+      .col(
+        ColumnDef::new(certificate::Column::Serial)
+        .string().not_null().unique_key())
+      .col(
+        ColumnDef::new(certificate::Column::Subject)
+        .string().not_null())
+      .col(
+        ColumnDef::new(certificate::Column::NotBefore)
+        .timestamp_with_time_zone().not_null())
+      .col(
+        ColumnDef::new(certificate::Column::NotAfter)
+        .timestamp_with_time_zone().not_null())
+      .col(
+        ColumnDef::new(certificate::Column::Certificate)
+        .text().not_null())
+      .col(
+        ColumnDef::new(certificate::Column::IsCa)
+        .boolean().not_null().default(false))
+      .col(
+        ColumnDef::new(certificate::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(certificate::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificates-organisation_id")
+        .from(certificate::Entity, certificate::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificates-certificate_authority_id")
+        .from(certificate::Entity, certificate::Column::CertificateAuthorityId)
+        .to(certificate_authority::Entity, certificate_authority::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-certificates-certificate_role_id")
+        .from(certificate::Entity, certificate::Column::CertificateRoleId)
+        .to(certificate_role::Entity, certificate_role::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::SetNull))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-certificates-organisation_id")
+      .table(certificate::Entity)
+      .col(certificate::Column::OrganisationId)
+      .to_owned())
+      .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(certificate::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(certificate_role::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(certificate_authority::Entity).to_owned())
+      .await?;
+    Ok(())
+  }
+}
@@ -0,0 +1,97 @@
+use sea_orm_migration::{
+  prelude::*,
+  sea_orm::DbBackend,
+  sea_query::extension::postgres::Type,
+};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .create_type(Type::create()
+        .as_enum(certificate::RevocationReasonEnum)
+        .values(certificate::RevocationReason::iden_values())
+        .to_owned())
+        .await?;
+    }
+
+    // SQLite adds one column per statement
+    manager
+      .alter_table(Table::alter()
+      .table(certificate::Entity)
+      .add_column(
+        ColumnDef::new(certificate::Column::RevokedAt)
+        .timestamp_with_time_zone().null())
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(certificate::Entity)
+      .add_column(
+        ColumnDef::new(certificate::Column::RevocationReason)
+        .enumeration(certificate::RevocationReasonEnum, certificate::RevocationReason::iden_values())
+        .null())
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(certificate_authority::Entity)
+      .add_column(
+        ColumnDef::new(certificate_authority::Column::CrlNumber)
+        .big_integer().not_null().default(0))
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(certificate_authority::Entity)
+      .add_column(
+        ColumnDef::new(certificate_authority::Column::Crl)
+        .text().null())
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(certificate_authority::Entity)
+      .add_column(
+        ColumnDef::new(certificate_authority::Column::CrlNextUpdate)
+        .timestamp_with_time_zone().null())
+      .to_owned())
+      .await?;
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    for column in [
+      certificate_authority::Column::CrlNextUpdate,
+      certificate_authority::Column::Crl,
+      certificate_authority::Column::CrlNumber,
+    ] {
+      manager
+        .alter_table(Table::alter()
+        .table(certificate_authority::Entity)
+        .drop_column(column)
+        .to_owned())
+        .await?;
+    }
+    for column in [certificate::Column::RevocationReason, certificate::Column::RevokedAt] {
+      manager
+        .alter_table(Table::alter()
+        .table(certificate::Entity)
+        .drop_column(column)
+        .to_owned())
+        .await?;
+    }
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .drop_type(Type::drop().name(certificate::RevocationReasonEnum).to_owned())
+        .await?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,219 @@
+use sea_orm_migration::prelude::*;
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    // SSH certificate authorities
+    manager
+      .create_table(Table::create()
+      .table(ssh_certificate_authority::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(ssh_certificate_authority::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(ssh_certificate_authority::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate_authority::Column::PkiKeyId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate_authority::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate_authority::Column::PublicKey)
+        .text().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate_authority::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(ssh_certificate_authority::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_certificate_authorities-organisation_id")
+        .from(ssh_certificate_authority::Entity, ssh_certificate_authority::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_certificate_authorities-pki_key_id")
+        .from(ssh_certificate_authority::Entity, ssh_certificate_authority::Column::PkiKeyId)
+        .to(pki_key::Entity, pki_key::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-ssh_certificate_authorities-organisation_id")
+      .table(ssh_certificate_authority::Entity)
+      .col(ssh_certificate_authority::Column::OrganisationId)
+      .to_owned())
+      .await?;
+
+    // How each group's members get certificates
+    manager
+      .create_table(Table::create()
+      .table(ssh_group_setting::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(ssh_group_setting::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(ssh_group_setting::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_group_setting::Column::GroupId)
+        .uuid().not_null().unique_key())
+      .col(
+        ColumnDef::new(ssh_group_setting::Column::SshCertificateAuthorityId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_group_setting::Column::Template)
+        .json_binary().not_null())
+      .col(
+        ColumnDef::new(ssh_group_setting::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(ssh_group_setting::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_group_settings-organisation_id")
+        .from(ssh_group_setting::Entity, ssh_group_setting::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_group_settings-group_id")
+        .from(ssh_group_setting::Entity, ssh_group_setting::Column::GroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_group_settings-ssh_certificate_authority_id")
+        .from(ssh_group_setting::Entity, ssh_group_setting::Column::SshCertificateAuthorityId)
+        .to(ssh_certificate_authority::Entity, ssh_certificate_authority::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    // Signed certificates
+    manager
+      .create_table(Table::create()
+      .table(ssh_certificate::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(ssh_certificate::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::SshCertificateAuthorityId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::UserId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::GroupId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::Serial)
+        .big_integer().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::KeyId)
+        .string().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::Principals)
+        .json_binary().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::Fingerprint)
+        .string().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::ValidAfter)
+        .timestamp_with_time_zone().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::ValidBefore)
+        .timestamp_with_time_zone().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::Certificate)
+        .text().not_null())
+      .col(
+        ColumnDef::new(ssh_certificate::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(ssh_certificate::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_certificates-organisation_id")
+        .from(ssh_certificate::Entity, ssh_certificate::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_certificates-ssh_certificate_authority_id")
+        .from(ssh_certificate::Entity, ssh_certificate::Column::SshCertificateAuthorityId)
+        .to(ssh_certificate_authority::Entity, ssh_certificate_authority::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_certificates-user_id")
+        .from(ssh_certificate::Entity, ssh_certificate::Column::UserId)
+        .to(user::Entity, user::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-ssh_certificates-group_id")
+        .from(ssh_certificate::Entity, ssh_certificate::Column::GroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-ssh_certificates-organisation_id")
+      .table(ssh_certificate::Entity)
+      .col(ssh_certificate::Column::OrganisationId)
+      .to_owned())
+      .await?;
+
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(ssh_certificate::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(ssh_group_setting::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(ssh_certificate_authority::Entity).to_owned())
+      .await?;
+    Ok(())
+  }
+}
@@ -0,0 +1,87 @@
+use sea_orm_migration::{
+  prelude::*,
+  sea_orm::{prelude::Uuid, ConnectionTrait, DbBackend},
+  sea_query::extension::postgres::Type,
+};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Existing keys become the first, active version of themselves
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .create_type(Type::create()
+        .as_enum(pki_key::KeyStatusEnum)
+        .values(pki_key::KeyStatus::iden_values())
+        .to_owned())
+        .await?;
+    }
+
+    // SQLite adds one column per statement
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .add_column(
+        ColumnDef::new(pki_key::Column::KeyRingId)
+        .uuid().not_null().default(Uuid::nil()))
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .add_column(
+        ColumnDef::new(pki_key::Column::Version)
+        .integer().not_null().default(1))
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .add_column(
+        ColumnDef::new(pki_key::Column::Status)
+        .enumeration(pki_key::KeyStatusEnum, pki_key::KeyStatus::iden_values())
+        .not_null().default("Active"))
+      .to_owned())
+      .await?;
+    manager.get_connection().execute_unprepared("UPDATE pki_key SET key_ring_id = id").await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-pki_key-key_ring_id-version")
+      .table(pki_key::Entity)
+      .col(pki_key::Column::KeyRingId)
+      .col(pki_key::Column::Version)
+      .unique()
+      .to_owned())
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop()
+      .name("idx-pki_key-key_ring_id-version")
+      .table(pki_key::Entity)
+      .to_owned())
+      .await?;
+    // Older versions are left behind as keys of their own
+    for column in [pki_key::Column::Status, pki_key::Column::Version, pki_key::Column::KeyRingId] {
+      manager
+        .alter_table(Table::alter()
+        .table(pki_key::Entity)
+        .drop_column(column)
+        .to_owned())
+        .await?;
+    }
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .drop_type(Type::drop().name(pki_key::KeyStatusEnum).to_owned())
+        .await?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,30 @@
+use sea_orm_migration::prelude::*;
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Existing keys were never chosen to be exported, so they can't be
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .add_column(
+        ColumnDef::new(pki_key::Column::Exportable)
+        .boolean().not_null().default(false))
+      .to_owned())
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(Table::alter()
+      .table(pki_key::Entity)
+      .drop_column(pki_key::Column::Exportable)
+      .to_owned())
+      .await
+  }
+}
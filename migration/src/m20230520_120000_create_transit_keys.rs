@@ -0,0 +1,151 @@
+use sea_orm_migration::{
+  prelude::*,
+  sea_orm::DbBackend,
+  sea_query::extension::postgres::Type,
+};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .create_type(Type::create()
+        .as_enum(transit_key::TransitKeyAlgosEnum)
+        .values(transit_key::TransitKeyAlgos::iden_values())
+        .to_owned())
+        .await?;
+    }
+
+    // Named encryption keys
+    manager
+      .create_table(Table::create()
+      .table(transit_key::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(transit_key::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(transit_key::Column::OrganisationId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(transit_key::Column::GroupId)
+        .uuid())
+      .col(
+        ColumnDef::new(transit_key::Column::Name)
+        .string().not_null())
+      .col(
+        ColumnDef::new(transit_key::Column::Algo)
+        .enumeration(transit_key::TransitKeyAlgosEnum, transit_key::TransitKeyAlgos::iden_values())
+        .not_null())
+      .col(
+        ColumnDef::new(transit_key::Column::LatestVersion)
+        .integer().not_null().default(1))
+      .col(
+        ColumnDef::new(transit_key::Column::MinDecryptionVersion)
+        .integer().not_null().default(1))
+      .col(
+        ColumnDef::new(transit_key::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(transit_key::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-transit_keys-organisation_id")
+        .from(transit_key::Entity, transit_key::Column::OrganisationId)
+        .to(organisation::Entity, organisation::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-transit_keys-group_id")
+        .from(transit_key::Entity, transit_key::Column::GroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-transit_keys-organisation_id-name")
+      .table(transit_key::Entity)
+      .col(transit_key::Column::OrganisationId)
+      .col(transit_key::Column::Name)
+      .to_owned())
+      .await?;
+
+    // Each key's versions, their material sealed like private keys
+    manager
+      .create_table(Table::create()
+      .table(transit_key_version::Entity)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(transit_key_version::Column::Id)
+        .uuid().not_null().primary_key())
+      .col(
+        ColumnDef::new(transit_key_version::Column::TransitKeyId)
+        .uuid().not_null())
+      .col(
+        ColumnDef::new(transit_key_version::Column::Version)
+        .integer().not_null())
+      .col(
+        ColumnDef::new(transit_key_version::Column::KeyMaterial)
+        .text().not_null())
+      .col(
+        ColumnDef::new(transit_key_version::Column::EncryptedDataKey)
+        .text().not_null())
+      .col(
+        ColumnDef::new(transit_key_version::Column::MasterKeyId)
+        .string().not_null())
+      .col(
+        ColumnDef::new(transit_key_version::Column::CreatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .col(
+        ColumnDef::new(transit_key_version::Column::UpdatedAt)
+        .timestamp_with_time_zone().not_null()
+        .extra("DEFAULT CURRENT_TIMESTAMP".into()))
+      .foreign_key(
+        ForeignKey::create()
+        .name("fk-transit_key_versions-transit_key_id")
+        .from(transit_key_version::Entity, transit_key_version::Column::TransitKeyId)
+        .to(transit_key::Entity, transit_key::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::Cascade))
+      .to_owned())
+      .await?;
+
+    manager
+      .create_index(Index::create()
+      .name("idx-transit_key_versions-transit_key_id-version")
+      .table(transit_key_version::Entity)
+      .col(transit_key_version::Column::TransitKeyId)
+      .col(transit_key_version::Column::Version)
+      .unique()
+      .to_owned())
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(transit_key_version::Entity).to_owned())
+      .await?;
+    manager
+      .drop_table(Table::drop().table(transit_key::Entity).to_owned())
+      .await?;
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .drop_type(Type::drop().name(transit_key::TransitKeyAlgosEnum).to_owned())
+        .await?;
+    }
+    Ok(())
+  }
+}
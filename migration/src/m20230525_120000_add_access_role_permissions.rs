@@ -0,0 +1,53 @@
+use sea_orm_migration::{prelude::*, sea_orm::{ConnectionTrait, DbBackend}};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Custom access roles carry their own permission sets
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    // Databases made before custom roles existed lack the enum value
+    if manager.get_database_backend() == DbBackend::Postgres {
+      for enum_type in ["org_role_permissions", "group_role_permissions"] {
+        manager.get_connection()
+          .execute_unprepared(&format!("ALTER TYPE {} ADD VALUE IF NOT EXISTS 'Custom'", enum_type))
+          .await?;
+      }
+    }
+    manager
+      .alter_table(Table::alter()
+      .table(organisation_access_role::Entity)
+      .add_column(
+        ColumnDef::new(organisation_access_role::Column::Permissions)
+        .json_binary().null())
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(group_access_role::Entity)
+      .add_column(
+        ColumnDef::new(group_access_role::Column::Permissions)
+        .json_binary().null())
+      .to_owned())
+      .await
+  }
+
+  /// Postgres can't drop an enum value, `Custom` stays on the types
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(Table::alter()
+      .table(group_access_role::Entity)
+      .drop_column(group_access_role::Column::Permissions)
+      .to_owned())
+      .await?;
+    manager
+      .alter_table(Table::alter()
+      .table(organisation_access_role::Entity)
+      .drop_column(organisation_access_role::Column::Permissions)
+      .to_owned())
+      .await
+  }
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::{prelude::*, sea_orm::DbBackend};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Groups can be nested under a parent group in the same organisation
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let mut parent_group_id = ColumnDef::new(group::Column::ParentGroupId);
+    parent_group_id.uuid().null();
+    // SQLite can only add a foreign key along with the column
+    if manager.get_database_backend() == DbBackend::Sqlite {
+      parent_group_id.extra("REFERENCES groups (id) ON UPDATE CASCADE ON DELETE SET NULL".into());
+    }
+    manager
+      .alter_table(Table::alter()
+      .table(group::Entity)
+      .add_column(&mut parent_group_id)
+      .to_owned())
+      .await?;
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .create_foreign_key(ForeignKey::create()
+        .name("fk-groups-parent_group_id")
+        .from(group::Entity, group::Column::ParentGroupId)
+        .to(group::Entity, group::Column::Id)
+        .on_update(ForeignKeyAction::Cascade)
+        .on_delete(ForeignKeyAction::SetNull)
+        .to_owned())
+        .await?;
+    }
+
+    // Parent lookups when walking the group hierarchy
+    manager
+      .create_index(Index::create()
+      .name("idx-groups-parent_group_id")
+      .table(group::Entity)
+      .col(group::Column::ParentGroupId)
+      .to_owned())
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop()
+      .name("idx-groups-parent_group_id")
+      .table(group::Entity)
+      .to_owned())
+      .await?;
+    if manager.get_database_backend() == DbBackend::Postgres {
+      manager
+        .drop_foreign_key(ForeignKey::drop()
+        .name("fk-groups-parent_group_id")
+        .table(group::Entity)
+        .to_owned())
+        .await?;
+    }
+    manager
+      .alter_table(Table::alter()
+      .table(group::Entity)
+      .drop_column(group::Column::ParentGroupId)
+      .to_owned())
+      .await
+  }
+}
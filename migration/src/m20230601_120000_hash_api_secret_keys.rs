@@ -0,0 +1,36 @@
+use sea_orm_migration::{prelude::*, sea_orm::{prelude::Uuid, ConnectionTrait}};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Api secrets were stored as issued, only their hashes are kept now
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+    let backend = manager.get_database_backend();
+    let keys = db
+      .query_all(backend.build(Query::select()
+      .columns([auth_api_key::Column::Id, auth_api_key::Column::ApiSecretKey])
+      .from(auth_api_key::Entity)))
+      .await?;
+    for key in keys {
+      let id: Uuid = key.try_get("", "id")?;
+      let secret: String = key.try_get("", "api_secret_key")?;
+      db
+        .execute(backend.build(Query::update()
+        .table(auth_api_key::Entity)
+        .value(auth_api_key::Column::ApiSecretKey, auth_api_key::hash_secret(&secret))
+        .and_where(Expr::col(auth_api_key::Column::Id).eq(id))))
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// The hashes can't be turned back into secrets, keys have to be reissued
+  async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+    Ok(())
+  }
+}
@@ -0,0 +1,48 @@
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+use entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Transit key names are unique to their organisation or group. NULLs never equal each other in a
+/// unique index, so organisation keys get their own partial index without group_id.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+    db.execute_unprepared(
+      "CREATE UNIQUE INDEX \"idx-transit_keys-organisation_id-name-unique\" ON transit_keys (organisation_id, name) WHERE group_id IS NULL",
+    ).await?;
+    db.execute_unprepared(
+      "CREATE UNIQUE INDEX \"idx-transit_keys-organisation_id-group_id-name-unique\" ON transit_keys (organisation_id, group_id, name) WHERE group_id IS NOT NULL",
+    ).await?;
+    // Covered by the unique indexes
+    manager
+      .drop_index(Index::drop()
+      .name("idx-transit_keys-organisation_id-name")
+      .table(transit_key::Entity)
+      .to_owned())
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_index(Index::create()
+      .name("idx-transit_keys-organisation_id-name")
+      .table(transit_key::Entity)
+      .col(transit_key::Column::OrganisationId)
+      .col(transit_key::Column::Name)
+      .to_owned())
+      .await?;
+    for name in ["idx-transit_keys-organisation_id-group_id-name-unique", "idx-transit_keys-organisation_id-name-unique"] {
+      manager
+        .drop_index(Index::drop()
+        .name(name)
+        .table(transit_key::Entity)
+        .to_owned())
+        .await?;
+    }
+    Ok(())
+  }
+}
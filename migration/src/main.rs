@@ -0,0 +1,94 @@
+use clap::{Args, Parser, Subcommand};
+use migration::{
+  drift,
+  runner::{self, Action},
+  Migrator,
+};
+use sea_orm_migration::sea_orm::{Database, DbErr};
+
+#[derive(Debug, Parser)]
+#[clap(name = "migration", about = "Applies and rolls back the database migrations")]
+struct Cli {
+  #[clap(long, env = "DATABASE_URL")]
+  database_url: String,
+  #[clap(subcommand)]
+  command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+  /// Apply pending migrations up to and including the target, or all of them
+  Up(ActionArgs),
+  /// Roll back every migration after the target, or only the last one
+  Down(ActionArgs),
+  /// List every migration and whether it has been applied
+  Status,
+  /// Drop every table and type, then apply migrations up to the target
+  Fresh(ActionArgs),
+  /// Roll back every applied migration, then apply migrations up to the target
+  Refresh(ActionArgs),
+  /// Compare the migrated schema against the entities
+  Drift,
+}
+
+#[derive(Debug, Args)]
+struct ActionArgs {
+  /// Migration name or its timestamp prefix, e.g. m20230401_120000
+  #[clap(long)]
+  target: Option<String>,
+  /// Print the SQL that would run instead of running it
+  #[clap(long)]
+  dry_run: bool,
+}
+
+async fn run(cli: Cli) -> Result<(), DbErr> {
+  let db = Database::connect(&cli.database_url).await?;
+  let (action, args) = match cli.command {
+    Command::Up(args) => (Action::Up, args),
+    Command::Down(args) => (Action::Down, args),
+    Command::Fresh(args) => (Action::Fresh, args),
+    Command::Refresh(args) => (Action::Refresh, args),
+    Command::Status => {
+      for state in runner::status::<Migrator, _>(&db).await? {
+        println!("{:<8} {}", if state.applied { "applied" } else { "pending" }, state.name);
+      }
+      return Ok(());
+    },
+    Command::Drift => {
+      let mismatches = drift::check(&db).await?;
+      if mismatches.is_empty() {
+        println!("schema matches the entities");
+        return Ok(());
+      }
+      return Err(DbErr::Custom(format!("schema drift:\n{}", mismatches.join("\n"))));
+    },
+  };
+
+  if args.dry_run {
+    for line in runner::dry_run::<Migrator>(&db, action, args.target.as_deref()).await? {
+      println!("{}", line);
+    }
+    return Ok(());
+  }
+  let plan = runner::run::<Migrator>(&db, action, args.target.as_deref()).await?;
+  if plan.is_empty() {
+    println!("nothing to do");
+  }
+  if plan.drop_all {
+    println!("dropped every table and type");
+  }
+  plan.down.iter().for_each(|name| println!("rolled back {}", name));
+  plan.up.iter().for_each(|name| println!("applied {}", name));
+  Ok(())
+}
+
+#[async_std::main]
+async fn main() {
+  dotenvy::dotenv().ok();
+  env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
+
+  if let Err(err) = run(Cli::parse()).await {
+    eprintln!("{}", err);
+    std::process::exit(1);
+  }
+}
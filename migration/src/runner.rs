@@ -0,0 +1,262 @@
+//! Targeted, locked and dry-run migrations on top of `MigratorTrait`,
+//! used by both the migration binary and the server on startup.
+use std::{
+  collections::HashSet,
+  sync::{Arc, Mutex},
+};
+use sea_orm_migration::{
+  prelude::*,
+  sea_orm::{
+    ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbBackend, ExecResult, FromQueryResult, MockDatabase,
+    MockDatabaseConnection, MockDatabaseTrait, MockExecResult, QueryResult, Statement, TransactionTrait,
+  },
+};
+
+/// Postgres advisory lock held for the whole run, "migratio" in ascii
+pub const LOCK_KEY: i64 = 0x6d69_6772_6174_696f;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+  /// Apply pending migrations up to and including the target, or all of them
+  Up,
+  /// Roll back every migration after the target, or only the last one
+  Down,
+  /// Drop every table and type, then apply migrations up to the target
+  Fresh,
+  /// Roll back every applied migration, then apply migrations up to the target
+  Refresh,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationState {
+  pub name: String,
+  pub applied: bool,
+}
+
+/// What an action will do, in the order it does it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+  pub drop_all: bool,
+  pub down: Vec<String>,
+  pub up: Vec<String>,
+}
+
+impl Plan {
+  pub fn is_empty(&self) -> bool {
+    !self.drop_all && self.down.is_empty() && self.up.is_empty()
+  }
+}
+
+/// Every known migration in order along with whether it has been applied
+pub async fn status<M, C>(db: &C) -> Result<Vec<MigrationState>, DbErr>
+where
+  M: MigratorTrait,
+  C: ConnectionTrait,
+{
+  let applied: HashSet<String> = M::get_migration_models(db).await?
+    .into_iter()
+    .map(|model| model.version)
+    .collect();
+  let migrations = M::migrations();
+  let known: HashSet<&str> = migrations.iter().map(|migration| migration.name()).collect();
+  if let Some(missing) = applied.iter().find(|version| !known.contains(version.as_str())) {
+    return Err(DbErr::Custom(format!("migration '{}' has been applied but its file is missing", missing)));
+  }
+  Ok(migrations.iter().map(|migration| MigrationState {
+    name: migration.name().to_string(),
+    applied: applied.contains(migration.name()),
+  }).collect())
+}
+
+/// Accepts the full migration name or just its timestamp prefix
+fn find_target(states: &[MigrationState], target: &str) -> Result<usize, DbErr> {
+  states.iter()
+    .position(|state| state.name == target || state.name.starts_with(&format!("{}_", target)))
+    .ok_or_else(|| DbErr::Custom(format!("unknown migration '{}'", target)))
+}
+
+pub fn plan(states: &[MigrationState], action: Action, target: Option<&str>) -> Result<Plan, DbErr> {
+  let last = match target {
+    Some(target) => find_target(states, target)?,
+    None => states.len().saturating_sub(1),
+  };
+  let up_to_target = |pending_only: bool| -> Vec<String> {
+    states.iter()
+      .take(last + 1)
+      .filter(|state| !pending_only || !state.applied)
+      .map(|state| state.name.clone())
+      .collect()
+  };
+  let applied_after = |first: usize| -> Vec<String> {
+    states.iter()
+      .enumerate()
+      .rev()
+      .filter(|(index, state)| *index >= first && state.applied)
+      .map(|(_, state)| state.name.clone())
+      .collect()
+  };
+  // `MigratorTrait` takes the oldest pending and newest applied migrations, which is exactly these
+  let plan = match action {
+    Action::Up => Plan { up: up_to_target(true), ..Default::default() },
+    Action::Down => match target {
+      Some(_) => Plan { down: applied_after(last + 1), ..Default::default() },
+      None => Plan { down: applied_after(0).into_iter().take(1).collect(), ..Default::default() },
+    },
+    Action::Fresh => Plan { drop_all: true, up: up_to_target(false), ..Default::default() },
+    Action::Refresh => Plan { down: applied_after(0), up: up_to_target(false), ..Default::default() },
+  };
+  Ok(plan)
+}
+
+#[derive(Debug, FromQueryResult)]
+struct NamedObject {
+  name: String,
+}
+
+/// Statements dropping every table and enum type in the database
+async fn drop_statements<C>(db: &C) -> Result<Vec<Statement>, DbErr>
+where
+  C: ConnectionTrait,
+{
+  let backend = db.get_database_backend();
+  let (tables_sql, types_sql) = match backend {
+    DbBackend::Postgres => (
+      "SELECT table_name::text AS name FROM information_schema.tables \
+        WHERE table_schema = 'public' AND table_type = 'BASE TABLE'",
+      Some("SELECT t.typname::text AS name FROM pg_type t JOIN pg_namespace n ON n.oid = t.typnamespace \
+        WHERE n.nspname = 'public' AND t.typtype = 'e'"),
+    ),
+    // SQLite has no enum types, and with foreign keys enforced tables have to go before the ones they reference
+    DbBackend::Sqlite => (
+      "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY rowid DESC",
+      None,
+    ),
+    DbBackend::MySql => return Err(DbErr::Custom("MySQL is not supported".to_string())),
+  };
+  let tables = NamedObject::find_by_statement(Statement::from_string(backend, tables_sql.to_string())).all(db).await?;
+  let types = match types_sql {
+    Some(sql) => NamedObject::find_by_statement(Statement::from_string(backend, sql.to_string())).all(db).await?,
+    None => Vec::new(),
+  };
+
+  let mut statements: Vec<Statement> = tables.into_iter().map(|table| {
+    backend.build(Table::drop().table(Alias::new(&table.name)).if_exists().cascade())
+  }).collect();
+  statements.extend(types.into_iter().map(|pg_type| {
+    backend.build(extension::postgres::Type::drop().name(Alias::new(&pg_type.name)).if_exists())
+  }));
+  Ok(statements)
+}
+
+/// Begins the transaction every migration runs in, waiting for any other instance to finish first
+pub async fn begin_locked(db: &DatabaseConnection) -> Result<DatabaseTransaction, DbErr> {
+  let txn = db.begin().await?;
+  if txn.get_database_backend() == DbBackend::Postgres {
+    txn.execute(Statement::from_sql_and_values(
+      DbBackend::Postgres,
+      "SELECT pg_advisory_xact_lock($1)",
+      vec![LOCK_KEY.into()],
+    )).await?;
+  }
+  Ok(txn)
+}
+
+/// Runs an action under the migration lock, the plan is worked out once the lock is held
+/// so instances starting together don't apply the same migration twice
+pub async fn run<M>(db: &DatabaseConnection, action: Action, target: Option<&str>) -> Result<Plan, DbErr>
+where
+  M: MigratorTrait,
+{
+  let txn = begin_locked(db).await?;
+  let plan = plan(&status::<M, _>(&txn).await?, action, target)?;
+  if plan.drop_all {
+    for statement in drop_statements(&txn).await? {
+      txn.execute(statement).await?;
+    }
+  }
+  if !plan.down.is_empty() {
+    M::down(&txn, Some(plan.down.len() as u32)).await?;
+  }
+  if !plan.up.is_empty() {
+    M::up(&txn, Some(plan.up.len() as u32)).await?;
+  }
+  txn.commit().await?;
+  Ok(plan)
+}
+
+/// Stands in for the database during a dry run, keeping every statement instead of executing it
+#[derive(Debug)]
+struct StatementRecorder {
+  backend: DbBackend,
+  statements: Arc<Mutex<Vec<Statement>>>,
+}
+
+impl MockDatabaseTrait for StatementRecorder {
+  fn execute(&mut self, _counter: usize, statement: Statement) -> Result<ExecResult, DbErr> {
+    if let Ok(mut statements) = self.statements.lock() {
+      statements.push(statement.clone());
+    }
+    MockDatabase::new(self.backend)
+      .append_exec_results(vec![MockExecResult::default()])
+      .execute(0, statement)
+  }
+
+  fn query(&mut self, _counter: usize, statement: Statement) -> Result<Vec<QueryResult>, DbErr> {
+    if let Ok(mut statements) = self.statements.lock() {
+      statements.push(statement);
+    }
+    Ok(Vec::new())
+  }
+
+  fn begin(&mut self) {}
+
+  fn commit(&mut self) {}
+
+  fn rollback(&mut self) {}
+
+  fn drain_transaction_log(&mut self) -> Vec<sea_orm_migration::sea_orm::Transaction> {
+    Vec::new()
+  }
+
+  fn get_database_backend(&self) -> DbBackend {
+    self.backend
+  }
+}
+
+/// The SQL an action would run, without changing anything but the migrations table
+pub async fn dry_run<M>(db: &DatabaseConnection, action: Action, target: Option<&str>) -> Result<Vec<String>, DbErr>
+where
+  M: MigratorTrait,
+{
+  let plan = plan(&status::<M, _>(db).await?, action, target)?;
+  let mut sql = Vec::new();
+  if plan.drop_all {
+    sql.push("-- drop everything".to_string());
+    sql.extend(drop_statements(db).await?.iter().map(|statement| format!("{};", statement)));
+  }
+
+  let statements = Arc::new(Mutex::new(Vec::new()));
+  let recorder = DatabaseConnection::MockDatabaseConnection(Arc::new(MockDatabaseConnection::new(StatementRecorder {
+    backend: db.get_database_backend(),
+    statements: statements.clone(),
+  })));
+  let manager = SchemaManager::new(&recorder);
+  let migrations = M::migrations();
+  let steps = plan.down.iter().map(|name| (name, false)).chain(plan.up.iter().map(|name| (name, true)));
+  for (name, up) in steps {
+    let Some(migration) = migrations.iter().find(|migration| migration.name() == name) else {
+      continue;
+    };
+    if up {
+      sql.push(format!("-- apply {}", name));
+      migration.up(&manager).await?;
+    } else {
+      sql.push(format!("-- roll back {}", name));
+      migration.down(&manager).await?;
+    }
+    if let Ok(mut statements) = statements.lock() {
+      sql.extend(statements.drain(..).map(|statement| format!("{};", statement)));
+    }
+  }
+  Ok(sql)
+}
@@ -0,0 +1,41 @@
+use migration::runner::{plan, Action, MigrationState, Plan};
+
+fn states(applied: &[bool]) -> Vec<MigrationState> {
+  applied.iter().enumerate().map(|(index, applied)| MigrationState {
+    name: format!("m2023010{}_000000_step", index + 1),
+    applied: *applied,
+  }).collect()
+}
+
+fn names(indexes: &[usize]) -> Vec<String> {
+  indexes.iter().map(|index| format!("m2023010{}_000000_step", index + 1)).collect()
+}
+
+#[test]
+fn up_stops_at_target() {
+  let plan = plan(&states(&[true, false, false]), Action::Up, Some("m20230102_000000")).unwrap();
+  assert_eq!(plan, Plan { up: names(&[1]), ..Default::default() });
+}
+
+#[test]
+fn down_defaults_to_last_applied() {
+  let plan = plan(&states(&[true, true, false]), Action::Down, None).unwrap();
+  assert_eq!(plan, Plan { down: names(&[1]), ..Default::default() });
+}
+
+#[test]
+fn down_keeps_target_applied() {
+  let plan = plan(&states(&[true, true, true]), Action::Down, Some("m20230101_000000_step")).unwrap();
+  assert_eq!(plan, Plan { down: names(&[2, 1]), ..Default::default() });
+}
+
+#[test]
+fn refresh_rolls_back_everything_first() {
+  let plan = plan(&states(&[true, true, false]), Action::Refresh, Some("m20230102_000000")).unwrap();
+  assert_eq!(plan, Plan { drop_all: false, down: names(&[1, 0]), up: names(&[0, 1]) });
+}
+
+#[test]
+fn unknown_target_is_rejected() {
+  assert!(plan(&states(&[true]), Action::Up, Some("m20991231_000000")).is_err());
+}
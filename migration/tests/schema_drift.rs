@@ -0,0 +1,51 @@
+use migration::{drift::{self, ColumnSchema, TableSchema}, Migrator, MigratorTrait};
+use sea_orm_migration::sea_orm::{ConnectionTrait, Database};
+
+/// Swaps the database name at the end of a connection url
+fn with_database(url: &str, database: &str) -> String {
+  let (base, query) = match url.split_once('?') {
+    Some((base, query)) => (base, format!("?{}", query)),
+    None => (url, String::new()),
+  };
+  let base = base.rsplit_once('/').map(|(host, _)| host).unwrap_or(base);
+  format!("{}/{}{}", base, database, query)
+}
+
+/// Applies every migration to a scratch database and compares it against the entities.
+/// Needs `DATABASE_URL` pointing at a Postgres server the user can create databases on,
+/// run it with `cargo test -- --include-ignored`.
+#[async_std::test]
+#[ignore = "needs DATABASE_URL pointing at Postgres"]
+async fn migrations_match_entities() {
+  let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must point at Postgres for the schema drift check");
+  let admin = Database::connect(&url).await.expect("connect to DATABASE_URL");
+  let scratch = format!("drift_{}", uuid::Uuid::new_v4().simple());
+  admin.execute_unprepared(&format!("CREATE DATABASE \"{}\"", scratch)).await.expect("create scratch database");
+
+  let db = Database::connect(&with_database(&url, &scratch)).await.expect("connect to scratch database");
+  let result = match Migrator::up(&db, None).await {
+    Ok(()) => drift::check(&db).await,
+    Err(err) => Err(err),
+  };
+  db.close().await.expect("close scratch database");
+  admin.execute_unprepared(&format!("DROP DATABASE \"{}\"", scratch)).await.expect("drop scratch database");
+
+  let mismatches = result.expect("migrate and introspect scratch database");
+  assert!(mismatches.is_empty(), "schema drift:\n{}", mismatches.join("\n"));
+}
+
+#[test]
+fn composite_unique_keys_are_compared() {
+  let table = |unique_keys: Vec<Vec<String>>| TableSchema {
+    name: "pki_key".to_string(),
+    columns: vec![ColumnSchema { name: "id".to_string(), data_type: "uuid".to_string(), nullable: false, unique: false }],
+    primary_key: vec!["id".to_string()],
+    foreign_keys: vec![],
+    unique_keys,
+  };
+  let key = vec!["key_ring_id".to_string(), "version".to_string()];
+  let expected = [table(vec![key.clone()])];
+  assert!(drift::compare(&expected, &[table(vec![key.clone()])]).is_empty());
+  assert_eq!(drift::compare(&expected, &[table(vec![])]), ["pki_key: missing unique key (key_ring_id, version)"]);
+  assert_eq!(drift::compare(&[table(vec![])], &[table(vec![key])]), ["pki_key: unexpected unique key (key_ring_id, version)"]);
+}
@@ -0,0 +1,29 @@
+use migration::{runner::{self, Action}, Migrator};
+use sea_orm_migration::sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, Statement};
+
+async fn connect() -> DatabaseConnection {
+  Database::connect("sqlite::memory:").await.expect("open in-memory sqlite")
+}
+
+async fn table_count(db: &DatabaseConnection) -> i64 {
+  let row = db.query_one(Statement::from_string(
+    DbBackend::Sqlite,
+    "SELECT COUNT(*) AS count FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'".to_string(),
+  )).await.unwrap().unwrap();
+  row.try_get("", "count").unwrap()
+}
+
+#[async_std::test]
+async fn migrations_apply_and_roll_back() {
+  let db = connect().await;
+  Migrator::run_migrations(&db).await.unwrap();
+  assert!(runner::status::<Migrator, _>(&db).await.unwrap().iter().all(|state| state.applied));
+  let migrated = table_count(&db).await;
+
+  runner::run::<Migrator>(&db, Action::Down, Some("m20230315_143439")).await.unwrap();
+  runner::run::<Migrator>(&db, Action::Refresh, None).await.unwrap();
+  assert_eq!(table_count(&db).await, migrated);
+
+  runner::run::<Migrator>(&db, Action::Fresh, None).await.unwrap();
+  assert_eq!(table_count(&db).await, migrated);
+}
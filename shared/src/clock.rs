@@ -0,0 +1,80 @@
+//! Entities read the time through `now()`, which is the system clock unless a test
+//! has swapped in its own clock for the current thread.
+use std::{cell::RefCell, sync::{Arc, Mutex}};
+use chrono::{DateTime, Duration, Utc};
+
+thread_local! {
+  static THREAD_CLOCK: RefCell<Option<Arc<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+/// Where expiry logic gets the current time from
+pub trait Clock: Send + Sync {
+  fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime<Utc> {
+    Utc::now()
+  }
+}
+
+/// Stands still until it is moved, for tests
+#[derive(Debug)]
+pub struct FixedClock {
+  now: Mutex<DateTime<Utc>>,
+}
+
+impl FixedClock {
+  pub fn new(now: DateTime<Utc>) -> Self {
+    Self { now: Mutex::new(now) }
+  }
+
+  pub fn set(&self, now: DateTime<Utc>) {
+    *self.now.lock().unwrap() = now;
+  }
+
+  pub fn advance(&self, by: Duration) {
+    let mut now = self.now.lock().unwrap();
+    *now += by;
+  }
+}
+
+impl Default for FixedClock {
+  fn default() -> Self {
+    Self::new(Utc::now())
+  }
+}
+
+impl Clock for FixedClock {
+  fn now(&self) -> DateTime<Utc> {
+    *self.now.lock().unwrap()
+  }
+}
+
+/// The current time from this thread's clock
+pub fn now() -> DateTime<Utc> {
+  THREAD_CLOCK.with(|clock| clock.borrow().as_ref().map(|clock| clock.now())).unwrap_or_else(Utc::now)
+}
+
+/// Puts the previous clock back when dropped
+#[must_use = "the clock is only replaced until the guard is dropped"]
+pub struct ClockGuard {
+  previous: Option<Arc<dyn Clock>>,
+}
+
+impl Drop for ClockGuard {
+  fn drop(&mut self) {
+    let previous = self.previous.take();
+    THREAD_CLOCK.with(|clock| *clock.borrow_mut() = previous);
+  }
+}
+
+/// Replaces the clock for the current thread, keep the `Arc` to move it along
+pub fn set_thread_clock(clock: Arc<dyn Clock>) -> ClockGuard {
+  let previous = THREAD_CLOCK.with(|current| current.borrow_mut().replace(clock));
+  ClockGuard { previous }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use sea_orm::DbErr;
+
+/// Prefix `before_save` hooks put on the errors they return for invalid models
+const VALIDATION_PREFIX: &str = "[before_save]";
+
+/// Errors callers can see, each mapped to an HTTP status
+#[derive(Debug)]
+pub enum Error {
+  Db(DbErr),
+  Validation(String),
+  /// A ciphertext that doesn't parse or decrypt, the caller's fault rather than a model's
+  InvalidCiphertext(String),
+  Unauthorized,
+  Forbidden(String),
+  NotFound,
+}
+
+impl Error {
+  pub fn status_code(&self) -> u16 {
+    match self {
+      Error::Db(_) => 500,
+      Error::Validation(_) | Error::InvalidCiphertext(_) => 400,
+      Error::Unauthorized => 401,
+      Error::Forbidden(_) => 403,
+      Error::NotFound => 404,
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Db(err) => write!(f, "database error: {}", err),
+      Error::Validation(msg) => write!(f, "{}", msg),
+      Error::InvalidCiphertext(msg) => write!(f, "invalid ciphertext: {}", msg),
+      Error::Unauthorized => write!(f, "unauthorized"),
+      Error::Forbidden(msg) => write!(f, "forbidden: {}", msg),
+      Error::NotFound => write!(f, "not found"),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+/// Models rejected by their `before_save` hooks are the caller's fault, anything else is ours
+impl From<DbErr> for Error {
+  fn from(err: DbErr) -> Self {
+    match err {
+      DbErr::Custom(msg) if msg.starts_with(VALIDATION_PREFIX) => {
+        Error::Validation(msg.trim_start_matches(VALIDATION_PREFIX).trim_start().to_string())
+      },
+      DbErr::RecordNotFound(_) => Error::NotFound,
+      err => Error::Db(err),
+    }
+  }
+}
+
+#[cfg(feature = "actix")]
+impl actix_web::ResponseError for Error {
+  fn status_code(&self) -> actix_web::http::StatusCode {
+    actix_web::http::StatusCode::from_u16(Error::status_code(self))
+      .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR)
+  }
+
+  fn error_response(&self) -> actix_web::HttpResponse {
+    // Don't leak database details to the caller
+    let message = match self {
+      Error::Db(err) => {
+        log::error!("{}", err);
+        "internal server error".to_string()
+      },
+      _ => self.to_string(),
+    };
+    actix_web::HttpResponse::build(actix_web::ResponseError::status_code(self))
+      .json(serde_json::json!({ "error": message }))
+  }
+}
@@ -0,0 +1,101 @@
+//! Typed ids so a user id can't be passed where an organisation id is expected.
+//! They are stored as plain uuids and serialize as one.
+use std::{fmt, str::FromStr};
+use sea_orm::{
+  sea_query::{ArrayType, ColumnType, Nullable, ValueType, ValueTypeErr},
+  ColIdx, DbErr, QueryResult, TryFromU64, TryGetError, TryGetable, Value,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+macro_rules! id_type {
+  ($(#[$meta:meta])* $name:ident) => {
+    $(#[$meta])*
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub struct $name(pub Uuid);
+
+    impl $name {
+      pub fn new() -> Self {
+        Self(Uuid::new_v4())
+      }
+
+      pub fn into_uuid(self) -> Uuid {
+        self.0
+      }
+    }
+
+    impl fmt::Display for $name {
+      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+      }
+    }
+
+    impl FromStr for $name {
+      type Err = uuid::Error;
+
+      fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::from_str(s).map(Self)
+      }
+    }
+
+    impl From<Uuid> for $name {
+      fn from(id: Uuid) -> Self {
+        Self(id)
+      }
+    }
+
+    impl From<$name> for Uuid {
+      fn from(id: $name) -> Self {
+        id.0
+      }
+    }
+
+    impl From<$name> for Value {
+      fn from(id: $name) -> Self {
+        Value::Uuid(Some(Box::new(id.0)))
+      }
+    }
+
+    impl TryGetable for $name {
+      fn try_get_by<I: ColIdx>(res: &QueryResult, index: I) -> Result<Self, TryGetError> {
+        Uuid::try_get_by(res, index).map(Self)
+      }
+    }
+
+    impl ValueType for $name {
+      fn try_from(v: Value) -> Result<Self, ValueTypeErr> {
+        <Uuid as ValueType>::try_from(v).map(Self)
+      }
+
+      fn type_name() -> String {
+        stringify!($name).to_string()
+      }
+
+      fn array_type() -> ArrayType {
+        ArrayType::Uuid
+      }
+
+      fn column_type() -> ColumnType {
+        ColumnType::Uuid
+      }
+    }
+
+    impl Nullable for $name {
+      fn null() -> Value {
+        Value::Uuid(None)
+      }
+    }
+
+    /// Ids are generated, never auto incremented
+    impl TryFromU64 for $name {
+      fn try_from_u64(_: u64) -> Result<Self, DbErr> {
+        Err(DbErr::ConvertFromU64(stringify!($name)))
+      }
+    }
+  };
+}
+
+id_type!(UserId);
+id_type!(OrgId);
+id_type!(GroupId);
@@ -0,0 +1,9 @@
+//! Types shared across the workspace: errors, typed ids, the clock, randomness and one-time codes.
+pub mod clock;
+pub mod error;
+pub mod id;
+pub mod otp;
+pub mod rng;
+
+pub use error::Error;
+pub use id::{GroupId, OrgId, UserId};
@@ -0,0 +1,57 @@
+//! One-time codes for verifying addresses and resetting passwords.
+//!
+//! Codes come from the operating system's generator (see `rng`), are always the requested length,
+//! and are compared in constant time. Each code carries an attempt counter so it stops working
+//! after too many wrong guesses, until a new one is issued.
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use subtle::ConstantTimeEq;
+use crate::{clock, rng};
+
+/// What happened when a guess was checked against a code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+  Accepted,
+  Rejected { attempts_left: i32 },
+  /// There is no code, or it has expired
+  Expired,
+  /// Too many wrong guesses, a new code has to be issued
+  LockedOut,
+}
+
+/// Digits only, leading zeros included, for typing in from an SMS or email
+pub fn numeric_code(len: usize) -> String {
+  rng::with_rng(|rng| (0..len).map(|_| char::from(b'0' + rng.gen_range(0..10u8))).collect())
+}
+
+/// Letters and digits, for including in links
+pub fn alphanumeric_code(len: usize) -> String {
+  rng::alphanumeric(len)
+}
+
+/// Compares without leaking how much of the guess matched
+pub fn constant_time_eq(expected: &str, guess: &str) -> bool {
+  expected.as_bytes().ct_eq(guess.as_bytes()).into()
+}
+
+/// `attempts` is the number of wrong guesses already made against this code
+pub fn check(
+  expected: Option<&str>,
+  expires_at: Option<DateTime<Utc>>,
+  attempts: i32,
+  max_attempts: i32,
+  guess: &str,
+) -> Outcome {
+  let expected = match (expected, expires_at) {
+    (Some(expected), Some(expires_at)) if expires_at > clock::now() => expected,
+    _ => return Outcome::Expired,
+  };
+  if attempts >= max_attempts {
+    return Outcome::LockedOut;
+  }
+  if constant_time_eq(expected, guess) {
+    Outcome::Accepted
+  } else {
+    Outcome::Rejected { attempts_left: max_attempts - attempts - 1 }
+  }
+}
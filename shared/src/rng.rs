@@ -0,0 +1,80 @@
+//! Tokens and codes draw from `OsRng`. Test builds can seed a generator for the current
+//! thread, which makes the generated values reproducible.
+#[cfg(any(test, feature = "test-util"))]
+use std::cell::RefCell;
+#[cfg(any(test, feature = "test-util"))]
+use rand::{rngs::StdRng, SeedableRng};
+use rand::{distributions::Alphanumeric, rngs::OsRng, CryptoRng, Rng, RngCore};
+
+#[cfg(any(test, feature = "test-util"))]
+thread_local! {
+  static THREAD_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with the operating system's generator
+#[cfg(not(any(test, feature = "test-util")))]
+pub fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+  f(&mut OsRng)
+}
+
+/// Runs `f` with this thread's seeded generator, or the operating system's
+#[cfg(any(test, feature = "test-util"))]
+pub fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+  THREAD_RNG.with(|seeded| match seeded.borrow_mut().as_mut() {
+    Some(rng) => f(rng),
+    None => f(&mut OsRng),
+  })
+}
+
+/// The same generator as `with_rng`, for crates that want an owned `CryptoRng`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SharedRng;
+
+impl RngCore for SharedRng {
+  fn next_u32(&mut self) -> u32 {
+    with_rng(|rng| rng.next_u32())
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    with_rng(|rng| rng.next_u64())
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    with_rng(|rng| rng.fill_bytes(dest))
+  }
+
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+    with_rng(|rng| rng.try_fill_bytes(dest))
+  }
+}
+
+// Outside tests this is always `OsRng`. A seeded `StdRng` is predictable to anyone who knows
+// the seed, which is why seeding only exists in test builds.
+impl CryptoRng for SharedRng {}
+
+/// A random string of ASCII letters and digits
+pub fn alphanumeric(len: usize) -> String {
+  with_rng(|rng| rng.sample_iter(&Alphanumeric).take(len).map(char::from).collect())
+}
+
+/// Puts the previous generator back when dropped
+#[cfg(any(test, feature = "test-util"))]
+#[must_use = "the generator is only seeded until the guard is dropped"]
+pub struct RngGuard {
+  previous: Option<StdRng>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Drop for RngGuard {
+  fn drop(&mut self) {
+    let previous = self.previous.take();
+    THREAD_RNG.with(|rng| *rng.borrow_mut() = previous);
+  }
+}
+
+/// Makes this thread's random values repeatable
+#[cfg(any(test, feature = "test-util"))]
+pub fn seed_thread_rng(seed: u64) -> RngGuard {
+  let previous = THREAD_RNG.with(|rng| rng.borrow_mut().replace(StdRng::seed_from_u64(seed)));
+  RngGuard { previous }
+}
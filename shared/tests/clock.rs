@@ -0,0 +1,11 @@
+use chrono::{Duration, TimeZone, Utc};
+use shared::clock::{Clock, FixedClock};
+
+#[test]
+fn fixed_clock_only_moves_when_told() {
+  let start = Utc.with_ymd_and_hms(2023, 3, 1, 12, 0, 0).unwrap();
+  let clock = FixedClock::new(start);
+  assert_eq!(clock.now(), start);
+  clock.advance(Duration::minutes(61));
+  assert_eq!(clock.now(), start + Duration::minutes(61));
+}
@@ -0,0 +1,28 @@
+use sea_orm::DbErr;
+use shared::Error;
+
+#[test]
+fn before_save_errors_are_validation_errors() {
+  let err = Error::from(DbErr::Custom("[before_save] Settings must be an object, insert: true".to_string()));
+  assert_eq!(err.status_code(), 400);
+  assert_eq!(err.to_string(), "Settings must be an object, insert: true");
+}
+
+#[test]
+fn other_database_errors_are_internal() {
+  assert_eq!(Error::from(DbErr::Custom("connection reset".to_string())).status_code(), 500);
+  assert_eq!(Error::from(DbErr::RecordNotFound("user".to_string())).status_code(), 404);
+}
+
+#[test]
+fn auth_errors_map_to_their_statuses() {
+  assert_eq!(Error::Unauthorized.status_code(), 401);
+  assert_eq!(Error::Forbidden("denied by policy".to_string()).status_code(), 403);
+}
+
+#[test]
+fn invalid_ciphertexts_are_the_callers_fault() {
+  let err = Error::InvalidCiphertext("the ciphertext is not base64".to_string());
+  assert_eq!(err.status_code(), 400);
+  assert_eq!(err.to_string(), "invalid ciphertext: the ciphertext is not base64");
+}
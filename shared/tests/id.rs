@@ -0,0 +1,11 @@
+use shared::{OrgId, UserId};
+use uuid::Uuid;
+
+#[test]
+fn ids_serialize_as_plain_uuids() {
+  let uuid = Uuid::new_v4();
+  let user_id = UserId::from(uuid);
+  assert_eq!(serde_json::to_string(&user_id).unwrap(), format!("\"{}\"", uuid));
+  assert_eq!(serde_json::from_str::<OrgId>(&format!("\"{}\"", uuid)).unwrap().into_uuid(), uuid);
+  assert_eq!(uuid.to_string().parse::<UserId>().unwrap(), user_id);
+}
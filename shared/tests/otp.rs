@@ -0,0 +1,30 @@
+use chrono::{Duration, Utc};
+use shared::{otp::{self, Outcome}, rng};
+
+#[test]
+fn numeric_codes_keep_their_width() {
+  let _guard = rng::seed_thread_rng(1);
+  for _ in 0..200 {
+    let code = otp::numeric_code(8);
+    assert_eq!(code.len(), 8);
+    assert!(code.chars().all(|c| c.is_ascii_digit()));
+  }
+  assert!((0..200).any(|_| otp::numeric_code(6).starts_with('0')));
+}
+
+#[test]
+fn guesses_are_counted_until_locked_out() {
+  let expires_at = Some(Utc::now() + Duration::minutes(5));
+  assert_eq!(otp::check(Some("012345"), expires_at, 0, 3, "012345"), Outcome::Accepted);
+  assert_eq!(otp::check(Some("012345"), expires_at, 0, 3, "12345"), Outcome::Rejected { attempts_left: 2 });
+  assert_eq!(otp::check(Some("012345"), expires_at, 2, 3, "000000"), Outcome::Rejected { attempts_left: 0 });
+  // Even the right code is refused once the attempts are used up
+  assert_eq!(otp::check(Some("012345"), expires_at, 3, 3, "012345"), Outcome::LockedOut);
+}
+
+#[test]
+fn missing_or_expired_codes_never_match() {
+  assert_eq!(otp::check(None, Some(Utc::now() + Duration::minutes(5)), 0, 3, ""), Outcome::Expired);
+  assert_eq!(otp::check(Some("012345"), Some(Utc::now() - Duration::seconds(1)), 0, 3, "012345"), Outcome::Expired);
+  assert_eq!(otp::check(Some("012345"), None, 0, 3, "012345"), Outcome::Expired);
+}
@@ -0,0 +1,16 @@
+use shared::rng;
+
+#[test]
+fn seeding_makes_values_repeatable() {
+  let first = {
+    let _guard = rng::seed_thread_rng(42);
+    rng::alphanumeric(32)
+  };
+  let second = {
+    let _guard = rng::seed_thread_rng(42);
+    rng::alphanumeric(32)
+  };
+  assert_eq!(first, second);
+  assert_ne!(rng::alphanumeric(32), first);
+  assert!(first.chars().all(|c| c.is_ascii_alphanumeric()));
+}
@@ -0,0 +1,93 @@
+use std::{future::Future, net::IpAddr, pin::Pin};
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use entities::{auth_api_key, organisation_policy, policy::RequestContext};
+use sea_orm::{entity::prelude::*, ActiveValue::Set, DatabaseConnection, IntoActiveModel};
+use shared::{clock, GroupId, OrgId, UserId};
+use crate::error::ApiError;
+
+pub const API_ACCESS_KEY_HEADER: &str = "x-api-access-key";
+pub const API_SECRET_KEY_HEADER: &str = "x-api-secret-key";
+
+/// The user making the request, authenticated with one of their api keys
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser {
+  pub user_id: UserId,
+  pub source_ip: Option<IpAddr>,
+  pub mfa: bool,
+}
+
+impl AuthenticatedUser {
+  /// Checks the user's roles and the organisation's policies allow the action
+  pub async fn authorize(
+    &self,
+    db: &DatabaseConnection,
+    organisation_id: OrgId,
+    group_id: Option<GroupId>,
+    resource: &str,
+    verb: &str,
+  ) -> Result<(), ApiError> {
+    let decision = organisation_policy::decide(db, RequestContext {
+      user_id: self.user_id,
+      organisation_id,
+      resource: resource.to_string(),
+      verb: verb.to_string(),
+      group_id,
+      source_ip: self.source_ip,
+      at: clock::now(),
+      mfa: self.mfa,
+      group_organisation_id: None,
+    }).await?;
+    if decision.allowed {
+      Ok(())
+    } else {
+      Err(ApiError::Forbidden(decision.reason))
+    }
+  }
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+  req.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+async fn authenticate(
+  db: web::Data<DatabaseConnection>,
+  access_key: String,
+  secret_key: String,
+  source_ip: Option<IpAddr>,
+) -> Result<AuthenticatedUser, ApiError> {
+  let api_key = auth_api_key::Entity::find()
+    .filter(auth_api_key::Column::ApiAccessKey.eq(access_key))
+    .one(db.get_ref())
+    .await?
+    .ok_or(ApiError::Unauthorized)?;
+  if !api_key.check_secret(&secret_key)
+    || api_key.expires_on.map(|expires_on| expires_on <= clock::now()).unwrap_or(false) {
+    return Err(ApiError::Unauthorized);
+  }
+  // Nothing can authorise an organisation yet, so only keys issued to users are usable
+  let user_id = api_key.user_id.ok_or(ApiError::Unauthorized)?;
+  let mut active_key = api_key.into_active_model();
+  active_key.ip_address_last_used = Set(source_ip.map(|ip| ip.to_string()));
+  active_key.update(db.get_ref()).await?;
+  // Api keys carry no second factor, policies can't ask for one until a login method does
+  Ok(AuthenticatedUser { user_id, source_ip, mfa: false })
+}
+
+impl FromRequest for AuthenticatedUser {
+  type Error = ApiError;
+  type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+  fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+    let db = req.app_data::<web::Data<DatabaseConnection>>().cloned();
+    let access_key = header(req, API_ACCESS_KEY_HEADER).map(str::to_string);
+    let secret_key = header(req, API_SECRET_KEY_HEADER).map(str::to_string);
+    // Use the socket address, forwarded headers are trivially spoofed
+    let source_ip = req.peer_addr().map(|addr| addr.ip());
+    Box::pin(async move {
+      match (db, access_key, secret_key) {
+        (Some(db), Some(access_key), Some(secret_key)) => authenticate(db, access_key, secret_key, source_ip).await,
+        _ => Err(ApiError::Unauthorized),
+      }
+    })
+  }
+}
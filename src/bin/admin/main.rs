@@ -0,0 +1,662 @@
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, Subcommand};
+use entities::{
+  auth_api_key, auth_method_pass, email, group,
+  group_access_role::{self, GroupRolePermissions},
+  key_provider, key_service, organisation,
+  organisation_access_role::{self, OrgRolePermissions},
+  pki_key::{self, KeyAlgos, Owner},
+  user::{self, LockedState},
+  users_groups_group_access_roles, users_organisations_organisations_access_roles,
+};
+use sea_orm::{
+  entity::prelude::*, ActiveValue::Set, Database, DatabaseConnection, IntoActiveModel, QueryOrder, TransactionTrait,
+};
+use serde::Serialize;
+use shared::{clock, GroupId, OrgId, UserId};
+use output::{print, print_one, Format};
+
+mod output;
+
+#[derive(Debug)]
+struct AdminError(String);
+
+impl From<DbErr> for AdminError {
+  fn from(err: DbErr) -> Self {
+    AdminError(err.to_string())
+  }
+}
+
+impl From<String> for AdminError {
+  fn from(err: String) -> Self {
+    AdminError(err)
+  }
+}
+
+type AdminResult = Result<(), AdminError>;
+
+#[derive(Debug, Parser)]
+#[clap(name = "admin", about = "Manages users, organisations and keys directly in the database")]
+struct Cli {
+  #[clap(long, env = "DATABASE_URL")]
+  database_url: String,
+  #[clap(long, arg_enum, default_value = "table")]
+  format: Format,
+  #[clap(subcommand)]
+  command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+  #[clap(subcommand)]
+  User(UserCommand),
+  #[clap(subcommand)]
+  Org(OrgCommand),
+  #[clap(subcommand)]
+  Group(GroupCommand),
+  #[clap(subcommand)]
+  Role(RoleCommand),
+  #[clap(subcommand)]
+  ApiKey(ApiKeyCommand),
+  #[clap(subcommand)]
+  PkiKey(PkiKeyCommand),
+}
+
+impl Command {
+  /// New users, top-level organisations and pki keys seal private keys with the master keys
+  fn seals_keys(&self) -> bool {
+    matches!(
+      self,
+      Command::User(UserCommand::Create { .. })
+        | Command::Org(OrgCommand::Create { .. })
+        | Command::PkiKey(
+          PkiKeyCommand::Generate { .. } | PkiKeyCommand::Register { .. } | PkiKeyCommand::Rotate { .. } | PkiKeyCommand::Rewrap { .. }
+        )
+    )
+  }
+}
+
+#[derive(Debug, Subcommand)]
+enum UserCommand {
+  /// Create a user with a verified primary email address
+  Create {
+    #[clap(long)]
+    email: String,
+    /// Read from the environment so it stays out of the shell history
+    #[clap(long, env = "ADMIN_USER_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+  },
+  List,
+  /// Lock for the given number of minutes, or permanently
+  Lock {
+    id: UserId,
+    #[clap(long)]
+    minutes: Option<i64>,
+  },
+  /// Unlock and clear the invalid login attempts
+  Unlock { id: UserId },
+  ForcePasswordChange { id: UserId },
+}
+
+#[derive(Debug, Subcommand)]
+enum OrgCommand {
+  Create {
+    #[clap(long)]
+    name: String,
+    #[clap(long)]
+    parent: Option<OrgId>,
+  },
+  List,
+}
+
+#[derive(Debug, Subcommand)]
+enum GroupCommand {
+  Create {
+    #[clap(long)]
+    organisation: OrgId,
+    #[clap(long)]
+    name: String,
+    #[clap(long)]
+    parent: Option<GroupId>,
+  },
+  List {
+    #[clap(long)]
+    organisation: Option<OrgId>,
+  },
+}
+
+#[derive(Debug, Subcommand)]
+enum RoleCommand {
+  /// List organisation and group roles
+  List,
+  /// Create an organisation role, e.g. --permissions AllowAdmin
+  CreateOrg {
+    #[clap(long)]
+    name: String,
+    #[clap(long)]
+    permissions: String,
+  },
+  /// Create a group role, e.g. --permissions AllowReadOnly
+  CreateGroup {
+    #[clap(long)]
+    name: String,
+    #[clap(long)]
+    permissions: String,
+  },
+  /// Give a user a role in an organisation, replacing any they had
+  AssignOrg {
+    #[clap(long)]
+    user: UserId,
+    #[clap(long)]
+    organisation: OrgId,
+    #[clap(long)]
+    role: Uuid,
+  },
+  /// Give a user a role in a group, replacing any they had
+  AssignGroup {
+    #[clap(long)]
+    user: UserId,
+    #[clap(long)]
+    group: GroupId,
+    #[clap(long)]
+    role: Uuid,
+  },
+}
+
+#[derive(Debug, Subcommand)]
+enum ApiKeyCommand {
+  /// Issue a key for a user, the secret is only shown here
+  Issue {
+    #[clap(long)]
+    user: UserId,
+    #[clap(long)]
+    expires_in_days: Option<i64>,
+  },
+  List {
+    #[clap(long)]
+    user: Option<UserId>,
+    #[clap(long)]
+    organisation: Option<OrgId>,
+  },
+  /// Expire a key immediately
+  Revoke { id: Uuid },
+}
+
+#[derive(Debug, Subcommand)]
+enum PkiKeyCommand {
+  /// Generate a key pair, a user's or organisation's key becomes their current one
+  Generate {
+    #[clap(long, required_unless_present_any = &["organisation", "group"], conflicts_with_all = &["organisation", "group"])]
+    user: Option<UserId>,
+    #[clap(long, conflicts_with = "group")]
+    organisation: Option<OrgId>,
+    #[clap(long)]
+    group: Option<GroupId>,
+    /// RSA, Ed25519, EcdsaP256 or EcdsaP384, the configured algorithm when not given
+    #[clap(long)]
+    algo: Option<String>,
+  },
+  /// Record a key held by a PKCS#11 token or AWS KMS, a user's or organisation's key becomes their current one
+  Register {
+    #[clap(long, required_unless_present_any = &["organisation", "group"], conflicts_with_all = &["organisation", "group"])]
+    user: Option<UserId>,
+    #[clap(long, conflicts_with = "group")]
+    organisation: Option<OrgId>,
+    #[clap(long)]
+    group: Option<GroupId>,
+    /// RSA, Ed25519, EcdsaP256 or EcdsaP384
+    #[clap(long)]
+    algo: String,
+    /// `pkcs11:token=..;object=..` or a KMS endpoint followed by the key id
+    #[clap(long)]
+    url: String,
+  },
+  /// Give a key a new active version, the versions it replaces only verify and decrypt
+  Rotate {
+    id: Uuid,
+    /// Where a provider created the new version of a key it holds
+    #[clap(long)]
+    url: Option<String>,
+  },
+  List,
+  /// Re-wrap data keys under the active master key
+  Rewrap {
+    /// Also seal private keys stored before envelope encryption, which can't be used until then
+    #[clap(long)]
+    seal_plaintext: bool,
+  },
+}
+
+const USER_COLUMNS: &[&str] = &["id", "email_address", "locked_state", "locked_state_expires_at", "invalid_login_attempts", "created_at"];
+const ORGANISATION_COLUMNS: &[&str] = &["id", "name", "parent_organisation_id", "pki_key_id", "created_at"];
+const GROUP_COLUMNS: &[&str] = &["id", "name", "organisation_id", "parent_group_id", "created_at"];
+const ROLE_COLUMNS: &[&str] = &["id", "kind", "name", "permissions"];
+const API_KEY_COLUMNS: &[&str] = &["id", "user_id", "organisation_id", "api_access_key", "expires_on", "key_last_used_at"];
+const ISSUED_API_KEY_COLUMNS: &[&str] = &["id", "api_access_key", "api_secret_key", "expires_on"];
+const PKI_KEY_COLUMNS: &[&str] = &["id", "algo", "key_ring_id", "version", "status", "exportable", "user_id", "organisation_id", "group_id", "created_at"];
+const MEMBERSHIP_COLUMNS: &[&str] = &["user_id", "organisation_id", "group_id", "role_id"];
+const REWRAP_COLUMNS: &[&str] = &["rewrapped", "sealed"];
+
+#[derive(Debug, Serialize)]
+struct UserRow {
+  #[serde(flatten)]
+  user: user::Model,
+  email_address: Option<String>,
+}
+
+/// The secret is only shown once, when the key is issued
+#[derive(Debug, Serialize)]
+struct IssuedApiKeyRow {
+  id: Uuid,
+  api_access_key: String,
+  api_secret_key: String,
+  expires_on: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+struct RoleRow {
+  id: Uuid,
+  kind: &'static str,
+  name: String,
+  permissions: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RewrapRow {
+  rewrapped: usize,
+  sealed: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct MembershipRow {
+  user_id: UserId,
+  organisation_id: Option<OrgId>,
+  group_id: Option<GroupId>,
+  role_id: Uuid,
+}
+
+fn not_found(what: &str, id: impl std::fmt::Display) -> AdminError {
+  AdminError(format!("{} {} not found", what, id))
+}
+
+async fn find_user(db: &DatabaseConnection, id: UserId) -> Result<user::Model, AdminError> {
+  user::Entity::find_by_id(id).one(db).await?.ok_or_else(|| not_found("user", id))
+}
+
+async fn user_command(db: &DatabaseConnection, format: Format, command: UserCommand) -> AdminResult {
+  match command {
+    UserCommand::Create { email: email_address, password } => {
+      let email_address = email_address.trim().to_lowercase();
+      let txn = db.begin().await?;
+      let new_user = user::ActiveModel {
+        locked_state: Set(LockedState::Unlocked),
+        locked_state_updated_at: Set(clock::now()),
+        locked_state_expires_at: Set(None),
+        pki_key_id: Set(None),
+        ..Default::default()
+      }.insert(&txn).await?;
+      email::ActiveModel {
+        user_id: Set(new_user.id),
+        email_address: Set(email_address.clone()),
+        verification_code: Set(None),
+        verification_code_expires_at: Set(None),
+        is_primary: Set(true),
+        is_verified: Set(true),
+        ..Default::default()
+      }.insert(&txn).await?;
+      if let Some(password) = password {
+        auth_method_pass::ActiveModel {
+          user_id: Set(new_user.id),
+          pass_hash: Set(Some(password)),
+          pass_hash_cipher: Set(auth_method_pass::PassHashCipher::Bcrypt),
+          pass_last_changed_at: Set(clock::now()),
+          force_pass_change: Set(false),
+          pass_reset_code: Set(None),
+          pass_reset_str: Set(None),
+          pass_reset_code_expires_at: Set(None),
+          ..Default::default()
+        }.insert(&txn).await?;
+      }
+      txn.commit().await?;
+      print_one(format, &UserRow { user: new_user, email_address: Some(email_address) }, USER_COLUMNS)?;
+    },
+    UserCommand::List => {
+      let users = user::Entity::find()
+        .order_by_asc(user::Column::CreatedAt)
+        .find_with_related(email::Entity)
+        .all(db)
+        .await?;
+      let rows: Vec<UserRow> = users.into_iter().map(|(user, emails)| {
+        let primary = emails.iter().find(|email| email.is_primary).or(emails.first());
+        UserRow { email_address: primary.map(|email| email.email_address.clone()), user }
+      }).collect();
+      print(format, &rows, USER_COLUMNS)?;
+    },
+    UserCommand::Lock { id, minutes } => {
+      let mut active_user = find_user(db, id).await?.into_active_model();
+      match minutes {
+        Some(minutes) if minutes <= 0 => return Err(AdminError("minutes must be positive".to_string())),
+        Some(minutes) => {
+          active_user.locked_state = Set(LockedState::TemporarilyLocked);
+          active_user.locked_state_expires_at = Set(Some(clock::now() + Duration::minutes(minutes)));
+        },
+        None => {
+          active_user.locked_state = Set(LockedState::PermanentlyLocked);
+          active_user.locked_state_expires_at = Set(None);
+        },
+      }
+      active_user.locked_state_updated_at = Set(clock::now());
+      let user = active_user.update(db).await?;
+      print_one(format, &UserRow { user, email_address: None }, USER_COLUMNS)?;
+    },
+    UserCommand::Unlock { id } => {
+      let mut active_user = find_user(db, id).await?.into_active_model();
+      active_user.locked_state = Set(LockedState::Unlocked);
+      active_user.locked_state_expires_at = Set(None);
+      active_user.locked_state_updated_at = Set(clock::now());
+      active_user.invalid_login_attempts = Set(0);
+      let user = active_user.update(db).await?;
+      print_one(format, &UserRow { user, email_address: None }, USER_COLUMNS)?;
+    },
+    UserCommand::ForcePasswordChange { id } => {
+      let pass = auth_method_pass::Entity::find()
+        .filter(auth_method_pass::Column::UserId.eq(id))
+        .one(db)
+        .await?
+        .ok_or_else(|| AdminError(format!("user {} has no password", id)))?;
+      let mut active_pass = pass.into_active_model();
+      active_pass.force_pass_change = Set(true);
+      active_pass.update(db).await?;
+      print_one(format, &UserRow { user: find_user(db, id).await?, email_address: None }, USER_COLUMNS)?;
+    },
+  }
+  Ok(())
+}
+
+async fn org_command(db: &DatabaseConnection, format: Format, command: OrgCommand) -> AdminResult {
+  match command {
+    OrgCommand::Create { name, parent } => {
+      let organisation = organisation::ActiveModel {
+        name: Set(name),
+        parent_organisation_id: Set(parent),
+        pki_key_id: Set(None),
+        ..Default::default()
+      }.insert(db).await?;
+      print_one(format, &organisation, ORGANISATION_COLUMNS)?;
+    },
+    OrgCommand::List => {
+      let organisations = organisation::Entity::find().order_by_asc(organisation::Column::Name).all(db).await?;
+      print(format, &organisations, ORGANISATION_COLUMNS)?;
+    },
+  }
+  Ok(())
+}
+
+async fn group_command(db: &DatabaseConnection, format: Format, command: GroupCommand) -> AdminResult {
+  match command {
+    GroupCommand::Create { organisation, name, parent } => {
+      let group = group::ActiveModel {
+        name: Set(name),
+        organisation_id: Set(organisation),
+        parent_group_id: Set(parent),
+        group_image_file_id: Set(None),
+        description: Set(None),
+        icon: Set(None),
+        color_rgb: Set(None),
+        ..Default::default()
+      }.insert(db).await?;
+      print_one(format, &group, GROUP_COLUMNS)?;
+    },
+    GroupCommand::List { organisation } => {
+      let mut find = group::Entity::find().order_by_asc(group::Column::Name);
+      if let Some(organisation) = organisation {
+        find = find.filter(group::Column::OrganisationId.eq(organisation));
+      }
+      print(format, &find.all(db).await?, GROUP_COLUMNS)?;
+    },
+  }
+  Ok(())
+}
+
+async fn role_command(db: &DatabaseConnection, format: Format, command: RoleCommand) -> AdminResult {
+  match command {
+    RoleCommand::List => {
+      let mut rows: Vec<RoleRow> = organisation_access_role::Entity::find().all(db).await?
+        .into_iter()
+        .map(|role| RoleRow { id: role.id, kind: "organisation", name: role.name, permissions: role.org_role_permissions.to_value() })
+        .collect();
+      rows.extend(group_access_role::Entity::find().all(db).await?
+        .into_iter()
+        .map(|role| RoleRow { id: role.id, kind: "group", name: role.name, permissions: role.group_role_permissions.to_value() }));
+      print(format, &rows, ROLE_COLUMNS)?;
+    },
+    RoleCommand::CreateOrg { name, permissions } => {
+      let permissions = OrgRolePermissions::try_from_value(&permissions)?;
+      let role = organisation_access_role::ActiveModel {
+        name: Set(name),
+        description: Set(None),
+        org_role_permissions: Set(permissions),
+        permissions: Set(None),
+        ..Default::default()
+      }.insert(db).await?;
+      let row = RoleRow { id: role.id, kind: "organisation", name: role.name, permissions: role.org_role_permissions.to_value() };
+      print_one(format, &row, ROLE_COLUMNS)?;
+    },
+    RoleCommand::CreateGroup { name, permissions } => {
+      let permissions = GroupRolePermissions::try_from_value(&permissions)?;
+      let role = group_access_role::ActiveModel {
+        name: Set(name),
+        description: Set(None),
+        group_role_permissions: Set(permissions),
+        permissions: Set(None),
+        ..Default::default()
+      }.insert(db).await?;
+      let row = RoleRow { id: role.id, kind: "group", name: role.name, permissions: role.group_role_permissions.to_value() };
+      print_one(format, &row, ROLE_COLUMNS)?;
+    },
+    RoleCommand::AssignOrg { user, organisation, role } => {
+      let membership = users_organisations_organisations_access_roles::Entity::find_by_id((user, organisation)).one(db).await?;
+      let membership = match membership {
+        Some(membership) => {
+          let mut active_membership = membership.into_active_model();
+          active_membership.organisation_access_role_id = Set(role);
+          active_membership.update(db).await?
+        },
+        None => users_organisations_organisations_access_roles::ActiveModel {
+          user_id: Set(user),
+          organisation_id: Set(organisation),
+          organisation_access_role_id: Set(role),
+          ..Default::default()
+        }.insert(db).await?,
+      };
+      let row = MembershipRow {
+        user_id: membership.user_id,
+        organisation_id: Some(membership.organisation_id),
+        group_id: None,
+        role_id: membership.organisation_access_role_id,
+      };
+      print_one(format, &row, MEMBERSHIP_COLUMNS)?;
+    },
+    RoleCommand::AssignGroup { user, group, role } => {
+      let membership = users_groups_group_access_roles::Entity::find_by_id((user, group)).one(db).await?;
+      let membership = match membership {
+        Some(membership) => {
+          let mut active_membership = membership.into_active_model();
+          active_membership.group_access_role_id = Set(role);
+          active_membership.update(db).await?
+        },
+        None => users_groups_group_access_roles::ActiveModel {
+          user_id: Set(user),
+          group_id: Set(group),
+          group_access_role_id: Set(role),
+          ..Default::default()
+        }.insert(db).await?,
+      };
+      let row = MembershipRow {
+        user_id: membership.user_id,
+        organisation_id: None,
+        group_id: Some(membership.group_id),
+        role_id: membership.group_access_role_id,
+      };
+      print_one(format, &row, MEMBERSHIP_COLUMNS)?;
+    },
+  }
+  Ok(())
+}
+
+async fn api_key_command(db: &DatabaseConnection, format: Format, command: ApiKeyCommand) -> AdminResult {
+  match command {
+    ApiKeyCommand::Issue { user, expires_in_days } => {
+      let expires_on = match expires_in_days {
+        Some(days) if days <= 0 => return Err(AdminError("expires-in-days must be positive".to_string())),
+        Some(days) => Some(clock::now() + Duration::days(days)),
+        None => None,
+      };
+      let (api_key, api_secret_key) = auth_api_key::issue(db, auth_api_key::ActiveModel {
+        user_id: Set(Some(user)),
+        organisation_id: Set(None),
+        expires_on: Set(expires_on),
+        ip_address_last_used: Set(None),
+        key_last_used_at: Set(None),
+        ..Default::default()
+      }).await?;
+      let row = IssuedApiKeyRow {
+        id: api_key.id,
+        api_access_key: api_key.api_access_key,
+        api_secret_key,
+        expires_on: api_key.expires_on,
+      };
+      print_one(format, &row, ISSUED_API_KEY_COLUMNS)?;
+    },
+    ApiKeyCommand::List { user, organisation } => {
+      let mut find = auth_api_key::Entity::find().order_by_asc(auth_api_key::Column::KeyIssuedAt);
+      if let Some(user) = user {
+        find = find.filter(auth_api_key::Column::UserId.eq(user));
+      }
+      if let Some(organisation) = organisation {
+        find = find.filter(auth_api_key::Column::OrganisationId.eq(organisation));
+      }
+      print(format, &find.all(db).await?, API_KEY_COLUMNS)?;
+    },
+    ApiKeyCommand::Revoke { id } => {
+      let api_key = auth_api_key::Entity::find_by_id(id).one(db).await?.ok_or_else(|| not_found("api key", id))?;
+      let mut active_key = api_key.into_active_model();
+      active_key.expires_on = Set(Some(clock::now()));
+      print_one(format, &active_key.update(db).await?, API_KEY_COLUMNS)?;
+    },
+  }
+  Ok(())
+}
+
+fn key_owner(user: Option<UserId>, organisation: Option<OrgId>, group: Option<GroupId>) -> Result<Owner, AdminError> {
+  match (user, organisation, group) {
+    (Some(user_id), _, _) => Ok(Owner::User(user_id)),
+    (_, Some(organisation_id), _) => Ok(Owner::Organisation(organisation_id)),
+    (_, _, Some(group_id)) => Ok(Owner::Group(group_id)),
+    _ => Err(AdminError("one of --user, --organisation or --group is required".to_string())),
+  }
+}
+
+/// Makes the key its user's or organisation's current one, groups don't have one
+async fn make_current_key<C>(db: &C, owner: Owner, key: &pki_key::Model) -> AdminResult
+where
+  C: ConnectionTrait,
+{
+  match owner {
+    Owner::User(user_id) => {
+      let mut active_user = user::Entity::find_by_id(user_id).one(db).await?
+        .ok_or_else(|| not_found("user", user_id))?
+        .into_active_model();
+      active_user.pki_key_id = Set(Some(key.id));
+      active_user.update(db).await?;
+    },
+    Owner::Organisation(organisation_id) => {
+      let mut active_organisation = organisation::Entity::find_by_id(organisation_id).one(db).await?
+        .ok_or_else(|| not_found("organisation", organisation_id))?
+        .into_active_model();
+      active_organisation.pki_key_id = Set(Some(key.id));
+      active_organisation.update(db).await?;
+    },
+    Owner::Group(_) => {},
+  }
+  Ok(())
+}
+
+async fn pki_key_command(db: &DatabaseConnection, format: Format, command: PkiKeyCommand) -> AdminResult {
+  match command {
+    PkiKeyCommand::Generate { user, organisation, group, algo } => {
+      let algo = algo.map(|algo| KeyAlgos::try_from_value(&algo)).transpose()?;
+      let owner = key_owner(user, organisation, group)?;
+      let txn = db.begin().await?;
+      let key = pki_key::generate(&txn, owner, algo).await?;
+      make_current_key(&txn, owner, &key).await?;
+      txn.commit().await?;
+      print_one(format, &key, PKI_KEY_COLUMNS)?;
+    },
+    PkiKeyCommand::Register { user, organisation, group, algo, url } => {
+      let algo = KeyAlgos::try_from_value(&algo)?;
+      let owner = key_owner(user, organisation, group)?;
+      let provider = key_provider::provider_for_url(&url)?;
+      let txn = db.begin().await?;
+      let key = key_provider::register(&txn, provider.as_ref(), owner, algo, &url).await?;
+      make_current_key(&txn, owner, &key).await?;
+      txn.commit().await?;
+      print_one(format, &key, PKI_KEY_COLUMNS)?;
+    },
+    PkiKeyCommand::Rotate { id, url } => {
+      let key = pki_key::Entity::find_by_id(id).one(db).await?.ok_or_else(|| not_found("pki_key", id))?;
+      let txn = db.begin().await?;
+      let rotated = match url {
+        Some(url) => {
+          let provider = key_provider::provider_for_url(&url)?;
+          key_provider::rotate(&txn, provider.as_ref(), &key, &url).await?
+        },
+        None => pki_key::rotate(&txn, &key).await?,
+      };
+      txn.commit().await?;
+      print_one(format, &rotated, PKI_KEY_COLUMNS)?;
+    },
+    PkiKeyCommand::List => {
+      let keys = pki_key::Entity::find().order_by_asc(pki_key::Column::CreatedAt).all(db).await?;
+      print(format, &keys, PKI_KEY_COLUMNS)?;
+    },
+    PkiKeyCommand::Rewrap { seal_plaintext } => {
+      let sealed = match seal_plaintext {
+        true => key_service::seal_plaintext(db).await?,
+        false => 0,
+      };
+      let rewrapped = key_service::rewrap(db).await?;
+      print_one(format, &RewrapRow { rewrapped, sealed }, REWRAP_COLUMNS)?;
+    },
+  }
+  Ok(())
+}
+
+async fn run(cli: Cli) -> AdminResult {
+  // Passwords and keys follow the same security settings as the server
+  let config = entities::config::Config::load()?;
+  if config.keys.master_keys.is_empty() && cli.command.seals_keys() {
+    return Err(AdminError("KEYS_MASTER_KEYS must be set".to_string()));
+  }
+  entities::config::init(config)?;
+  let db = Database::connect(&cli.database_url).await?;
+  match cli.command {
+    Command::User(command) => user_command(&db, cli.format, command).await,
+    Command::Org(command) => org_command(&db, cli.format, command).await,
+    Command::Group(command) => group_command(&db, cli.format, command).await,
+    Command::Role(command) => role_command(&db, cli.format, command).await,
+    Command::ApiKey(command) => api_key_command(&db, cli.format, command).await,
+    Command::PkiKey(command) => pki_key_command(&db, cli.format, command).await,
+  }
+}
+
+#[actix_web::main]
+async fn main() {
+  dotenvy::dotenv().ok();
+  env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
+
+  if let Err(AdminError(err)) = run(Cli::parse()).await {
+    eprintln!("{}", err);
+    std::process::exit(1);
+  }
+}
@@ -0,0 +1,55 @@
+use clap::ArgEnum;
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ArgEnum)]
+pub enum Format {
+  Table,
+  Json,
+}
+
+fn cell(value: Option<&Value>) -> String {
+  match value {
+    None | Some(Value::Null) => "-".to_string(),
+    Some(Value::String(value)) => value.clone(),
+    Some(value) => value.to_string(),
+  }
+}
+
+/// JSON prints every serialized field, the table only the given columns
+pub fn print<T: Serialize>(format: Format, rows: &[T], columns: &[&str]) -> Result<(), String> {
+  let rows: Vec<Value> = rows.iter()
+    .map(serde_json::to_value)
+    .collect::<Result<_, _>>()
+    .map_err(|err| err.to_string())?;
+  if format == Format::Json {
+    println!("{}", serde_json::to_string_pretty(&rows).map_err(|err| err.to_string())?);
+    return Ok(());
+  }
+
+  let cells: Vec<Vec<String>> = rows.iter()
+    .map(|row| columns.iter().map(|column| cell(row.get(column))).collect())
+    .collect();
+  let widths: Vec<usize> = columns.iter().enumerate()
+    .map(|(index, column)| cells.iter().map(|row| row[index].len()).fold(column.len(), usize::max))
+    .collect();
+  let line = |values: Vec<&str>| -> String {
+    values.iter().zip(&widths)
+      .map(|(value, width)| format!("{:<width$}", value, width = width))
+      .collect::<Vec<String>>()
+      .join("  ")
+      .trim_end()
+      .to_string()
+  };
+  println!("{}", line(columns.to_vec()));
+  println!("{}", line(widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<String>>().iter().map(String::as_str).collect()));
+  for row in &cells {
+    println!("{}", line(row.iter().map(String::as_str).collect()));
+  }
+  Ok(())
+}
+
+/// Single records print the same way as lists
+pub fn print_one<T: Serialize>(format: Format, row: &T, columns: &[&str]) -> Result<(), String> {
+  print(format, std::slice::from_ref(row), columns)
+}
@@ -0,0 +1,2 @@
+/// Handlers share the workspace error, its statuses and response body come from `shared`
+pub use shared::Error as ApiError;
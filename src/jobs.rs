@@ -0,0 +1,53 @@
+//! Work the server does on a timer alongside serving requests.
+use std::time::Duration;
+use actix_web::{rt, web};
+use entities::{certificate_authority, config, key_service, pki_key};
+use sea_orm::DatabaseConnection;
+
+/// Re-wraps data keys under the active master key straight away, then every `rewrap_interval_mins`
+pub fn spawn_key_rewrap(db: web::Data<DatabaseConnection>) {
+  let interval_mins = config::get().keys.rewrap_interval_mins as u64;
+  rt::spawn(async move {
+    let mut interval = rt::time::interval(Duration::from_secs(interval_mins * 60));
+    loop {
+      interval.tick().await;
+      match key_service::rewrap(&**db).await {
+        Ok(0) => {},
+        Ok(count) => log::info!("Re-wrapped {} keys", count),
+        Err(err) => log::error!("Re-wrapping keys failed: {}", err),
+      }
+    }
+  });
+}
+
+/// Re-signs CRLs before they lapse, checking every `crl_refresh_interval_mins`
+pub fn spawn_crl_refresh(db: web::Data<DatabaseConnection>) {
+  let interval_mins = config::get().pki.crl_refresh_interval_mins as u64;
+  rt::spawn(async move {
+    let mut interval = rt::time::interval(Duration::from_secs(interval_mins * 60));
+    loop {
+      interval.tick().await;
+      match certificate_authority::refresh_crls(&**db).await {
+        Ok(0) => {},
+        Ok(count) => log::info!("Re-signed {} CRLs", count),
+        Err(err) => log::error!("Re-signing CRLs failed: {}", err),
+      }
+    }
+  });
+}
+
+/// Gives keys past their organisation's `key_rotation_days` a new version, checking every `rotation_check_interval_mins`
+pub fn spawn_key_rotation(db: web::Data<DatabaseConnection>) {
+  let interval_mins = config::get().keys.rotation_check_interval_mins as u64;
+  rt::spawn(async move {
+    let mut interval = rt::time::interval(Duration::from_secs(interval_mins * 60));
+    loop {
+      interval.tick().await;
+      match pki_key::rotate_due(&**db).await {
+        Ok(0) => {},
+        Ok(count) => log::info!("Rotated {} keys", count),
+        Err(err) => log::error!("Rotating keys failed: {}", err),
+      }
+    }
+  });
+}
@@ -0,0 +1,39 @@
+use entities::config::MailConfig;
+use lettre::{message::Mailbox, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use sea_orm::DbErr;
+use crate::error::ApiError;
+
+/// Sends mail through the `[mail]` SMTP server
+pub struct Mailer {
+  transport: Option<(AsyncSmtpTransport<Tokio1Executor>, Mailbox)>,
+}
+
+impl Mailer {
+  /// Without an `smtp_url` every send fails, everything else keeps working
+  pub fn from_config(config: &MailConfig) -> Result<Self, String> {
+    if config.smtp_url.is_empty() {
+      return Ok(Self { transport: None });
+    }
+    let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(&config.smtp_url)
+      .map_err(|err| format!("mail.smtp_url: {}", err))?
+      .build();
+    let from = config.from.parse::<Mailbox>().map_err(|err| format!("mail.from: {}", err))?;
+    Ok(Self { transport: Some((transport, from)) })
+  }
+
+  pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), ApiError> {
+    let failed = |err: String| ApiError::Db(DbErr::Custom(format!("unable to send mail: {}", err)));
+    let Some((transport, from)) = &self.transport else {
+      return Err(failed("mail.smtp_url is not set".to_string()));
+    };
+    let to = to.parse::<Mailbox>().map_err(|err| failed(err.to_string()))?;
+    let message = Message::builder()
+      .from(from.clone())
+      .to(to)
+      .subject(subject)
+      .body(body)
+      .map_err(|err| failed(err.to_string()))?;
+    transport.send(message).await.map_err(|err| failed(err.to_string()))?;
+    Ok(())
+  }
+}
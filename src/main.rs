@@ -1,7 +1,44 @@
-// use migration::{Migrator, MigratorTrait};
+use actix_web::{middleware::Logger, web, App, HttpServer};
+use entities::config::{self, Config};
+use migration::Migrator;
+use sea_orm::Database;
 
-// let connection = sea_orm::Database::connect(&database_url).await?;
-// Migrator::up(&connection, None).await?;
+mod auth;
+mod error;
+mod jobs;
+mod mailer;
+mod routes;
 
-fn main() {
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+  dotenvy::dotenv().ok();
+  env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+
+  let settings = Config::load().unwrap_or_else(|err| panic!("Invalid configuration: {}", err));
+  let database_url = settings.database_url.clone().expect("DATABASE_URL must be set");
+  let bind_address = settings.bind_address.clone();
+  // A generated master key would leave every private key unreadable after a restart
+  if settings.keys.master_keys.is_empty() {
+    panic!("KEYS_MASTER_KEYS must be set");
+  }
+  let mailer = web::Data::new(mailer::Mailer::from_config(&settings.mail).unwrap_or_else(|err| panic!("Invalid configuration: {}", err)));
+  config::init(settings).expect("Unable to install configuration");
+
+  let connection = Database::connect(&database_url).await.expect("Unable to connect to db");
+  Migrator::run_migrations(&connection).await.expect("Unable to run migrations");
+
+  let db = web::Data::new(connection);
+  jobs::spawn_key_rewrap(db.clone());
+  jobs::spawn_crl_refresh(db.clone());
+  jobs::spawn_key_rotation(db.clone());
+  HttpServer::new(move || {
+    App::new()
+      .app_data(db.clone())
+      .app_data(mailer.clone())
+      .wrap(Logger::default())
+      .configure(routes::configure)
+  })
+  .bind(bind_address)?
+  .run()
+  .await
 }
@@ -0,0 +1,200 @@
+//! An organisation's certificate authorities, the roles they issue under and the certificates issued.
+//!
+//! CAs and roles are managed with `manage`, issuing needs `create` on certificates and revoking `manage`.
+//! CRLs and OCSP are served publicly, see `pki`.
+use actix_web::{web, HttpResponse};
+use entities::{
+  certificate::{self, IssueRequest, RevocationReason},
+  certificate_authority::{self, NewAuthority},
+  certificate_role::{self, RoleTemplate},
+};
+use sea_orm::{entity::prelude::*, ActiveValue::Set, DatabaseConnection, QueryOrder, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use shared::OrgId;
+use crate::{auth::AuthenticatedUser, error::ApiError};
+
+const AUTHORITY_RESOURCE: &str = "certificate_authority";
+const ROLE_RESOURCE: &str = "certificate_role";
+const RESOURCE: &str = "certificate";
+
+/// A root CA, or an intermediate when `parent_id` is set
+#[derive(Debug, Deserialize)]
+pub struct CreateAuthority {
+  pub parent_id: Option<Uuid>,
+  #[serde(flatten)]
+  pub authority: NewAuthority,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthorityWithChain {
+  #[serde(flatten)]
+  pub authority: certificate_authority::Model,
+  pub chain: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRole {
+  pub name: String,
+  #[serde(default)]
+  pub template: RoleTemplate,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Revoke {
+  pub reason: Option<RevocationReason>,
+}
+
+async fn find_authority<C>(db: &C, organisation_id: OrgId, authority_id: Uuid) -> Result<certificate_authority::Model, ApiError>
+where
+  C: ConnectionTrait,
+{
+  certificate_authority::Entity::find_by_id(authority_id)
+    .filter(certificate_authority::Column::OrganisationId.eq(organisation_id))
+    .one(db)
+    .await?
+    .ok_or(ApiError::NotFound)
+}
+
+async fn create_authority(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<CreateAuthority>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, AUTHORITY_RESOURCE, "manage").await?;
+  let body = body.into_inner();
+  let txn = db.begin().await?;
+  let authority = match body.parent_id {
+    Some(parent_id) => {
+      let parent = find_authority(&txn, organisation_id, parent_id).await?;
+      certificate_authority::create_intermediate(&txn, &parent, body.authority).await?
+    },
+    None => certificate_authority::create_root(&txn, organisation_id, body.authority).await?,
+  };
+  let chain = certificate_authority::chain(&txn, &authority).await?;
+  txn.commit().await?;
+  Ok(HttpResponse::Created().json(AuthorityWithChain { authority, chain }))
+}
+
+async fn list_authorities(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, AUTHORITY_RESOURCE, "list").await?;
+  let authorities = certificate_authority::Entity::find()
+    .filter(certificate_authority::Column::OrganisationId.eq(organisation_id))
+    .order_by_asc(certificate_authority::Column::CreatedAt)
+    .all(db.get_ref())
+    .await?;
+  Ok(HttpResponse::Ok().json(authorities))
+}
+
+async fn read_authority(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, authority_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, AUTHORITY_RESOURCE, "read").await?;
+  let authority = find_authority(db.get_ref(), organisation_id, authority_id).await?;
+  let chain = certificate_authority::chain(db.get_ref(), &authority).await?;
+  Ok(HttpResponse::Ok().json(AuthorityWithChain { authority, chain }))
+}
+
+async fn create_role(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+  body: web::Json<CreateRole>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, authority_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, ROLE_RESOURCE, "manage").await?;
+  let authority = find_authority(db.get_ref(), organisation_id, authority_id).await?;
+  let body = body.into_inner();
+  let role = certificate_role::ActiveModel {
+    organisation_id: Set(organisation_id),
+    certificate_authority_id: Set(authority.id),
+    name: Set(body.name),
+    template: Set(body.template),
+    ..Default::default()
+  }.insert(db.get_ref()).await?;
+  Ok(HttpResponse::Created().json(role))
+}
+
+async fn list_roles(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, authority_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, ROLE_RESOURCE, "list").await?;
+  let roles = certificate_role::Entity::find()
+    .filter(certificate_role::Column::OrganisationId.eq(organisation_id))
+    .filter(certificate_role::Column::CertificateAuthorityId.eq(authority_id))
+    .all(db.get_ref())
+    .await?;
+  Ok(HttpResponse::Ok().json(roles))
+}
+
+async fn issue(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid, Uuid)>,
+  body: web::Json<IssueRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, authority_id, role_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "create").await?;
+  let role = certificate_role::Entity::find_by_id(role_id)
+    .filter(certificate_role::Column::OrganisationId.eq(organisation_id))
+    .filter(certificate_role::Column::CertificateAuthorityId.eq(authority_id))
+    .one(db.get_ref())
+    .await?
+    .ok_or(ApiError::NotFound)?;
+  let issued = certificate::issue(db.get_ref(), &role, body.into_inner()).await?;
+  Ok(HttpResponse::Created().json(issued))
+}
+
+async fn list_certificates(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "list").await?;
+  let certificates = certificate::Entity::find()
+    .filter(certificate::Column::OrganisationId.eq(organisation_id))
+    .order_by_desc(certificate::Column::CreatedAt)
+    .all(db.get_ref())
+    .await?;
+  Ok(HttpResponse::Ok().json(certificates))
+}
+
+async fn revoke(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+  body: web::Json<Revoke>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, serial) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "manage").await?;
+  let reason = body.into_inner().reason.unwrap_or(RevocationReason::Unspecified);
+  let txn = db.begin().await?;
+  let revoked = certificate::revoke(&txn, organisation_id, &serial, reason).await?;
+  txn.commit().await?;
+  Ok(HttpResponse::Ok().json(revoked))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/organisations/{organisation_id}/cas", web::get().to(list_authorities))
+    .route("/organisations/{organisation_id}/cas", web::post().to(create_authority))
+    .route("/organisations/{organisation_id}/cas/{ca_id}", web::get().to(read_authority))
+    .route("/organisations/{organisation_id}/cas/{ca_id}/roles", web::get().to(list_roles))
+    .route("/organisations/{organisation_id}/cas/{ca_id}/roles", web::post().to(create_role))
+    .route("/organisations/{organisation_id}/cas/{ca_id}/roles/{role_id}/issue", web::post().to(issue))
+    .route("/organisations/{organisation_id}/certificates", web::get().to(list_certificates))
+    .route("/organisations/{organisation_id}/certificates/{serial}/revoke", web::post().to(revoke));
+}
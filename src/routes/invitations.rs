@@ -0,0 +1,88 @@
+use actix_web::{web, HttpResponse};
+use entities::{config, invitation::{self, NewInvitation}};
+use sea_orm::{entity::prelude::*, DatabaseConnection, TransactionTrait};
+use shared::OrgId;
+use crate::{auth::AuthenticatedUser, error::ApiError, mailer::Mailer};
+
+const RESOURCE: &str = "invitation";
+
+/// Inviting someone hands out a role so it needs manage rights rather than just create.
+/// The token is only ever emailed to the invitee, the invitation is kept only once that worked.
+async fn create(
+  db: web::Data<DatabaseConnection>,
+  mailer: web::Data<Mailer>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<NewInvitation>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  let body = body.into_inner();
+  user.authorize(&db, organisation_id, body.group_id, RESOURCE, "manage").await?;
+
+  let txn = db.begin().await?;
+  let invitation = invitation::create(&txn, organisation_id, user.user_id, body).await?;
+  let link = config::get().mail.invitation_url.replace("{token}", &invitation.token);
+  let message = format!(
+    "You have been invited to join an organisation as {}. Accept the invitation at {}, signing in first if you already have an account.\n\nIt expires at {}.",
+    invitation.email_address, link, invitation.expires_at
+  );
+  mailer.send(&invitation.email_address, "You have been invited", message).await?;
+  txn.commit().await?;
+  Ok(HttpResponse::Created().json(invitation))
+}
+
+async fn list(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "list").await?;
+  let invitations = invitation::Entity::find()
+    .filter(invitation::Column::OrganisationId.eq(organisation_id))
+    .all(db.get_ref())
+    .await?;
+  Ok(HttpResponse::Ok().json(invitations))
+}
+
+async fn revoke(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, invitation_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "manage").await?;
+  let invitation = invitation::Entity::find_by_id(invitation_id)
+    .filter(invitation::Column::OrganisationId.eq(organisation_id))
+    .one(db.get_ref())
+    .await?
+    .ok_or(ApiError::NotFound)?;
+  Ok(HttpResponse::Ok().json(invitation::revoke(db.get_ref(), &invitation).await?))
+}
+
+/// A signed in invitee needs to have verified the invited address. Without an account the
+/// token is the credential and the user is created on accepting.
+async fn accept(
+  db: web::Data<DatabaseConnection>,
+  user: Option<AuthenticatedUser>,
+  path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.map(|user| user.user_id);
+  Ok(HttpResponse::Ok().json(invitation::accept(db.get_ref(), &path.into_inner(), user_id).await?))
+}
+
+async fn decline(
+  db: web::Data<DatabaseConnection>,
+  path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+  Ok(HttpResponse::Ok().json(invitation::decline(db.get_ref(), &path.into_inner()).await?))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/organisations/{organisation_id}/invitations", web::get().to(list))
+    .route("/organisations/{organisation_id}/invitations", web::post().to(create))
+    .route("/organisations/{organisation_id}/invitations/{invitation_id}", web::delete().to(revoke))
+    .route("/invitations/{token}/accept", web::post().to(accept))
+    .route("/invitations/{token}/decline", web::post().to(decline));
+}
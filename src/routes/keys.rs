@@ -0,0 +1,531 @@
+//! Signing with and verifying against the `pki_key`s of users, organisations and groups,
+//! creating, importing and exporting them, and rotating them.
+//!
+//! Signing a payload or digest needs `create` on signatures in the owning organisation or group,
+//! verifying needs `read`. Listing keys and exporting public keys needs `read` on keys, creating
+//! and importing them `create`, rotating them and changing the status of older versions `manage`.
+//! Exporting a private key needs `export`, which only owners have unless it's granted, and the key
+//! must have been created exportable. Users sign with and manage their own keys only.
+use actix_web::{web, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use entities::{
+  group,
+  key_pair::{self, SignatureScheme},
+  key_format::KeyFormat,
+  key_provider,
+  pki_key::{self, ImportKey, KeyStatus, NewKey, Owner},
+};
+use sea_orm::{entity::prelude::*, DatabaseConnection, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use shared::{GroupId, OrgId};
+use crate::{auth::AuthenticatedUser, error::ApiError};
+
+const RESOURCE: &str = "signature";
+const KEY_RESOURCE: &str = "pki_key";
+
+/// Exactly one of `payload` or `digest`, base64 encoded. A digest must be made with the scheme's hash.
+#[derive(Debug, Deserialize)]
+pub struct SignRequest {
+  pub key_id: Option<Uuid>,
+  pub scheme: Option<SignatureScheme>,
+  pub payload: Option<String>,
+  pub digest: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+  pub key_id: Option<Uuid>,
+  pub scheme: Option<SignatureScheme>,
+  pub payload: Option<String>,
+  pub digest: Option<String>,
+  pub signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusChange {
+  pub status: KeyStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+  pub format: KeyFormat,
+}
+
+/// Left out of `Debug` so the passphrase isn't logged
+#[derive(Deserialize)]
+pub struct ExportRequest {
+  pub format: KeyFormat,
+  pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Signed {
+  pub key_id: Uuid,
+  pub version: i32,
+  pub scheme: SignatureScheme,
+  pub signature: String,
+}
+
+/// `key_id` is the version that verified the signature, or the one tried when none did
+#[derive(Debug, Serialize)]
+pub struct Verified {
+  pub key_id: Uuid,
+  pub version: i32,
+  pub scheme: SignatureScheme,
+  pub valid: bool,
+}
+
+#[derive(Serialize)]
+pub struct Exported {
+  pub key_id: Uuid,
+  pub version: i32,
+  pub format: KeyFormat,
+  pub private: bool,
+  pub data: String,
+}
+
+enum Signable {
+  Payload(Vec<u8>),
+  Digest(Vec<u8>),
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, ApiError> {
+  STANDARD.decode(value).map_err(|_| ApiError::Validation(format!("{} must be base64", field)))
+}
+
+fn signable(payload: Option<&str>, digest: Option<&str>) -> Result<Signable, ApiError> {
+  match (payload, digest) {
+    (Some(payload), None) => Ok(Signable::Payload(decode("payload", payload)?)),
+    (None, Some(digest)) => Ok(Signable::Digest(decode("digest", digest)?)),
+    _ => Err(ApiError::Validation("exactly one of payload or digest is required".to_string())),
+  }
+}
+
+/// The scheme asked for, or the key's default, checked against the key and the digest
+fn scheme_for(key: &pki_key::Model, scheme: Option<SignatureScheme>, signable: &Signable) -> Result<SignatureScheme, ApiError> {
+  let scheme = scheme.unwrap_or_else(|| SignatureScheme::default_for(key.algo));
+  scheme.check(key.algo).map_err(ApiError::Validation)?;
+  if let Signable::Digest(digest) = signable {
+    scheme.check_digest(digest).map_err(ApiError::Validation)?;
+  }
+  Ok(scheme)
+}
+
+async fn find_key(db: &DatabaseConnection, owner: Owner, key_id: Option<Uuid>) -> Result<pki_key::Model, ApiError> {
+  pki_key::find_for_owner(db, owner, key_id).await?.ok_or(ApiError::NotFound)
+}
+
+async fn find_group(db: &DatabaseConnection, organisation_id: OrgId, group_id: GroupId) -> Result<group::Model, ApiError> {
+  group::Entity::find_by_id(group_id)
+    .one(db)
+    .await?
+    .filter(|group| group.organisation_id == organisation_id)
+    .ok_or(ApiError::NotFound)
+}
+
+/// The version's status is the caller's problem rather than the provider's
+fn check_status(key: &pki_key::Model, usable: bool) -> Result<(), ApiError> {
+  match usable {
+    true => Ok(()),
+    false => Err(ApiError::Validation(format!("key {} version {} is {:?}", key.id, key.version, key.status))),
+  }
+}
+
+async fn sign_with(db: &DatabaseConnection, owner: Owner, body: SignRequest) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, owner, body.key_id).await?;
+  check_status(&key, key.status.can_sign())?;
+  let signable = signable(body.payload.as_deref(), body.digest.as_deref())?;
+  let scheme = scheme_for(&key, body.scheme, &signable)?;
+  // The key checked out, so a failure here is the provider's rather than the caller's
+  let signature = match &signable {
+    Signable::Payload(payload) => key_provider::sign(&key, scheme, payload).await,
+    Signable::Digest(digest) => key_provider::sign_digest(&key, scheme, digest).await,
+  }.map_err(|err| ApiError::Db(DbErr::Custom(format!("unable to sign with key {}: {}", key.id, err))))?;
+  Ok(HttpResponse::Ok().json(Signed { key_id: key.id, version: key.version, scheme, signature: STANDARD.encode(signature) }))
+}
+
+/// Without a `key_id` every version of the owner's current key that still verifies is tried
+async fn verify_with(db: &DatabaseConnection, owner: Owner, body: VerifyRequest) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, owner, body.key_id).await?;
+  let keys = match body.key_id {
+    Some(_) => {
+      check_status(&key, key.status.can_verify())?;
+      vec![key]
+    },
+    None => pki_key::verification_keys(db, &key).await?,
+  };
+  let signable = signable(body.payload.as_deref(), body.digest.as_deref())?;
+  let signature = decode("signature", &body.signature)?;
+  let mut verified = None;
+  let mut unusable = None;
+  for key in &keys {
+    // Versions that can't verify with the scheme are skipped, so another version's algorithm
+    // doesn't fail the request. Why the last one was skipped is returned if none could be tried.
+    let scheme = match scheme_for(key, body.scheme, &signable) {
+      Ok(scheme) => scheme,
+      Err(err) => {
+        unusable = Some(err);
+        continue;
+      },
+    };
+    let Some(public_key) = key.public_key.as_deref() else {
+      unusable = Some(ApiError::Validation(format!("key {} version {} has no public key", key.id, key.version)));
+      continue;
+    };
+    let result = match &signable {
+      Signable::Payload(payload) => key_pair::verify(key.algo, public_key, scheme, payload, &signature),
+      Signable::Digest(digest) => key_pair::verify_digest(key.algo, public_key, scheme, digest, &signature),
+    };
+    verified = Some(Verified { key_id: key.id, version: key.version, scheme, valid: result.is_ok() });
+    if result.is_ok() {
+      break;
+    }
+  }
+  match (verified, unusable) {
+    (Some(verified), _) => Ok(HttpResponse::Ok().json(verified)),
+    (None, Some(err)) => Err(err),
+    (None, None) => Err(ApiError::NotFound),
+  }
+}
+
+/// Keys held by a provider get their new version registered through the admin cli
+async fn rotate_for(db: &DatabaseConnection, owner: Owner, key_id: Uuid) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, owner, Some(key_id)).await?;
+  let txn = db.begin().await?;
+  let rotated = pki_key::rotate(&txn, &key).await?;
+  txn.commit().await?;
+  Ok(HttpResponse::Created().json(rotated))
+}
+
+async fn set_status_for(db: &DatabaseConnection, owner: Owner, key_id: Uuid, body: StatusChange) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, owner, Some(key_id)).await?;
+  Ok(HttpResponse::Ok().json(pki_key::set_status(db, &key, body.status).await?))
+}
+
+async fn create_for(db: &DatabaseConnection, owner: Owner, body: NewKey) -> Result<HttpResponse, ApiError> {
+  Ok(HttpResponse::Created().json(pki_key::create(db, owner, body).await?))
+}
+
+async fn import_for(db: &DatabaseConnection, owner: Owner, body: ImportKey) -> Result<HttpResponse, ApiError> {
+  Ok(HttpResponse::Created().json(pki_key::import(db, owner, body).await?))
+}
+
+async fn export_public_for(db: &DatabaseConnection, owner: Owner, key_id: Uuid, query: ExportQuery) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, owner, Some(key_id)).await?;
+  let data = pki_key::export_public(&key, query.format)?;
+  Ok(HttpResponse::Ok().json(Exported { key_id: key.id, version: key.version, format: query.format, private: false, data }))
+}
+
+async fn export_private_for(db: &DatabaseConnection, owner: Owner, key_id: Uuid, body: ExportRequest) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, owner, Some(key_id)).await?;
+  let data = pki_key::export_private(&key, body.format, body.passphrase.as_deref())?;
+  Ok(HttpResponse::Ok().json(Exported { key_id: key.id, version: key.version, format: body.format, private: true, data: data.to_string() }))
+}
+
+async fn sign_as_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  body: web::Json<SignRequest>,
+) -> Result<HttpResponse, ApiError> {
+  sign_with(&db, Owner::User(user.user_id), body.into_inner()).await
+}
+
+async fn verify_as_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  body: web::Json<VerifyRequest>,
+) -> Result<HttpResponse, ApiError> {
+  verify_with(&db, Owner::User(user.user_id), body.into_inner()).await
+}
+
+async fn sign_as_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<SignRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "create").await?;
+  sign_with(&db, Owner::Organisation(organisation_id), body.into_inner()).await
+}
+
+async fn verify_as_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<VerifyRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "read").await?;
+  verify_with(&db, Owner::Organisation(organisation_id), body.into_inner()).await
+}
+
+async fn sign_as_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+  body: web::Json<SignRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), RESOURCE, "create").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  sign_with(&db, Owner::Group(group_id), body.into_inner()).await
+}
+
+async fn verify_as_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+  body: web::Json<VerifyRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), RESOURCE, "read").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  verify_with(&db, Owner::Group(group_id), body.into_inner()).await
+}
+
+async fn list_for_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+  Ok(HttpResponse::Ok().json(pki_key::list_for_owner(&**db, Owner::User(user.user_id)).await?))
+}
+
+async fn create_for_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  body: web::Json<NewKey>,
+) -> Result<HttpResponse, ApiError> {
+  create_for(&db, Owner::User(user.user_id), body.into_inner()).await
+}
+
+async fn import_for_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  body: web::Json<ImportKey>,
+) -> Result<HttpResponse, ApiError> {
+  import_for(&db, Owner::User(user.user_id), body.into_inner()).await
+}
+
+async fn export_public_for_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<Uuid>,
+  query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+  export_public_for(&db, Owner::User(user.user_id), path.into_inner(), query.into_inner()).await
+}
+
+async fn export_private_for_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<Uuid>,
+  body: web::Json<ExportRequest>,
+) -> Result<HttpResponse, ApiError> {
+  export_private_for(&db, Owner::User(user.user_id), path.into_inner(), body.into_inner()).await
+}
+
+async fn rotate_for_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+  rotate_for(&db, Owner::User(user.user_id), path.into_inner()).await
+}
+
+async fn set_status_for_user(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<Uuid>,
+  body: web::Json<StatusChange>,
+) -> Result<HttpResponse, ApiError> {
+  set_status_for(&db, Owner::User(user.user_id), path.into_inner(), body.into_inner()).await
+}
+
+async fn list_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "read").await?;
+  Ok(HttpResponse::Ok().json(pki_key::list_for_owner(&**db, Owner::Organisation(organisation_id)).await?))
+}
+
+async fn create_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<NewKey>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "create").await?;
+  create_for(&db, Owner::Organisation(organisation_id), body.into_inner()).await
+}
+
+async fn import_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<ImportKey>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "create").await?;
+  import_for(&db, Owner::Organisation(organisation_id), body.into_inner()).await
+}
+
+async fn export_public_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+  query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "read").await?;
+  export_public_for(&db, Owner::Organisation(organisation_id), key_id, query.into_inner()).await
+}
+
+async fn export_private_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+  body: web::Json<ExportRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "export").await?;
+  export_private_for(&db, Owner::Organisation(organisation_id), key_id, body.into_inner()).await
+}
+
+async fn rotate_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "manage").await?;
+  rotate_for(&db, Owner::Organisation(organisation_id), key_id).await
+}
+
+async fn set_status_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+  body: web::Json<StatusChange>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "manage").await?;
+  set_status_for(&db, Owner::Organisation(organisation_id), key_id, body.into_inner()).await
+}
+
+async fn list_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "read").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  Ok(HttpResponse::Ok().json(pki_key::list_for_owner(&**db, Owner::Group(group_id)).await?))
+}
+
+async fn create_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+  body: web::Json<NewKey>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "create").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  create_for(&db, Owner::Group(group_id), body.into_inner()).await
+}
+
+async fn import_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+  body: web::Json<ImportKey>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "create").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  import_for(&db, Owner::Group(group_id), body.into_inner()).await
+}
+
+async fn export_public_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, Uuid)>,
+  query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "read").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  export_public_for(&db, Owner::Group(group_id), key_id, query.into_inner()).await
+}
+
+async fn export_private_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, Uuid)>,
+  body: web::Json<ExportRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "export").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  export_private_for(&db, Owner::Group(group_id), key_id, body.into_inner()).await
+}
+
+async fn rotate_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "manage").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  rotate_for(&db, Owner::Group(group_id), key_id).await
+}
+
+async fn set_status_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, Uuid)>,
+  body: web::Json<StatusChange>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, key_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "manage").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  set_status_for(&db, Owner::Group(group_id), key_id, body.into_inner()).await
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/users/me/keys", web::get().to(list_for_user))
+    .route("/users/me/keys", web::post().to(create_for_user))
+    .route("/users/me/keys/import", web::post().to(import_for_user))
+    .route("/users/me/keys/sign", web::post().to(sign_as_user))
+    .route("/users/me/keys/verify", web::post().to(verify_as_user))
+    .route("/users/me/keys/{key_id}/rotate", web::post().to(rotate_for_user))
+    .route("/users/me/keys/{key_id}/status", web::put().to(set_status_for_user))
+    .route("/users/me/keys/{key_id}/export", web::get().to(export_public_for_user))
+    .route("/users/me/keys/{key_id}/export", web::post().to(export_private_for_user))
+    .route("/organisations/{organisation_id}/keys", web::get().to(list_for_organisation))
+    .route("/organisations/{organisation_id}/keys", web::post().to(create_for_organisation))
+    .route("/organisations/{organisation_id}/keys/import", web::post().to(import_for_organisation))
+    .route("/organisations/{organisation_id}/keys/sign", web::post().to(sign_as_organisation))
+    .route("/organisations/{organisation_id}/keys/verify", web::post().to(verify_as_organisation))
+    .route("/organisations/{organisation_id}/keys/{key_id}/rotate", web::post().to(rotate_for_organisation))
+    .route("/organisations/{organisation_id}/keys/{key_id}/status", web::put().to(set_status_for_organisation))
+    .route("/organisations/{organisation_id}/keys/{key_id}/export", web::get().to(export_public_for_organisation))
+    .route("/organisations/{organisation_id}/keys/{key_id}/export", web::post().to(export_private_for_organisation))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys", web::get().to(list_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys", web::post().to(create_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys/import", web::post().to(import_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys/sign", web::post().to(sign_as_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys/verify", web::post().to(verify_as_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys/{key_id}/rotate", web::post().to(rotate_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys/{key_id}/status", web::put().to(set_status_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys/{key_id}/export", web::get().to(export_public_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/keys/{key_id}/export", web::post().to(export_private_for_group));
+}
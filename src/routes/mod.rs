@@ -0,0 +1,21 @@
+use actix_web::web;
+
+pub mod certificates;
+pub mod invitations;
+pub mod keys;
+pub mod organisations;
+pub mod pki;
+pub mod policies;
+pub mod ssh;
+pub mod transit;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  policies::configure(cfg);
+  organisations::configure(cfg);
+  invitations::configure(cfg);
+  keys::configure(cfg);
+  certificates::configure(cfg);
+  pki::configure(cfg);
+  ssh::configure(cfg);
+  transit::configure(cfg);
+}
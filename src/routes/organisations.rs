@@ -0,0 +1,127 @@
+use actix_web::{web, HttpResponse};
+use entities::organisation;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, DatabaseConnection, IntoActiveModel};
+use serde::Deserialize;
+use shared::OrgId;
+use serde_json::{json, Map, Value};
+use crate::{auth::AuthenticatedUser, error::ApiError};
+
+const RESOURCE: &str = "organisation";
+
+#[derive(Debug, Deserialize)]
+pub struct ChildrenQuery {
+  #[serde(default)]
+  pub recursive: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveOrganisation {
+  pub parent_organisation_id: Option<OrgId>,
+}
+
+async fn find_organisation(db: &DatabaseConnection, organisation_id: OrgId) -> Result<organisation::Model, ApiError> {
+  organisation::Entity::find_by_id(organisation_id)
+    .one(db)
+    .await?
+    .ok_or(ApiError::NotFound)
+}
+
+async fn children(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  query: web::Query<ChildrenQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "list").await?;
+  let find = if query.recursive {
+    let mut descendant_ids = organisation::find_descendant_ids(db.get_ref(), organisation_id).await?;
+    descendant_ids.retain(|id| *id != organisation_id);
+    organisation::Entity::find().filter(organisation::Column::Id.is_in(descendant_ids))
+  } else {
+    organisation::Entity::find().filter(organisation::Column::ParentOrganisationId.eq(organisation_id))
+  };
+  Ok(HttpResponse::Ok().json(find.all(db.get_ref()).await?))
+}
+
+async fn ancestors(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "read").await?;
+  let ancestor_ids: Vec<OrgId> = organisation::find_ancestor_ids(db.get_ref(), organisation_id).await?
+    .into_iter()
+    .skip(1)
+    .collect();
+  let mut ancestors = organisation::Entity::find()
+    .filter(organisation::Column::Id.is_in(ancestor_ids.clone()))
+    .all(db.get_ref())
+    .await?;
+  // Nearest parent first
+  ancestors.sort_by_key(|org| ancestor_ids.iter().position(|id| *id == org.id));
+  Ok(HttpResponse::Ok().json(ancestors))
+}
+
+/// Moving needs manage rights on the organisation, the parent it leaves and the parent it joins
+async fn move_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<MoveOrganisation>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  let org = find_organisation(&db, organisation_id).await?;
+  user.authorize(&db, organisation_id, None, RESOURCE, "manage").await?;
+  if let Some(current_parent_id) = org.parent_organisation_id {
+    user.authorize(&db, current_parent_id, None, RESOURCE, "manage").await?;
+  }
+  if let Some(new_parent_id) = body.parent_organisation_id {
+    find_organisation(&db, new_parent_id).await?;
+    user.authorize(&db, new_parent_id, None, RESOURCE, "manage").await?;
+  }
+  let mut active_org = org.into_active_model();
+  active_org.parent_organisation_id = Set(body.parent_organisation_id);
+  let org = active_org.update(db.get_ref()).await?;
+  Ok(HttpResponse::Ok().json(org))
+}
+
+async fn get_settings(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "read").await?;
+  let org = find_organisation(&db, organisation_id).await?;
+  let effective_settings = organisation::find_effective_settings(db.get_ref(), organisation_id).await?;
+  Ok(HttpResponse::Ok().json(json!({
+    "settings": org.settings,
+    "effective_settings": effective_settings,
+  })))
+}
+
+/// The settings carry the `security` overrides for the organisation and its children, so
+/// changing them needs manage rights
+async fn update_settings(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<Map<String, Value>>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "manage").await?;
+  let mut active_org = find_organisation(&db, organisation_id).await?.into_active_model();
+  active_org.settings = Set(Value::Object(body.into_inner()));
+  Ok(HttpResponse::Ok().json(active_org.update(db.get_ref()).await?))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/organisations/{organisation_id}/children", web::get().to(children))
+    .route("/organisations/{organisation_id}/ancestors", web::get().to(ancestors))
+    .route("/organisations/{organisation_id}/move", web::post().to(move_organisation))
+    .route("/organisations/{organisation_id}/settings", web::get().to(get_settings))
+    .route("/organisations/{organisation_id}/settings", web::put().to(update_settings));
+}
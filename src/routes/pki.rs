@@ -0,0 +1,64 @@
+//! What relying parties fetch without credentials: each CA's CRL and its OCSP responder, and the
+//! public keys of SSH CAs for servers' `TrustedUserCAKeys`.
+use actix_web::{web, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use entities::{certificate_authority, ocsp, ssh_certificate_authority, x509};
+use sea_orm::{entity::prelude::*, DatabaseConnection};
+use crate::error::ApiError;
+
+async fn find_authority(db: &DatabaseConnection, authority_id: Uuid) -> Result<certificate_authority::Model, ApiError> {
+  certificate_authority::Entity::find_by_id(authority_id)
+    .one(db)
+    .await?
+    .ok_or(ApiError::NotFound)
+}
+
+async fn crl(db: web::Data<DatabaseConnection>, path: web::Path<Uuid>) -> Result<HttpResponse, ApiError> {
+  let mut authority = find_authority(&db, path.into_inner()).await?;
+  if authority.crl.is_none() {
+    authority = certificate_authority::publish_crl(db.get_ref(), &authority).await?;
+  }
+  let der = x509::crl_der(authority.crl.as_deref().unwrap_or_default()).map_err(|err| ApiError::Db(DbErr::Custom(err)))?;
+  Ok(HttpResponse::Ok().content_type("application/pkix-crl").body(der))
+}
+
+async fn answer(db: &DatabaseConnection, authority_id: Uuid, request: &[u8]) -> Result<HttpResponse, ApiError> {
+  let authority = find_authority(db, authority_id).await?;
+  let response = ocsp::respond(db, &authority, request).await.unwrap_or_else(|err| {
+    log::error!("OCSP responder for {} failed: {}", authority.id, err);
+    ocsp::internal_error()
+  });
+  Ok(HttpResponse::Ok().content_type("application/ocsp-response").body(response))
+}
+
+async fn ocsp_post(
+  db: web::Data<DatabaseConnection>,
+  path: web::Path<Uuid>,
+  body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+  answer(&db, path.into_inner(), &body).await
+}
+
+/// RFC 6960 appendix A, the base64 DER request is the last part of the path
+async fn ocsp_get(db: web::Data<DatabaseConnection>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, ApiError> {
+  let (authority_id, request) = path.into_inner();
+  // Malformed requests still get an OCSP answer, an empty one decodes as malformed
+  let request = STANDARD.decode(request).unwrap_or_default();
+  answer(&db, authority_id, &request).await
+}
+
+async fn ssh_public_key(db: web::Data<DatabaseConnection>, path: web::Path<Uuid>) -> Result<HttpResponse, ApiError> {
+  let authority = ssh_certificate_authority::Entity::find_by_id(path.into_inner())
+    .one(db.get_ref())
+    .await?
+    .ok_or(ApiError::NotFound)?;
+  Ok(HttpResponse::Ok().content_type("text/plain").body(format!("{}\n", authority.public_key)))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/pki/cas/{ca_id}/crl", web::get().to(crl))
+    .route("/pki/cas/{ca_id}/ocsp", web::post().to(ocsp_post))
+    .route("/pki/cas/{ca_id}/ocsp/{request:.*}", web::get().to(ocsp_get))
+    .route("/pki/ssh/cas/{ca_id}/public_key", web::get().to(ssh_public_key));
+}
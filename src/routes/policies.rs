@@ -0,0 +1,122 @@
+use std::net::IpAddr;
+use actix_web::{web, HttpResponse};
+use entities::{organisation_policy, policy::{PolicyDocument, RequestContext}};
+use sea_orm::{entity::prelude::*, ActiveValue::Set, DatabaseConnection};
+use serde::Deserialize;
+use shared::{clock, GroupId, OrgId, UserId};
+use crate::{auth::AuthenticatedUser, error::ApiError};
+
+const RESOURCE: &str = "policy";
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePolicy {
+  pub name: String,
+  pub description: Option<String>,
+  pub document: PolicyDocument,
+  #[serde(default = "default_enabled")]
+  pub is_enabled: bool,
+}
+
+fn default_enabled() -> bool {
+  true
+}
+
+/// A hypothetical request to explain, defaults to the caller from their current address
+#[derive(Debug, Deserialize)]
+pub struct ExplainRequest {
+  pub user_id: Option<UserId>,
+  pub resource: String,
+  pub verb: String,
+  pub group_id: Option<GroupId>,
+  pub source_ip: Option<IpAddr>,
+  pub at: Option<ChronoDateTimeUtc>,
+  #[serde(default)]
+  pub mfa: bool,
+}
+
+async fn list(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "list").await?;
+  let policies = organisation_policy::Entity::find()
+    .filter(organisation_policy::Column::OrganisationId.eq(organisation_id))
+    .all(db.get_ref())
+    .await?;
+  Ok(HttpResponse::Ok().json(policies))
+}
+
+async fn create(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<CreatePolicy>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "create").await?;
+  let body = body.into_inner();
+  body.document.validate().map_err(ApiError::Validation)?;
+  let policy = organisation_policy::ActiveModel {
+    organisation_id: Set(organisation_id),
+    name: Set(body.name),
+    description: Set(body.description),
+    document: Set(body.document),
+    is_enabled: Set(body.is_enabled),
+    ..Default::default()
+  }.insert(db.get_ref()).await?;
+  Ok(HttpResponse::Created().json(policy))
+}
+
+async fn delete(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, policy_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "delete").await?;
+  let result = organisation_policy::Entity::delete_many()
+    .filter(organisation_policy::Column::Id.eq(policy_id))
+    .filter(organisation_policy::Column::OrganisationId.eq(organisation_id))
+    .exec(db.get_ref())
+    .await?;
+  if result.rows_affected == 0 {
+    return Err(ApiError::NotFound);
+  }
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Dry run of an access decision showing which role and statements applied
+async fn explain(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<ExplainRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "read").await?;
+  let body = body.into_inner();
+  let decision = organisation_policy::decide(db.get_ref(), RequestContext {
+    user_id: body.user_id.unwrap_or(user.user_id),
+    organisation_id,
+    resource: body.resource,
+    verb: body.verb,
+    group_id: body.group_id,
+    source_ip: body.source_ip.or(user.source_ip),
+    at: body.at.unwrap_or_else(clock::now),
+    mfa: body.mfa,
+    group_organisation_id: None,
+  }).await?;
+  Ok(HttpResponse::Ok().json(decision))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg.service(
+    web::scope("/organisations/{organisation_id}/policies")
+      .route("", web::get().to(list))
+      .route("", web::post().to(create))
+      .route("/explain", web::post().to(explain))
+      .route("/{policy_id}", web::delete().to(delete))
+  );
+}
@@ -0,0 +1,145 @@
+//! An organisation's SSH CAs, how each group's members get certificates and the certificates signed.
+//!
+//! CAs and group settings are managed with `manage`. Members sign their own keys with `create` on
+//! SSH certificates in the group, the principals come from the group they're a member of.
+use actix_web::{web, HttpResponse};
+use entities::{
+  group,
+  ssh_certificate::{self, SignRequest},
+  ssh_certificate_authority::{self, NewSshAuthority},
+  ssh_group_setting::{self, SshTemplate},
+};
+use sea_orm::{entity::prelude::*, DatabaseConnection, QueryOrder};
+use serde::Deserialize;
+use shared::{GroupId, OrgId};
+use crate::{auth::AuthenticatedUser, error::ApiError};
+
+const AUTHORITY_RESOURCE: &str = "ssh_certificate_authority";
+const SETTING_RESOURCE: &str = "ssh_group_setting";
+const RESOURCE: &str = "ssh_certificate";
+
+#[derive(Debug, Deserialize)]
+pub struct PutSetting {
+  pub ssh_certificate_authority_id: Uuid,
+  #[serde(default)]
+  pub template: SshTemplate,
+}
+
+async fn find_group(db: &DatabaseConnection, organisation_id: OrgId, group_id: GroupId) -> Result<group::Model, ApiError> {
+  group::Entity::find_by_id(group_id)
+    .one(db)
+    .await?
+    .filter(|group| group.organisation_id == organisation_id)
+    .ok_or(ApiError::NotFound)
+}
+
+async fn create_authority(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<NewSshAuthority>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, AUTHORITY_RESOURCE, "manage").await?;
+  let authority = ssh_certificate_authority::create(db.get_ref(), organisation_id, body.into_inner()).await?;
+  Ok(HttpResponse::Created().json(authority))
+}
+
+async fn list_authorities(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, AUTHORITY_RESOURCE, "list").await?;
+  let authorities = ssh_certificate_authority::Entity::find()
+    .filter(ssh_certificate_authority::Column::OrganisationId.eq(organisation_id))
+    .order_by_asc(ssh_certificate_authority::Column::CreatedAt)
+    .all(db.get_ref())
+    .await?;
+  Ok(HttpResponse::Ok().json(authorities))
+}
+
+async fn read_authority(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, authority_id) = path.into_inner();
+  user.authorize(&db, organisation_id, None, AUTHORITY_RESOURCE, "read").await?;
+  let authority = ssh_certificate_authority::Entity::find_by_id(authority_id)
+    .filter(ssh_certificate_authority::Column::OrganisationId.eq(organisation_id))
+    .one(db.get_ref())
+    .await?
+    .ok_or(ApiError::NotFound)?;
+  Ok(HttpResponse::Ok().json(authority))
+}
+
+async fn read_setting(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), SETTING_RESOURCE, "read").await?;
+  let group = find_group(&db, organisation_id, group_id).await?;
+  let setting = ssh_group_setting::Entity::find()
+    .filter(ssh_group_setting::Column::GroupId.eq(group.id))
+    .one(db.get_ref())
+    .await?
+    .ok_or(ApiError::NotFound)?;
+  Ok(HttpResponse::Ok().json(setting))
+}
+
+async fn put_setting(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+  body: web::Json<PutSetting>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), SETTING_RESOURCE, "manage").await?;
+  let group = find_group(&db, organisation_id, group_id).await?;
+  let body = body.into_inner();
+  let setting = ssh_group_setting::put(db.get_ref(), &group, body.ssh_certificate_authority_id, body.template).await?;
+  Ok(HttpResponse::Ok().json(setting))
+}
+
+async fn sign(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+  body: web::Json<SignRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), RESOURCE, "create").await?;
+  let group = find_group(&db, organisation_id, group_id).await?;
+  let certificate = ssh_certificate::sign(db.get_ref(), user.user_id, &group, body.into_inner()).await?;
+  Ok(HttpResponse::Created().json(certificate))
+}
+
+async fn list_certificates(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "list").await?;
+  let certificates = ssh_certificate::Entity::find()
+    .filter(ssh_certificate::Column::OrganisationId.eq(organisation_id))
+    .order_by_desc(ssh_certificate::Column::CreatedAt)
+    .all(db.get_ref())
+    .await?;
+  Ok(HttpResponse::Ok().json(certificates))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/organisations/{organisation_id}/ssh/cas", web::get().to(list_authorities))
+    .route("/organisations/{organisation_id}/ssh/cas", web::post().to(create_authority))
+    .route("/organisations/{organisation_id}/ssh/cas/{ca_id}", web::get().to(read_authority))
+    .route("/organisations/{organisation_id}/ssh/certificates", web::get().to(list_certificates))
+    .route("/organisations/{organisation_id}/groups/{group_id}/ssh", web::get().to(read_setting))
+    .route("/organisations/{organisation_id}/groups/{group_id}/ssh", web::put().to(put_setting))
+    .route("/organisations/{organisation_id}/groups/{group_id}/ssh/sign", web::post().to(sign));
+}
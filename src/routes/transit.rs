@@ -0,0 +1,355 @@
+//! Encrypting and decrypting with organisations' and groups' named transit keys, which never leave.
+//!
+//! Listing and reading keys needs `list` and `read` on transit keys, creating them `create`, and
+//! rotating them or setting their minimum decryption version `manage`. Encrypting and generating
+//! data keys needs `create` on transit data, decrypting `read` and rewrapping `update`.
+use actix_web::{web, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use entities::{group, transit_key::{self, NewTransitKey}};
+use sea_orm::{entity::prelude::*, DatabaseConnection, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use shared::{GroupId, OrgId};
+use crate::{auth::AuthenticatedUser, error::ApiError};
+
+const RESOURCE: &str = "transit_data";
+const KEY_RESOURCE: &str = "transit_key";
+
+#[derive(Debug, Deserialize)]
+pub struct KeyConfig {
+  pub min_decryption_version: i32,
+}
+
+/// Base64 encoded, left out of `Debug` so the plaintext isn't logged
+#[derive(Deserialize)]
+pub struct EncryptRequest {
+  pub plaintext: String,
+  pub associated_data: Option<String>,
+}
+
+/// `associated_data` must be what it was encrypted with
+#[derive(Debug, Deserialize)]
+pub struct DecryptRequest {
+  pub ciphertext: String,
+  pub associated_data: Option<String>,
+}
+
+/// Leave the plaintext out with `wrapped_only` to hand the data key to something that can't see it
+#[derive(Debug, Deserialize)]
+pub struct DataKeyRequest {
+  pub bits: Option<usize>,
+  pub associated_data: Option<String>,
+  #[serde(default)]
+  pub wrapped_only: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Encrypted {
+  pub ciphertext: String,
+  pub version: i32,
+}
+
+#[derive(Serialize)]
+pub struct Decrypted {
+  pub plaintext: String,
+}
+
+#[derive(Serialize)]
+pub struct DataKey {
+  pub ciphertext: String,
+  pub version: i32,
+  pub plaintext: Option<String>,
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, ApiError> {
+  STANDARD.decode(value).map_err(|_| ApiError::Validation(format!("{} must be base64", field)))
+}
+
+fn associated_data(value: Option<&str>) -> Result<Vec<u8>, ApiError> {
+  value.map(|value| decode("associated_data", value)).transpose().map(Option::unwrap_or_default)
+}
+
+async fn find_group(db: &DatabaseConnection, organisation_id: OrgId, group_id: GroupId) -> Result<group::Model, ApiError> {
+  group::Entity::find_by_id(group_id)
+    .one(db)
+    .await?
+    .filter(|group| group.organisation_id == organisation_id)
+    .ok_or(ApiError::NotFound)
+}
+
+async fn find_key(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str) -> Result<transit_key::Model, ApiError> {
+  transit_key::find(db, organisation_id, group_id, name).await?.ok_or(ApiError::NotFound)
+}
+
+async fn create_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, body: NewTransitKey) -> Result<HttpResponse, ApiError> {
+  let txn = db.begin().await?;
+  let key = transit_key::create(&txn, organisation_id, group_id, body).await?;
+  txn.commit().await?;
+  Ok(HttpResponse::Created().json(key))
+}
+
+async fn read_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str) -> Result<HttpResponse, ApiError> {
+  Ok(HttpResponse::Ok().json(find_key(db, organisation_id, group_id, name).await?))
+}
+
+async fn rotate_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, organisation_id, group_id, name).await?;
+  let txn = db.begin().await?;
+  let rotated = transit_key::rotate(&txn, &key).await?;
+  txn.commit().await?;
+  Ok(HttpResponse::Created().json(rotated))
+}
+
+async fn configure_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str, body: KeyConfig) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, organisation_id, group_id, name).await?;
+  Ok(HttpResponse::Ok().json(transit_key::set_min_decryption_version(db, &key, body.min_decryption_version).await?))
+}
+
+async fn encrypt_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str, body: EncryptRequest) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, organisation_id, group_id, name).await?;
+  let plaintext = decode("plaintext", &body.plaintext)?;
+  let ciphertext = transit_key::encrypt(db, &key, &plaintext, &associated_data(body.associated_data.as_deref())?).await?;
+  Ok(HttpResponse::Ok().json(Encrypted { ciphertext, version: key.latest_version }))
+}
+
+async fn decrypt_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str, body: DecryptRequest) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, organisation_id, group_id, name).await?;
+  let plaintext = transit_key::decrypt(db, &key, &body.ciphertext, &associated_data(body.associated_data.as_deref())?).await?;
+  Ok(HttpResponse::Ok().json(Decrypted { plaintext: STANDARD.encode(plaintext) }))
+}
+
+async fn rewrap_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str, body: DecryptRequest) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, organisation_id, group_id, name).await?;
+  let ciphertext = transit_key::rewrap(db, &key, &body.ciphertext, &associated_data(body.associated_data.as_deref())?).await?;
+  Ok(HttpResponse::Ok().json(Encrypted { ciphertext, version: key.latest_version }))
+}
+
+async fn data_key_for(db: &DatabaseConnection, organisation_id: OrgId, group_id: Option<GroupId>, name: &str, body: DataKeyRequest) -> Result<HttpResponse, ApiError> {
+  let key = find_key(db, organisation_id, group_id, name).await?;
+  let bits = body.bits.unwrap_or(256);
+  let (plaintext, ciphertext) = transit_key::data_key(db, &key, bits, &associated_data(body.associated_data.as_deref())?).await?;
+  let plaintext = (!body.wrapped_only).then(|| STANDARD.encode(plaintext));
+  Ok(HttpResponse::Ok().json(DataKey { ciphertext, version: key.latest_version, plaintext }))
+}
+
+async fn list_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "list").await?;
+  Ok(HttpResponse::Ok().json(transit_key::list(&**db, organisation_id, None).await?))
+}
+
+async fn create_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<OrgId>,
+  body: web::Json<NewTransitKey>,
+) -> Result<HttpResponse, ApiError> {
+  let organisation_id = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "create").await?;
+  create_for(&db, organisation_id, None, body.into_inner()).await
+}
+
+async fn read_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "read").await?;
+  read_for(&db, organisation_id, None, &name).await
+}
+
+async fn rotate_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "manage").await?;
+  rotate_for(&db, organisation_id, None, &name).await
+}
+
+async fn configure_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+  body: web::Json<KeyConfig>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, None, KEY_RESOURCE, "manage").await?;
+  configure_for(&db, organisation_id, None, &name, body.into_inner()).await
+}
+
+async fn encrypt_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+  body: web::Json<EncryptRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "create").await?;
+  encrypt_for(&db, organisation_id, None, &name, body.into_inner()).await
+}
+
+async fn decrypt_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+  body: web::Json<DecryptRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "read").await?;
+  decrypt_for(&db, organisation_id, None, &name, body.into_inner()).await
+}
+
+async fn rewrap_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+  body: web::Json<DecryptRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "update").await?;
+  rewrap_for(&db, organisation_id, None, &name, body.into_inner()).await
+}
+
+async fn data_key_for_organisation(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, String)>,
+  body: web::Json<DataKeyRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, None, RESOURCE, "create").await?;
+  data_key_for(&db, organisation_id, None, &name, body.into_inner()).await
+}
+
+async fn list_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "list").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  Ok(HttpResponse::Ok().json(transit_key::list(&**db, organisation_id, Some(group_id)).await?))
+}
+
+async fn create_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId)>,
+  body: web::Json<NewTransitKey>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "create").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  create_for(&db, organisation_id, Some(group_id), body.into_inner()).await
+}
+
+async fn read_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, String)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "read").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  read_for(&db, organisation_id, Some(group_id), &name).await
+}
+
+async fn rotate_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, String)>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "manage").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  rotate_for(&db, organisation_id, Some(group_id), &name).await
+}
+
+async fn configure_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, String)>,
+  body: web::Json<KeyConfig>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), KEY_RESOURCE, "manage").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  configure_for(&db, organisation_id, Some(group_id), &name, body.into_inner()).await
+}
+
+async fn encrypt_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, String)>,
+  body: web::Json<EncryptRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), RESOURCE, "create").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  encrypt_for(&db, organisation_id, Some(group_id), &name, body.into_inner()).await
+}
+
+async fn decrypt_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, String)>,
+  body: web::Json<DecryptRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), RESOURCE, "read").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  decrypt_for(&db, organisation_id, Some(group_id), &name, body.into_inner()).await
+}
+
+async fn rewrap_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, String)>,
+  body: web::Json<DecryptRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), RESOURCE, "update").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  rewrap_for(&db, organisation_id, Some(group_id), &name, body.into_inner()).await
+}
+
+async fn data_key_for_group(
+  db: web::Data<DatabaseConnection>,
+  user: AuthenticatedUser,
+  path: web::Path<(OrgId, GroupId, String)>,
+  body: web::Json<DataKeyRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let (organisation_id, group_id, name) = path.into_inner();
+  user.authorize(&db, organisation_id, Some(group_id), RESOURCE, "create").await?;
+  find_group(&db, organisation_id, group_id).await?;
+  data_key_for(&db, organisation_id, Some(group_id), &name, body.into_inner()).await
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/organisations/{organisation_id}/transit/keys", web::get().to(list_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys", web::post().to(create_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys/{name}", web::get().to(read_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys/{name}/config", web::put().to(configure_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys/{name}/rotate", web::post().to(rotate_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys/{name}/encrypt", web::post().to(encrypt_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys/{name}/decrypt", web::post().to(decrypt_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys/{name}/rewrap", web::post().to(rewrap_for_organisation))
+    .route("/organisations/{organisation_id}/transit/keys/{name}/datakey", web::post().to(data_key_for_organisation))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys", web::get().to(list_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys", web::post().to(create_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys/{name}", web::get().to(read_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys/{name}/config", web::put().to(configure_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys/{name}/rotate", web::post().to(rotate_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys/{name}/encrypt", web::post().to(encrypt_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys/{name}/decrypt", web::post().to(decrypt_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys/{name}/rewrap", web::post().to(rewrap_for_group))
+    .route("/organisations/{organisation_id}/groups/{group_id}/transit/keys/{name}/datakey", web::post().to(data_key_for_group));
+}
@@ -0,0 +1,207 @@
+//! `new_*` build a valid unsaved model to tweak before inserting,
+//! the plain names insert one with defaults and return it.
+use entities::{
+  auth_api_key, auth_method_magiclink, auth_method_pass::{self, PassHashCipher}, email, group,
+  group_access_role::{self, GroupRolePermissions}, organisation, organisation_access_role::{self, OrgRolePermissions},
+  user::{self, LockedState}, user_profile, users_groups_group_access_roles, users_organisations_organisations_access_roles,
+};
+use sea_orm::{entity::prelude::*, ActiveValue::Set};
+use shared::{clock, GroupId, OrgId, UserId};
+
+/// Keeps names and addresses unique across the factories
+fn unique(prefix: &str) -> String {
+  format!("{}-{}", prefix, Uuid::new_v4().simple())
+}
+
+pub fn new_user() -> user::ActiveModel {
+  user::ActiveModel {
+    locked_state: Set(LockedState::Unlocked),
+    locked_state_updated_at: Set(clock::now()),
+    locked_state_expires_at: Set(None),
+    pki_key_id: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn user<C: ConnectionTrait>(db: &C) -> user::Model {
+  new_user().insert(db).await.expect("insert user")
+}
+
+pub fn new_user_profile(user_id: UserId) -> user_profile::ActiveModel {
+  user_profile::ActiveModel {
+    user_id: Set(user_id),
+    username: Set(None),
+    profile_image_file_id: Set(None),
+    name: Set(unique("name")),
+    contact_details: Set(Json::Object(Default::default())),
+    notes: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn user_profile<C: ConnectionTrait>(db: &C, user_id: UserId) -> user_profile::Model {
+  new_user_profile(user_id).insert(db).await.expect("insert user profile")
+}
+
+pub fn new_email(user_id: UserId) -> email::ActiveModel {
+  email::ActiveModel {
+    user_id: Set(user_id),
+    email_address: Set(format!("{}@example.com", unique("user"))),
+    verification_code: Set(None),
+    verification_code_expires_at: Set(None),
+    is_primary: Set(true),
+    is_verified: Set(false),
+    ..Default::default()
+  }
+}
+
+pub async fn email<C: ConnectionTrait>(db: &C, user_id: UserId) -> email::Model {
+  new_email(user_id).insert(db).await.expect("insert email")
+}
+
+pub fn new_password(user_id: UserId, password: &str) -> auth_method_pass::ActiveModel {
+  auth_method_pass::ActiveModel {
+    user_id: Set(user_id),
+    pass_hash: Set(Some(password.to_string())),
+    pass_hash_cipher: Set(PassHashCipher::Bcrypt),
+    pass_last_changed_at: Set(clock::now()),
+    force_pass_change: Set(false),
+    pass_reset_code: Set(None),
+    pass_reset_str: Set(None),
+    pass_reset_code_expires_at: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn password<C: ConnectionTrait>(db: &C, user_id: UserId, password: &str) -> auth_method_pass::Model {
+  new_password(user_id, password).insert(db).await.expect("insert password")
+}
+
+pub fn new_magiclink(user_id: UserId, email_id: Option<Uuid>) -> auth_method_magiclink::ActiveModel {
+  auth_method_magiclink::ActiveModel {
+    user_id: Set(user_id),
+    email_id: Set(email_id),
+    phone_id: Set(None),
+    link_used_at: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn magiclink<C: ConnectionTrait>(db: &C, user_id: UserId, email_id: Option<Uuid>) -> auth_method_magiclink::Model {
+  new_magiclink(user_id, email_id).insert(db).await.expect("insert magiclink")
+}
+
+pub fn new_api_key(user_id: UserId) -> auth_api_key::ActiveModel {
+  auth_api_key::ActiveModel {
+    user_id: Set(Some(user_id)),
+    organisation_id: Set(None),
+    expires_on: Set(None),
+    ip_address_last_used: Set(None),
+    key_last_used_at: Set(None),
+    ..Default::default()
+  }
+}
+
+/// The key and its secret, which is not stored
+pub async fn api_key<C: ConnectionTrait>(db: &C, user_id: UserId) -> (auth_api_key::Model, String) {
+  auth_api_key::issue(db, new_api_key(user_id)).await.expect("issue api key")
+}
+
+pub fn new_organisation() -> organisation::ActiveModel {
+  organisation::ActiveModel {
+    name: Set(unique("organisation")),
+    pki_key_id: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn organisation<C: ConnectionTrait>(db: &C) -> organisation::Model {
+  new_organisation().insert(db).await.expect("insert organisation")
+}
+
+pub async fn child_organisation<C: ConnectionTrait>(db: &C, parent_id: OrgId) -> organisation::Model {
+  organisation::ActiveModel {
+    parent_organisation_id: Set(Some(parent_id)),
+    ..new_organisation()
+  }.insert(db).await.expect("insert child organisation")
+}
+
+pub fn new_organisation_role(permissions: OrgRolePermissions) -> organisation_access_role::ActiveModel {
+  organisation_access_role::ActiveModel {
+    name: Set(unique("organisation-role")),
+    description: Set(None),
+    org_role_permissions: Set(permissions),
+    permissions: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn organisation_role<C: ConnectionTrait>(db: &C, permissions: OrgRolePermissions) -> organisation_access_role::Model {
+  new_organisation_role(permissions).insert(db).await.expect("insert organisation role")
+}
+
+pub fn new_group(organisation_id: OrgId) -> group::ActiveModel {
+  group::ActiveModel {
+    name: Set(unique("group")),
+    organisation_id: Set(organisation_id),
+    parent_group_id: Set(None),
+    group_image_file_id: Set(None),
+    description: Set(None),
+    icon: Set(None),
+    color_rgb: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn group<C: ConnectionTrait>(db: &C, organisation_id: OrgId) -> group::Model {
+  new_group(organisation_id).insert(db).await.expect("insert group")
+}
+
+pub async fn child_group<C: ConnectionTrait>(db: &C, parent: &group::Model) -> group::Model {
+  group::ActiveModel {
+    parent_group_id: Set(Some(parent.id)),
+    ..new_group(parent.organisation_id)
+  }.insert(db).await.expect("insert child group")
+}
+
+pub fn new_group_role(permissions: GroupRolePermissions) -> group_access_role::ActiveModel {
+  group_access_role::ActiveModel {
+    name: Set(unique("group-role")),
+    description: Set(None),
+    group_role_permissions: Set(permissions),
+    permissions: Set(None),
+    ..Default::default()
+  }
+}
+
+pub async fn group_role<C: ConnectionTrait>(db: &C, permissions: GroupRolePermissions) -> group_access_role::Model {
+  new_group_role(permissions).insert(db).await.expect("insert group role")
+}
+
+pub async fn assign_organisation_role<C: ConnectionTrait>(
+  db: &C,
+  user_id: UserId,
+  organisation_id: OrgId,
+  organisation_access_role_id: Uuid,
+) -> users_organisations_organisations_access_roles::Model {
+  users_organisations_organisations_access_roles::ActiveModel {
+    user_id: Set(user_id),
+    organisation_id: Set(organisation_id),
+    organisation_access_role_id: Set(organisation_access_role_id),
+    ..Default::default()
+  }.insert(db).await.expect("assign organisation role")
+}
+
+pub async fn assign_group_role<C: ConnectionTrait>(
+  db: &C,
+  user_id: UserId,
+  group_id: GroupId,
+  group_access_role_id: Uuid,
+) -> users_groups_group_access_roles::Model {
+  users_groups_group_access_roles::ActiveModel {
+    user_id: Set(user_id),
+    group_id: Set(group_id),
+    group_access_role_id: Set(group_access_role_id),
+    ..Default::default()
+  }.insert(db).await.expect("assign group role")
+}
@@ -0,0 +1,62 @@
+//! Isolated, migrated databases and entity factories for tests.
+//!
+//! Every `TestDb` gets a database nobody else can see: an in-memory SQLite database by default,
+//! or a throwaway schema on the Postgres server in `TEST_DATABASE_URL` when that is set.
+use std::ops::Deref;
+use migration::Migrator;
+use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection};
+
+pub mod factory;
+
+pub const TEST_DATABASE_URL: &str = "TEST_DATABASE_URL";
+
+pub struct TestDb {
+  db: DatabaseConnection,
+  /// The admin connection and schema to drop once the test is done, Postgres only
+  postgres: Option<(DatabaseConnection, String)>,
+}
+
+impl TestDb {
+  pub async fn new() -> Self {
+    match std::env::var(TEST_DATABASE_URL) {
+      Ok(url) => Self::postgres(&url).await,
+      Err(_) => Self::sqlite().await,
+    }
+  }
+
+  pub async fn sqlite() -> Self {
+    let db = Database::connect("sqlite::memory:").await.expect("open in-memory sqlite");
+    Migrator::run_migrations(&db).await.expect("migrate sqlite");
+    Self { db, postgres: None }
+  }
+
+  /// Migrates a fresh schema and points the connection's search path at it
+  pub async fn postgres(url: &str) -> Self {
+    let admin = Database::connect(url).await.expect("connect to postgres");
+    let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+    admin.execute_unprepared(&format!("CREATE SCHEMA \"{}\"", schema)).await.expect("create test schema");
+
+    let mut options = ConnectOptions::new(url.to_string());
+    options.set_schema_search_path(schema.clone());
+    let db = Database::connect(options).await.expect("connect to test schema");
+    Migrator::run_migrations(&db).await.expect("migrate test schema");
+    Self { db, postgres: Some((admin, schema)) }
+  }
+
+  /// Drops the Postgres schema, an in-memory database just goes away with the connection
+  pub async fn close(self) {
+    self.db.close().await.expect("close test database");
+    if let Some((admin, schema)) = self.postgres {
+      admin.execute_unprepared(&format!("DROP SCHEMA \"{}\" CASCADE", schema)).await.expect("drop test schema");
+      admin.close().await.expect("close admin connection");
+    }
+  }
+}
+
+impl Deref for TestDb {
+  type Target = DatabaseConnection;
+
+  fn deref(&self) -> &Self::Target {
+    &self.db
+  }
+}